@@ -0,0 +1,47 @@
+Feature: Account Merge
+  As an operations team
+  I want to merge two client IDs that turn out to be the same customer
+  So that their balances and history live in one account
+
+  Scenario: Merging moves balances and closes the old account
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I process a deposit of 40.0 for client 2 with transaction id 2
+    And I dispute transaction 2 for client 2
+    When I merge client 2 into client 1 from transaction id 1000
+    Then the available balance for client 1 should be 100.0
+    And the held balance for client 1 should be 40.0
+    And the total balance for client 2 should be 0.0
+    And the state of client 2 should be closed
+
+  Scenario: Merged transactions are referenced by their new IDs
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I process a deposit of 40.0 for client 2 with transaction id 2
+    And I merge client 2 into client 1 from transaction id 1000
+    When I dispute transaction 1000 for client 1
+    Then the available balance for client 1 should be 100.0
+    And the held balance for client 1 should be 40.0
+
+  Scenario: A lock carries over to the merged account
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I process a deposit of 40.0 for client 2 with transaction id 2
+    And I dispute transaction 2 for client 2
+    And I chargeback transaction 2 for client 2
+    When I merge client 2 into client 1 from transaction id 1000
+    Then the account for client 1 should be locked
+
+  Scenario: New transaction IDs must be free
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I process a deposit of 40.0 for client 2 with transaction id 2
+    When I merge client 2 into client 1 from transaction id 1
+    Then the transaction should fail with "Transaction ID already in use"
+    And the total balance for client 2 should be 40.0
+
+  Scenario: An account cannot be merged into itself
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    When I merge client 1 into client 1 from transaction id 1000
+    Then the transaction should fail with "Cannot merge an account into itself"
@@ -0,0 +1,40 @@
+Feature: Account Split
+  As an operations team
+  I want to move mis-keyed transactions to the client they belong to
+  So that both accounts show the right balances
+
+  Scenario: Moving deposits and withdrawals moves their balance effect
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I process a deposit of 30.0 for client 1 with transaction id 2
+    And I process a withdrawal of 10.0 for client 1 with transaction id 3
+    When I move transactions 2,3 from client 1 to new client 5
+    Then the available balance for client 1 should be 100.0
+    And the available balance for client 5 should be 20.0
+
+  Scenario: A disputed deposit stays held in the new account
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I process a deposit of 30.0 for client 1 with transaction id 2
+    And I dispute transaction 2 for client 1
+    When I move transactions 2 from client 1 to new client 5
+    Then the held balance for client 1 should be 0.0
+    And the held balance for client 5 should be 30.0
+    When I resolve transaction 2 for client 5
+    Then the available balance for client 5 should be 30.0
+
+  Scenario: Transactions can only move to a new client
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I process a deposit of 30.0 for client 2 with transaction id 2
+    When I move transactions 1 from client 1 to new client 2
+    Then the transaction should fail with "Account already exists"
+    And the available balance for client 1 should be 100.0
+
+  Scenario: Charged back deposits stay put
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I dispute transaction 1 for client 1
+    And I chargeback transaction 1 for client 1
+    When I move transactions 1 from client 1 to new client 5
+    Then the transaction should fail with "Transaction already charged back"
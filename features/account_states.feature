@@ -0,0 +1,76 @@
+Feature: Account States
+  As a trading platform
+  I want accounts to move through well-defined states
+  So that operators can freeze and close accounts safely
+
+  Scenario: Chargeback locks an active account
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I dispute transaction 1 for client 1
+    When I chargeback transaction 1 for client 1
+    Then the state of client 1 should be locked
+
+  Scenario: Frozen account rejects deposits
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I set the state of client 1 to frozen
+    When I attempt to process a deposit of 50.0 for client 1 with transaction id 2
+    Then the transaction should fail with "Account is frozen"
+    And the available balance for client 1 should be 100.0
+
+  Scenario: Frozen account still accepts disputes
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I set the state of client 1 to frozen
+    When I dispute transaction 1 for client 1
+    Then the held balance for client 1 should be 100.0
+
+  Scenario: Account frozen for outgoing funds rejects withdrawals
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I set the state of client 1 to frozen_outgoing
+    When I attempt to process a withdrawal of 40.0 for client 1 with transaction id 2
+    Then the transaction should fail with "Account is frozen"
+    And the available balance for client 1 should be 100.0
+
+  Scenario: Account frozen for outgoing funds still accepts deposits and disputes
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I set the state of client 1 to frozen_outgoing
+    When I process a deposit of 50.0 for client 1 with transaction id 2
+    And I dispute transaction 1 for client 1
+    Then the available balance for client 1 should be 50.0
+    And the held balance for client 1 should be 100.0
+    And the state of client 1 should be frozen_outgoing
+
+  Scenario: Unfreezing restores normal operation
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I set the state of client 1 to frozen
+    And I set the state of client 1 to active
+    When I process a withdrawal of 40.0 for client 1 with transaction id 2
+    Then the available balance for client 1 should be 60.0
+    And the state of client 1 should be active
+
+  Scenario: Cannot close an account with funds
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    When I set the state of client 1 to closed
+    Then the transaction should fail with "Account has non-zero balances"
+    And the state of client 1 should be active
+
+  Scenario: Closed account rejects deposits
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I process a withdrawal of 100.0 for client 1 with transaction id 2
+    And I set the state of client 1 to closed
+    When I attempt to process a deposit of 10.0 for client 1 with transaction id 3
+    Then the transaction should fail with "Account is closed"
+
+  Scenario: Closed is a final state
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I process a withdrawal of 100.0 for client 1 with transaction id 2
+    And I set the state of client 1 to closed
+    When I set the state of client 1 to active
+    Then the transaction should fail with "Cannot change account state from closed to active"
@@ -0,0 +1,50 @@
+Feature: Approval Workflow
+  As a risk team
+  I want high-risk operations to need a second person's approval
+  So that no single operator can move large sums or lift a lock alone
+
+  Scenario: A large withdrawal waits for approval
+    Given a new database that requires approval for withdrawals over 1000.0
+    And I process a deposit of 5000.0 for client 1 with transaction id 1
+    When I process a withdrawal of 2500.0 for client 1 with transaction id 2
+    Then the transaction should fail with "pending approval"
+    And the available balance for client 1 should be 5000.0
+    And there should be 1 operation pending approval
+    When I approve the pending operation as "supervisor"
+    Then the available balance for client 1 should be 2500.0
+    And there should be 0 operations pending approval
+
+  Scenario: Withdrawals up to the threshold apply straight away
+    Given a new database that requires approval for withdrawals over 1000.0
+    And I process a deposit of 5000.0 for client 1 with transaction id 1
+    When I process a withdrawal of 1000.0 for client 1 with transaction id 2
+    Then the available balance for client 1 should be 4000.0
+    And there should be 0 operations pending approval
+
+  Scenario: A rejected withdrawal is discarded
+    Given a new database that requires approval for withdrawals over 1000.0
+    And I process a deposit of 5000.0 for client 1 with transaction id 1
+    And I process a withdrawal of 2500.0 for client 1 with transaction id 2
+    When I reject the pending operation as "supervisor"
+    Then the available balance for client 1 should be 5000.0
+    And there should be 0 operations pending approval
+
+  Scenario: Approval fails if the funds are gone
+    Given a new database that requires approval for withdrawals over 1000.0
+    And I process a deposit of 3000.0 for client 1 with transaction id 1
+    And I process a withdrawal of 2500.0 for client 1 with transaction id 2
+    And I process a withdrawal of 1000.0 for client 1 with transaction id 3
+    When I approve the pending operation as "supervisor"
+    Then the transaction should fail with "Insufficient funds"
+    And there should be 1 operation pending approval
+
+  Scenario: Unlocking needs approval
+    Given a new database that requires approval for unlocks
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I dispute transaction 1 for client 1
+    And I chargeback transaction 1 for client 1
+    When I set the state of client 1 to active
+    Then the transaction should fail with "pending approval"
+    And the account for client 1 should be locked
+    When I approve the pending operation as "compliance"
+    Then the state of client 1 should be active
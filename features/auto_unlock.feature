@@ -0,0 +1,51 @@
+Feature: Automatic Unlock
+  As a trading platform
+  I want locked accounts to be released by policy
+  So that a single chargeback does not block a client forever
+
+  Scenario: Accounts stay locked without an unlock policy
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I dispute transaction 1 for client 1
+    And I chargeback transaction 1 for client 1 at time 1000
+    When I apply automatic unlocks at time 999999
+    Then the account for client 1 should be locked
+
+  Scenario: Account unlocks after the cooling-off period
+    Given a new database that unlocks accounts 86400 seconds after a chargeback
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I dispute transaction 1 for client 1
+    And I chargeback transaction 1 for client 1 at time 1000
+    When I apply automatic unlocks at time 87400
+    Then the account for client 1 should not be locked
+
+  Scenario: Account stays locked during the cooling-off period
+    Given a new database that unlocks accounts 86400 seconds after a chargeback
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I dispute transaction 1 for client 1
+    And I chargeback transaction 1 for client 1 at time 1000
+    When I apply automatic unlocks at time 87399
+    Then the account for client 1 should be locked
+
+  Scenario: Review-gated unlock waits for review
+    Given a new database that unlocks accounts after review
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I dispute transaction 1 for client 1
+    And I chargeback transaction 1 for client 1
+    When I apply automatic unlocks at time 5000
+    Then the account for client 1 should be locked
+
+  Scenario: Review-gated unlock releases reviewed accounts
+    Given a new database that unlocks accounts after review
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I dispute transaction 1 for client 1
+    And I chargeback transaction 1 for client 1
+    And I mark the account for client 1 as reviewed by "alice"
+    When I apply automatic unlocks at time 5000
+    Then the account for client 1 should not be locked
+
+  Scenario: Only locked accounts can be reviewed
+    Given a new database that unlocks accounts after review
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    When I mark the account for client 1 as reviewed by "alice"
+    Then the transaction should fail with "Account is not locked"
@@ -0,0 +1,29 @@
+Feature: Atomic Dispute Bundles
+  As a fraud operations team
+  I want to dispute several transactions in one step
+  So that a case never ends up with only some of its holds in place
+
+  Scenario: Dispute several deposits together
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I process a deposit of 50.0 for client 1 with transaction id 2
+    When I dispute transactions 1,2 for client 1 together
+    Then the available balance for client 1 should be 0.0
+    And the held balance for client 1 should be 150.0
+
+  Scenario: One invalid transaction prevents every hold
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I process a deposit of 50.0 for client 1 with transaction id 2
+    And I dispute transaction 2 for client 1
+    When I dispute transactions 1,2 for client 1 together
+    Then the transaction should fail with "Transaction already disputed"
+    And the available balance for client 1 should be 100.0
+    And the held balance for client 1 should be 50.0
+
+  Scenario: Listing a transaction twice is rejected
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    When I dispute transactions 1,1 for client 1 together
+    Then the transaction should fail with "Transaction already disputed"
+    And the held balance for client 1 should be 0.0
@@ -0,0 +1,52 @@
+Feature: Chargeback Reversal
+  As a trading platform
+  I want to reverse chargebacks that the merchant wins on representment
+  So that clients are re-credited the charged back funds
+
+  Scenario: Reverse a chargeback
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I dispute transaction 1 for client 1
+    And I chargeback transaction 1 for client 1
+    When I reverse the chargeback of transaction 1 for client 1
+    Then the available balance for client 1 should be 100.0
+    And the held balance for client 1 should be 0.0
+    And the account for client 1 should be locked
+
+  Scenario: Cannot reverse a chargeback that did not happen
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I dispute transaction 1 for client 1
+    When I reverse the chargeback of transaction 1 for client 1
+    Then the transaction should fail with "Transaction is not charged back"
+    And the held balance for client 1 should be 100.0
+
+  Scenario: Represented transactions cannot be disputed again
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I dispute transaction 1 for client 1
+    And I chargeback transaction 1 for client 1
+    And I reverse the chargeback of transaction 1 for client 1
+    When I attempt to dispute transaction 1 for client 1
+    Then the transaction should fail with "Transaction already represented"
+
+  Scenario: Policy unlocks the account once no chargebacks remain
+    Given a new database that unlocks accounts on chargeback reversal
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I dispute transaction 1 for client 1
+    And I chargeback transaction 1 for client 1
+    When I reverse the chargeback of transaction 1 for client 1
+    Then the account for client 1 should not be locked
+    And the state of client 1 should be active
+
+  Scenario: Policy keeps the account locked while other chargebacks remain
+    Given a new database that unlocks accounts on chargeback reversal
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I process a deposit of 50.0 for client 1 with transaction id 2
+    And I dispute transaction 1 for client 1
+    And I chargeback transaction 1 for client 1
+    And I dispute transaction 2 for client 1
+    And I chargeback transaction 2 for client 1
+    When I reverse the chargeback of transaction 1 for client 1
+    Then the account for client 1 should be locked
+    And the available balance for client 1 should be 100.0
@@ -0,0 +1,32 @@
+Feature: Rolling Reserve
+  As a merchant acquirer
+  I want part of each deposit held back for a period
+  So that there are funds to cover chargebacks that arrive later
+
+  Scenario: Part of a deposit is held until the reserve matures
+    Given a new database that reserves 10 percent of deposits for 90 days
+    When I process a deposit of 100.0 for client 1 with transaction id 1 on day 0
+    Then the available balance for client 1 should be 90.0
+    And the held balance for client 1 should be 10.0
+    And the total balance for client 1 should be 100.0
+
+  Scenario: Reserves are released once matured
+    Given a new database that reserves 10 percent of deposits for 90 days
+    And I process a deposit of 100.0 for client 1 with transaction id 1 on day 0
+    And I process a deposit of 50.0 for client 1 with transaction id 2 on day 30
+    When I release matured reserves on day 90
+    Then the available balance for client 1 should be 145.0
+    And the held balance for client 1 should be 5.0
+    When I release matured reserves on day 120
+    Then the available balance for client 1 should be 150.0
+    And the held balance for client 1 should be 0.0
+
+  Scenario: Resolving a dispute leaves the reserve in place
+    Given a new database that reserves 10 percent of deposits for 90 days
+    And I process a deposit of 100.0 for client 1 with transaction id 1 on day 0
+    When I dispute transaction 1 for client 1
+    Then the available balance for client 1 should be -10.0
+    And the held balance for client 1 should be 110.0
+    When I resolve transaction 1 for client 1
+    Then the available balance for client 1 should be 90.0
+    And the held balance for client 1 should be 10.0
@@ -0,0 +1,39 @@
+Feature: Withdrawal Disputes
+  As a trading platform
+  I want clients to dispute withdrawals they did not make
+  So that erroneous debits can be returned to them
+
+  Scenario: A disputed withdrawal is credited back to held funds
+    Given a new database that allows withdrawal disputes
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I process a withdrawal of 40.0 for client 1 with transaction id 2
+    When I dispute transaction 2 for client 1
+    Then the available balance for client 1 should be 60.0
+    And the held balance for client 1 should be 40.0
+    And the total balance for client 1 should be 100.0
+
+  Scenario: Resolving a withdrawal dispute lets the withdrawal stand
+    Given a new database that allows withdrawal disputes
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I process a withdrawal of 40.0 for client 1 with transaction id 2
+    And I dispute transaction 2 for client 1
+    When I resolve transaction 2 for client 1
+    Then the available balance for client 1 should be 60.0
+    And the held balance for client 1 should be 0.0
+
+  Scenario: Charging back a withdrawal returns the funds without locking the account
+    Given a new database that allows withdrawal disputes
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I process a withdrawal of 40.0 for client 1 with transaction id 2
+    And I dispute transaction 2 for client 1
+    When I chargeback transaction 2 for client 1
+    Then the available balance for client 1 should be 100.0
+    And the held balance for client 1 should be 0.0
+    And the account for client 1 should not be locked
+
+  Scenario: Withdrawals cannot be disputed by default
+    Given a new database
+    And I process a deposit of 100.0 for client 1 with transaction id 1
+    And I process a withdrawal of 40.0 for client 1 with transaction id 2
+    When I attempt to dispute transaction 2 for client 1
+    Then the transaction should fail with "Withdrawal transaction cannot be disputed"
@@ -0,0 +1,223 @@
+//! Role-based access control for services embedding the engine
+//!
+//! A service exposing the engine to several callers maps each caller's API key to a
+//! [`Role`] with [`ApiKeys`], and checks every request with [`ApiKeys::authorize`]
+//! before passing it on to the [`Database`](crate::Database). Roles are deliberately
+//! coarse: feeds submit the everyday transactions, operators may additionally submit
+//! corrections and perform administrative operations, and auditors may only read.
+
+use crate::db::{AccountState, Transaction};
+use std::collections::HashMap;
+#[cfg(feature = "csv")]
+use std::io::Read;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// What a caller is allowed to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Role {
+    /// Read reports and statements only
+    Auditor,
+    /// Submit deposits, withdrawals, refunds, disputes, resolves and chargebacks
+    Feed,
+    /// Everything a feed may do, plus chargeback reversals, custom transactions,
+    /// manual adjustments, state changes, unlocks, approvals, merges, splits and balance
+    /// repairs
+    Ops,
+}
+
+impl Role {
+    /// Whether this role may perform `action`
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{AccountState, Action, Role, Transaction};
+    /// let deposit = Action::Transaction(Transaction::deposit("10").unwrap());
+    /// assert!(Role::Feed.allows(&deposit));
+    /// assert!(!Role::Auditor.allows(&deposit));
+    ///
+    /// let reversal = Action::Transaction(Transaction::chargeback_reversal());
+    /// assert!(Role::Ops.allows(&reversal));
+    /// assert!(!Role::Feed.allows(&reversal));
+    ///
+    /// let unlock = Action::SetState(AccountState::Active);
+    /// assert!(Role::Ops.allows(&unlock));
+    /// assert!(!Role::Feed.allows(&unlock));
+    /// assert!(!Role::Feed.allows(&Action::Unlock));
+    /// ```
+    pub fn allows(self, action: &Action) -> bool {
+        match action {
+            Action::Read => true,
+            Action::Transaction(transaction) => match transaction {
+                Transaction::Deposit { .. }
+                | Transaction::Withdrawal { .. }
+                | Transaction::Refund { .. }
+                | Transaction::Dispute
+                | Transaction::Resolve
+                | Transaction::Chargeback => matches!(self, Role::Feed | Role::Ops),
+                Transaction::ChargebackReversal | Transaction::Custom { .. } => self == Role::Ops,
+            },
+            Action::Adjustment
+            | Action::SetState(_)
+            | Action::Unlock
+            | Action::Approve
+            | Action::Merge
+            | Action::Split
+            | Action::AuditAndRepair => self == Role::Ops,
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Role::Auditor => "auditor",
+            Role::Feed => "feed",
+            Role::Ops => "ops",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "auditor" => Ok(Role::Auditor),
+            "feed" => Ok(Role::Feed),
+            "ops" => Ok(Role::Ops),
+            other => Err(format!("Unknown role: {}", other)),
+        }
+    }
+}
+
+/// A request a caller may make
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Read balances, reports or statements
+    Read,
+    /// Submit a transaction
+    Transaction(Transaction),
+    /// Post a correcting transaction through
+    /// [`AccountOps::post_adjustment`](crate::AccountOps::post_adjustment)
+    Adjustment,
+    /// Change an account's state, including unlocking it
+    SetState(AccountState),
+    /// Unlock an account through [`Database::unlock_account`](crate::Database::unlock_account)
+    Unlock,
+    /// Approve or reject a pending operation
+    Approve,
+    /// Merge two accounts
+    Merge,
+    /// Move transactions to a new account
+    Split,
+    /// Reset drifted balances through
+    /// [`Database::audit_and_repair`](crate::Database::audit_and_repair)
+    AuditAndRepair,
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Action::Read => write!(f, "read"),
+            Action::Transaction(transaction) => write!(f, "{}", transaction.name()),
+            Action::Adjustment => write!(f, "post adjustment"),
+            Action::SetState(state) => write!(f, "set state to {}", state),
+            Action::Unlock => write!(f, "unlock"),
+            Action::Approve => write!(f, "approve"),
+            Action::Merge => write!(f, "merge"),
+            Action::Split => write!(f, "split"),
+            Action::AuditAndRepair => write!(f, "audit and repair"),
+        }
+    }
+}
+
+/// Failure to authorize a request
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum AccessError {
+    /// The API key is not known
+    #[error("Unknown API key")]
+    UnknownKey,
+    /// The caller's role does not allow the request
+    #[error("Role {role} may not {action}")]
+    Forbidden {
+        /// Role of the caller
+        role: Role,
+        /// What the caller tried to do
+        action: String,
+    },
+}
+
+/// API keys and the role each one grants
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{AccessError, AccountState, Action, ApiKeys, Role, Transaction};
+/// let keys = ApiKeys::from_csv("key,role\nfeed-key,feed\nops-key,ops\n".as_bytes()).unwrap();
+///
+/// let deposit = Action::Transaction(Transaction::deposit("10").unwrap());
+/// assert_eq!(keys.authorize("feed-key", &deposit), Ok(Role::Feed));
+///
+/// let unlock = Action::SetState(AccountState::Active);
+/// assert_eq!(keys.authorize("ops-key", &unlock), Ok(Role::Ops));
+/// assert!(matches!(keys.authorize("feed-key", &unlock), Err(AccessError::Forbidden { .. })));
+/// assert_eq!(keys.authorize("guess", &deposit), Err(AccessError::UnknownKey));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ApiKeys {
+    roles: HashMap<String, Role>,
+}
+
+impl ApiKeys {
+    /// Create an empty key set, which authorizes nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Grant `role` to `key`, replacing any role it had
+    pub fn insert(&mut self, key: impl Into<String>, role: Role) {
+        self.roles.insert(key.into(), role);
+    }
+
+    /// Read keys from CSV with `key` and `role` columns
+    ///
+    /// Needs the `csv` feature.
+    ///
+    /// # Errors
+    /// Returns an error if the CSV cannot be read or names an unknown role.
+    #[cfg(feature = "csv")]
+    pub fn from_csv<R: Read>(reader: R) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut keys = Self::new();
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        for record in reader.deserialize() {
+            let (key, role): (String, String) = record?;
+            keys.insert(key, role.parse::<Role>()?);
+        }
+        Ok(keys)
+    }
+
+    /// The role granted to `key`, if any
+    pub fn role(&self, key: &str) -> Option<Role> {
+        self.roles.get(key).copied()
+    }
+
+    /// Check that `key` may perform `action`, returning its role
+    ///
+    /// # Errors
+    /// - [`AccessError::UnknownKey`] - `key` is not known
+    /// - [`AccessError::Forbidden`] - The key's role does not allow `action`
+    pub fn authorize(&self, key: &str, action: &Action) -> Result<Role, AccessError> {
+        let role = self.role(key).ok_or(AccessError::UnknownKey)?;
+        if role.allows(action) {
+            Ok(role)
+        } else {
+            Err(AccessError::Forbidden {
+                role,
+                action: action.to_string(),
+            })
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! Canned analytics reports over account histories
+//!
+//! - [`disputes_after_withdrawal`] looks for a known fraud pattern in the order
+//!   transactions were applied, as recorded in every account's
+//!   [balance history](crate::Account::balance_history)
+//! - [`held_funds_aging`] ages disputed balances by how long they have been held
+
+use crate::db::{Activity, Database, DisputeState, EntryKind, Timestamp};
+use crate::fixed4::Fixed4;
+use std::collections::HashMap;
+#[cfg(feature = "csv")]
+use std::io::Write;
+
+/// A deposit disputed after its funds had already been withdrawn
+///
+/// The classic first-party fraud pattern: deposit, withdraw the funds, then dispute the
+/// deposit so the bank reverses it, leaving the account negative.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisputeAfterWithdrawal {
+    /// Client ID
+    pub client: u16,
+    /// The disputed deposit
+    pub tx: u32,
+    /// Amount of the deposit
+    pub amount: Fixed4,
+    /// Total withdrawn between the deposit and the dispute
+    pub withdrawn: Fixed4,
+    /// When the dispute was made, if known
+    pub disputed_at: Option<Timestamp>,
+    /// Funds available right after the dispute (negative)
+    pub available: Fixed4,
+}
+
+/// Find deposits that were disputed after their funds had been withdrawn
+///
+/// A dispute is listed when there were withdrawals between the deposit and the dispute
+/// and the dispute left the account's available balance negative. Clients are listed in
+/// ascending order and each client's disputes in the order they were made.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Database, Transaction, disputes_after_withdrawal};
+/// let mut db = Database::new();
+/// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+/// db.process_transaction(1, 2, Transaction::withdrawal("90").unwrap()).unwrap();
+/// db.process_transaction(1, 1, Transaction::dispute()).unwrap();
+///
+/// // Client 2 disputed before withdrawing anything
+/// db.process_transaction(2, 3, Transaction::deposit("50").unwrap()).unwrap();
+/// db.process_transaction(2, 3, Transaction::dispute()).unwrap();
+///
+/// let report = disputes_after_withdrawal(&db);
+/// assert_eq!(report.len(), 1);
+/// assert_eq!((report[0].client, report[0].tx), (1, 1));
+/// assert_eq!(report[0].withdrawn.to_string(), "90.0000");
+/// assert_eq!(report[0].available.to_string(), "-90.0000");
+/// ```
+pub fn disputes_after_withdrawal(database: &Database) -> Vec<DisputeAfterWithdrawal> {
+    let mut client_ids = database.get_all_client_ids();
+    client_ids.sort();
+
+    let mut report = Vec::new();
+    for client_id in client_ids {
+        let Some(account) = database.get_account(client_id) else {
+            continue;
+        };
+        // Deposit amount and total withdrawn before it, by deposit transaction ID
+        let mut deposits: HashMap<u32, (Fixed4, Fixed4)> = HashMap::new();
+        let mut withdrawn = Fixed4::zero();
+        let (mut available, mut total) = (Fixed4::zero(), Fixed4::zero());
+
+        for row in account.balance_history() {
+            let row_total = row.available + row.held;
+            match row.activity {
+                Activity::Deposit => {
+                    deposits.insert(row.tx, (row_total - total, withdrawn));
+                }
+                Activity::Withdrawal => withdrawn += available - row.available,
+                Activity::Dispute => {
+                    if let Some(&(amount, withdrawn_before)) = deposits.get(&row.tx)
+                        && withdrawn > withdrawn_before
+                        && row.available < Fixed4::zero()
+                    {
+                        report.push(DisputeAfterWithdrawal {
+                            client: client_id,
+                            tx: row.tx,
+                            amount,
+                            withdrawn: withdrawn - withdrawn_before,
+                            disputed_at: row.timestamp,
+                            available: row.available,
+                        });
+                    }
+                }
+                _ => {}
+            }
+            available = row.available;
+            total = row_total;
+        }
+    }
+    report
+}
+
+/// Write the [dispute-after-withdrawal report](disputes_after_withdrawal) as CSV
+///
+/// Columns are `client,tx,amount,withdrawn,disputed_at,available`.
+///
+/// # Errors
+/// Returns an error if writing to `writer` fails.
+#[cfg(feature = "csv")]
+pub fn write_disputes_after_withdrawal<W: Write>(
+    database: &Database,
+    writer: W,
+) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record([
+        "client",
+        "tx",
+        "amount",
+        "withdrawn",
+        "disputed_at",
+        "available",
+    ])?;
+    for row in disputes_after_withdrawal(database) {
+        writer.write_record([
+            row.client.to_string(),
+            row.tx.to_string(),
+            row.amount.to_string(),
+            row.withdrawn.to_string(),
+            row.disputed_at.map(|t| t.to_string()).unwrap_or_default(),
+            row.available.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Disputed funds of one client, or of every client, by how long they have been held
+///
+/// Ages are whole days since the dispute. Disputes that carried no timestamp cannot be
+/// aged and are counted as `undated`. Buckets saturate at the largest amount Fixed4
+/// holds, rather than overflow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeldFundsAging {
+    /// Held for up to 30 days
+    pub days_0_30: Fixed4,
+    /// Held for 31 to 60 days
+    pub days_31_60: Fixed4,
+    /// Held for 61 to 90 days
+    pub days_61_90: Fixed4,
+    /// Held for more than 90 days
+    pub days_over_90: Fixed4,
+    /// Held since a dispute without a timestamp
+    pub undated: Fixed4,
+}
+
+impl Default for HeldFundsAging {
+    fn default() -> Self {
+        Self {
+            days_0_30: Fixed4::zero(),
+            days_31_60: Fixed4::zero(),
+            days_61_90: Fixed4::zero(),
+            days_over_90: Fixed4::zero(),
+            undated: Fixed4::zero(),
+        }
+    }
+}
+
+impl HeldFundsAging {
+    /// Every held amount, whatever its age
+    pub fn total(&self) -> Fixed4 {
+        self.days_0_30 + self.days_31_60 + self.days_61_90 + self.days_over_90 + self.undated
+    }
+
+    /// Add `amount`, disputed at `disputed_at`, to its bucket as of `now`
+    fn add(&mut self, amount: Fixed4, disputed_at: Option<Timestamp>, now: Timestamp) {
+        let bucket = match disputed_at.map(|at| now.saturating_sub(at) / SECONDS_PER_DAY) {
+            None => &mut self.undated,
+            Some(0..=30) => &mut self.days_0_30,
+            Some(31..=60) => &mut self.days_31_60,
+            Some(61..=90) => &mut self.days_61_90,
+            Some(_) => &mut self.days_over_90,
+        };
+        *bucket = bucket.saturating_add(amount);
+    }
+
+    /// Add every bucket of `other` to this one
+    fn merge(&mut self, other: &HeldFundsAging) {
+        self.days_0_30 = self.days_0_30.saturating_add(other.days_0_30);
+        self.days_31_60 = self.days_31_60.saturating_add(other.days_31_60);
+        self.days_61_90 = self.days_61_90.saturating_add(other.days_61_90);
+        self.days_over_90 = self.days_over_90.saturating_add(other.days_over_90);
+        self.undated = self.undated.saturating_add(other.undated);
+    }
+}
+
+/// Age every client's disputed funds as of `now`
+///
+/// Returns the clients holding disputed funds, in ascending order, and the aggregate
+/// over all of them. Funds held by a rolling reserve are not disputed and not included.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Database, Transaction, held_funds_aging};
+/// const DAY: u64 = 24 * 60 * 60;
+/// let mut db = Database::new();
+/// for (client, tx, disputed_at) in [(1, 1, 0), (1, 2, 45 * DAY), (2, 3, 80 * DAY)] {
+///     db.process_transaction(client, tx, Transaction::deposit("10").unwrap()).unwrap();
+///     db.process_transaction_at(client, tx, Transaction::dispute(), Some(disputed_at))
+///         .unwrap();
+/// }
+///
+/// let (clients, total) = held_funds_aging(&db, 100 * DAY);
+/// assert_eq!(clients.len(), 2);
+/// let (client, aging) = clients[0];
+/// assert_eq!(client, 1);
+/// assert_eq!(aging.days_31_60.to_string(), "10.0000");
+/// assert_eq!(aging.days_over_90.to_string(), "10.0000");
+/// assert_eq!(total.days_0_30.to_string(), "10.0000");
+/// assert_eq!(total.total().to_string(), "30.0000");
+/// ```
+pub fn held_funds_aging(
+    database: &Database,
+    now: Timestamp,
+) -> (Vec<(u16, HeldFundsAging)>, HeldFundsAging) {
+    let mut client_ids = database.get_all_client_ids();
+    client_ids.sort();
+
+    let mut clients = Vec::new();
+    let mut total = HeldFundsAging::default();
+    for client_id in client_ids {
+        let Some(statement) = database.statement(client_id) else {
+            continue;
+        };
+        let mut aging = HeldFundsAging::default();
+        let mut disputed = false;
+        for line in &statement.lines {
+            if line.dispute_state != Some(DisputeState::Disputed) {
+                continue;
+            }
+            let disputed_at = line
+                .transitions
+                .iter()
+                .rev()
+                .find(|transition| transition.to == DisputeState::Disputed)
+                .and_then(|transition| transition.timestamp);
+            // A dispute only holds what is left of a deposit unrefunded
+            let refunded = match line.kind {
+                EntryKind::Deposit => database
+                    .get_account(client_id)
+                    .map_or(Fixed4::zero(), |account| account.refunded(line.tx)),
+                _ => Fixed4::zero(),
+            };
+            aging.add(line.amount - refunded, disputed_at, now);
+            disputed = true;
+        }
+        if disputed {
+            total.merge(&aging);
+            clients.push((client_id, aging));
+        }
+    }
+    (clients, total)
+}
+
+/// Write the [held-funds aging report](held_funds_aging) as of `now` as CSV
+///
+/// Columns are `client,0-30,31-60,61-90,90+,undated,total`: one row per client holding
+/// disputed funds, followed by a row with client `total` aggregating all of them.
+///
+/// # Errors
+/// Returns an error if writing to `writer` fails.
+#[cfg(feature = "csv")]
+pub fn write_held_funds_aging<W: Write>(
+    database: &Database,
+    now: Timestamp,
+    writer: W,
+) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record([
+        "client", "0-30", "31-60", "61-90", "90+", "undated", "total",
+    ])?;
+    let (clients, total) = held_funds_aging(database, now);
+    let rows = clients
+        .into_iter()
+        .map(|(client, aging)| (client.to_string(), aging))
+        .chain(std::iter::once(("total".to_string(), total)));
+    for (client, aging) in rows {
+        writer.write_record([
+            client,
+            aging.days_0_30.to_string(),
+            aging.days_31_60.to_string(),
+            aging.days_61_90.to_string(),
+            aging.days_over_90.to_string(),
+            aging.undated.to_string(),
+            aging.total().to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,383 @@
+//! Avro exports of the account summary and balance history (`avro` feature)
+//!
+//! Pipelines feeding Kafka or warehouse loaders often need records with a schema rather
+//! than CSV. These exports write Avro object container files: the schema is embedded in
+//! the file header, followed by the records in blocks. Amounts are Avro `decimal`s with
+//! scale 4, so they load as exact decimals rather than floats.
+//!
+//! Blocks are written uncompressed (the `null` codec), which every Avro reader
+//! supports. The container format is small enough that it is written here directly,
+//! so the feature adds no dependencies. The 16-byte sync marker separating blocks is
+//! derived from the schema rather than drawn at random, so the same data always gives
+//! the same bytes.
+
+use crate::db::{Account, Database};
+use crate::fixed4::Fixed4;
+use crate::sink::SummarySink;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::io::{self, Write};
+
+/// Magic bytes starting every object container file
+const MAGIC: &[u8; 4] = b"Obj\x01";
+
+/// Records per block; readers hold a block in memory at a time
+const BLOCK_RECORDS: usize = 4096;
+
+/// Schema of the account summary records
+pub const SUMMARY_SCHEMA: &str = r#"{
+  "type": "record",
+  "name": "AccountSummary",
+  "namespace": "transaction_processor",
+  "fields": [
+    {"name": "client", "type": "int"},
+    {
+      "name": "available",
+      "type": {"type": "bytes", "logicalType": "decimal", "precision": 19, "scale": 4}
+    },
+    {
+      "name": "held",
+      "type": {"type": "bytes", "logicalType": "decimal", "precision": 19, "scale": 4}
+    },
+    {
+      "name": "total",
+      "type": {"type": "bytes", "logicalType": "decimal", "precision": 19, "scale": 4}
+    },
+    {"name": "locked", "type": "boolean"},
+    {"name": "state", "type": "string"}
+  ]
+}"#;
+
+/// Schema of the balance history records
+pub const LEDGER_SCHEMA: &str = r#"{
+  "type": "record",
+  "name": "BalanceRow",
+  "namespace": "transaction_processor",
+  "fields": [
+    {"name": "client", "type": "int"},
+    {"name": "tx", "type": "long"},
+    {"name": "type", "type": "string"},
+    {"name": "timestamp", "type": ["null", "long"]},
+    {
+      "name": "available",
+      "type": {"type": "bytes", "logicalType": "decimal", "precision": 19, "scale": 4}
+    },
+    {
+      "name": "held",
+      "type": {"type": "bytes", "logicalType": "decimal", "precision": 19, "scale": 4}
+    }
+  ]
+}"#;
+
+/// Write one record per account, in the given order, as an Avro container file with
+/// [`SUMMARY_SCHEMA`]
+///
+/// The fields are those of the default CSV summary: `client`, `available`, `held`,
+/// `total`, `locked` and `state`. The rows usually come from [`Database::summaries`].
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Database, SummaryQuery, Transaction, write_summaries_avro};
+/// let mut db = Database::new();
+/// db.process_transaction(1, 1, Transaction::deposit("10").unwrap()).unwrap();
+///
+/// let mut output = Vec::new();
+/// write_summaries_avro(&db.summaries(&SummaryQuery::default()), &mut output).unwrap();
+/// assert!(output.starts_with(b"Obj\x01"));
+/// assert!(String::from_utf8_lossy(&output).contains(r#""name": "AccountSummary""#));
+/// ```
+///
+/// # Errors
+/// Returns an error if writing to `writer` fails.
+pub fn write_summaries_avro<W: Write>(summaries: &[(u16, &Account)], writer: W) -> io::Result<()> {
+    let mut file = Container::new(writer, SUMMARY_SCHEMA)?;
+    for &(client_id, account) in summaries {
+        let record = file.record();
+        put_long(record, i64::from(client_id));
+        put_decimal(record, account.available());
+        put_decimal(record, account.held());
+        put_decimal(record, account.total());
+        record.push(u8::from(account.is_locked()));
+        put_string(record, &account.state().to_string());
+        file.end_record()?;
+    }
+    file.finish()
+}
+
+/// Avro summary report output, for [`Engine::report`](crate::Engine::report)
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{AvroSink, CsvSource, Engine};
+/// let input = "type,client,tx,amount\ndeposit,1,1,10\n";
+/// let mut engine = Engine::new();
+/// engine.process(CsvSource::new("input", input.as_bytes()));
+///
+/// let mut output = Vec::new();
+/// engine.report(AvroSink::new(&mut output)).unwrap();
+/// assert!(output.starts_with(b"Obj\x01"));
+/// ```
+pub struct AvroSink<W> {
+    writer: W,
+}
+
+impl<W: Write> AvroSink<W> {
+    /// Write the report to `writer`
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> SummarySink for AvroSink<W> {
+    fn write_summaries(&mut self, summaries: &[(u16, &Account)]) -> Result<(), Box<dyn Error>> {
+        write_summaries_avro(summaries, &mut self.writer)?;
+        Ok(())
+    }
+}
+
+/// Write every client's balance history as an Avro container file with
+/// [`LEDGER_SCHEMA`]
+///
+/// The records are those of the CSV
+/// [`write_running_balances`](crate::write_running_balances) export, in the same order:
+/// clients ascending, each client's rows in the order they were applied.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Database, Transaction, write_ledger_avro};
+/// let mut db = Database::new();
+/// db.process_transaction(1, 1, Transaction::deposit("10").unwrap()).unwrap();
+/// db.process_transaction(1, 1, Transaction::dispute()).unwrap();
+///
+/// let mut output = Vec::new();
+/// write_ledger_avro(&db, &mut output).unwrap();
+/// assert!(output.starts_with(b"Obj\x01"));
+/// assert!(String::from_utf8_lossy(&output).contains(r#""name": "BalanceRow""#));
+/// ```
+///
+/// # Errors
+/// Returns an error if writing to `writer` fails.
+pub fn write_ledger_avro<W: Write>(database: &Database, writer: W) -> io::Result<()> {
+    let mut client_ids = database.get_all_client_ids();
+    client_ids.sort();
+
+    let mut file = Container::new(writer, LEDGER_SCHEMA)?;
+    for client_id in client_ids {
+        let Some(account) = database.get_account(client_id) else {
+            continue;
+        };
+        for row in account.balance_history() {
+            let record = file.record();
+            put_long(record, i64::from(client_id));
+            put_long(record, i64::from(row.tx));
+            put_string(record, &row.activity.to_string());
+            match row.timestamp {
+                None => put_long(record, 0),
+                Some(timestamp) => {
+                    put_long(record, 1);
+                    put_long(record, i64::try_from(timestamp).unwrap_or(i64::MAX));
+                }
+            }
+            put_decimal(record, row.available);
+            put_decimal(record, row.held);
+            file.end_record()?;
+        }
+    }
+    file.finish()
+}
+
+/// An object container file being written, one block of records at a time
+struct Container<W: Write> {
+    writer: W,
+    sync: [u8; 16],
+    /// Encoded records of the block being filled
+    block: Vec<u8>,
+    records: usize,
+}
+
+impl<W: Write> Container<W> {
+    /// Write the header, embedding `schema`
+    fn new(mut writer: W, schema: &str) -> io::Result<Self> {
+        let mut sync = [0; 16];
+        sync.copy_from_slice(&Sha256::digest(schema.as_bytes())[..16]);
+
+        let mut header = MAGIC.to_vec();
+        // File metadata: a map of two entries, then the empty block ending it
+        put_long(&mut header, 2);
+        put_string(&mut header, "avro.schema");
+        put_bytes(&mut header, schema.as_bytes());
+        put_string(&mut header, "avro.codec");
+        put_bytes(&mut header, b"null");
+        put_long(&mut header, 0);
+        header.extend_from_slice(&sync);
+        writer.write_all(&header)?;
+
+        Ok(Self {
+            writer,
+            sync,
+            block: Vec::new(),
+            records: 0,
+        })
+    }
+
+    /// Buffer to encode the next record into
+    fn record(&mut self) -> &mut Vec<u8> {
+        &mut self.block
+    }
+
+    /// Count the record just encoded, writing the block out once full
+    fn end_record(&mut self) -> io::Result<()> {
+        self.records += 1;
+        if self.records == BLOCK_RECORDS {
+            self.write_block()?;
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        let mut prefix = Vec::new();
+        put_long(&mut prefix, self.records as i64);
+        put_long(&mut prefix, self.block.len() as i64);
+        self.writer.write_all(&prefix)?;
+        self.writer.write_all(&self.block)?;
+        self.writer.write_all(&self.sync)?;
+        self.block.clear();
+        self.records = 0;
+        Ok(())
+    }
+
+    /// Write the last block, if any records are left, and flush
+    fn finish(mut self) -> io::Result<()> {
+        if self.records > 0 {
+            self.write_block()?;
+        }
+        self.writer.flush()
+    }
+}
+
+/// Append `value` as an Avro `long` (or `int`): zig-zag encoded, then as a varint
+fn put_long(out: &mut Vec<u8>, value: i64) {
+    let mut n = ((value << 1) ^ (value >> 63)) as u64;
+    while n >= 0x80 {
+        out.push((n as u8 & 0x7f) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_long(out, bytes.len() as i64);
+    out.extend_from_slice(bytes);
+}
+
+fn put_string(out: &mut Vec<u8>, value: &str) {
+    put_bytes(out, value.as_bytes());
+}
+
+/// Append `amount` as a `decimal` with scale 4: its ten-thousandths as big-endian
+/// two's complement, in as few bytes as keep the sign
+fn put_decimal(out: &mut Vec<u8>, amount: Fixed4) {
+    let bytes = amount.to_raw().to_be_bytes();
+    let mut start = 0;
+    while start < bytes.len() - 1 {
+        let redundant = (bytes[start] == 0x00 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xff && bytes[start + 1] & 0x80 != 0);
+        if !redundant {
+            break;
+        }
+        start += 1;
+    }
+    put_bytes(out, &bytes[start..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn long(value: i64) -> Vec<u8> {
+        let mut out = Vec::new();
+        put_long(&mut out, value);
+        out
+    }
+
+    fn decimal(raw: i64) -> Vec<u8> {
+        let mut out = Vec::new();
+        put_decimal(&mut out, Fixed4::from_raw(raw));
+        out
+    }
+
+    #[test]
+    fn test_longs_are_zig_zag_varints() {
+        assert_eq!(long(0), [0x00]);
+        assert_eq!(long(-1), [0x01]);
+        assert_eq!(long(1), [0x02]);
+        assert_eq!(long(63), [0x7e]);
+        assert_eq!(long(64), [0x80, 0x01]);
+        assert_eq!(long(-65), [0x81, 0x01]);
+        assert_eq!(
+            long(i64::MAX),
+            [0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]
+        );
+        assert_eq!(
+            long(i64::MIN),
+            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]
+        );
+    }
+
+    #[test]
+    fn test_decimals_are_minimal_twos_complement() {
+        // Length prefix, then the ten-thousandths big-endian
+        assert_eq!(decimal(100_000), [0x06, 0x01, 0x86, 0xa0]);
+        assert_eq!(decimal(0), [0x02, 0x00]);
+        assert_eq!(decimal(127), [0x02, 0x7f]);
+        // A set top bit needs a leading byte to stay positive
+        assert_eq!(decimal(128), [0x04, 0x00, 0x80]);
+        assert_eq!(decimal(0x8000), [0x06, 0x00, 0x80, 0x00]);
+        assert_eq!(decimal(-1), [0x02, 0xff]);
+        assert_eq!(decimal(-128), [0x02, 0x80]);
+        assert_eq!(decimal(-129), [0x04, 0xff, 0x7f]);
+        assert_eq!(decimal(-100_000), [0x06, 0xfe, 0x79, 0x60]);
+        assert_eq!(
+            decimal(i64::MIN),
+            [0x10, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            decimal(i64::MAX),
+            [0x10, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+    }
+
+    #[test]
+    fn test_blocks_are_framed_by_the_sync_marker() {
+        let schema = r#""long""#;
+        let mut output = Vec::new();
+        let mut file = Container::new(&mut output, schema).unwrap();
+        for _ in 0..=BLOCK_RECORDS {
+            put_long(file.record(), 0);
+            file.end_record().unwrap();
+        }
+        file.finish().unwrap();
+
+        let sync = &Sha256::digest(schema.as_bytes())[..16];
+        let mut expected = MAGIC.to_vec();
+        expected.push(0x04);
+        expected.push(0x16);
+        expected.extend_from_slice(b"avro.schema");
+        expected.push(0x0c);
+        expected.extend_from_slice(schema.as_bytes());
+        expected.push(0x14);
+        expected.extend_from_slice(b"avro.codec");
+        expected.push(0x08);
+        expected.extend_from_slice(b"null");
+        expected.push(0x00);
+        expected.extend_from_slice(sync);
+        // A full block of 4096 one-byte records
+        expected.extend_from_slice(&[0x80, 0x40, 0x80, 0x40]);
+        expected.extend_from_slice(&[0x00; BLOCK_RECORDS]);
+        expected.extend_from_slice(sync);
+        // The last record in a block of its own
+        expected.extend_from_slice(&[0x02, 0x02, 0x00]);
+        expected.extend_from_slice(sync);
+        assert_eq!(output, expected);
+    }
+}
@@ -0,0 +1,213 @@
+//! Fault injection for testing recovery logic (`testing` feature)
+//!
+//! Embedders need to know that their retry and recovery paths work before production
+//! finds out for them. The wrappers here inject failures into the places an embedding
+//! touches the engine, at configurable probabilities:
+//! - [`FaultySource`] - rows that fail to parse, and slow deliveries
+//! - [`FaultyWriter`] - failing writes to reports, event logs and other outputs
+//! - [`FaultySink`] - report consumers that are slow or fail
+//!
+//! Faults are drawn from a seeded generator, so a failing run can be reproduced exactly.
+
+use crate::db::Account;
+use crate::sink::SummarySink;
+use crate::source::{SourceError, SourcedTransaction, TransactionSource};
+use std::error::Error;
+use std::io::{self, Write};
+use std::thread;
+use std::time::Duration;
+
+/// Which faults to inject, and how often
+///
+/// Probabilities are between `0.0` (never) and `1.0` (always); all default to never.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::Faults;
+/// let mut faults = Faults::new(42).with_errors(0.5);
+/// let failures = (0..1000).filter(|_| faults.error()).count();
+/// assert!((400..600).contains(&failures));
+///
+/// // The same seed injects the same faults
+/// let mut a = Faults::new(7).with_errors(0.5);
+/// let mut b = Faults::new(7).with_errors(0.5);
+/// assert!((0..100).all(|_| a.error() == b.error()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Faults {
+    state: u64,
+    error_probability: f64,
+    delay_probability: f64,
+    delay: Duration,
+}
+
+impl Faults {
+    /// Inject nothing until configured, drawing faults from `seed`
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: seed,
+            error_probability: 0.0,
+            delay_probability: 0.0,
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// Fail operations with probability `probability`
+    pub fn with_errors(mut self, probability: f64) -> Self {
+        self.error_probability = probability;
+        self
+    }
+
+    /// Stall operations for `delay` with probability `probability`
+    pub fn with_delays(mut self, probability: f64, delay: Duration) -> Self {
+        self.delay_probability = probability;
+        self.delay = delay;
+        self
+    }
+
+    /// Draw whether the next operation fails
+    pub fn error(&mut self) -> bool {
+        let probability = self.error_probability;
+        self.roll(probability)
+    }
+
+    /// Draw whether the next operation is delayed, sleeping if so
+    pub fn maybe_delay(&mut self) {
+        let probability = self.delay_probability;
+        if self.roll(probability) {
+            thread::sleep(self.delay);
+        }
+    }
+
+    /// Whether an event with the given probability happens, using splitmix64
+    fn roll(&mut self, probability: f64) -> bool {
+        if probability <= 0.0 {
+            return false;
+        }
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        let sample = (z >> 11) as f64 / (1u64 << 53) as f64;
+        sample < probability
+    }
+}
+
+/// A source whose rows sometimes fail to parse or arrive late
+///
+/// A failed row is replaced by a [`SourceError::Malformed`] error, as if its input had
+/// been garbled in transit.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{CsvSource, Engine, Faults, FaultySource};
+/// let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,1.0\n";
+/// let faults = Faults::new(1).with_errors(1.0);
+/// let source = FaultySource::new(CsvSource::new("input", input.as_bytes()), faults);
+///
+/// let mut engine = Engine::new();
+/// let stats = engine.process(source);
+/// assert_eq!((stats.rows, stats.malformed), (2, 2));
+/// assert!(engine.errors()[0].contains("injected fault"));
+/// ```
+pub struct FaultySource<S> {
+    source: S,
+    faults: Faults,
+}
+
+impl<S: TransactionSource> FaultySource<S> {
+    /// Inject `faults` into the rows of `source`
+    pub fn new(source: S, faults: Faults) -> Self {
+        Self { source, faults }
+    }
+}
+
+impl<S: TransactionSource> TransactionSource for FaultySource<S> {
+    fn next_transaction(&mut self) -> Option<Result<SourcedTransaction, SourceError>> {
+        let next = self.source.next_transaction()?;
+        self.faults.maybe_delay();
+        match next {
+            Ok(item) if self.faults.error() => Some(Err(SourceError::Malformed {
+                location: item.location,
+                message: "injected fault".to_string(),
+            })),
+            next => Some(next),
+        }
+    }
+}
+
+/// A writer whose writes sometimes fail or stall, standing in for unreliable storage
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Faults, FaultyWriter};
+/// # use std::io::Write;
+/// let mut writer = FaultyWriter::new(Vec::new(), Faults::new(3).with_errors(1.0));
+/// assert!(writer.write_all(b"data").is_err());
+/// ```
+pub struct FaultyWriter<W> {
+    writer: W,
+    faults: Faults,
+}
+
+impl<W: Write> FaultyWriter<W> {
+    /// Inject `faults` into writes to `writer`
+    pub fn new(writer: W, faults: Faults) -> Self {
+        Self { writer, faults }
+    }
+
+    /// The wrapped writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Write for FaultyWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.faults.maybe_delay();
+        if self.faults.error() {
+            return Err(io::Error::other("injected fault"));
+        }
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A report consumer that is sometimes slow or fails
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{CsvSink, CsvSource, Engine, Faults, FaultySink};
+/// let input = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+/// let sink = FaultySink::new(CsvSink::new(Vec::new()), Faults::new(9).with_errors(1.0));
+///
+/// let mut engine = Engine::new();
+/// assert!(engine.run(CsvSource::new("input", input.as_bytes()), sink).is_err());
+/// // The transactions were applied even though the report failed
+/// assert!(engine.database().get_account(1).is_some());
+/// ```
+pub struct FaultySink<K> {
+    sink: K,
+    faults: Faults,
+}
+
+impl<K: SummarySink> FaultySink<K> {
+    /// Inject `faults` into reports written to `sink`
+    pub fn new(sink: K, faults: Faults) -> Self {
+        Self { sink, faults }
+    }
+}
+
+impl<K: SummarySink> SummarySink for FaultySink<K> {
+    fn write_summaries(&mut self, summaries: &[(u16, &Account)]) -> Result<(), Box<dyn Error>> {
+        self.faults.maybe_delay();
+        if self.faults.error() {
+            return Err("injected fault".into());
+        }
+        self.sink.write_summaries(summaries)
+    }
+}
@@ -1,70 +1,1346 @@
-use crate::{Database, Transaction};
-use serde::Deserialize;
+use crate::engine::{Rejected, RunId, RunStats, process_source};
+use crate::record::{
+    RecordAction, RecordAmount, RecordHook, TransactionDecoder, TransactionRecord, Warning,
+};
+use crate::router::Router;
+use crate::sample::{Sample, Sampled};
+use crate::source::{SourceError, SourceOffset, SourcedTransaction, TransactionSource};
+use crate::{
+    AmountHistogram, ConflictPolicy, Database, EngineError, Fixed4, Policy, Transaction,
+    TransactionError, TransactionMeta,
+};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Receiver;
 
-#[derive(Debug, Deserialize)]
-pub struct TransactionRecord {
-    #[serde(rename = "type")]
-    pub transaction_type: String,
-    pub client: u16,
-    pub tx: u32,
-    pub amount: Option<String>, // Optional because dispute, resolve, chargeback don't have amounts
+/// Resulting database plus the errors and warnings encountered, in that order
+///
+/// See [`ProcessingOutcome`] for the same with the run's statistics and ID.
+pub type ProcessingResult = (Database, Vec<String>, Vec<String>);
+
+/// Everything a run of [`process_csv_outcome`] produced
+///
+/// Fields can be added without breaking callers, unlike the [`ProcessingResult`]
+/// tuple, which an outcome converts into with [`From`].
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{ProcessingResult, process_csv_outcome};
+/// let file = tempfile::NamedTempFile::new().unwrap();
+/// std::fs::write(&file, "type,client,tx,amount\ndeposit,1,1,5\nwithdrawal,1,2,9\n").unwrap();
+///
+/// let outcome = process_csv_outcome(file.path().to_str().unwrap()).unwrap();
+/// assert_eq!((outcome.stats.applied, outcome.stats.rejected), (1, 1));
+/// assert_eq!(outcome.errors.len(), 1);
+///
+/// let (database, errors, _): ProcessingResult = outcome.into();
+/// assert_eq!(database.get_account(1).unwrap().available().to_string(), "5.0000");
+/// assert!(errors[0].contains("Insufficient funds"));
+/// ```
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ProcessingOutcome {
+    /// The resulting accounts
+    pub database: Database,
+    /// Errors encountered, each formatted with the input and line it relates to
+    pub errors: Vec<String>,
+    /// Warnings encountered, formatted the same way
+    pub warnings: Vec<String>,
+    /// Counts of what happened to the input rows
+    pub stats: RunStats,
+    /// Identifies this run
+    pub run_id: RunId,
+}
+
+impl From<ProcessingOutcome> for ProcessingResult {
+    fn from(outcome: ProcessingOutcome) -> Self {
+        (outcome.database, outcome.errors, outcome.warnings)
+    }
+}
+
+/// Process every row of `source` into a new database applying `policy`
+fn process_csv_source<S: TransactionSource>(source: S, policy: Policy) -> ProcessingOutcome {
+    let run_id = RunId::generate();
+    let mut database = Database::with_policy(policy);
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+
+    let stats = process_source(
+        &mut database,
+        source,
+        Rejected {
+            errors: &mut errors,
+            summary: None,
+            unmatched: None,
+            rejections: None,
+        },
+        &mut warnings,
+        None,
+        None,
+        None,
+    );
+
+    ProcessingOutcome {
+        database,
+        errors,
+        warnings,
+        stats,
+        run_id,
+    }
+}
+
+/// Process a CSV file of transactions
+///
+/// Returns the resulting database along with the errors and warnings encountered,
+/// each formatted with the file and line they relate to.
+pub fn process_csv_file(file_path: &str) -> Result<ProcessingResult, Box<dyn Error>> {
+    process_csv_outcome(file_path).map(ProcessingResult::from)
+}
+
+/// Process a CSV file of transactions, returning everything the run produced
+///
+/// The same as [`process_csv_file`], with the run's statistics and ID as well.
+pub fn process_csv_outcome(file_path: &str) -> Result<ProcessingOutcome, Box<dyn Error>> {
+    CsvProcessor::default().process_file(file_path)
 }
 
-pub fn process_csv_file(file_path: &str) -> Result<(Database, Vec<String>), Box<dyn Error>> {
+/// Process CSV transactions held in a string
+///
+/// Returns the same as [`process_csv_file`], with errors and warnings located as
+/// `input:LINE`. Handy for tests and notebooks, which then need no temporary files.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::process_csv_str;
+/// let (database, errors, warnings) = process_csv_str(
+///     "type,client,tx,amount
+/// deposit,1,1,5.0
+/// withdrawal,1,2,9.0",
+/// );
+/// assert_eq!(database.get_account(1).unwrap().available().to_string(), "5.0000");
+/// assert!(errors[0].starts_with("Error processing transaction at input:3: Insufficient funds"));
+/// assert!(warnings.is_empty());
+/// ```
+pub fn process_csv_str(input: &str) -> ProcessingResult {
+    process_csv_reader("input", input.as_bytes())
+}
+
+/// Process CSV transactions streamed from any reader, such as stdin
+///
+/// Rows are processed as they are read, so input piped from another program is never
+/// staged in full. Returns the same as [`process_csv_file`], with errors and warnings
+/// located as `NAME:LINE`.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::process_csv_reader;
+/// let input = std::io::Cursor::new("type,client,tx,amount\ndeposit,1,1,5.0\ndispute,1,2,\n");
+///
+/// let (database, errors, _) = process_csv_reader("stdin", input);
+/// assert_eq!(database.get_account(1).unwrap().available().to_string(), "5.0000");
+/// assert!(errors[0].contains("stdin:3"));
+/// ```
+pub fn process_csv_reader<R: Read>(name: &str, reader: R) -> ProcessingResult {
+    CsvProcessor::default().process_reader(name, reader).into()
+}
+
+/// Process the first `rows` rows of a CSV file of transactions
+///
+/// Returns the same as [`process_csv_file`], for a quick look at a file too large to
+/// process in full. See [`Sampled`] for random samples.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::process_csv_sample;
+/// let file = tempfile::NamedTempFile::new().unwrap();
+/// std::fs::write(&file, "type,client,tx,amount\ndeposit,1,1,1\ndeposit,2,2,1\n").unwrap();
+///
+/// let (database, errors, _) = process_csv_sample(file.path().to_str().unwrap(), 1).unwrap();
+/// assert_eq!(database.get_all_client_ids(), [1]);
+/// assert!(errors.is_empty());
+/// ```
+pub fn process_csv_sample(
+    file_path: &str,
+    rows: usize,
+) -> Result<ProcessingResult, Box<dyn Error>> {
+    let source = Sampled::new(CsvSource::from_path(file_path)?, Sample::First(rows));
+    Ok(process_csv_source(source, Policy::default()).into())
+}
+
+/// Process a CSV file of transactions on `threads` worker threads
+///
+/// Returns the same as [`process_csv_file`], much sooner for large files on a machine
+/// with cores to spare. Each client's rows are applied in order by one worker; see
+/// [`CsvProcessor::process_file_parallel`].
+///
+/// # Errors
+/// Returns an error if the file cannot be opened.
+///
+/// # Panics
+/// Panics if `threads` is zero.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{process_csv_file, process_csv_file_parallel};
+/// let file = tempfile::NamedTempFile::new().unwrap();
+/// let mut input = String::from("type,client,tx,amount\n");
+/// for tx in 1..=200 {
+///     input += &format!("deposit,{},{},10\n", tx % 7, tx);
+///     input += &format!("withdrawal,{},{},15\n", tx % 7, tx + 1000);
+/// }
+/// std::fs::write(&file, input).unwrap();
+/// let path = file.path().to_str().unwrap();
+///
+/// let (database, errors, _) = process_csv_file_parallel(path, 4).unwrap();
+/// let (expected, expected_errors, _) = process_csv_file(path).unwrap();
+/// assert_eq!(database.digest(), expected.digest());
+/// assert_eq!(errors, expected_errors);
+/// ```
+pub fn process_csv_file_parallel(
+    file_path: &str,
+    threads: usize,
+) -> Result<ProcessingResult, Box<dyn Error>> {
+    CsvProcessor::default()
+        .process_file_parallel(file_path, threads)
+        .map(ProcessingResult::from)
+}
+
+/// Process several CSV files of transactions concurrently
+///
+/// For feeds pre-partitioned by client range: each file is processed into a database of
+/// its own, and the databases are then merged. The files are shared out among at most
+/// as many threads as the machine can run in parallel. Returns the same as
+/// [`process_csv_file`], with the errors and warnings of each file in the order the
+/// files were given.
+///
+/// # Errors
+/// Returns an error if a file cannot be opened, if a client has transactions in more
+/// than one file (processed apart, a dispute in one file of a deposit in another would
+/// be wrongly rejected), or if the same transaction ID is used in more than one file.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::process_csv_files_parallel;
+/// let first = tempfile::NamedTempFile::new().unwrap();
+/// std::fs::write(&first, "type,client,tx,amount\ndeposit,1,1,10\n").unwrap();
+/// let second = tempfile::NamedTempFile::new().unwrap();
+/// std::fs::write(&second, "type,client,tx,amount\ndeposit,2,2,20\n").unwrap();
+///
+/// let paths = [first.path().to_str().unwrap(), second.path().to_str().unwrap()];
+/// let (database, errors, _) = process_csv_files_parallel(&paths).unwrap();
+/// assert_eq!(database.get_all_client_ids().len(), 2);
+/// assert!(errors.is_empty());
+///
+/// // Transaction 1 again, in another file
+/// std::fs::write(&second, "type,client,tx,amount\ndeposit,2,1,20\n").unwrap();
+/// let err = process_csv_files_parallel(&paths).unwrap_err();
+/// assert!(err.to_string().contains("transaction 1 is in both databases"));
+///
+/// // Client 1 in both files
+/// std::fs::write(&second, "type,client,tx,amount\ndeposit,1,2,20\n").unwrap();
+/// let err = process_csv_files_parallel(&paths).unwrap_err();
+/// assert!(err.to_string().starts_with("Client 1 is in both"));
+/// ```
+pub fn process_csv_files_parallel(paths: &[&str]) -> Result<ProcessingResult, Box<dyn Error>> {
+    let workers = std::thread::available_parallelism()
+        .map_or(1, usize::from)
+        .min(paths.len());
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<Option<Result<ProcessingResult, String>>> = Vec::new();
+    results.resize_with(paths.len(), || None);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(path) = paths.get(index) else {
+                            return done;
+                        };
+                        done.push((index, process_csv_file(path).map_err(|e| e.to_string())));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            let done = handle
+                .join()
+                .unwrap_or_else(|payload| std::panic::resume_unwind(payload));
+            for (index, result) in done {
+                results[index] = Some(result);
+            }
+        }
+    });
+
     let mut database = Database::new();
     let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    let mut owners: HashMap<u16, &str> = HashMap::new();
+    for (path, result) in paths.iter().zip(results) {
+        let (shard, shard_errors, shard_warnings) = result.expect("every file is processed")?;
+        for client in shard.get_all_client_ids() {
+            if let Some(other) = owners.insert(client, path) {
+                return Err(format!("Client {} is in both {} and {}", client, other, path).into());
+            }
+        }
+        database
+            .merge(shard, ConflictPolicy::Error)
+            .map_err(|conflict| format!("Merging {}: {}", path, conflict))?;
+        errors.extend(shard_errors);
+        warnings.extend(shard_warnings);
+    }
 
-    let mut reader = csv::ReaderBuilder::new()
-        .trim(csv::Trim::All) // Trim whitespace from both headers and fields
-        .from_path(file_path)?;
+    Ok((database, errors, warnings))
+}
 
-    for (line_num, result) in reader.deserialize().enumerate() {
-        let line_number = line_num + 2; // +1 for 0-based index, +1 for header row
+/// How a CSV input is laid out, beyond the columns it has
+///
+/// The default is standard CSV: fields separated by commas, quoted with double quotes,
+/// and no comment lines.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{CsvDialect, CsvSource, Engine};
+/// let input = "# exported nightly\ntype;client;tx;amount\ndeposit;1;1;'2.5'\n";
+/// let dialect = CsvDialect {
+///     delimiter: b';',
+///     quote: b'\'',
+///     comment: Some(b'#'),
+/// };
+///
+/// let mut engine = Engine::new();
+/// engine.process(CsvSource::with_dialect("input", input.as_bytes(), dialect, true));
+/// assert_eq!(engine.database().get_account(1).unwrap().available().to_string(), "2.5000");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvDialect {
+    /// Byte separating fields
+    pub delimiter: u8,
+    /// Byte quoting fields that contain the delimiter
+    pub quote: u8,
+    /// Lines starting with this byte are skipped, if given
+    pub comment: Option<u8>,
+}
 
-        let record: TransactionRecord = match result {
-            Ok(record) => record,
-            Err(e) => {
-                errors.push(format!(
-                    "Error parsing CSV at {}:{}: {}",
-                    file_path, line_number, e
-                ));
-                continue;
+impl Default for CsvDialect {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            quote: b'"',
+            comment: None,
+        }
+    }
+}
+
+/// Processes CSV inputs with one configuration, reusable across any number of them
+///
+/// Built with [`CsvProcessor::builder`]; anything not set keeps the defaults of
+/// [`process_csv_file`]. Each input is processed into a database of its own.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{AmountUnit, CsvDialect, CsvProcessor, Policy};
+/// let processor = CsvProcessor::builder()
+///     .dialect(CsvDialect {
+///         delimiter: b'\t',
+///         ..CsvDialect::default()
+///     })
+///     .amount_unit(AmountUnit::Cents)
+///     .policy(Policy::default())
+///     .build();
+///
+/// // The same configuration for every file of the batch
+/// for (client, cents, available) in [(1, 250, "2.5000"), (2, 1999, "19.9900")] {
+///     let input = format!("type\tclient\ttx\tamount\ndeposit\t{}\t1\t{}\n", client, cents);
+///     let outcome = processor.process_reader("input", input.as_bytes());
+///     assert!(outcome.errors.is_empty());
+///     let account = outcome.database.get_account(client).unwrap();
+///     assert_eq!(account.available().to_string(), available);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct CsvProcessor {
+    trim: bool,
+    dialect: CsvDialect,
+    policy: Policy,
+    unknown_types: UnknownTypes,
+    amount_unit: AmountUnit,
+    deterministic: bool,
+}
+
+impl Default for CsvProcessor {
+    fn default() -> Self {
+        Self {
+            trim: true,
+            dialect: CsvDialect::default(),
+            policy: Policy::default(),
+            unknown_types: UnknownTypes::default(),
+            amount_unit: AmountUnit::default(),
+            deterministic: false,
+        }
+    }
+}
+
+impl CsvProcessor {
+    /// Start configuring a processor
+    pub fn builder() -> CsvProcessorBuilder {
+        CsvProcessorBuilder::default()
+    }
+
+    /// A source reading `reader` with this configuration, for use with an
+    /// [`Engine`](crate::Engine) or further `with_*` settings
+    pub fn source<R: Read>(&self, name: impl Into<String>, reader: R) -> CsvSource<R> {
+        CsvSource::with_dialect(name, reader, self.dialect, self.trim)
+            .with_unknown_types(self.unknown_types)
+            .with_amount_unit(self.amount_unit)
+    }
+
+    /// Process CSV streamed from `reader`, locating problems as `NAME:LINE`
+    pub fn process_reader<R: Read>(&self, name: &str, reader: R) -> ProcessingOutcome {
+        let mut outcome = process_csv_source(self.source(name, reader), self.policy.clone());
+        if self.deterministic {
+            outcome.run_id = derived_run_id(&outcome, name);
+        }
+        outcome
+    }
+
+    /// Process the CSV file at `path`
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be opened.
+    pub fn process_file(&self, path: &str) -> Result<ProcessingOutcome, Box<dyn Error>> {
+        Ok(self.process_reader(path, File::open(path)?))
+    }
+
+    /// Process the CSV file at `path`, applying transactions on `threads` worker threads
+    ///
+    /// The file is read and decoded on the calling thread, and each row is passed to the
+    /// worker for its client, chosen by a [`Router`], so every client's rows are applied
+    /// in their original order and the accounts come out exactly as
+    /// [`process_file`](Self::process_file) leaves them. Errors and warnings are sorted
+    /// back into the order of the lines they relate to.
+    ///
+    /// A [deterministic](CsvProcessorBuilder::deterministic) processor ignores `threads`
+    /// and processes the file on the calling thread, as `process_file` does.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be opened.
+    ///
+    /// # Panics
+    /// Panics if `threads` is zero.
+    pub fn process_file_parallel(
+        &self,
+        path: &str,
+        threads: usize,
+    ) -> Result<ProcessingOutcome, Box<dyn Error>> {
+        if self.deterministic {
+            assert!(threads > 0, "a router needs at least one shard");
+            return self.process_file(path);
+        }
+        let run_id = RunId::generate();
+        let mut source = self.source(path, File::open(path)?);
+        let policy = self.policy.clone();
+        let pool = Router::new(threads).spawn(PARALLEL_BACKLOG, move |_, rows| {
+            process_csv_source(Channel(rows), policy.clone())
+        });
+        while let Some(row) = source.next_transaction() {
+            // Rows that couldn't be decoded have no client; any worker can report them
+            let client = row.as_ref().map_or(0, |row| row.client);
+            if pool.send(client, row).is_err() {
+                // The worker panicked; joining passes the panic on
+                break;
             }
+        }
+
+        let mut outcome = ProcessingOutcome {
+            database: Database::with_policy(self.policy.clone()),
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            stats: RunStats::default(),
+            run_id,
         };
+        for shard in pool.join() {
+            // Each client's account is in one shard only
+            outcome.database.absorb(shard.database)?;
+            outcome.errors.extend(shard.errors);
+            outcome.warnings.extend(shard.warnings);
+            outcome.stats += shard.stats;
+        }
+        sort_by_line(&mut outcome.errors, path);
+        sort_by_line(&mut outcome.warnings, path);
+        Ok(outcome)
+    }
+}
 
-        // Process the transaction
-        if let Err(e) = process_transaction_record(&mut database, record) {
-            errors.push(format!(
-                "Error processing transaction at {}:{}: {}",
-                file_path, line_number, e
-            ));
-            continue;
+/// A run ID that depends only on what the run produced: its final state, errors and
+/// warnings
+///
+/// Messages locate problems as `NAME:LINE`; the input `name` is left out, so the same
+/// input gives the same ID wherever it is read from.
+fn derived_run_id(outcome: &ProcessingOutcome, name: &str) -> RunId {
+    let location = format!("{}:", name);
+    let mut hasher = Sha256::new();
+    hasher.update(outcome.database.digest());
+    for message in &outcome.errors {
+        hash_message(&mut hasher, message, &location);
+    }
+    hasher.update(b"\0");
+    for message in &outcome.warnings {
+        hash_message(&mut hasher, message, &location);
+    }
+    let hash = hasher.finalize();
+    let mut raw = [0; 8];
+    raw.copy_from_slice(&hash[..8]);
+    RunId::from_raw(u64::from_be_bytes(raw))
+}
+
+/// Hash `message` without the input name of its `location` prefix
+///
+/// The location leads the message, after at most a label such as `Warning at `, which
+/// has no colon. Only that occurrence is left out, not any in the rest of the message.
+fn hash_message(hasher: &mut Sha256, message: &str, location: &str) {
+    hasher.update(b"\n");
+    match message.find(location) {
+        Some(at) if !message[..at].contains(':') => {
+            hasher.update(&message[..at]);
+            hasher.update(&message[at + location.len()..]);
+        }
+        _ => hasher.update(message),
+    }
+}
+
+/// Rows each worker of [`CsvProcessor::process_file_parallel`] can have waiting
+const PARALLEL_BACKLOG: usize = 1024;
+
+/// Rows received from the thread reading the input
+struct Channel(Receiver<Result<SourcedTransaction, SourceError>>);
+
+impl TransactionSource for Channel {
+    fn next_transaction(&mut self) -> Option<Result<SourcedTransaction, SourceError>> {
+        self.0.recv().ok()
+    }
+}
+
+/// Sort `messages` about input `name` by the line they relate to, keeping the order of
+/// messages about the same line
+fn sort_by_line(messages: &mut [String], name: &str) {
+    let prefix = format!("{}:", name);
+    messages.sort_by_cached_key(|message| {
+        message
+            .find(&prefix)
+            .map(|at| &message[at + prefix.len()..])
+            .and_then(|rest| {
+                let digits = rest
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len());
+                rest[..digits].parse::<usize>().ok()
+            })
+    });
+}
+
+/// Configures a [`CsvProcessor`]
+#[derive(Debug, Clone, Default)]
+pub struct CsvProcessorBuilder {
+    processor: CsvProcessor,
+}
+
+impl CsvProcessorBuilder {
+    /// Whether to trim whitespace around headers and fields (the default) or keep it
+    pub fn trim(mut self, trim: bool) -> Self {
+        self.processor.trim = trim;
+        self
+    }
+
+    /// Read inputs laid out as `dialect`
+    pub fn dialect(mut self, dialect: CsvDialect) -> Self {
+        self.processor.dialect = dialect;
+        self
+    }
+
+    /// Apply `policy` to every input
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.processor.policy = policy;
+        self
+    }
+
+    /// Handle rows of unrecognised types as `unknown_types` says
+    pub fn unknown_types(mut self, unknown_types: UnknownTypes) -> Self {
+        self.processor.unknown_types = unknown_types;
+        self
+    }
+
+    /// Read amounts in `unit`
+    pub fn amount_unit(mut self, unit: AmountUnit) -> Self {
+        self.processor.amount_unit = unit;
+        self
+    }
+
+    /// Whether to guarantee reproducible outcomes, e.g. for audit submissions
+    ///
+    /// A deterministic processor applies every row on the calling thread, in input
+    /// order, even when asked to [process in parallel](CsvProcessor::process_file_parallel),
+    /// and derives the run ID from what the run produced rather than generating a fresh
+    /// one. Processing the same input with the same configuration then always gives the
+    /// same database, dumping to the same [JSON](Database::to_json), the same errors and
+    /// warnings in the same order, and the same run ID, even if the input is read from
+    /// another path.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::CsvProcessor;
+    /// let file = tempfile::NamedTempFile::new().unwrap();
+    /// let mut input = String::from("type,client,tx,amount\n");
+    /// for tx in 1..=100 {
+    ///     input += &format!("deposit,{},{},10\n", tx % 7, tx);
+    ///     input += &format!("dispute,{},{},\n", tx % 5, tx);
+    /// }
+    /// std::fs::write(&file, input).unwrap();
+    /// let path = file.path().to_str().unwrap();
+    ///
+    /// let processor = CsvProcessor::builder().deterministic(true).build();
+    /// let first = processor.process_file_parallel(path, 4).unwrap();
+    /// let second = processor.process_file(path).unwrap();
+    /// assert_eq!(first.database.to_json(), second.database.to_json());
+    /// assert_eq!(first.errors, second.errors);
+    /// assert_eq!(first.run_id, second.run_id);
+    /// ```
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.processor.deterministic = deterministic;
+        self
+    }
+
+    /// The processor configured so far
+    pub fn build(self) -> CsvProcessor {
+        self.processor
+    }
+}
+
+/// What a [`CsvSource`] does with rows whose type neither it nor its
+/// [decoder](TransactionDecoder) recognises
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnknownTypes {
+    /// Reject the row with [`EngineError::UnknownTransactionType`]
+    #[default]
+    Reject,
+    /// Skip the row with a [`Warning::UnknownType`], so feeds carrying extra record
+    /// types can still be processed
+    Ignore,
+}
+
+/// Unit of the amounts in a CSV input
+///
+/// Parses from and displays as `units` or `cents`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AmountUnit {
+    /// Decimal amounts in whole currency units, e.g. `12.34`
+    #[default]
+    Units,
+    /// Integer amounts in hundredths of a unit, e.g. `1234` for 12.34
+    Cents,
+}
+
+impl std::fmt::Display for AmountUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Units => write!(f, "units"),
+            Self::Cents => write!(f, "cents"),
         }
     }
+}
 
-    Ok((database, errors))
+impl std::str::FromStr for AmountUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "units" => Ok(Self::Units),
+            "cents" => Ok(Self::Cents),
+            other => Err(format!("Expected units or cents, got {}", other)),
+        }
+    }
 }
 
-fn process_transaction_record(
-    database: &mut Database,
+/// Fewest amounts [`suspect_amount_unit`] will judge
+const MIN_AMOUNTS_FOR_UNIT_CHECK: u64 = 20;
+
+/// The unit `amounts` look like they were really in, if not `unit`
+///
+/// A heuristic, for catching a feed read in the wrong unit before its amounts are off
+/// by a factor of a hundred. Read as units, a feed in cents has only whole amounts (99%
+/// of them, allowing for the odd stray row), and large ones: a median of 1000 or more.
+/// Read as cents, a feed in units has a median below one unit, as its 25s become 0.25
+/// (and any 12.34 is rejected). At least 20 amounts are needed for a verdict.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{AmountHistogram, AmountUnit, Fixed4, suspect_amount_unit};
+/// let mut amounts = AmountHistogram::new();
+/// for cents in 1000..1050 {
+///     amounts.record(cents.to_string().parse().unwrap());
+/// }
+/// assert_eq!(suspect_amount_unit(&amounts, AmountUnit::Units), Some(AmountUnit::Cents));
+///
+/// let mut amounts = AmountHistogram::new();
+/// for cents in 1000..1050 {
+///     amounts.record(Fixed4::from_cents(cents).unwrap());
+/// }
+/// assert_eq!(suspect_amount_unit(&amounts, AmountUnit::Cents), None);
+///
+/// let mut amounts = AmountHistogram::new();
+/// for units in 20..70 {
+///     amounts.record(Fixed4::from_cents(units).unwrap());
+/// }
+/// assert_eq!(suspect_amount_unit(&amounts, AmountUnit::Cents), Some(AmountUnit::Units));
+/// ```
+pub fn suspect_amount_unit(amounts: &AmountHistogram, unit: AmountUnit) -> Option<AmountUnit> {
+    let count = amounts.count();
+    if count < MIN_AMOUNTS_FOR_UNIT_CHECK {
+        return None;
+    }
+    let median = amounts.percentile(50.0)?;
+    match unit {
+        AmountUnit::Units => (amounts.whole_count() * 100 >= count * 99
+            && median >= Fixed4::from_cents(100_000)?)
+        .then_some(AmountUnit::Cents),
+        AmountUnit::Cents => (median < Fixed4::from_cents(100)?).then_some(AmountUnit::Units),
+    }
+}
+
+/// Columns every input must have
+const REQUIRED_COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// Columns an input may have
+const OPTIONAL_COLUMNS: [&str; 6] = [
+    "timestamp",
+    "case_id",
+    "effective_at",
+    "initiator",
+    "offset",
+    "original_tx",
+];
+
+/// Other names feeds commonly use for each required column
+const COLUMN_SYNONYMS: [(&str, &[&str]); 4] = [
+    ("type", &["transactiontype", "txtype", "txntype", "kind"]),
+    (
+        "client",
+        &["clientid", "customer", "customerid", "account", "accountid"],
+    ),
+    (
+        "tx",
+        &["txid", "txn", "txnid", "transaction", "transactionid"],
+    ),
+    ("amount", &["amt", "value"]),
+];
+
+/// Problems with a CSV header, found before any row is read
+///
+/// Rather than every row failing with the same confusing decode error, a
+/// [`CsvSource`] whose header is [invalid](HeaderReport::is_valid) reports this once,
+/// against line 1, and reads nothing further. Where a missing column looks like one the
+/// input has under another name, the report suggests mapping it with
+/// [`CsvSource::with_column_map`] (`--column-map` on the command line).
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::HeaderReport;
+/// let report = HeaderReport::check(&["Type", "client_id", "tx", "amount", "note"]);
+/// assert!(!report.is_valid());
+/// assert_eq!(report.missing, ["type", "client"]);
+/// assert_eq!(
+///     report.suggestions,
+///     [("Type".to_string(), "type".to_string()), ("client_id".to_string(), "client".to_string())]
+/// );
+/// assert_eq!(
+///     report.to_string(),
+///     "missing column 'type' (map 'Type' with --column-map Type=type); \
+///      missing column 'client' (map 'client_id' with --column-map client_id=client); \
+///      unknown column 'Type' ignored; unknown column 'client_id' ignored; \
+///      unknown column 'note' ignored"
+/// );
+///
+/// let report = HeaderReport::check(&["client", "tx", "type", "amount", "timestamp"]);
+/// assert!(report.is_valid());
+/// assert!(report.reordered);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderReport {
+    /// The header's columns, in order
+    pub columns: Vec<String>,
+    /// Required columns the header lacks
+    pub missing: Vec<String>,
+    /// Columns that are neither required nor optional, and are ignored
+    pub extra: Vec<String>,
+    /// Columns that appear more than once
+    pub duplicated: Vec<String>,
+    /// Whether the required columns are out of the usual `type,client,tx,amount` order;
+    /// columns are matched by name, so this is harmless
+    pub reordered: bool,
+    /// Column renames, from the header's name to the expected one, that would supply
+    /// missing columns
+    pub suggestions: Vec<(String, String)>,
+}
+
+impl HeaderReport {
+    /// Check a header's columns against the expected ones
+    pub fn check(columns: &[&str]) -> Self {
+        let known =
+            |column: &str| REQUIRED_COLUMNS.contains(&column) || OPTIONAL_COLUMNS.contains(&column);
+        let mut report = Self {
+            columns: columns.iter().map(|column| column.to_string()).collect(),
+            ..Self::default()
+        };
+        for (i, column) in columns.iter().enumerate() {
+            if columns[..i].contains(column) {
+                if !report.duplicated.iter().any(|seen| seen == column) {
+                    report.duplicated.push(column.to_string());
+                }
+            } else if !known(column) {
+                report.extra.push(column.to_string());
+            }
+        }
+
+        let positions: Vec<usize> = REQUIRED_COLUMNS
+            .iter()
+            .filter_map(|required| columns.iter().position(|column| column == required))
+            .collect();
+        report.reordered = positions.windows(2).any(|pair| pair[0] > pair[1]);
+
+        for (required, synonyms) in COLUMN_SYNONYMS {
+            if columns.contains(&required) {
+                continue;
+            }
+            report.missing.push(required.to_string());
+            let candidate = report.extra.iter().find(|column| {
+                let normalized = normalize_column(column);
+                normalized == required || synonyms.contains(&normalized.as_str())
+            });
+            if let Some(candidate) = candidate {
+                report
+                    .suggestions
+                    .push((candidate.clone(), required.to_string()));
+            }
+        }
+        report
+    }
+
+    /// Whether rows can be read with this header: no required column is missing and none
+    /// is duplicated
+    pub fn is_valid(&self) -> bool {
+        self.missing.is_empty() && self.duplicated.is_empty()
+    }
+
+    /// Whether the header is exactly as expected, with nothing to report
+    pub fn is_clean(&self) -> bool {
+        self.is_valid() && self.extra.is_empty() && !self.reordered
+    }
+}
+
+/// A column name lower-cased, with separators removed
+fn normalize_column(column: &str) -> String {
+    column
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Every problem found, separated by semicolons
+impl std::fmt::Display for HeaderReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut problems = Vec::new();
+        for column in &self.missing {
+            match self.suggestions.iter().find(|(_, to)| to == column) {
+                Some((from, to)) => problems.push(format!(
+                    "missing column '{}' (map '{}' with --column-map {}={})",
+                    column, from, from, to
+                )),
+                None => problems.push(format!("missing column '{}'", column)),
+            }
+        }
+        for column in &self.duplicated {
+            problems.push(format!("column '{}' appears more than once", column));
+        }
+        for column in &self.extra {
+            problems.push(format!("unknown column '{}' ignored", column));
+        }
+        if self.reordered {
+            problems.push("columns out of the usual type,client,tx,amount order".to_string());
+        }
+        if problems.is_empty() {
+            return write!(f, "header OK");
+        }
+        write!(f, "{}", problems.join("; "))
+    }
+}
+
+/// CSV transaction input, read one record at a time
+///
+/// Errors and warnings are located by the source name and line number,
+/// e.g. `input.csv:7`.
+pub struct CsvSource<R> {
+    name: String,
+    records: csv::StringRecordsIntoIter<R>,
+    line: usize,
+    unknown_types: UnknownTypes,
+    decoder: Option<Box<dyn TransactionDecoder>>,
+    hooks: Vec<Box<dyn RecordHook>>,
+    column_map: Vec<(String, String)>,
+    header: Option<(csv::StringRecord, HeaderReport)>,
+    header_state: HeaderState,
+    skip_rows: usize,
+    limit_rows: Option<usize>,
+    amount_unit: AmountUnit,
+}
+
+/// Whether a [`CsvSource`] has checked its header before reading rows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HeaderState {
+    Unchecked,
+    Valid,
+    /// Invalid, and reported: no rows are read
+    Rejected,
+}
+
+impl<R: Read> CsvSource<R> {
+    /// Read CSV from `reader`, reporting problems against `name` (e.g. `stdin`)
+    pub fn new(name: impl Into<String>, reader: R) -> Self {
+        Self::with_dialect(name, reader, CsvDialect::default(), true)
+    }
+
+    /// Read CSV laid out as `dialect` from `reader`, trimming whitespace around headers
+    /// and fields if `trim`
+    pub fn with_dialect(
+        name: impl Into<String>,
+        reader: R,
+        dialect: CsvDialect,
+        trim: bool,
+    ) -> Self {
+        let reader = csv::ReaderBuilder::new()
+            .trim(if trim {
+                csv::Trim::All
+            } else {
+                csv::Trim::None
+            })
+            .delimiter(dialect.delimiter)
+            .quote(dialect.quote)
+            .comment(dialect.comment)
+            .from_reader(reader);
+        Self {
+            name: name.into(),
+            records: reader.into_records(),
+            line: 1, // The header row
+            unknown_types: UnknownTypes::default(),
+            decoder: None,
+            hooks: Vec::new(),
+            column_map: Vec::new(),
+            header: None,
+            header_state: HeaderState::Unchecked,
+            skip_rows: 0,
+            limit_rows: None,
+            amount_unit: AmountUnit::default(),
+        }
+    }
+
+    /// Handle rows of unrecognised types as `unknown_types` says
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{CsvSource, Engine, UnknownTypes};
+    /// let input = "type,client,tx,amount\ndeposit,1,1,10\nfee,1,2,1\n";
+    /// let source =
+    ///     CsvSource::new("input", input.as_bytes()).with_unknown_types(UnknownTypes::Ignore);
+    ///
+    /// let mut engine = Engine::new();
+    /// let stats = engine.process(source);
+    /// assert_eq!((stats.applied, stats.rejected, stats.warnings), (1, 0, 1));
+    /// assert_eq!(engine.warnings()[0], "Warning at input:3: Row of unknown type 'fee' skipped");
+    /// ```
+    pub fn with_unknown_types(mut self, unknown_types: UnknownTypes) -> Self {
+        self.unknown_types = unknown_types;
+        self
+    }
+
+    /// Decode rows of types the built-in decoder doesn't recognise with `decoder`
+    pub fn with_decoder(mut self, decoder: impl TransactionDecoder + 'static) -> Self {
+        self.decoder = Some(Box::new(decoder));
+        self
+    }
+
+    /// Pass every row through `hook` before decoding it, after any hooks already added
+    pub fn with_record_hook(mut self, hook: impl RecordHook + 'static) -> Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Skip the first `rows` rows after the header without decoding them
+    ///
+    /// Skipped rows still count towards line numbers, so problems are reported at the
+    /// same locations as when processing the whole input. With
+    /// [`with_limit_rows`](Self::with_limit_rows), a known range of lines can be
+    /// processed or reprocessed on its own.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{CsvSource, Engine};
+    /// let input = "type,client,tx,amount\n\
+    ///              deposit,1,1,1\n\
+    ///              deposit,1,2,x\n\
+    ///              deposit,1,3,1\n\
+    ///              deposit,1,4,1\n";
+    /// // Lines 3 and 4 only
+    /// let source = CsvSource::new("input", input.as_bytes())
+    ///     .with_skip_rows(1)
+    ///     .with_limit_rows(2);
+    ///
+    /// let mut engine = Engine::new();
+    /// let stats = engine.process(source);
+    /// assert_eq!((stats.rows, stats.applied), (2, 1));
+    /// assert!(engine.errors()[0].contains("input:3"));
+    /// ```
+    pub fn with_skip_rows(mut self, rows: usize) -> Self {
+        self.skip_rows = rows;
+        self
+    }
+
+    /// Read no more than `rows` rows, after any skipped
+    pub fn with_limit_rows(mut self, rows: usize) -> Self {
+        self.limit_rows = Some(rows);
+        self
+    }
+
+    /// Read amounts in `unit`
+    ///
+    /// With [`AmountUnit::Cents`], amounts must be whole numbers of cents; anything else
+    /// is rejected as [`EngineError::InvalidAmountFormat`].
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{AmountUnit, CsvSource, Engine};
+    /// let input = "type,client,tx,amount\ndeposit,1,1,1234\ndeposit,1,2,5.5\n";
+    /// let source = CsvSource::new("input", input.as_bytes()).with_amount_unit(AmountUnit::Cents);
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.process(source);
+    /// let account = engine.database().get_account(1).unwrap();
+    /// assert_eq!(account.available().to_string(), "12.3400");
+    /// assert!(engine.errors()[0].contains("not a whole number of cents"));
+    /// ```
+    pub fn with_amount_unit(mut self, unit: AmountUnit) -> Self {
+        self.amount_unit = unit;
+        self
+    }
+
+    /// Read the header's column `from` as column `to`, e.g. `client_id` as `client`
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{CsvSource, Engine};
+    /// let input = "kind,client_id,tx,amount\ndeposit,1,1,10\n";
+    /// let source = CsvSource::new("input", input.as_bytes())
+    ///     .with_column_map("kind", "type")
+    ///     .with_column_map("client_id", "client");
+    ///
+    /// let mut engine = Engine::new();
+    /// assert_eq!(engine.process(source).applied, 1);
+    /// ```
+    pub fn with_column_map(mut self, from: &str, to: &str) -> Self {
+        self.column_map.push((from.to_string(), to.to_string()));
+        self
+    }
+
+    /// Read the header, if not read already, and check it
+    ///
+    /// Column maps are applied first. Useful to reject an input before processing
+    /// starts; otherwise an invalid header is reported as the source's only error.
+    ///
+    /// # Errors
+    /// Returns an error if the header cannot be read.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{CsvSource, Engine};
+    /// let input = "type,client_id,tx,amount\ndeposit,1,1,10\ndeposit,1,2,10\n";
+    /// let mut source = CsvSource::new("input", input.as_bytes());
+    /// let report = source.header_report().unwrap();
+    /// assert_eq!(report.missing, ["client"]);
+    ///
+    /// let mut engine = Engine::new();
+    /// let stats = engine.process(source);
+    /// assert_eq!((stats.rows, stats.malformed), (1, 1));
+    /// assert_eq!(
+    ///     engine.errors(),
+    ///     ["Error parsing CSV at input:1: Invalid header: missing column 'client' \
+    ///       (map 'client_id' with --column-map client_id=client); \
+    ///       unknown column 'client_id' ignored"]
+    /// );
+    /// ```
+    pub fn header_report(&mut self) -> Result<HeaderReport, csv::Error> {
+        if let Some((_, report)) = &self.header {
+            return Ok(report.clone());
+        }
+        let reader = self.records.reader_mut();
+        let mut headers = reader.headers()?.clone();
+        if !self.column_map.is_empty() {
+            headers = headers
+                .iter()
+                .map(|column| {
+                    self.column_map
+                        .iter()
+                        .find(|(from, _)| from == column)
+                        .map_or(column, |(_, to)| to.as_str())
+                })
+                .collect();
+        }
+        let columns: Vec<&str> = headers.iter().collect();
+        let report = HeaderReport::check(&columns);
+        self.header = Some((headers, report.clone()));
+        Ok(report)
+    }
+}
+
+impl CsvSource<File> {
+    /// Read CSV from the file at `path`
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be opened.
+    pub fn from_path(path: &str) -> std::io::Result<Self> {
+        Ok(Self::new(path, File::open(path)?))
+    }
+}
+
+impl<R: Read> TransactionSource for CsvSource<R> {
+    fn next_transaction(&mut self) -> Option<Result<SourcedTransaction, SourceError>> {
+        match self.header_state {
+            HeaderState::Valid => {}
+            HeaderState::Rejected => return None,
+            HeaderState::Unchecked => {
+                self.header_state = HeaderState::Valid;
+                // An empty input has no header, and nothing to read anyway
+                if let Ok(report) = self.header_report()
+                    && !report.columns.is_empty()
+                    && !report.is_valid()
+                {
+                    self.header_state = HeaderState::Rejected;
+                    return Some(Err(SourceError::Malformed {
+                        location: format!("{}:1", self.name),
+                        message: format!("Invalid header: {}", report),
+                    }));
+                }
+            }
+        }
+
+        while self.skip_rows > 0 {
+            self.skip_rows -= 1;
+            self.records.next()?.ok();
+            self.line += 1;
+        }
+        match &mut self.limit_rows {
+            Some(0) => return None,
+            Some(rows) => *rows -= 1,
+            None => {}
+        }
+
+        let headers = self.header.as_ref().map(|(headers, _)| headers);
+        let result = self
+            .records
+            .next()?
+            .and_then(|record| record.deserialize::<TransactionRecord>(headers));
+        self.line += 1;
+        let location = format!("{}:{}", self.name, self.line);
+
+        Some(match result {
+            Ok(mut record) => match self.preprocess(&mut record) {
+                RecordAction::Keep => self.decode_record(record, location),
+                RecordAction::Skip(reason) => Err(SourceError::Skipped {
+                    location,
+                    warning: Warning::SkippedByHook(reason),
+                }),
+            },
+            Err(e) => Err(SourceError::Malformed {
+                location,
+                message: e.to_string(),
+            }),
+        })
+    }
+}
+
+impl<R> CsvSource<R> {
+    /// Run the record hooks in order, stopping at the first that skips the row
+    fn preprocess(&self, record: &mut TransactionRecord) -> RecordAction {
+        for hook in &self.hooks {
+            if let RecordAction::Skip(reason) = hook.preprocess(record) {
+                return RecordAction::Skip(reason);
+            }
+        }
+        RecordAction::Keep
+    }
+
+    /// Turn a CSV record into a transaction ready to apply
+    fn decode_record(
+        &self,
+        mut record: TransactionRecord,
+        location: String,
+    ) -> Result<SourcedTransaction, SourceError> {
+        let decoded = self
+            .convert_amount(&mut record)
+            .and_then(|()| parse_transaction(&record))
+            .or_else(|kind| match kind {
+                EngineError::UnknownTransactionType(_) => self
+                    .decoder
+                    .as_ref()
+                    .and_then(|decoder| {
+                        decoder.decode(
+                            &record.transaction_type.to_lowercase(),
+                            record.amount.as_ref(),
+                        )
+                    })
+                    .unwrap_or(Err(kind)),
+                kind => Err(kind),
+            });
+        let transaction = match decoded {
+            Ok(transaction) => transaction,
+            Err(EngineError::UnknownTransactionType(kind))
+                if self.unknown_types == UnknownTypes::Ignore =>
+            {
+                return Err(SourceError::Skipped {
+                    location,
+                    warning: Warning::UnknownType(kind),
+                });
+            }
+            Err(kind) => {
+                return Err(SourceError::Invalid {
+                    location,
+                    error: TransactionError {
+                        client: record.client,
+                        tx: record.tx,
+                        amount: None,
+                        kind,
+                        lock: None,
+                    },
+                });
+            }
+        };
+        decode_meta(record, transaction, location)
+    }
+
+    /// Rewrite the record's amount, if it has one, from the source's unit to a decimal
+    fn convert_amount(&self, record: &mut TransactionRecord) -> Result<(), EngineError> {
+        let (AmountUnit::Cents, Some(amount)) = (self.amount_unit, &mut record.amount) else {
+            return Ok(());
+        };
+        let cents = match amount {
+            RecordAmount::Valid(cents) if cents.to_raw() % Fixed4::SCALE == 0 => {
+                Fixed4::from_cents(cents.to_raw() / Fixed4::SCALE)
+            }
+            _ => None,
+        };
+        match cents {
+            Some(converted) => {
+                *amount = RecordAmount::Valid(converted);
+                Ok(())
+            }
+            None => {
+                let text = match amount {
+                    RecordAmount::Valid(cents) => cents.to_string(),
+                    RecordAmount::Invalid { text, .. } => text.trim().to_string(),
+                };
+                Err(EngineError::InvalidAmountFormat(format!(
+                    "{} is not a whole number of cents",
+                    text
+                )))
+            }
+        }
+    }
+}
+
+/// Attach the record's metadata to its decoded transaction
+fn decode_meta(
     record: TransactionRecord,
-) -> Result<(), Box<dyn Error>> {
+    transaction: Transaction,
+    location: String,
+) -> Result<SourcedTransaction, SourceError> {
+    let offset = match record.offset.as_deref().map(str::parse::<SourceOffset>) {
+        Some(Err(message)) => return Err(SourceError::Malformed { location, message }),
+        Some(Ok(offset)) => Some(offset),
+        None => None,
+    };
+
+    let warning = match (&record.amount, transaction.amount()) {
+        (Some(_), None) => Some(Warning::IgnoredAmount),
+        _ => None,
+    };
+
+    Ok(SourcedTransaction {
+        location,
+        client: record.client,
+        tx: record.tx,
+        transaction,
+        meta: TransactionMeta {
+            timestamp: record.timestamp,
+            case_id: record.case_id,
+            effective_at: record.effective_at,
+            initiator: record.initiator,
+            offset,
+        },
+        warning,
+    })
+}
+
+/// The record's amount, which its type needs
+fn record_amount(record: &TransactionRecord) -> Result<Fixed4, EngineError> {
+    record
+        .amount
+        .as_ref()
+        .ok_or(EngineError::MissingAmount)?
+        .value()
+}
+
+/// The record's amount, which its type needs to be positive
+fn positive_amount(record: &TransactionRecord) -> Result<Fixed4, EngineError> {
+    let amount = record_amount(record)?;
+    if amount <= Fixed4::zero() {
+        return Err(EngineError::AmountMustBePositive);
+    }
+    Ok(amount)
+}
+
+fn parse_transaction(record: &TransactionRecord) -> Result<Transaction, EngineError> {
     let transaction = match record.transaction_type.to_lowercase().as_str() {
         "deposit" => {
-            let amount = record.amount.ok_or("Deposit requires an amount")?;
-            Transaction::deposit(&amount)?
+            let amount = record_amount(record)?;
+            // Only zero is rejected here: a negative deposit is a refund under some
+            // policies, so left for the database to judge
+            if amount == Fixed4::zero() {
+                return Err(EngineError::AmountMustBePositive);
+            }
+            Transaction::Deposit { amount }
         }
-        "withdrawal" => {
-            let amount = record.amount.ok_or("Withdrawal requires an amount")?;
-            Transaction::withdrawal(&amount)?
+        "withdrawal" => Transaction::Withdrawal {
+            amount: positive_amount(record)?,
+        },
+        "refund" => {
+            let amount = positive_amount(record)?;
+            let original_tx = record.original_tx.ok_or(EngineError::MissingOriginalTx)?;
+            Transaction::Refund {
+                original_tx,
+                amount,
+            }
         }
         "dispute" => Transaction::dispute(),
         "resolve" => Transaction::resolve(),
         "chargeback" => Transaction::chargeback(),
-        _ => return Err(format!("Unknown transaction type: {}", record.transaction_type).into()),
+        "chargeback_reversal" => Transaction::chargeback_reversal(),
+        _ => {
+            return Err(EngineError::UnknownTransactionType(
+                record.transaction_type.clone(),
+            ));
+        }
     };
-
-    database.process_transaction(record.client, record.tx, transaction)?;
-    Ok(())
+    Ok(transaction)
 }
@@ -0,0 +1,129 @@
+//! Domain-specific transaction types registered by name
+//!
+//! Downstream crates add operations such as bonus credits or loyalty adjustments by
+//! implementing [`CustomTransaction`] and registering it in a [`CustomTransactions`]
+//! set, rather than forking [`Transaction`]. Rows of a registered type are carried as
+//! [`Transaction::Custom`] and applied by their handler.
+//!
+//! The same set serves both ends: the [`Engine`](crate::Engine) (or
+//! [`Database`](crate::Database)) applies the types, and as a
+//! [`TransactionDecoder`] it lets a [`CsvSource`](crate::CsvSource) read them.
+//! Event logs record custom rows under their type name, so they replay only into an
+//! engine with the same types registered.
+
+use crate::db::{AccountOps, EngineError, Transaction};
+use crate::fixed4::Fixed4;
+use crate::record::{RecordAmount, TransactionDecoder};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Handler for a custom transaction type
+///
+/// Handlers act on the account through [`AccountOps`], so balances only ever change
+/// through transactions posted with [`AccountOps::post`], which go through the same
+/// checks and ledger as any other. A handler that posts several transactions and then
+/// fails leaves the earlier ones applied.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{
+/// #     AccountOps, CsvSource, CustomTransaction, CustomTransactions, Engine, Fixed4, EngineError,
+/// #     Transaction,
+/// # };
+/// /// Credits 1% of the available balance, whatever the row's amount
+/// struct LoyaltyBonus;
+///
+/// impl CustomTransaction for LoyaltyBonus {
+///     fn apply(
+///         &self,
+///         account: &mut AccountOps<'_>,
+///         tx: u32,
+///         _amount: Option<Fixed4>,
+///     ) -> Result<(), EngineError> {
+///         let balance = account.view().available().to_f64();
+///         let bonus = format!("{:.4}", balance / 100.0);
+///         account.post(tx, Transaction::deposit(&bonus)?)?;
+///         Ok(())
+///     }
+/// }
+///
+/// let types = CustomTransactions::new().with("loyalty", LoyaltyBonus);
+/// let mut engine = Engine::new().with_custom_transactions(types.clone());
+///
+/// let input = "type,client,tx,amount\ndeposit,1,1,200\nloyalty,1,2,\n";
+/// engine.process(CsvSource::new("input", input.as_bytes()).with_decoder(types));
+/// let account = engine.database().get_account(1).unwrap();
+/// assert_eq!(account.available().to_string(), "202.0000");
+/// ```
+pub trait CustomTransaction: Send + Sync {
+    /// Apply a row of this type, with transaction ID `tx` and its amount if it had one
+    ///
+    /// # Errors
+    /// Returns why the row was rejected; it is reported against the row like any other
+    /// rejected transaction.
+    fn apply(
+        &self,
+        account: &mut AccountOps<'_>,
+        tx: u32,
+        amount: Option<Fixed4>,
+    ) -> Result<(), EngineError>;
+}
+
+/// Custom transaction types by name
+///
+/// Cheap to clone: handlers are shared.
+#[derive(Clone, Default)]
+pub struct CustomTransactions {
+    types: HashMap<String, Arc<dyn CustomTransaction>>,
+}
+
+impl CustomTransactions {
+    /// No custom types
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `handler` for rows of type `name`, replacing any handler already
+    /// registered for it
+    ///
+    /// Names are matched case-insensitively. Built-in types such as `deposit` cannot be
+    /// overridden.
+    pub fn with(mut self, name: &str, handler: impl CustomTransaction + 'static) -> Self {
+        self.types.insert(name.to_lowercase(), Arc::new(handler));
+        self
+    }
+
+    /// Handler registered for `name`
+    pub fn get(&self, name: &str) -> Option<Arc<dyn CustomTransaction>> {
+        self.types.get(&name.to_lowercase()).cloned()
+    }
+
+    /// Registered type names, sorted
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.types.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+}
+
+impl std::fmt::Debug for CustomTransactions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.names()).finish()
+    }
+}
+
+/// Decodes rows of the registered types as [`Transaction::Custom`]
+impl TransactionDecoder for CustomTransactions {
+    fn decode(
+        &self,
+        transaction_type: &str,
+        amount: Option<&RecordAmount>,
+    ) -> Option<Result<Transaction, EngineError>> {
+        self.types.get(transaction_type)?;
+        let amount = amount.map(RecordAmount::value).transpose();
+        Some(amount.map(|amount| Transaction::Custom {
+            name: transaction_type.to_string(),
+            amount,
+        }))
+    }
+}
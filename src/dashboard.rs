@@ -0,0 +1,339 @@
+//! Live terminal dashboard of a run (`tui` feature)
+//!
+//! While a big file or a stream is processed, a [`Dashboard`] follows the
+//! [`Progress`] reported by [`Engine::process_with_progress`](crate::Engine::process_with_progress)
+//! and draws, with [ratatui](https://ratatui.rs): throughput, overall and over the last
+//! second, error rates, the accounts holding the most funds, and the latest
+//! chargebacks. It only keeps what it draws, so it can follow runs of any length.
+//!
+//! Setting up the terminal and deciding when to draw is left to the caller; the binary
+//! does both for `--dashboard`.
+
+use crate::db::{Database, DisputeAction};
+use crate::engine::{Progress, RunStats};
+use crate::fixed4::Fixed4;
+use crate::report::{SortKey, SummaryQuery, SummarySort};
+use ratatui::Frame;
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, Paragraph, Row, Sparkline, Table};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How often [`Dashboard::update`] refreshes the figures it draws
+const REFRESH: Duration = Duration::from_millis(200);
+
+/// Span over which the current throughput and error rate are measured
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Throughput readings kept for the chart, one per refresh
+const HISTORY: usize = 300;
+
+/// Accounts listed by held funds
+const TOP_ACCOUNTS: usize = 10;
+
+/// Chargebacks listed, latest first
+const RECENT_CHARGEBACKS: usize = 10;
+
+/// Throughput, error rates, top accounts by held funds and recent chargebacks of a run
+///
+/// Feed it every [`Progress`] with [`update`](Dashboard::update), and draw it with
+/// [`render`](Dashboard::render) whenever that returns `true`. After the run, a last
+/// [`refresh`](Dashboard::refresh) brings it up to date for a final frame.
+///
+/// # Examples
+/// ```
+/// # use std::time::Instant;
+/// # use ratatui::{Terminal, backend::TestBackend};
+/// # use transaction_processor::{CsvSource, Dashboard, Engine};
+/// let input = "type,client,tx,amount\n\
+///              deposit,1,1,10\n\
+///              deposit,2,2,70\n\
+///              deposit,3,3,5\n\
+///              withdrawal,1,4,50\n\
+///              dispute,2,2,\n\
+///              dispute,3,3,\n\
+///              chargeback,3,3,\n";
+///
+/// let mut engine = Engine::new();
+/// let mut dashboard = Dashboard::new(Instant::now());
+/// let mut terminal = Terminal::new(TestBackend::new(100, 30)).unwrap();
+/// engine.process_with_progress(CsvSource::new("input", input.as_bytes()), |progress| {
+///     if dashboard.update(progress, Instant::now()) {
+///         terminal.draw(|frame| dashboard.render(frame)).unwrap();
+///     }
+/// });
+/// dashboard.refresh(engine.database(), &engine.stats(), Instant::now());
+/// terminal.draw(|frame| dashboard.render(frame)).unwrap();
+///
+/// assert_eq!(dashboard.top_held()[0].0, 2);
+/// assert_eq!(dashboard.recent_chargebacks()[0].tx, 3);
+/// let buffer = terminal.backend().buffer();
+/// let screen: String = buffer.content().iter().map(|cell| cell.symbol()).collect();
+/// assert!(screen.contains("rejected 1 (0 malformed)"));
+/// assert!(screen.contains("client 3 tx 3 at row 7"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Dashboard {
+    started: Instant,
+    refreshed_at: Option<Instant>,
+    stats: RunStats,
+    /// Rows and rejections counted at each refresh within the last [`WINDOW`]
+    samples: VecDeque<(Instant, usize, usize)>,
+    /// Rows per second at each refresh, oldest first
+    throughput: VecDeque<u64>,
+    top_held: Vec<(u16, Fixed4, Fixed4, bool)>,
+    chargebacks: VecDeque<Chargeback>,
+}
+
+/// A chargeback listed on a [`Dashboard`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chargeback {
+    /// Client charged back
+    pub client: u16,
+    /// Transaction charged back
+    pub tx: u32,
+    /// Number of the input row that charged it back, counting from 1
+    pub row: usize,
+}
+
+impl Dashboard {
+    /// An empty dashboard for a run started at `started`
+    pub fn new(started: Instant) -> Self {
+        Self {
+            started,
+            refreshed_at: None,
+            stats: RunStats::default(),
+            samples: VecDeque::new(),
+            throughput: VecDeque::new(),
+            top_held: Vec::new(),
+            chargebacks: VecDeque::new(),
+        }
+    }
+
+    /// Take in `progress`, refreshing the figures if they are more than a fifth of a
+    /// second old at `now`
+    ///
+    /// Returns whether they were refreshed, i.e. whether it is worth drawing again.
+    pub fn update(&mut self, progress: Progress<'_>, now: Instant) -> bool {
+        if let Some(row) = progress.applied
+            && row.action == Some(DisputeAction::Chargeback)
+        {
+            if self.chargebacks.len() == RECENT_CHARGEBACKS {
+                self.chargebacks.pop_back();
+            }
+            self.chargebacks.push_front(Chargeback {
+                client: row.client,
+                tx: row.tx,
+                row: progress.stats.rows,
+            });
+        }
+        if self
+            .refreshed_at
+            .is_some_and(|refreshed_at| now.saturating_duration_since(refreshed_at) < REFRESH)
+        {
+            return false;
+        }
+        self.refresh(progress.database, progress.stats, now);
+        true
+    }
+
+    /// Bring the figures up to date with `database` and `stats` at `now`
+    pub fn refresh(&mut self, database: &Database, stats: &RunStats, now: Instant) {
+        self.stats = stats.clone();
+        self.refreshed_at = Some(now);
+
+        self.samples.push_back((now, stats.rows, stats.rejected));
+        while self.samples.len() > 2
+            && self
+                .samples
+                .get(1)
+                .is_some_and(|&(at, _, _)| now.saturating_duration_since(at) >= WINDOW)
+        {
+            self.samples.pop_front();
+        }
+        if self.throughput.len() == HISTORY {
+            self.throughput.pop_front();
+        }
+        self.throughput.push_back(self.current_throughput() as u64);
+
+        let query = SummaryQuery {
+            sort: SummarySort {
+                key: SortKey::Held,
+                descending: true,
+            },
+            limit: Some(TOP_ACCOUNTS),
+            ..SummaryQuery::default()
+        };
+        self.top_held = database
+            .summaries(&query)
+            .into_iter()
+            .filter(|(_, account)| account.held() > Fixed4::zero())
+            .map(|(client, account)| (client, account.held(), account.total(), account.is_locked()))
+            .collect();
+    }
+
+    /// Statistics as of the last refresh
+    pub fn stats(&self) -> RunStats {
+        self.stats.clone()
+    }
+
+    /// Rows per second over the last second or so
+    pub fn current_throughput(&self) -> f64 {
+        match (self.samples.front(), self.samples.back()) {
+            (Some(&(from, rows_from, _)), Some(&(to, rows_to, _))) if to > from => {
+                (rows_to - rows_from) as f64 / to.duration_since(from).as_secs_f64()
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Rows per second since the run started
+    pub fn average_throughput(&self) -> f64 {
+        let elapsed = self.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            self.stats.rows as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    /// Share of the rows read over the last second or so that were rejected
+    pub fn current_error_rate(&self) -> f64 {
+        match (self.samples.front(), self.samples.back()) {
+            (Some(&(_, rows_from, rejected_from)), Some(&(_, rows_to, rejected_to)))
+                if rows_to > rows_from =>
+            {
+                (rejected_to - rejected_from) as f64 / (rows_to - rows_from) as f64
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Share of all rows read that were rejected
+    pub fn error_rate(&self) -> f64 {
+        if self.stats.rows > 0 {
+            self.stats.rejected as f64 / self.stats.rows as f64
+        } else {
+            0.0
+        }
+    }
+
+    /// The accounts holding the most funds, most first, as `(client, held, total,
+    /// locked)`; accounts holding nothing are left out
+    pub fn top_held(&self) -> &[(u16, Fixed4, Fixed4, bool)] {
+        &self.top_held
+    }
+
+    /// The latest chargebacks, latest first
+    pub fn recent_chargebacks(&self) -> Vec<Chargeback> {
+        self.chargebacks.iter().copied().collect()
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.refreshed_at.map_or(Duration::ZERO, |at| {
+            at.saturating_duration_since(self.started)
+        })
+    }
+
+    /// Draw the dashboard over the whole of `frame`
+    pub fn render(&self, frame: &mut Frame) {
+        let [totals, chart, lists, help] = Layout::vertical([
+            Constraint::Length(6),
+            Constraint::Length(8),
+            Constraint::Min(6),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [accounts, chargebacks] =
+            Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+                .areas(lists);
+
+        let stats = &self.stats;
+        let errors = if self.current_error_rate() > 0.0 {
+            Style::new().fg(Color::Red)
+        } else {
+            Style::new()
+        };
+        let lines = vec![
+            Line::from(format!(
+                "rows {}   applied {}   rejected {} ({} malformed)   warnings {}",
+                stats.rows, stats.applied, stats.rejected, stats.malformed, stats.warnings
+            )),
+            Line::from(format!(
+                "throughput {:.0} rows/s now, {:.0} rows/s average, over {:.1}s",
+                self.current_throughput(),
+                self.average_throughput(),
+                self.elapsed().as_secs_f64()
+            )),
+            Line::styled(
+                format!(
+                    "error rate {:.2}% now, {:.2}% overall",
+                    self.current_error_rate() * 100.0,
+                    self.error_rate() * 100.0
+                ),
+                errors,
+            ),
+            Line::from(format!(
+                "unmatched {}   deferred {}   redelivered {}",
+                stats.unmatched, stats.deferred, stats.redelivered
+            )),
+        ];
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title("Run")),
+            totals,
+        );
+
+        // Newest readings on the right, as many as fit
+        let width = usize::from(chart.width.saturating_sub(2));
+        let readings: Vec<u64> = self
+            .throughput
+            .iter()
+            .skip(self.throughput.len().saturating_sub(width))
+            .copied()
+            .collect();
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title("Throughput (rows/s)"))
+                .data(&readings)
+                .style(Style::new().fg(Color::Cyan)),
+            chart,
+        );
+
+        let rows = self.top_held.iter().map(|(client, held, total, locked)| {
+            let row = Row::new([
+                client.to_string(),
+                held.to_string(),
+                total.to_string(),
+                if *locked { "locked" } else { "" }.to_string(),
+            ]);
+            if *locked { row.red() } else { row }
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(8),
+                Constraint::Length(24),
+                Constraint::Length(24),
+                Constraint::Length(8),
+            ],
+        )
+        .header(Row::new(["client", "held", "total", ""]).bold())
+        .block(Block::bordered().title("Top accounts by held funds"));
+        frame.render_widget(table, accounts);
+
+        let items = self.chargebacks.iter().map(|chargeback| {
+            format!(
+                "client {} tx {} at row {}",
+                chargeback.client, chargeback.tx, chargeback.row
+            )
+        });
+        frame.render_widget(
+            List::new(items).block(Block::bordered().title("Recent chargebacks")),
+            chargebacks,
+        );
+
+        frame.render_widget(Line::from("q: stop the run").dim(), help);
+    }
+}
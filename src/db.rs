@@ -5,16 +5,31 @@
 //! - Account management with transaction history  
 //! - Database for multi-client account management
 
+use crate::custom::CustomTransactions;
+use crate::engine::Snapshot;
+use crate::event_log::Event;
 use crate::fixed4::Fixed4;
-use std::collections::HashMap;
+use crate::journal::{self, Journal};
+use crate::policy::{LimitAction, Policy};
+use crate::report::{SegmentSummary, SummaryQuery};
+use crate::source::SourceOffset;
+use crate::storage::{self, MemoryStorage, Storage};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
 use thiserror::Error;
 
 // =============================================================================
 // ERROR TYPES
 // =============================================================================
 
+/// Why the engine rejected a transaction or operation
+///
+/// Variants fall into the broad categories of [`ErrorClass`], returned by
+/// [`class`](Self::class), so handlers can match on the category rather than on every
+/// variant.
 #[derive(Debug, Error)]
-pub enum MyError {
+pub enum EngineError {
     /// Attempted withdrawal or operation when insufficient funds are available
     #[error("Insufficient funds")]
     InsufficientFunds,
@@ -30,7 +45,8 @@ pub enum MyError {
     /// Attempted operation on a transaction that has been charged back
     #[error("Transaction already charged back")]
     TransactionAlreadyChargedBack,
-    /// Attempted to dispute a withdrawal transaction (only deposits can be disputed)
+    /// Attempted to dispute a withdrawal transaction (only deposits can be disputed,
+    /// unless [`Policy::withdrawal_disputes`] allows withdrawals too)
     #[error("Withdrawal transaction cannot be disputed")]
     TransactionIsWithdrawal,
     /// Attempted to resolve or chargeback a transaction that is not disputed
@@ -42,6 +58,504 @@ pub enum MyError {
     /// Attempted deposit or withdrawal with non-positive amount
     #[error("Amount must be positive")]
     AmountMustBePositive,
+    /// Deposit or withdrawal record without an amount
+    #[error("Amount is required")]
+    MissingAmount,
+    /// Record type is not one of the supported transaction types
+    #[error("Unknown transaction type: {0}")]
+    UnknownTransactionType(String),
+    /// Attempted deposit or withdrawal on a frozen account, or withdrawal or refund on
+    /// one frozen for outgoing funds
+    #[error("Account is frozen")]
+    AccountFrozen,
+    /// Attempted deposit or withdrawal on a closed account
+    #[error("Account is closed")]
+    AccountClosed,
+    /// Referenced client has no account
+    #[error("Account not found")]
+    AccountNotFound,
+    /// Requested account state change is not allowed from the current state
+    #[error("Cannot change account state from {from} to {to}")]
+    InvalidStateTransition {
+        /// State the account is currently in
+        from: AccountState,
+        /// State that was requested
+        to: AccountState,
+    },
+    /// Attempted to close an account that still holds funds
+    #[error("Account has non-zero balances")]
+    AccountNotSettled,
+    /// Attempted to dispute a transaction whose chargeback was already reversed
+    #[error("Transaction already represented")]
+    TransactionAlreadyRepresented,
+    /// Attempted to reverse a chargeback on a transaction that is not charged back
+    #[error("Transaction is not charged back")]
+    TransactionNotChargedBack,
+    /// Attempted a lock-specific operation on an account that is not locked
+    #[error("Account is not locked")]
+    AccountNotLocked,
+    /// Attempted to merge an account into itself
+    #[error("Cannot merge an account into itself")]
+    SameAccount,
+    /// A transaction ID to be assigned is already used by the account
+    #[error("Transaction ID already in use")]
+    TransactionIdInUse,
+    /// Attempted to create an account for a client that already has one
+    #[error("Account already exists")]
+    AccountAlreadyExists,
+    /// The operation needs approval; it was staged rather than applied
+    #[error("Operation {id} is pending approval")]
+    PendingApproval {
+        /// ID to pass to [`Database::approve`] or [`Database::reject`]
+        id: u64,
+    },
+    /// No operation is pending approval under the given ID
+    #[error("Approval not found")]
+    ApprovalNotFound,
+    /// Attempted to dispute a refund (only deposits can be disputed)
+    #[error("Refund transaction cannot be disputed")]
+    TransactionIsRefund,
+    /// Attempted to refund more of a deposit than is left unrefunded
+    #[error("Refund exceeds the unrefunded amount of the deposit")]
+    RefundExceedsDeposit,
+    /// Refund record without the transaction ID of the deposit it refunds
+    #[error("Refund needs the original transaction ID")]
+    MissingOriginalTx,
+    /// Attempted a refund or chargeback that would take back more of a deposit, in
+    /// refunds and chargebacks together, than was deposited
+    #[error("Refunds and chargebacks would exceed the deposit")]
+    ReversalExceedsDeposit,
+    /// Reading or writing state failed, e.g. when flushing [`Storage`]
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    /// Applying the transaction would take a balance, or the total, beyond what a
+    /// [`Fixed4`] can hold
+    #[error("Balance would overflow")]
+    BalanceOverflow,
+}
+
+/// The former name of [`EngineError`]
+#[deprecated(note = "renamed to EngineError")]
+pub type MyError = EngineError;
+
+impl EngineError {
+    /// Stable code identifying this kind of error
+    ///
+    /// Codes never change meaning once assigned, so downstream systems can key off
+    /// them instead of the English message text.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{ErrorCode, EngineError};
+    /// let code = EngineError::InsufficientFunds.code();
+    /// assert_eq!(code, ErrorCode::INSUFFICIENT_FUNDS);
+    /// assert_eq!(code.to_string(), "E1001 INSUFFICIENT_FUNDS");
+    /// ```
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::InsufficientFunds => ErrorCode::INSUFFICIENT_FUNDS,
+            Self::AccountLocked => ErrorCode::ACCOUNT_LOCKED,
+            Self::TransactionNotFound => ErrorCode::TRANSACTION_NOT_FOUND,
+            Self::TransactionAlreadyDisputed => ErrorCode::TRANSACTION_ALREADY_DISPUTED,
+            Self::TransactionAlreadyChargedBack => ErrorCode::TRANSACTION_ALREADY_CHARGED_BACK,
+            Self::TransactionIsWithdrawal => ErrorCode::TRANSACTION_IS_WITHDRAWAL,
+            Self::TransactionNotDisputed => ErrorCode::TRANSACTION_NOT_DISPUTED,
+            Self::InvalidAmountFormat(_) => ErrorCode::INVALID_AMOUNT_FORMAT,
+            Self::AmountMustBePositive => ErrorCode::AMOUNT_MUST_BE_POSITIVE,
+            Self::MissingAmount => ErrorCode::MISSING_AMOUNT,
+            Self::UnknownTransactionType(_) => ErrorCode::UNKNOWN_TRANSACTION_TYPE,
+            Self::AccountFrozen => ErrorCode::ACCOUNT_FROZEN,
+            Self::AccountClosed => ErrorCode::ACCOUNT_CLOSED,
+            Self::AccountNotFound => ErrorCode::ACCOUNT_NOT_FOUND,
+            Self::InvalidStateTransition { .. } => ErrorCode::INVALID_STATE_TRANSITION,
+            Self::AccountNotSettled => ErrorCode::ACCOUNT_NOT_SETTLED,
+            Self::TransactionAlreadyRepresented => ErrorCode::TRANSACTION_ALREADY_REPRESENTED,
+            Self::TransactionNotChargedBack => ErrorCode::TRANSACTION_NOT_CHARGED_BACK,
+            Self::AccountNotLocked => ErrorCode::ACCOUNT_NOT_LOCKED,
+            Self::SameAccount => ErrorCode::SAME_ACCOUNT,
+            Self::TransactionIdInUse => ErrorCode::TRANSACTION_ID_IN_USE,
+            Self::AccountAlreadyExists => ErrorCode::ACCOUNT_ALREADY_EXISTS,
+            Self::PendingApproval { .. } => ErrorCode::PENDING_APPROVAL,
+            Self::ApprovalNotFound => ErrorCode::APPROVAL_NOT_FOUND,
+            Self::TransactionIsRefund => ErrorCode::TRANSACTION_IS_REFUND,
+            Self::RefundExceedsDeposit => ErrorCode::REFUND_EXCEEDS_DEPOSIT,
+            Self::MissingOriginalTx => ErrorCode::MISSING_ORIGINAL_TX,
+            Self::ReversalExceedsDeposit => ErrorCode::REVERSAL_EXCEEDS_DEPOSIT,
+            Self::Io(_) => ErrorCode::IO,
+            Self::BalanceOverflow => ErrorCode::BALANCE_OVERFLOW,
+        }
+    }
+
+    /// Broad class of this error
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{ErrorClass, EngineError};
+    /// assert_eq!(EngineError::AmountMustBePositive.class(), ErrorClass::InputError);
+    /// assert_eq!(EngineError::InsufficientFunds.class(), ErrorClass::BusinessRule);
+    /// assert_eq!(EngineError::AccountLocked.class(), ErrorClass::State);
+    /// ```
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Self::InvalidAmountFormat(_)
+            | Self::AmountMustBePositive
+            | Self::MissingAmount
+            | Self::UnknownTransactionType(_)
+            | Self::MissingOriginalTx => ErrorClass::InputError,
+            Self::InsufficientFunds
+            | Self::TransactionIsWithdrawal
+            | Self::TransactionIsRefund
+            | Self::RefundExceedsDeposit
+            | Self::ReversalExceedsDeposit
+            | Self::BalanceOverflow
+            | Self::AccountNotSettled
+            | Self::SameAccount
+            | Self::PendingApproval { .. } => ErrorClass::BusinessRule,
+            Self::AccountLocked
+            | Self::TransactionNotFound
+            | Self::TransactionAlreadyDisputed
+            | Self::TransactionAlreadyChargedBack
+            | Self::TransactionNotDisputed
+            | Self::AccountFrozen
+            | Self::AccountClosed
+            | Self::AccountNotFound
+            | Self::InvalidStateTransition { .. }
+            | Self::TransactionAlreadyRepresented
+            | Self::TransactionNotChargedBack
+            | Self::AccountNotLocked
+            | Self::TransactionIdInUse
+            | Self::AccountAlreadyExists
+            | Self::ApprovalNotFound => ErrorClass::State,
+            Self::Io(_) => ErrorClass::SystemError,
+        }
+    }
+
+    /// Whether resubmitting the same transaction later could succeed
+    ///
+    /// System errors are always retryable. A dispute, resolve or chargeback that references
+    /// an unknown transaction is also retryable, since feeds can deliver the referenced
+    /// deposit late. Everything else is terminal and should be dead-lettered.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::EngineError;
+    /// assert!(EngineError::TransactionNotFound.is_retryable());
+    /// assert!(!EngineError::InsufficientFunds.is_retryable());
+    /// assert!(!EngineError::MissingAmount.is_retryable());
+    /// ```
+    pub fn is_retryable(&self) -> bool {
+        match self.class() {
+            ErrorClass::SystemError => true,
+            ErrorClass::State => matches!(self, Self::TransactionNotFound),
+            ErrorClass::InputError | ErrorClass::BusinessRule => false,
+        }
+    }
+}
+
+/// Broad classification of errors, for deciding how an integration should react
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+    /// The transaction itself is malformed; it can never succeed as submitted
+    InputError,
+    /// The transaction is well formed but a business rule forbids it, e.g. insufficient
+    /// funds
+    BusinessRule,
+    /// The account or referenced transaction is in the wrong state, e.g. locked or not
+    /// disputed
+    State,
+    /// The engine or its environment failed; the transaction was not judged at all
+    SystemError,
+}
+
+/// Stable numeric and symbolic identifier for an error kind
+///
+/// Displayed as `E<number> <NAME>`, e.g. `E1001 INSUFFICIENT_FUNDS`.
+/// New kinds are only ever appended; existing codes keep their number and name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ErrorCode {
+    /// Numeric code, unique across all error kinds
+    pub number: u16,
+    /// Upper snake case name, unique across all error kinds
+    pub name: &'static str,
+}
+
+impl ErrorCode {
+    pub const INSUFFICIENT_FUNDS: Self = Self::new(1001, "INSUFFICIENT_FUNDS");
+    pub const ACCOUNT_LOCKED: Self = Self::new(1002, "ACCOUNT_LOCKED");
+    pub const TRANSACTION_NOT_FOUND: Self = Self::new(1003, "TRANSACTION_NOT_FOUND");
+    pub const TRANSACTION_ALREADY_DISPUTED: Self = Self::new(1004, "TRANSACTION_ALREADY_DISPUTED");
+    pub const TRANSACTION_ALREADY_CHARGED_BACK: Self =
+        Self::new(1005, "TRANSACTION_ALREADY_CHARGED_BACK");
+    pub const TRANSACTION_IS_WITHDRAWAL: Self = Self::new(1006, "TRANSACTION_IS_WITHDRAWAL");
+    pub const TRANSACTION_NOT_DISPUTED: Self = Self::new(1007, "TRANSACTION_NOT_DISPUTED");
+    pub const INVALID_AMOUNT_FORMAT: Self = Self::new(1008, "INVALID_AMOUNT_FORMAT");
+    pub const AMOUNT_MUST_BE_POSITIVE: Self = Self::new(1009, "AMOUNT_MUST_BE_POSITIVE");
+    pub const MISSING_AMOUNT: Self = Self::new(1010, "MISSING_AMOUNT");
+    pub const UNKNOWN_TRANSACTION_TYPE: Self = Self::new(1011, "UNKNOWN_TRANSACTION_TYPE");
+    pub const ACCOUNT_FROZEN: Self = Self::new(1012, "ACCOUNT_FROZEN");
+    pub const ACCOUNT_CLOSED: Self = Self::new(1013, "ACCOUNT_CLOSED");
+    pub const ACCOUNT_NOT_FOUND: Self = Self::new(1014, "ACCOUNT_NOT_FOUND");
+    pub const INVALID_STATE_TRANSITION: Self = Self::new(1015, "INVALID_STATE_TRANSITION");
+    pub const ACCOUNT_NOT_SETTLED: Self = Self::new(1016, "ACCOUNT_NOT_SETTLED");
+    pub const TRANSACTION_ALREADY_REPRESENTED: Self =
+        Self::new(1017, "TRANSACTION_ALREADY_REPRESENTED");
+    pub const TRANSACTION_NOT_CHARGED_BACK: Self = Self::new(1018, "TRANSACTION_NOT_CHARGED_BACK");
+    pub const ACCOUNT_NOT_LOCKED: Self = Self::new(1019, "ACCOUNT_NOT_LOCKED");
+    pub const SAME_ACCOUNT: Self = Self::new(1020, "SAME_ACCOUNT");
+    pub const TRANSACTION_ID_IN_USE: Self = Self::new(1021, "TRANSACTION_ID_IN_USE");
+    pub const ACCOUNT_ALREADY_EXISTS: Self = Self::new(1022, "ACCOUNT_ALREADY_EXISTS");
+    pub const PENDING_APPROVAL: Self = Self::new(1023, "PENDING_APPROVAL");
+    pub const APPROVAL_NOT_FOUND: Self = Self::new(1024, "APPROVAL_NOT_FOUND");
+    pub const TRANSACTION_IS_REFUND: Self = Self::new(1025, "TRANSACTION_IS_REFUND");
+    pub const REFUND_EXCEEDS_DEPOSIT: Self = Self::new(1026, "REFUND_EXCEEDS_DEPOSIT");
+    pub const MISSING_ORIGINAL_TX: Self = Self::new(1027, "MISSING_ORIGINAL_TX");
+    pub const REVERSAL_EXCEEDS_DEPOSIT: Self = Self::new(1028, "REVERSAL_EXCEEDS_DEPOSIT");
+    pub const IO: Self = Self::new(1029, "IO");
+    pub const BALANCE_OVERFLOW: Self = Self::new(1030, "BALANCE_OVERFLOW");
+
+    /// Every assigned error code, in numeric order
+    ///
+    /// ```
+    /// # use transaction_processor::ErrorCode;
+    /// let mut numbers: Vec<_> = ErrorCode::ALL.iter().map(|code| code.number).collect();
+    /// numbers.dedup();
+    /// assert_eq!(numbers.len(), ErrorCode::ALL.len());
+    /// ```
+    pub const ALL: &'static [Self] = &[
+        Self::INSUFFICIENT_FUNDS,
+        Self::ACCOUNT_LOCKED,
+        Self::TRANSACTION_NOT_FOUND,
+        Self::TRANSACTION_ALREADY_DISPUTED,
+        Self::TRANSACTION_ALREADY_CHARGED_BACK,
+        Self::TRANSACTION_IS_WITHDRAWAL,
+        Self::TRANSACTION_NOT_DISPUTED,
+        Self::INVALID_AMOUNT_FORMAT,
+        Self::AMOUNT_MUST_BE_POSITIVE,
+        Self::MISSING_AMOUNT,
+        Self::UNKNOWN_TRANSACTION_TYPE,
+        Self::ACCOUNT_FROZEN,
+        Self::ACCOUNT_CLOSED,
+        Self::ACCOUNT_NOT_FOUND,
+        Self::INVALID_STATE_TRANSITION,
+        Self::ACCOUNT_NOT_SETTLED,
+        Self::TRANSACTION_ALREADY_REPRESENTED,
+        Self::TRANSACTION_NOT_CHARGED_BACK,
+        Self::ACCOUNT_NOT_LOCKED,
+        Self::SAME_ACCOUNT,
+        Self::TRANSACTION_ID_IN_USE,
+        Self::ACCOUNT_ALREADY_EXISTS,
+        Self::PENDING_APPROVAL,
+        Self::APPROVAL_NOT_FOUND,
+        Self::TRANSACTION_IS_REFUND,
+        Self::REFUND_EXCEEDS_DEPOSIT,
+        Self::MISSING_ORIGINAL_TX,
+        Self::REVERSAL_EXCEEDS_DEPOSIT,
+        Self::IO,
+        Self::BALANCE_OVERFLOW,
+    ];
+
+    const fn new(number: u16, name: &'static str) -> Self {
+        Self { number, name }
+    }
+
+    /// Look up a code by its symbolic name
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::ErrorCode;
+    /// assert_eq!(ErrorCode::from_name("ACCOUNT_LOCKED"), Some(ErrorCode::ACCOUNT_LOCKED));
+    /// assert_eq!(ErrorCode::from_name("NOPE"), None);
+    /// ```
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|code| code.name == name)
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "E{} {}", self.number, self.name)
+    }
+}
+
+/// An engine error together with the transaction it was raised for
+///
+/// Returned by [`Database::process_transaction`] so callers can tell which client
+/// and transaction a failure belongs to without re-stitching that context into strings.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Database, EngineError, Transaction};
+/// let mut db = Database::new();
+/// let withdrawal = Transaction::withdrawal("10.00").unwrap();
+///
+/// let err = db.process_transaction(7, 42, withdrawal).unwrap_err();
+/// assert_eq!(err.client, 7);
+/// assert_eq!(err.tx, 42);
+/// assert!(matches!(err.kind, EngineError::InsufficientFunds));
+/// assert_eq!(err.to_string(), "Insufficient funds (client 7, tx 42, amount 10.0000)");
+/// ```
+#[derive(Debug, Error)]
+pub struct TransactionError {
+    /// Client the transaction was submitted for
+    pub client: u16,
+    /// Transaction ID that was being processed (or referenced, for disputes)
+    pub tx: u32,
+    /// Amount involved, if known (the referenced deposit's amount for disputes)
+    pub amount: Option<Fixed4>,
+    /// The underlying reason the transaction was rejected
+    pub kind: EngineError,
+    /// Why and when the account was locked, for [`EngineError::AccountLocked`]
+    pub lock: Option<LockDetails>,
+}
+
+impl TransactionError {
+    /// Stable code of the underlying error kind
+    pub fn code(&self) -> ErrorCode {
+        self.kind.code()
+    }
+
+    /// Broad class of the underlying error kind
+    pub fn class(&self) -> ErrorClass {
+        self.kind.class()
+    }
+
+    /// Whether resubmitting this transaction later could succeed (see [`EngineError::is_retryable`])
+    pub fn is_retryable(&self) -> bool {
+        self.kind.is_retryable()
+    }
+}
+
+/// The underlying error kind, so handlers can propagate rejected postings with `?`
+impl From<TransactionError> for EngineError {
+    fn from(error: TransactionError) -> Self {
+        error.kind
+    }
+}
+
+impl std::fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (client {}, tx {}", self.kind, self.client, self.tx)?;
+        if let Some(amount) = self.amount {
+            write!(f, ", amount {}", amount)?;
+        }
+        if let Some(lock) = &self.lock {
+            write!(f, "; {}", lock)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// A transaction ID found in both of two databases being merged
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("transaction {tx} is in both databases (client {left} and client {right})")]
+pub struct MergeConflict {
+    /// The transaction ID
+    pub tx: u32,
+    /// Client it belongs to in the database merged into
+    pub left: u16,
+    /// Client it belongs to in the database merged from
+    pub right: u16,
+}
+
+/// Why [`Database::merge`] failed
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum MergeError {
+    /// A transaction ID is in both databases, under [`ConflictPolicy::Error`]
+    #[error(transparent)]
+    Conflict(#[from] MergeConflict),
+    /// A client's combined balances would not fit in a [`Fixed4`]
+    #[error("client {client}'s combined balances would overflow")]
+    BalanceOverflow {
+        /// The client in both databases
+        client: u16,
+    },
+}
+
+/// How [`Database::merge`] settles a transaction ID found in both databases
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Fail the merge
+    #[default]
+    Error,
+    /// Keep the entry in the database merged into
+    PreferLeft,
+    /// Keep the entry in the database merged from
+    PreferRight,
+}
+
+/// Why an account was locked
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LockReason {
+    /// A chargeback of the given transaction
+    Chargeback {
+        /// The charged-back deposit
+        tx: u32,
+    },
+    /// Locked through [`Database::set_account_state`]
+    Manual,
+    /// Chargebacks reached a [`ChargebackLimit`](crate::ChargebackLimit) of the policy
+    ChargebackLimit {
+        /// Chargebacks counted towards the limit
+        chargebacks: u32,
+        /// Amount they charged back
+        volume: Fixed4,
+    },
+}
+
+/// Why and when an account was locked
+///
+/// Attached to [`TransactionError`]s for transactions a locked account rejected, so
+/// support staff can see why the account is blocked without digging through its ledger.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Database, LockReason, Transaction};
+/// let mut db = Database::new();
+/// db.process_transaction_at(1, 1, Transaction::deposit("10").unwrap(), Some(1_700_000_000))
+///     .unwrap();
+/// db.process_transaction(1, 1, Transaction::dispute()).unwrap();
+/// db.process_transaction_at(1, 1, Transaction::chargeback(), Some(1_700_000_600))
+///     .unwrap();
+///
+/// let err = db
+///     .process_transaction(1, 2, Transaction::deposit("5").unwrap())
+///     .unwrap_err();
+/// let lock = err.lock.as_ref().unwrap();
+/// assert_eq!(lock.reason, LockReason::Chargeback { tx: 1 });
+/// assert_eq!(lock.locked_at, Some(1_700_000_600));
+/// assert_eq!(
+///     err.to_string(),
+///     "Account is locked (client 1, tx 2, amount 5.0000; \
+///      locked at 1700000600 by chargeback of tx 1)"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockDetails {
+    /// What locked the account
+    pub reason: LockReason,
+    /// When it was locked, if the locking transaction carried a timestamp
+    pub locked_at: Option<Timestamp>,
+}
+
+impl std::fmt::Display for LockDetails {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "locked")?;
+        if let Some(locked_at) = self.locked_at {
+            write!(f, " at {}", locked_at)?;
+        }
+        match self.reason {
+            LockReason::Chargeback { tx } => write!(f, " by chargeback of tx {}", tx),
+            LockReason::Manual => write!(f, " manually"),
+            LockReason::ChargebackLimit {
+                chargebacks,
+                volume,
+            } => write!(
+                f,
+                " by chargeback limit ({} chargebacks totalling {})",
+                chargebacks, volume
+            ),
+        }
+    }
 }
 
 // =============================================================================
@@ -53,10 +567,13 @@ pub enum MyError {
 /// Represents the different types of financial transactions that can be processed:
 /// - Basic operations: deposits and withdrawals
 /// - Dispute resolution: dispute, resolve, and chargeback flows
+/// - Representment: reversing a chargeback the merchant has won
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Transaction {
     /// Add funds to an account
     Deposit {
-        /// Amount to deposit (must be positive)
+        /// Amount to deposit (must be positive, unless negative deposits are refunds
+        /// under [`Policy::negative_deposits_as_refunds`])
         amount: Fixed4,
     },
     /// Remove funds from an account (requires sufficient available balance)
@@ -64,12 +581,31 @@ pub enum Transaction {
         /// Amount to withdraw (must be positive and ≤ available balance)
         amount: Fixed4,
     },
+    /// Return part or all of a previous deposit to the payer (requires sufficient
+    /// available balance)
+    Refund {
+        /// Transaction ID of the deposit refunded
+        original_tx: u32,
+        /// Amount to refund (must be positive, and no more than what is left unrefunded
+        /// of the deposit)
+        amount: Fixed4,
+    },
     /// Dispute a previous deposit transaction (moves funds from available to held)
     Dispute,
     /// Resolve a disputed transaction (moves funds back from held to available)
     Resolve,
     /// Chargeback a disputed transaction (removes funds and locks account)
     Chargeback,
+    /// Reverse a chargeback after successful representment (re-credits the funds)
+    ChargebackReversal,
+    /// A type registered as a [`CustomTransaction`](crate::CustomTransaction), applied
+    /// by its handler
+    Custom {
+        /// Registered type name, lower-case
+        name: String,
+        /// Amount given on the row, if any
+        amount: Option<Fixed4>,
+    },
 }
 
 impl Transaction {
@@ -83,19 +619,19 @@ impl Transaction {
     /// # use transaction_processor::Transaction;
     /// let deposit = Transaction::deposit("123.45").unwrap();
     /// let small_deposit = Transaction::deposit("0.0001").unwrap();
-    /// 
+    ///
     /// // Zero and negative amounts are rejected
     /// assert!(Transaction::deposit("0").is_err());
     /// assert!(Transaction::deposit("-10.50").is_err());
     /// ```
     ///
     /// # Errors
-    /// Returns [`MyError::InvalidAmountFormat`] if the string cannot be parsed
-    /// Returns [`MyError::AmountMustBePositive`] if the amount is zero or negative
-    pub fn deposit(amount: &str) -> Result<Self, MyError> {
-        let amount: Fixed4 = amount.parse().map_err(MyError::InvalidAmountFormat)?;
+    /// Returns [`EngineError::InvalidAmountFormat`] if the string cannot be parsed
+    /// Returns [`EngineError::AmountMustBePositive`] if the amount is zero or negative
+    pub fn deposit(amount: &str) -> Result<Self, EngineError> {
+        let amount: Fixed4 = amount.parse().map_err(EngineError::InvalidAmountFormat)?;
         if amount <= Fixed4::zero() {
-            return Err(MyError::AmountMustBePositive);
+            return Err(EngineError::AmountMustBePositive);
         }
         Ok(Self::Deposit { amount })
     }
@@ -109,23 +645,67 @@ impl Transaction {
     /// ```
     /// # use transaction_processor::Transaction;
     /// let withdrawal = Transaction::withdrawal("50.00").unwrap();
-    /// 
+    ///
     /// // Zero and negative amounts are rejected
     /// assert!(Transaction::withdrawal("0").is_err());
     /// assert!(Transaction::withdrawal("-5.00").is_err());
     /// ```
     ///
     /// # Errors
-    /// Returns [`MyError::InvalidAmountFormat`] if the string cannot be parsed
-    /// Returns [`MyError::AmountMustBePositive`] if the amount is zero or negative
-    pub fn withdrawal(amount: &str) -> Result<Self, MyError> {
-        let amount: Fixed4 = amount.parse().map_err(MyError::InvalidAmountFormat)?;
+    /// Returns [`EngineError::InvalidAmountFormat`] if the string cannot be parsed
+    /// Returns [`EngineError::AmountMustBePositive`] if the amount is zero or negative
+    pub fn withdrawal(amount: &str) -> Result<Self, EngineError> {
+        let amount: Fixed4 = amount.parse().map_err(EngineError::InvalidAmountFormat)?;
         if amount <= Fixed4::zero() {
-            return Err(MyError::AmountMustBePositive);
+            return Err(EngineError::AmountMustBePositive);
         }
         Ok(Self::Withdrawal { amount })
     }
 
+    /// Create a refund of deposit `original_tx` from a string amount
+    ///
+    /// Refunds debit available funds. Together, the refunds of a deposit can't add up
+    /// to more than the deposit itself, and a deposit that is disputed or was charged
+    /// back can't be refunded. Disputing a deposit that was partly refunded holds only
+    /// what is left unrefunded, so a chargeback never takes back more than was deposited.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Database, EngineError, Transaction};
+    /// let mut db = Database::new();
+    /// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+    ///
+    /// db.process_transaction(1, 2, Transaction::refund(1, "60").unwrap()).unwrap();
+    /// let too_much = db.process_transaction(1, 3, Transaction::refund(1, "50").unwrap());
+    /// assert!(matches!(too_much.unwrap_err().kind, EngineError::RefundExceedsDeposit));
+    ///
+    /// let account = db.get_account(1).unwrap();
+    /// assert_eq!(account.available().to_string(), "40.0000");
+    /// assert_eq!(account.refunded(1).to_string(), "60.0000");
+    /// assert!(Transaction::refund(1, "0").is_err());
+    ///
+    /// // Only the 40 left unrefunded can be disputed and charged back
+    /// db.process_transaction(1, 1, Transaction::dispute()).unwrap();
+    /// db.process_transaction(1, 1, Transaction::chargeback()).unwrap();
+    /// let account = db.get_account(1).unwrap();
+    /// assert_eq!(account.available().to_string(), "0.0000");
+    /// assert_eq!(account.total().to_string(), "0.0000");
+    /// ```
+    ///
+    /// # Errors
+    /// Returns [`EngineError::InvalidAmountFormat`] if the string cannot be parsed
+    /// Returns [`EngineError::AmountMustBePositive`] if the amount is zero or negative
+    pub fn refund(original_tx: u32, amount: &str) -> Result<Self, EngineError> {
+        let amount: Fixed4 = amount.parse().map_err(EngineError::InvalidAmountFormat)?;
+        if amount <= Fixed4::zero() {
+            return Err(EngineError::AmountMustBePositive);
+        }
+        Ok(Self::Refund {
+            original_tx,
+            amount,
+        })
+    }
+
     /// Create a dispute transaction
     ///
     /// Disputes move funds from available to held status for the referenced transaction.
@@ -149,22 +729,142 @@ impl Transaction {
     pub fn chargeback() -> Self {
         Self::Chargeback
     }
+
+    /// Create a chargeback reversal transaction
+    ///
+    /// Reversals re-credit the funds of a charged back deposit to available and mark it
+    /// as represented. Whether the account is unlocked is decided by the
+    /// [`Policy`](crate::Policy). Can only be applied to charged back transactions.
+    pub fn chargeback_reversal() -> Self {
+        Self::ChargebackReversal
+    }
+
+    /// Type name, as written in the `type` column of CSV input
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Deposit { .. } => "deposit",
+            Self::Withdrawal { .. } => "withdrawal",
+            Self::Refund { .. } => "refund",
+            Self::Dispute => "dispute",
+            Self::Resolve => "resolve",
+            Self::Chargeback => "chargeback",
+            Self::ChargebackReversal => "chargeback_reversal",
+            Self::Custom { name, .. } => name,
+        }
+    }
+
+    /// The dispute-lifecycle action this transaction performs, if any
+    pub fn dispute_action(&self) -> Option<DisputeAction> {
+        match self {
+            Self::Deposit { .. }
+            | Self::Withdrawal { .. }
+            | Self::Refund { .. }
+            | Self::Custom { .. } => None,
+            Self::Dispute => Some(DisputeAction::Dispute),
+            Self::Resolve => Some(DisputeAction::Resolve),
+            Self::Chargeback => Some(DisputeAction::Chargeback),
+            Self::ChargebackReversal => Some(DisputeAction::ChargebackReversal),
+        }
+    }
+
+    /// What the transaction does, as shown in an account's balance history
+    pub(crate) fn activity(&self) -> Activity {
+        match self {
+            Self::Deposit { amount } if *amount < Fixed4::zero() => Activity::Refund,
+            Self::Deposit { .. } => Activity::Deposit,
+            Self::Withdrawal { .. } => Activity::Withdrawal,
+            Self::Refund { .. } => Activity::Refund,
+            Self::Dispute => Activity::Dispute,
+            Self::Resolve => Activity::Resolve,
+            Self::Chargeback => Activity::Chargeback,
+            Self::ChargebackReversal => Activity::ChargebackReversal,
+            Self::Custom { .. } => unreachable!("custom transactions are applied by their handler"),
+        }
+    }
+
+    /// Amount carried by the transaction itself (deposits, withdrawals, refunds, and
+    /// custom transactions given one)
+    pub fn amount(&self) -> Option<Fixed4> {
+        match self {
+            Self::Deposit { amount }
+            | Self::Withdrawal { amount }
+            | Self::Refund { amount, .. } => Some(*amount),
+            Self::Custom { amount, .. } => *amount,
+            Self::Dispute | Self::Resolve | Self::Chargeback | Self::ChargebackReversal => None,
+        }
+    }
+}
+
+/// Point in time, in seconds since the Unix epoch
+pub type Timestamp = u64;
+
+/// Optional information that accompanies a transaction
+///
+/// Passed to [`Database::process_transaction_with`]. Every field defaults to "not known".
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionMeta {
+    /// When the transaction happened
+    pub timestamp: Option<Timestamp>,
+    /// Dispute case the transaction belongs to (dispute-lifecycle transactions only)
+    pub case_id: Option<String>,
+    /// When the transaction takes effect, if it is a correction backdated to before
+    /// `timestamp`, when it was posted
+    ///
+    /// Balances are updated when the correction is posted; the effective time is
+    /// recorded so [`EventLog::state_as_of`](crate::EventLog::state_as_of) can report
+    /// balances as of either time.
+    pub effective_at: Option<Timestamp>,
+    /// Who submitted the transaction, e.g. `system`, an operator ID or an API client
+    ///
+    /// Recorded in the account's [balance history](Account::balance_history).
+    pub initiator: Option<String>,
+    /// Position in the queue or stream the transaction was delivered from, used by the
+    /// [`Engine`](crate::Engine) to skip redelivered messages
+    pub offset: Option<SourceOffset>,
 }
 
-/// Internal state tracking for deposit transactions
+/// Dispute lifecycle state of a deposit, or of a withdrawal under
+/// [`Policy::withdrawal_disputes`]
 ///
 /// Deposits can be in different states during the dispute resolution process:
 /// - Normal: Standard deposit, funds are available
 /// - Disputed: Under dispute, funds moved to held status  
-/// - ChargedBack: Permanently removed, account locked
-#[derive(Debug)]
-enum DepositState {
+/// - ChargedBack: Removed from the account, account locked
+/// - Represented: Chargeback reversed, funds available again
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DisputeState {
     /// Normal deposit state - funds are available for use
+    #[default]
     Normal,
     /// Disputed state - funds are held pending resolution
     Disputed,
-    /// Charged back state - funds permanently removed
+    /// Charged back state - funds removed from the account
     ChargedBack,
+    /// Represented state - chargeback reversed, funds available and cannot be disputed again
+    Represented,
+}
+
+impl std::fmt::Display for DisputeState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            DisputeState::Normal => "normal",
+            DisputeState::Disputed => "disputed",
+            DisputeState::ChargedBack => "charged_back",
+            DisputeState::Represented => "represented",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A recorded change of a deposit's [`DisputeState`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateTransition {
+    /// State before the transition
+    pub from: DisputeState,
+    /// State after the transition
+    pub to: DisputeState,
+    /// When the transition happened, if the triggering transaction carried a timestamp
+    pub timestamp: Option<Timestamp>,
 }
 
 /// Internal ledger entries for transaction history
@@ -172,27 +872,444 @@ enum DepositState {
 /// Each transaction is recorded in the account's ledger for audit trail and
 /// dispute resolution. The ledger maintains the original transaction amounts
 /// and states for regulatory compliance.
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 enum LedgerEntry {
     /// Deposit transaction with amount and current dispute state
     Deposit {
+        /// Position of this entry in the account's processing order
+        seq: u64,
+        /// When the deposit was made, if known
+        timestamp: Option<Timestamp>,
         /// Original deposit amount
         amount: Fixed4,
         /// Current state in dispute resolution process
-        state: DepositState,
+        state: DisputeState,
+        /// Every state change since the deposit was made, oldest first
+        transitions: Vec<StateTransition>,
+        /// Portion of the deposit still held in the rolling reserve
+        reserve: Option<Reserve>,
+        /// Client and transaction ID the deposit was originally recorded under, if it was
+        /// moved here by an account merge or split
+        origin: Option<(u16, u32)>,
     },
     /// Withdrawal transaction with amount (for audit trail)
     Withdrawal {
+        /// Position of this entry in the account's processing order
+        seq: u64,
+        /// When the withdrawal was made, if known
+        timestamp: Option<Timestamp>,
         /// Original withdrawal amount (stored for compliance)
-        #[allow(dead_code)]
+        amount: Fixed4,
+        /// Current state in dispute resolution process
+        #[serde(default)]
+        state: DisputeState,
+        /// Every state change since the withdrawal was made, oldest first
+        #[serde(default)]
+        transitions: Vec<StateTransition>,
+        /// Client and transaction ID the withdrawal was originally recorded under, if it
+        /// was moved here by an account merge or split
+        origin: Option<(u16, u32)>,
+    },
+    /// Refund, received as a refund of a deposit or as a deposit with a negative amount
+    Refund {
+        /// Position of this entry in the account's processing order
+        seq: u64,
+        /// When the refund was made, if known
+        timestamp: Option<Timestamp>,
+        /// Amount refunded, positive
+        amount: Fixed4,
+        /// Deposit refunded, if the refund named one
+        original: Option<u32>,
+        /// Client and transaction ID the refund was originally recorded under, if it was
+        /// moved here by an account merge or split
+        origin: Option<(u16, u32)>,
+    },
+}
+
+impl LedgerEntry {
+    /// Position of this entry in the account's processing order
+    fn seq(&self) -> u64 {
+        match self {
+            LedgerEntry::Deposit { seq, .. }
+            | LedgerEntry::Withdrawal { seq, .. }
+            | LedgerEntry::Refund { seq, .. } => *seq,
+        }
+    }
+
+    /// Change to the account's `(available, held)` balances that this entry accounts for
+    ///
+    /// `refunded` is how much of a deposit has been refunded: the refunds account for
+    /// that part themselves, and a dispute only holds the rest.
+    fn balance_effect(&self, refunded: Fixed4) -> (Fixed4, Fixed4) {
+        match self {
+            LedgerEntry::Deposit {
+                amount,
+                state,
+                reserve,
+                ..
+            } => {
+                let reserved = reserve.as_ref().map_or(Fixed4::zero(), |r| r.amount);
+                let mut available = Fixed4::zero();
+                let mut held = reserved;
+                available -= reserved;
+                match state {
+                    DisputeState::Normal | DisputeState::Represented => available += *amount,
+                    DisputeState::Disputed => {
+                        available += refunded;
+                        held += *amount - refunded;
+                    }
+                    DisputeState::ChargedBack => available += refunded,
+                }
+                (available, held)
+            }
+            LedgerEntry::Withdrawal { amount, state, .. } => {
+                let mut available = Fixed4::zero();
+                let mut held = Fixed4::zero();
+                match state {
+                    DisputeState::Normal | DisputeState::Represented => available -= *amount,
+                    DisputeState::Disputed => {
+                        available -= *amount;
+                        held += *amount;
+                    }
+                    DisputeState::ChargedBack => {}
+                }
+                (available, held)
+            }
+            LedgerEntry::Refund { amount, .. } => (-*amount, Fixed4::zero()),
+        }
+    }
+
+    /// The entry's public view, as transaction `tx`
+    fn record(&self, tx: u32) -> LedgerRecord {
+        match *self {
+            LedgerEntry::Deposit {
+                timestamp,
+                amount,
+                state,
+                ..
+            } => LedgerRecord {
+                tx,
+                kind: EntryKind::Deposit,
+                amount,
+                timestamp,
+                dispute_state: Some(state),
+                refund_of: None,
+            },
+            LedgerEntry::Withdrawal {
+                timestamp,
+                amount,
+                state,
+                ref transitions,
+                ..
+            } => LedgerRecord {
+                tx,
+                kind: EntryKind::Withdrawal,
+                amount,
+                timestamp,
+                dispute_state: (!transitions.is_empty()).then_some(state),
+                refund_of: None,
+            },
+            LedgerEntry::Refund {
+                timestamp,
+                amount,
+                original,
+                ..
+            } => LedgerRecord {
+                tx,
+                kind: EntryKind::Refund,
+                amount,
+                timestamp,
+                dispute_state: None,
+                refund_of: original,
+            },
+        }
+    }
+
+    /// Move the entry to a new position, recording where it came from unless it was
+    /// already moved before
+    fn rekey(&mut self, new_seq: u64, client: u16, tx: u32) {
+        match self {
+            LedgerEntry::Deposit { seq, origin, .. }
+            | LedgerEntry::Withdrawal { seq, origin, .. }
+            | LedgerEntry::Refund { seq, origin, .. } => {
+                *seq = new_seq;
+                origin.get_or_insert((client, tx));
+            }
+        }
+    }
+}
+
+/// Seconds in a day, used to convert reserve periods
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Part of a deposit held back by the rolling reserve until it matures
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Reserve {
+    /// Amount held back
+    amount: Fixed4,
+    /// When the reserve may be released
+    release_at: Timestamp,
+}
+
+/// Panic if `policy` is invalid, rather than apply rules that make no sense
+fn check_policy(policy: &Policy) {
+    if let Err(err) = policy.validate() {
+        panic!("invalid policy: {}", err);
+    }
+}
+
+/// Serialize `map` in key order, so dumps of the same state are byte for byte the same
+fn sorted<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    K: Ord + Serialize,
+    V: Serialize,
+    S: serde::Serializer,
+{
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+}
+
+/// `value` with the keys of every object in sorted order
+fn canonical(value: serde_json::Value) -> serde_json::Value {
+    use serde_json::Value;
+    match value {
+        Value::Object(map) => {
+            let sorted: BTreeMap<String, Value> =
+                map.into_iter().map(|(k, v)| (k, canonical(v))).collect();
+            Value::Object(sorted.into_iter().collect())
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(canonical).collect()),
+        other => other,
+    }
+}
+
+/// Move a deposit to a new dispute state, recording the transition
+fn transition(
+    state: &mut DisputeState,
+    transitions: &mut Vec<StateTransition>,
+    to: DisputeState,
+    timestamp: Option<Timestamp>,
+) {
+    transitions.push(StateTransition {
+        from: *state,
+        to,
+        timestamp,
+    });
+    *state = to;
+}
+
+/// Every change a transaction makes to an account, worked out before any is made
+///
+/// Transactions are applied in two steps: the delta is computed from the account as
+/// it stands, which is where every check happens, and then committed in one step that
+/// cannot fail. [`Database::simulate`] stops after the first step, so a dry run goes
+/// through exactly the same checks and arithmetic as the real thing.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Database, DisputeState, LedgerOp, LockChange, Transaction};
+/// let mut db = Database::new();
+/// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+///
+/// let delta = db.simulate(1, 1, Transaction::dispute(), None).unwrap();
+/// assert_eq!(delta.d_available.to_string(), "-100.0000");
+/// assert_eq!(delta.d_held.to_string(), "100.0000");
+/// assert_eq!(
+///     delta.ledger_op,
+///     LedgerOp::Transition { tx: 1, from: DisputeState::Normal, to: DisputeState::Disputed }
+/// );
+/// assert_eq!(delta.lock, LockChange::None);
+/// ```
+#[derive(Debug, Clone)]
+pub struct AccountDelta {
+    /// Change to available funds
+    pub d_available: Fixed4,
+    /// Change to held funds
+    pub d_held: Fixed4,
+    /// Change to the ledger
+    pub ledger_op: LedgerOp,
+    /// Change to whether the account is locked
+    pub lock: LockChange,
+    /// When the transaction happened, if known
+    timestamp: Option<Timestamp>,
+    /// The entry to add, for [`LedgerOp::Record`]
+    entry: Option<LedgerEntry>,
+}
+
+impl AccountDelta {
+    /// Record a new ledger entry
+    fn record(d_available: Fixed4, d_held: Fixed4, tx: u32, entry: LedgerEntry) -> Self {
+        let (kind, amount, timestamp) = match &entry {
+            LedgerEntry::Deposit {
+                amount, timestamp, ..
+            } => (EntryKind::Deposit, *amount, *timestamp),
+            LedgerEntry::Withdrawal {
+                amount, timestamp, ..
+            } => (EntryKind::Withdrawal, *amount, *timestamp),
+            LedgerEntry::Refund {
+                amount, timestamp, ..
+            } => (EntryKind::Refund, *amount, *timestamp),
+        };
+        Self {
+            d_available,
+            d_held,
+            ledger_op: LedgerOp::Record { tx, kind, amount },
+            lock: LockChange::None,
+            timestamp,
+            entry: Some(entry),
+        }
+    }
+
+    /// Move deposit `tx` between dispute states
+    fn transition(
+        d_available: Fixed4,
+        d_held: Fixed4,
+        tx: u32,
+        (from, to): (DisputeState, DisputeState),
+        timestamp: Option<Timestamp>,
+    ) -> Self {
+        Self {
+            d_available,
+            d_held,
+            ledger_op: LedgerOp::Transition { tx, from, to },
+            lock: LockChange::None,
+            timestamp,
+            entry: None,
+        }
+    }
+}
+
+/// How a transaction changes the ledger
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedgerOp {
+    /// Record a new deposit, withdrawal or refund as a ledger entry of its own
+    Record {
+        /// Transaction ID
+        tx: u32,
+        /// Deposit, withdrawal or refund
+        kind: EntryKind,
+        /// Amount of the transaction
         amount: Fixed4,
     },
+    /// Move a deposit, or a withdrawal if withdrawals may be disputed, to a new dispute
+    /// state
+    Transition {
+        /// Transaction ID of the deposit or withdrawal
+        tx: u32,
+        /// State before
+        from: DisputeState,
+        /// State after
+        to: DisputeState,
+    },
+}
+
+/// How a transaction changes whether the account is locked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LockChange {
+    /// The lock is left alone
+    None,
+    /// The account is locked, or its lock renewed, for this reason
+    Lock(LockReason),
+    /// The account is unlocked
+    Unlock,
 }
 
 // =============================================================================
 // ACCOUNT MANAGEMENT
 // =============================================================================
 
+/// Lifecycle state of a client account
+///
+/// Deposits and withdrawals are only accepted on [`AccountState::Active`] accounts,
+/// except that [`AccountState::FrozenOutgoing`] accounts still accept deposits.
+/// Dispute, resolve and chargeback operations are accepted in every state, since a
+/// client may still challenge earlier transactions.
+///
+/// Allowed transitions:
+/// - `Active` → `Frozen`, `FrozenOutgoing`, `Locked`, `Closed`
+/// - `Frozen` → `Active`, `FrozenOutgoing`, `Locked`
+/// - `FrozenOutgoing` → `Active`, `Frozen`, `Locked`
+/// - `Locked` → `Active`
+/// - `Closed` is final
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum AccountState {
+    /// Normal operation
+    #[default]
+    Active,
+    /// Locked after a chargeback
+    Locked,
+    /// Closed by an operator; requires zero balances
+    Closed,
+    /// Temporarily blocked by an operator (e.g. a compliance hold)
+    Frozen,
+    /// Blocked by an operator for money going out only: deposits and disputes carry
+    /// on, but withdrawals and refunds are rejected (e.g. a compliance hold that
+    /// shouldn't bounce incoming payments)
+    FrozenOutgoing,
+}
+
+impl AccountState {
+    /// Check whether the account may move from this state to `next`
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::AccountState;
+    /// assert!(AccountState::Active.can_transition_to(AccountState::Frozen));
+    /// assert!(AccountState::Frozen.can_transition_to(AccountState::Active));
+    /// assert!(AccountState::FrozenOutgoing.can_transition_to(AccountState::Frozen));
+    /// assert!(!AccountState::Closed.can_transition_to(AccountState::Active));
+    /// ```
+    pub fn can_transition_to(self, next: AccountState) -> bool {
+        use AccountState::*;
+        matches!(
+            (self, next),
+            (Active, Frozen | FrozenOutgoing | Locked | Closed)
+                | (Frozen, Active | FrozenOutgoing | Locked)
+                | (FrozenOutgoing, Active | Frozen | Locked)
+                | (Locked, Active)
+        )
+    }
+
+    /// Reject deposits and withdrawals unless the account is active, or only outgoing
+    /// funds if it is frozen for them
+    fn check_accepts_funds(self, outgoing: bool) -> Result<(), EngineError> {
+        match self {
+            AccountState::Active => Ok(()),
+            AccountState::FrozenOutgoing if !outgoing => Ok(()),
+            AccountState::Locked => Err(EngineError::AccountLocked),
+            AccountState::Frozen | AccountState::FrozenOutgoing => Err(EngineError::AccountFrozen),
+            AccountState::Closed => Err(EngineError::AccountClosed),
+        }
+    }
+}
+
+impl std::fmt::Display for AccountState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            AccountState::Active => "active",
+            AccountState::Locked => "locked",
+            AccountState::Closed => "closed",
+            AccountState::Frozen => "frozen",
+            AccountState::FrozenOutgoing => "frozen_outgoing",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::str::FromStr for AccountState {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "active" => Ok(AccountState::Active),
+            "locked" => Ok(AccountState::Locked),
+            "closed" => Ok(AccountState::Closed),
+            "frozen" => Ok(AccountState::Frozen),
+            "frozen_outgoing" => Ok(AccountState::FrozenOutgoing),
+            other => Err(format!("Unknown account state: {}", other)),
+        }
+    }
+}
+
 /// Represents a client's account with financial transaction history
 ///
 /// Uses HashMap for O(1) transaction lookups during disputes/resolves/chargebacks.
@@ -202,9 +1319,9 @@ enum LedgerEntry {
 /// # Balance Types
 /// - `available`: Funds available for withdrawal
 /// - `held`: Funds held due to disputes (not available for withdrawal)
-/// 
+///
 /// If a chargeback occurs, the account is locked and no further deposits or withdrawals
-/// are allowed.
+/// are allowed. See [`AccountState`] for the full set of states.
 ///
 /// # Examples
 /// ```
@@ -217,272 +1334,3478 @@ enum LedgerEntry {
 ///
 /// // Check account state
 /// let account = db.get_account(1).unwrap();
-/// assert_eq!(account.available.to_f64(), 100.50);
+/// assert_eq!(account.available().to_f64(), 100.50);
 /// assert_eq!(account.total().to_f64(), 100.50);
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Account {
     /// Transaction ledger for audit trail and dispute resolution
+    #[serde(serialize_with = "sorted")]
     ledger: HashMap<u32, LedgerEntry>,
+    /// Sequence number for the next ledger entry
+    next_seq: u64,
     /// Funds available for withdrawal
+    #[deprecated(since = "0.1.0", note = "use `Account::available()`")]
     pub available: Fixed4,
     /// Funds held due to disputes (not available for withdrawal)
+    #[deprecated(since = "0.1.0", note = "use `Account::held()`")]
     pub held: Fixed4,
-    /// Account locked status (true after chargeback)
-    pub locked: bool,
+    /// Lifecycle state (locked after chargeback)
+    #[deprecated(since = "0.1.0", note = "use `Account::state()`")]
+    pub state: AccountState,
+    /// When the account was locked by a chargeback, if the chargeback carried a timestamp
+    locked_at: Option<Timestamp>,
+    /// Why the account is locked, while it is
+    #[serde(default)]
+    lock_reason: Option<LockReason>,
+    /// Whether the current lock has been reviewed
+    reviewed: bool,
+    /// Administrative events, oldest first
+    audit: Vec<AuditEntry>,
+    /// Balances after every change, oldest first
+    history: Vec<BalanceRow>,
+    /// Free-form labels set by embedders through [`AccountOps::set_metadata`]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: BTreeMap<String, String>,
 }
 
-impl Account {
-    /// Create a new empty account with zero balances
-    fn new() -> Self {
-        Self {
-            ledger: HashMap::new(),
-            available: Fixed4::zero(),
-            held: Fixed4::zero(),
-            locked: false,
-        }
+/// Read-only snapshot of an account's balances and state
+///
+/// Balances only change through the engine, which keeps them consistent with the
+/// ledger; a view is a copy, so holding one never blocks or desyncs processing.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{AccountState, Database, Transaction};
+/// let mut db = Database::new();
+/// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+/// db.process_transaction(1, 1, Transaction::Dispute).unwrap();
+///
+/// let view = db.view(1).unwrap();
+/// assert_eq!(view.client(), 1);
+/// assert_eq!(view.available().to_string(), "0.0000");
+/// assert_eq!(view.held().to_string(), "100.0000");
+/// assert_eq!(view.total().to_string(), "100.0000");
+/// assert_eq!(view.state(), AccountState::Active);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountView {
+    client: u16,
+    available: Fixed4,
+    held: Fixed4,
+    state: AccountState,
+}
+
+impl AccountView {
+    /// Client ID
+    pub fn client(&self) -> u16 {
+        self.client
     }
 
-    /// Calculate the total balance (available + held)
-    ///
-    /// Total balance represents all funds associated with the account,
-    /// regardless of whether they are available for withdrawal or held.
+    /// Funds available for withdrawal
+    pub fn available(&self) -> Fixed4 {
+        self.available
+    }
+
+    /// Funds held due to disputes and reserves
+    pub fn held(&self) -> Fixed4 {
+        self.held
+    }
+
+    /// Total funds (available + held)
+    pub fn total(&self) -> Fixed4 {
+        self.available + self.held
+    }
+
+    /// Lifecycle state
+    pub fn state(&self) -> AccountState {
+        self.state
+    }
+
+    /// Whether the account has been locked by a chargeback
+    pub fn is_locked(&self) -> bool {
+        self.state == AccountState::Locked
+    }
+}
+
+/// An account's balances and activity counts, without its ledger
+///
+/// The compact, stable form of an account for logs and APIs: an [`Account`] displays
+/// as its summary, and the summary serializes with the field names below. Serializing
+/// the [`Account`] itself writes its full state, ledger included, as snapshots need.
+///
+/// Displays as space-separated `name=value` pairs, named like the summary report's
+/// columns.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Database, Transaction};
+/// let mut db = Database::new();
+/// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+/// db.process_transaction(1, 2, Transaction::deposit("20").unwrap()).unwrap();
+/// db.process_transaction(1, 2, Transaction::dispute()).unwrap();
+///
+/// let account = db.get_account(1).unwrap();
+/// assert_eq!(
+///     account.to_string(),
+///     "available=100.0000 held=20.0000 total=120.0000 locked=false open_disputes=1 tx_count=2"
+/// );
+/// assert_eq!(
+///     serde_json::to_string(&account.summary()).unwrap(),
+///     r#"{"available":"100.0000","held":"20.0000","total":"120.0000","locked":false,"open_disputes":1,"tx_count":2}"#
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountSummary {
+    /// Funds available for withdrawal
+    pub available: Fixed4,
+    /// Funds held due to disputes and reserves
+    pub held: Fixed4,
+    /// Total funds (available + held)
+    pub total: Fixed4,
+    /// Whether the account is locked
+    pub locked: bool,
+    /// Number of deposits currently under dispute
+    pub open_disputes: usize,
+    /// Number of transactions in the ledger
+    pub tx_count: usize,
+}
+
+impl std::fmt::Display for AccountSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "available={} held={} total={} locked={} open_disputes={} tx_count={}",
+            self.available, self.held, self.total, self.locked, self.open_disputes, self.tx_count
+        )
+    }
+}
+
+/// The account's [summary](Account::summary), leaving out the ledger
+impl std::fmt::Display for Account {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.summary().fmt(f)
+    }
+}
+
+/// Operations an embedder may perform on an account, handed out by
+/// [`Database::with_account_mut`] and to [`CustomTransaction`](crate::CustomTransaction)
+/// handlers
+///
+/// Balances are never exposed for writing: money moves only through transactions
+/// posted with [`post`](Self::post) or [`post_adjustment`](Self::post_adjustment), which
+/// go through the same checks and ledger as any other transaction.
+pub struct AccountOps<'a> {
+    db: &'a mut Database,
+    client: u16,
+    /// Metadata of the row being applied, for custom transactions
+    meta: TransactionMeta,
+}
+
+impl AccountOps<'_> {
+    /// Client ID of the account
+    pub fn client(&self) -> u16 {
+        self.client
+    }
+
+    /// Read-only copy of the current balances and state
+    pub fn view(&self) -> AccountView {
+        self.account().view(self.client)
+    }
+
+    /// Post a transaction
+    ///
+    /// The transaction is processed exactly as [`Database::process_transaction_with`]
+    /// would. In a custom transaction handler it carries the timestamp and other
+    /// metadata of the row being applied.
+    ///
+    /// # Errors
+    /// Returns the error the transaction was rejected with.
+    pub fn post(&mut self, tx: u32, transaction: Transaction) -> Result<(), TransactionError> {
+        self.db
+            .process_transaction_with(self.client, tx, transaction, self.meta.clone())
+    }
+
+    /// Post a correcting transaction on behalf of `initiator`
+    ///
+    /// As [`post`](Self::post), with `initiator` recorded in the balance history. An
+    /// adjustment moving more than [`Policy::approval_threshold`] is staged for approval
+    /// like a large withdrawal.
+    ///
+    /// # Errors
+    /// Returns the error the transaction was rejected with.
+    pub fn post_adjustment(
+        &mut self,
+        tx: u32,
+        transaction: Transaction,
+        initiator: &str,
+    ) -> Result<(), TransactionError> {
+        let meta = TransactionMeta {
+            initiator: Some(initiator.to_string()),
+            ..self.meta.clone()
+        };
+        self.db
+            .submit_transaction(self.client, tx, transaction, meta, true)
+    }
+
+    /// Set the label `key` to `value`, returning the previous value
+    pub fn set_metadata(&mut self, key: &str, value: &str) -> Option<String> {
+        self.account_mut()
+            .metadata
+            .insert(key.to_string(), value.to_string())
+    }
+
+    /// Remove the label `key`, returning its value
+    pub fn remove_metadata(&mut self, key: &str) -> Option<String> {
+        self.account_mut().metadata.remove(key)
+    }
+
+    fn account(&self) -> &Account {
+        self.db
+            .accounts
+            .get(self.client)
+            .expect("AccountOps is only created for existing accounts")
+    }
+
+    fn account_mut(&mut self) -> &mut Account {
+        self.db
+            .accounts
+            .get_mut(self.client)
+            .expect("AccountOps is only created for existing accounts")
+    }
+}
+
+impl Account {
+    /// Create a new empty account with zero balances
+    #[allow(deprecated)]
+    fn new() -> Self {
+        Self {
+            ledger: HashMap::new(),
+            next_seq: 0,
+            available: Fixed4::zero(),
+            held: Fixed4::zero(),
+            state: AccountState::Active,
+            locked_at: None,
+            lock_reason: None,
+            reviewed: false,
+            audit: Vec::new(),
+            history: Vec::new(),
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    /// Funds available for withdrawal
+    #[allow(deprecated)]
+    pub fn available(&self) -> Fixed4 {
+        self.available
+    }
+
+    /// Funds held due to disputes and reserves (not available for withdrawal)
+    #[allow(deprecated)]
+    pub fn held(&self) -> Fixed4 {
+        self.held
+    }
+
+    /// Lifecycle state
+    #[allow(deprecated)]
+    pub fn state(&self) -> AccountState {
+        self.state
+    }
+
+    /// Whether the account has been locked by a chargeback
+    pub fn is_locked(&self) -> bool {
+        self.state() == AccountState::Locked
+    }
+
+    /// Why and when the account was locked, if it is
+    pub fn lock_details(&self) -> Option<LockDetails> {
+        self.is_locked().then(|| LockDetails {
+            reason: self.lock_reason.clone().unwrap_or(LockReason::Manual),
+            locked_at: self.locked_at,
+        })
+    }
+
+    /// Read-only copy of the balances and state, labelled with `client_id`
+    pub fn view(&self, client_id: u16) -> AccountView {
+        AccountView {
+            client: client_id,
+            available: self.available(),
+            held: self.held(),
+            state: self.state(),
+        }
+    }
+
+    /// Labels set through [`AccountOps::set_metadata`], by key
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    /// Administrative events recorded against this account, oldest first
+    pub fn audit_log(&self) -> &[AuditEntry] {
+        &self.audit
+    }
+
+    /// Balances after every accepted transaction and reserve release, oldest first
+    ///
+    /// Only the latest [`Policy::balance_history_limit`] rows are kept, if the policy
+    /// sets a limit.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Database, Transaction, TransactionMeta};
+    /// let mut db = Database::new();
+    /// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+    ///
+    /// let adjustment = TransactionMeta {
+    ///     initiator: Some("operator:jsmith".to_string()),
+    ///     ..TransactionMeta::default()
+    /// };
+    /// db.process_transaction_with(1, 2, Transaction::withdrawal("5").unwrap(), adjustment)
+    ///     .unwrap();
+    ///
+    /// let history = db.get_account(1).unwrap().balance_history();
+    /// assert_eq!(history[0].initiator, None);
+    /// assert_eq!(history[1].initiator.as_deref(), Some("operator:jsmith"));
+    /// ```
+    pub fn balance_history(&self) -> &[BalanceRow] {
+        &self.history
+    }
+
+    /// Append the current balances to the balance history, dropping the oldest rows past
+    /// `limit`
+    fn record_balances(
+        &mut self,
+        tx: u32,
+        activity: Activity,
+        timestamp: Option<Timestamp>,
+        initiator: Option<String>,
+        limit: Option<usize>,
+    ) {
+        self.history.push(BalanceRow {
+            tx,
+            activity,
+            timestamp,
+            initiator,
+            available: self.available(),
+            held: self.held(),
+        });
+        if let Some(limit) = limit
+            && self.history.len() > limit
+        {
+            let excess = self.history.len() - limit;
+            self.history.drain(..excess);
+        }
+    }
+
+    /// Append an administrative event to the audit log
+    fn record(&mut self, timestamp: Option<Timestamp>, event: AuditEvent) {
+        self.audit.push(AuditEntry { timestamp, event });
+    }
+
+    /// Whether the account is locked by a chargeback limit, which only an operator may
+    /// lift
+    fn is_limit_locked(&self) -> bool {
+        matches!(self.lock_reason, Some(LockReason::ChargebackLimit { .. }))
+    }
+
+    /// Number and total amount of the chargebacks at or after `since`, or of every
+    /// chargeback if `since` is `None`
+    fn chargebacks_since(&self, since: Option<Timestamp>) -> (u32, Fixed4) {
+        let mut count = 0;
+        let mut volume = Fixed4::zero();
+        for entry in self.ledger.values() {
+            if let LedgerEntry::Deposit {
+                amount,
+                transitions,
+                ..
+            } = entry
+            {
+                for transition in transitions {
+                    let in_period = match since {
+                        None => true,
+                        Some(since) => transition.timestamp.is_some_and(|at| at >= since),
+                    };
+                    if transition.to == DisputeState::ChargedBack && in_period {
+                        count += 1;
+                        volume = volume.saturating_add(*amount);
+                    }
+                }
+            }
+        }
+        (count, volume)
+    }
+
+    /// Lock or freeze the account if its chargebacks, as of the one at `now`, reach a
+    /// limit of the policy
+    #[allow(deprecated)]
+    fn apply_chargeback_limits(&mut self, policy: &Policy, now: Option<Timestamp>) {
+        if self.state() == AccountState::Frozen
+            || self.state() == AccountState::Closed
+            || self.is_limit_locked()
+        {
+            return;
+        }
+        // The strongest action of every limit reached, with the counts that reached it
+        let mut reached: Option<(LimitAction, u32, Fixed4)> = None;
+        for limit in &policy.chargeback_limits {
+            let since = match (limit.period, now) {
+                (None, _) => None,
+                (Some(period), Some(now)) => Some(now.saturating_sub(period)),
+                // A period needs the time of the chargeback
+                (Some(_), None) => continue,
+            };
+            let (count, volume) = self.chargebacks_since(since);
+            let over = limit.max_chargebacks.is_some_and(|max| count >= max)
+                || limit.max_volume.is_some_and(|max| volume > max);
+            if over && reached.is_none_or(|(action, ..)| action == LimitAction::Lock) {
+                reached = Some((limit.action, count, volume));
+            }
+        }
+        let Some((action, chargebacks, volume)) = reached else {
+            return;
+        };
+        match action {
+            LimitAction::Lock => {
+                if self.state() != AccountState::Locked {
+                    self.state = AccountState::Locked;
+                    self.locked_at = now;
+                }
+                self.lock_reason = Some(LockReason::ChargebackLimit {
+                    chargebacks,
+                    volume,
+                });
+                self.reviewed = false;
+            }
+            // Replaces any lock: the freeze is the stronger hold
+            LimitAction::Freeze => {
+                self.unlock();
+                self.state = AccountState::Frozen;
+            }
+        }
+        self.record(
+            now,
+            AuditEvent::ChargebackLimitReached {
+                chargebacks,
+                volume,
+                action,
+            },
+        );
+    }
+
+    /// Return a locked account to active, clearing the lock bookkeeping
+    #[allow(deprecated)]
+    fn unlock(&mut self) {
+        self.state = AccountState::Active;
+        self.locked_at = None;
+        self.lock_reason = None;
+        self.reviewed = false;
+    }
+
+    /// Whether the automatic unlock policy allows unlocking this account at `now`
+    fn is_due_for_unlock(&self, policy: &Policy, now: Timestamp) -> bool {
+        if self.state() != AccountState::Locked || self.is_limit_locked() {
+            return false;
+        }
+        if policy.auto_unlock_after.is_none() && !policy.unlock_requires_review {
+            return false;
+        }
+        let cooled_off = match policy.auto_unlock_after {
+            Some(period) => self
+                .locked_at
+                .is_some_and(|locked_at| now >= locked_at.saturating_add(period)),
+            None => true,
+        };
+        let reviewed = !policy.unlock_requires_review || self.reviewed;
+        cooled_off && reviewed
+    }
+
+    /// Funds currently held in the rolling reserve
+    ///
+    /// These are included in [`held`](Account::held) until
+    /// [`Database::release_matured_reserves`] makes them available.
+    pub fn reserved(&self) -> Fixed4 {
+        let mut reserved = Fixed4::zero();
+        for entry in self.ledger.values() {
+            if let LedgerEntry::Deposit {
+                reserve: Some(reserve),
+                ..
+            } = entry
+            {
+                reserved += reserve.amount;
+            }
+        }
+        reserved
+    }
+
+    /// Move every reserve that has matured by `now` from held to available
+    ///
+    /// Returns whether anything was released. The balance history is capped at
+    /// `history_limit` rows.
+    #[allow(deprecated)]
+    fn release_reserves(&mut self, now: Timestamp, history_limit: Option<usize>) -> bool {
+        let mut released = Vec::new();
+        for (&tx, entry) in self.ledger.iter_mut() {
+            if let LedgerEntry::Deposit { seq, reserve, .. } = entry
+                && reserve.as_ref().is_some_and(|r| now >= r.release_at)
+                && let Some(reserve) = reserve.take()
+            {
+                released.push((*seq, tx, reserve.amount));
+            }
+        }
+        // Release in deposit order so the balance history is deterministic
+        released.sort_by_key(|&(seq, _, _)| seq);
+        for &(_, tx, amount) in &released {
+            self.held -= amount;
+            self.available += amount;
+            self.record_balances(tx, Activity::ReserveRelease, Some(now), None, history_limit);
+        }
+        !released.is_empty()
+    }
+
+    /// Calculate the total balance (available + held)
+    ///
+    /// Total balance represents all funds associated with the account,
+    /// regardless of whether they are available for withdrawal or held.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Database, Transaction};
+    /// let mut db = Database::new();
+    /// let deposit = Transaction::deposit("100.00").unwrap();
+    /// db.process_transaction(1, 1, deposit).unwrap();
+    ///
+    /// let account = db.get_account(1).unwrap();
+    /// assert_eq!(account.total().to_f64(), 100.00);
+    /// ```
+    pub fn total(&self) -> Fixed4 {
+        self.available() + self.held()
+    }
+
+    /// Get transaction count for testing/audit purposes
+    ///
+    /// Returns the total number of transactions recorded in this account's ledger.
+    /// Useful for audit trails and testing transaction history completeness.
+    pub fn transaction_count(&self) -> usize {
+        self.ledger.len()
+    }
+
+    /// Balances and activity counts, without the ledger
+    pub fn summary(&self) -> AccountSummary {
+        AccountSummary {
+            available: self.available(),
+            held: self.held(),
+            total: self.total(),
+            locked: self.is_locked(),
+            open_disputes: self.open_disputes(),
+            tx_count: self.transaction_count(),
+        }
+    }
+
+    /// The `(available, held)` balances implied by the ledger, worked out afresh from
+    /// every entry's amount, dispute state and reserve
+    ///
+    /// These always equal [`available`](Self::available) and [`held`](Self::held)
+    /// unless something has gone wrong, so comparing them is a cheap check of the engine
+    /// (see [`Database::audit_balances`]).
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Database, Transaction};
+    /// let mut db = Database::new();
+    /// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+    /// db.process_transaction(1, 2, Transaction::deposit("40").unwrap()).unwrap();
+    /// db.process_transaction(1, 3, Transaction::withdrawal("30").unwrap()).unwrap();
+    /// db.process_transaction(1, 2, Transaction::dispute()).unwrap();
+    ///
+    /// let account = db.get_account(1).unwrap();
+    /// let (available, held) = account.recompute_balances();
+    /// assert_eq!(available.to_string(), "70.0000");
+    /// assert_eq!(held.to_string(), "40.0000");
+    /// assert_eq!((available, held), (account.available(), account.held()));
+    /// ```
+    pub fn recompute_balances(&self) -> (Fixed4, Fixed4) {
+        let mut refunds: HashMap<u32, Fixed4> = HashMap::new();
+        for entry in self.ledger.values() {
+            if let LedgerEntry::Refund {
+                amount,
+                original: Some(original),
+                ..
+            } = entry
+            {
+                *refunds.entry(*original).or_insert(Fixed4::zero()) += *amount;
+            }
+        }
+        let effects = self.ledger.iter().map(|(tx, entry)| {
+            entry.balance_effect(refunds.get(tx).copied().unwrap_or(Fixed4::zero()))
+        });
+        effects.fold(
+            (Fixed4::zero(), Fixed4::zero()),
+            |(available, held), (entry_available, entry_held)| {
+                (available + entry_available, held + entry_held)
+            },
+        )
+    }
+
+    /// Number of deposits (and withdrawals) currently under dispute
+    pub fn open_disputes(&self) -> usize {
+        self.ledger
+            .values()
+            .filter(|entry| {
+                matches!(
+                    entry,
+                    LedgerEntry::Deposit {
+                        state: DisputeState::Disputed,
+                        ..
+                    } | LedgerEntry::Withdrawal {
+                        state: DisputeState::Disputed,
+                        ..
+                    }
+                )
+            })
+            .count()
+    }
+
+    /// Number of deposits, and how many of them have ever been charged back
+    pub(crate) fn chargeback_counts(&self) -> (usize, usize) {
+        let mut counts = (0, 0);
+        for entry in self.ledger.values() {
+            if let LedgerEntry::Deposit { transitions, .. } = entry {
+                counts.0 += 1;
+                if transitions
+                    .iter()
+                    .any(|transition| transition.to == DisputeState::ChargedBack)
+                {
+                    counts.1 += 1;
+                }
+            }
+        }
+        counts
+    }
+
+    /// Latest known timestamp of any change to the account's balances
+    ///
+    /// `None` if no accepted transaction carried a timestamp.
+    pub fn last_activity(&self) -> Option<Timestamp> {
+        self.history.iter().filter_map(|row| row.timestamp).max()
+    }
+
+    /// Amount recorded in the ledger for a transaction, if it exists
+    fn ledger_amount(&self, txn_id: u32) -> Option<Fixed4> {
+        self.ledger.get(&txn_id).map(|entry| match entry {
+            LedgerEntry::Deposit { amount, .. }
+            | LedgerEntry::Withdrawal { amount, .. }
+            | LedgerEntry::Refund { amount, .. } => *amount,
+        })
+    }
+
+    /// Whether a deposit or withdrawal repeats a ledger entry with the same ID, kind and amount
+    fn is_duplicate(&self, txn_id: u32, txn: &Transaction) -> bool {
+        match (self.ledger.get(&txn_id), txn) {
+            (Some(LedgerEntry::Deposit { amount, .. }), Transaction::Deposit { amount: new }) => {
+                amount == new
+            }
+            (
+                Some(LedgerEntry::Withdrawal { amount, .. }),
+                Transaction::Withdrawal { amount: new },
+            ) => amount == new,
+            (Some(LedgerEntry::Refund { amount, .. }), Transaction::Deposit { amount: new }) => {
+                -*amount == *new
+            }
+            (
+                Some(LedgerEntry::Refund {
+                    amount, original, ..
+                }),
+                Transaction::Refund {
+                    original_tx,
+                    amount: new,
+                },
+            ) => amount == new && *original == Some(*original_tx),
+            _ => false,
+        }
+    }
+
+    /// Total refunded so far of deposit `original_tx`, by refunds naming it
+    ///
+    /// See [`Transaction::refund`].
+    pub fn refunded(&self, original_tx: u32) -> Fixed4 {
+        let mut refunded = Fixed4::zero();
+        for entry in self.ledger.values() {
+            if let LedgerEntry::Refund {
+                amount,
+                original: Some(original),
+                ..
+            } = entry
+                && *original == original_tx
+            {
+                refunded += *amount;
+            }
+        }
+        refunded
+    }
+
+    /// Check if a transaction exists (for testing)
+    ///
+    /// # Arguments
+    /// * `txn_id` - Transaction ID to check
+    ///
+    /// # Returns
+    /// `true` if the transaction exists in the account's ledger, `false` otherwise
+    pub fn has_transaction(&self, txn_id: u32) -> bool {
+        self.ledger.contains_key(&txn_id)
+    }
+
+    /// Every transaction in the account's ledger, in the order they were processed
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Database, DisputeState, EntryKind, Transaction};
+    /// let mut db = Database::new();
+    /// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+    /// db.process_transaction(1, 2, Transaction::withdrawal("40").unwrap()).unwrap();
+    /// db.process_transaction(1, 1, Transaction::dispute()).unwrap();
+    ///
+    /// let ledger: Vec<_> = db.get_account(1).unwrap().ledger().collect();
+    /// assert_eq!(ledger.len(), 2);
+    /// assert_eq!((ledger[0].tx, ledger[0].kind), (1, EntryKind::Deposit));
+    /// assert_eq!(ledger[0].amount.to_string(), "100.0000");
+    /// assert_eq!(ledger[0].dispute_state, Some(DisputeState::Disputed));
+    /// assert_eq!((ledger[1].tx, ledger[1].dispute_state), (2, None));
+    /// ```
+    pub fn ledger(&self) -> impl Iterator<Item = LedgerRecord> + '_ {
+        let mut entries: Vec<(&u32, &LedgerEntry)> = self.ledger.iter().collect();
+        entries.sort_by_key(|(_, entry)| entry.seq());
+        entries.into_iter().map(|(&tx, entry)| entry.record(tx))
+    }
+
+    /// Work out every change a transaction makes to this account, without making any
+    fn delta(
+        &self,
+        txn_id: u32,
+        txn: Transaction,
+        timestamp: Option<Timestamp>,
+        policy: &Policy,
+    ) -> Result<AccountDelta, EngineError> {
+        let zero = Fixed4::zero();
+        let delta = match txn {
+            Transaction::Deposit { amount } if amount < zero => {
+                if !policy.negative_deposits_as_refunds {
+                    return Err(EngineError::AmountMustBePositive);
+                }
+                let amount = -amount;
+                if self.available() < amount {
+                    return Err(EngineError::InsufficientFunds);
+                }
+                let entry = LedgerEntry::Refund {
+                    seq: self.next_seq,
+                    timestamp,
+                    amount,
+                    original: None,
+                    origin: None,
+                };
+                AccountDelta::record(-amount, zero, txn_id, entry)
+            }
+            Transaction::Refund {
+                original_tx,
+                amount,
+            } => {
+                let (deposited, state) = self.deposit(original_tx)?;
+                let unrefunded = deposited - self.refunded(original_tx);
+                if amount > unrefunded {
+                    return Err(EngineError::RefundExceedsDeposit);
+                }
+                // Funds charged back have already been taken back, and disputed funds
+                // are held pending the outcome
+                match state {
+                    DisputeState::ChargedBack => return Err(EngineError::ReversalExceedsDeposit),
+                    DisputeState::Disputed => {
+                        return Err(EngineError::TransactionAlreadyDisputed);
+                    }
+                    DisputeState::Normal | DisputeState::Represented => {}
+                }
+                if self.available() < amount {
+                    return Err(EngineError::InsufficientFunds);
+                }
+                let entry = LedgerEntry::Refund {
+                    seq: self.next_seq,
+                    timestamp,
+                    amount,
+                    original: Some(original_tx),
+                    origin: None,
+                };
+                AccountDelta::record(-amount, zero, txn_id, entry)
+            }
+            Transaction::Deposit { amount } => {
+                let reserve = match timestamp {
+                    Some(timestamp) if policy.reserve_percent > 0 => Some(Reserve {
+                        amount: amount.percentage(policy.reserve_percent),
+                        release_at: timestamp
+                            .saturating_add(policy.reserve_days.saturating_mul(SECONDS_PER_DAY)),
+                    }),
+                    _ => None,
+                };
+                let reserved = reserve.as_ref().map_or(zero, |r| r.amount);
+                let available = amount - reserved;
+                let entry = LedgerEntry::Deposit {
+                    seq: self.next_seq,
+                    timestamp,
+                    amount,
+                    state: DisputeState::Normal,
+                    transitions: Vec::new(),
+                    reserve,
+                    origin: None,
+                };
+                AccountDelta::record(available, reserved, txn_id, entry)
+            }
+            Transaction::Withdrawal { amount } => {
+                if self.available() < amount {
+                    return Err(EngineError::InsufficientFunds);
+                }
+                let entry = LedgerEntry::Withdrawal {
+                    seq: self.next_seq,
+                    timestamp,
+                    amount,
+                    state: DisputeState::Normal,
+                    transitions: Vec::new(),
+                    origin: None,
+                };
+                AccountDelta::record(-amount, zero, txn_id, entry)
+            }
+            Transaction::Dispute => {
+                self.check_dispute(txn_id, policy)?;
+                let (amount, from, kind) = self.disputable(txn_id, policy)?;
+                let states = (from, DisputeState::Disputed);
+                // A disputed withdrawal is credited back to held, a deposit moves there
+                let d_available = match kind {
+                    EntryKind::Withdrawal => zero,
+                    _ => -amount,
+                };
+                AccountDelta::transition(d_available, amount, txn_id, states, timestamp)
+            }
+            Transaction::Resolve => {
+                let (amount, state, kind) = self.disputable(txn_id, policy)?;
+                let amount = match (amount, state) {
+                    (amount, DisputeState::Disputed) => amount,
+                    (_, DisputeState::Normal | DisputeState::Represented) => {
+                        return Err(EngineError::TransactionNotDisputed);
+                    }
+                    (_, DisputeState::ChargedBack) => {
+                        return Err(EngineError::TransactionAlreadyChargedBack);
+                    }
+                };
+                let states = (DisputeState::Disputed, DisputeState::Normal);
+                // A resolved withdrawal stands, a deposit is released back to available
+                let d_available = match kind {
+                    EntryKind::Withdrawal => zero,
+                    _ => amount,
+                };
+                AccountDelta::transition(d_available, -amount, txn_id, states, timestamp)
+            }
+            Transaction::Chargeback => {
+                let (amount, state, kind) = self.disputable(txn_id, policy)?;
+                let amount = match (amount, state) {
+                    (amount, DisputeState::Disputed) => amount,
+                    (_, DisputeState::ChargedBack) => {
+                        return Err(EngineError::TransactionAlreadyChargedBack);
+                    }
+                    (_, DisputeState::Normal | DisputeState::Represented) => {
+                        return Err(EngineError::TransactionNotDisputed);
+                    }
+                };
+                let states = (DisputeState::Disputed, DisputeState::ChargedBack);
+                if kind == EntryKind::Withdrawal {
+                    // The withdrawal is reversed: the held funds go back to the client
+                    return Ok(AccountDelta::transition(
+                        amount, -amount, txn_id, states, timestamp,
+                    ));
+                }
+                let mut delta = AccountDelta::transition(zero, -amount, txn_id, states, timestamp);
+                // A further chargeback renews a chargeback lock, restarting the cooling-off
+                // period and requiring a fresh review; a limit lock is left to the limits
+                let relock = self.state() == AccountState::Locked && !self.is_limit_locked();
+                if relock || self.state().can_transition_to(AccountState::Locked) {
+                    delta.lock = LockChange::Lock(LockReason::Chargeback { tx: txn_id });
+                }
+                delta
+            }
+            Transaction::ChargebackReversal => {
+                let (amount, state, kind) = self.disputable(txn_id, policy)?;
+                let amount = match (amount, state) {
+                    (amount, DisputeState::ChargedBack) => amount,
+                    (
+                        _,
+                        DisputeState::Normal | DisputeState::Disputed | DisputeState::Represented,
+                    ) => {
+                        return Err(EngineError::TransactionNotChargedBack);
+                    }
+                };
+                let states = (DisputeState::ChargedBack, DisputeState::Represented);
+                if kind == EntryKind::Withdrawal {
+                    // The withdrawal is upheld after all and debited again
+                    return Ok(AccountDelta::transition(
+                        -amount, zero, txn_id, states, timestamp,
+                    ));
+                }
+                let mut delta = AccountDelta::transition(amount, zero, txn_id, states, timestamp);
+                if policy.unlock_on_chargeback_reversal
+                    && self.state() == AccountState::Locked
+                    && !self.is_limit_locked()
+                    && !self.has_other_chargebacks(txn_id)
+                {
+                    delta.lock = LockChange::Unlock;
+                }
+                delta
+            }
+            // Applied by their handler, never recorded directly
+            Transaction::Custom { name, .. } => {
+                return Err(EngineError::UnknownTransactionType(name));
+            }
+        };
+        self.check_balances(delta.d_available, delta.d_held)?;
+        Ok(delta)
+    }
+
+    /// Check that the balances, and their total, stay within what a [`Fixed4`] holds
+    /// once `d_available` and `d_held` are added
+    fn check_balances(&self, d_available: Fixed4, d_held: Fixed4) -> Result<(), EngineError> {
+        let available = self.available().checked_add(d_available);
+        let held = self.held().checked_add(d_held);
+        match (available, held) {
+            (Some(available), Some(held)) if available.checked_add(held).is_some() => Ok(()),
+            _ => Err(EngineError::BalanceOverflow),
+        }
+    }
+
+    /// Apply a change worked out by [`delta`](Self::delta)
+    ///
+    /// [`delta`](Self::delta) checked that the new balances don't overflow, and they are
+    /// worked out before anything is assigned, so even so the only step that could
+    /// panic runs while the account is still untouched.
+    #[allow(deprecated)]
+    fn commit(&mut self, delta: AccountDelta) {
+        let available = self.available + delta.d_available;
+        let held = self.held + delta.d_held;
+
+        // Nothing from here on can fail
+        self.available = available;
+        self.held = held;
+        match (delta.ledger_op, delta.entry) {
+            (LedgerOp::Record { tx, .. }, Some(entry)) => {
+                self.next_seq = entry.seq() + 1;
+                self.ledger.insert(tx, entry);
+            }
+            (LedgerOp::Transition { tx, to, .. }, _) => {
+                if let Some(
+                    LedgerEntry::Deposit {
+                        state, transitions, ..
+                    }
+                    | LedgerEntry::Withdrawal {
+                        state, transitions, ..
+                    },
+                ) = self.ledger.get_mut(&tx)
+                {
+                    transition(state, transitions, to, delta.timestamp);
+                }
+            }
+            (LedgerOp::Record { .. }, None) => unreachable!("recorded entries are staged"),
+        }
+        match delta.lock {
+            LockChange::None => {}
+            LockChange::Lock(reason) => {
+                self.state = AccountState::Locked;
+                self.locked_at = delta.timestamp;
+                self.lock_reason = Some(reason);
+                self.reviewed = false;
+            }
+            LockChange::Unlock => self.unlock(),
+        }
+    }
+
+    /// Amount and dispute state of deposit `txn_id`
+    fn deposit(&self, txn_id: u32) -> Result<(Fixed4, DisputeState), EngineError> {
+        match self.ledger.get(&txn_id) {
+            None => Err(EngineError::TransactionNotFound),
+            Some(LedgerEntry::Withdrawal { .. }) => Err(EngineError::TransactionIsWithdrawal),
+            Some(LedgerEntry::Refund { .. }) => Err(EngineError::TransactionIsRefund),
+            Some(LedgerEntry::Deposit { amount, state, .. }) => Ok((*amount, *state)),
+        }
+    }
+
+    /// Amount, dispute state and kind of transaction `txn_id`, if `policy` allows it to
+    /// be disputed: a deposit, or a withdrawal under [`Policy::withdrawal_disputes`]
+    ///
+    /// The amount of a deposit is what is left of it unrefunded, which is all a dispute
+    /// can hold.
+    fn disputable(
+        &self,
+        txn_id: u32,
+        policy: &Policy,
+    ) -> Result<(Fixed4, DisputeState, EntryKind), EngineError> {
+        match self.ledger.get(&txn_id) {
+            Some(LedgerEntry::Withdrawal { amount, state, .. }) if policy.withdrawal_disputes => {
+                Ok((*amount, *state, EntryKind::Withdrawal))
+            }
+            _ => {
+                let (amount, state) = self.deposit(txn_id)?;
+                Ok((amount - self.refunded(txn_id), state, EntryKind::Deposit))
+            }
+        }
+    }
+
+    /// Check that a transaction can be disputed under `policy`, without changing anything
+    fn check_dispute(&self, txn_id: u32, policy: &Policy) -> Result<(), EngineError> {
+        match self.disputable(txn_id, policy)?.1 {
+            DisputeState::Normal => Ok(()),
+            DisputeState::Disputed => Err(EngineError::TransactionAlreadyDisputed),
+            DisputeState::ChargedBack => Err(EngineError::TransactionAlreadyChargedBack),
+            DisputeState::Represented => Err(EngineError::TransactionAlreadyRepresented),
+        }
+    }
+
+    /// Whether any deposit other than `txn_id` is still charged back (and not
+    /// represented)
+    fn has_other_chargebacks(&self, txn_id: u32) -> bool {
+        self.ledger.iter().any(|(&tx, entry)| {
+            tx != txn_id
+                && matches!(
+                    entry,
+                    LedgerEntry::Deposit {
+                        state: DisputeState::ChargedBack,
+                        ..
+                    }
+                )
+        })
+    }
+
+    /// Remove ledger entry `txn_id` along with its effect on the balances
+    #[allow(deprecated)]
+    fn drop_entry(&mut self, txn_id: u32) {
+        let refunded = self.refunded(txn_id);
+        if let Some(entry) = self.ledger.remove(&txn_id) {
+            let (available, held) = entry.balance_effect(refunded);
+            self.available -= available;
+            self.held -= held;
+        }
+    }
+
+    /// Available and held funds of this account combined with `other`, or `None` if
+    /// either would overflow
+    #[allow(deprecated)]
+    fn combined_balances(&self, other: &Account) -> Option<(Fixed4, Fixed4)> {
+        Some((
+            self.available.checked_add(other.available)?,
+            self.held.checked_add(other.held)?,
+        ))
+    }
+
+    /// Add `offset` to the ID of every approval in the audit log
+    fn shift_approval_ids(&mut self, offset: u64) {
+        for entry in &mut self.audit {
+            match &mut entry.event {
+                AuditEvent::ApprovalRequested { id, .. }
+                | AuditEvent::Approved { id, .. }
+                | AuditEvent::Rejected { id, .. } => *id += offset,
+                _ => {}
+            }
+        }
+    }
+
+    /// Combine `other`, the same client's account from another database, into this one
+    ///
+    /// The two ledgers must not share a transaction ID.
+    ///
+    /// # Errors
+    /// - [`EngineError::BalanceOverflow`] - The combined balances would overflow; the
+    ///   account is left as it was
+    #[allow(deprecated)]
+    fn absorb(&mut self, other: Account) -> Result<(), EngineError> {
+        let (available, held) = self
+            .combined_balances(&other)
+            .ok_or(EngineError::BalanceOverflow)?;
+        let mut entries: Vec<(u32, LedgerEntry)> = other.ledger.into_iter().collect();
+        entries.sort_by_key(|(_, entry)| entry.seq());
+        for (tx, mut entry) in entries {
+            match &mut entry {
+                LedgerEntry::Deposit { seq, .. }
+                | LedgerEntry::Withdrawal { seq, .. }
+                | LedgerEntry::Refund { seq, .. } => {
+                    *seq = self.next_seq;
+                }
+            }
+            self.next_seq += 1;
+            self.ledger.insert(tx, entry);
+        }
+        self.available = available;
+        self.held = held;
+        if self.state == AccountState::Active && other.state != AccountState::Active {
+            self.state = other.state;
+            self.locked_at = other.locked_at;
+            self.lock_reason = other.lock_reason;
+            self.reviewed = other.reviewed;
+        }
+        self.audit.extend(other.audit);
+        self.history.extend(other.history);
+        for (key, value) in other.metadata {
+            self.metadata.entry(key).or_insert(value);
+        }
+        Ok(())
+    }
+
+    /// Allocate the sequence number for a new ledger entry
+    fn take_seq(&mut self) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        seq
+    }
+
+    /// Build a statement of this account's balances and ledger, in processing order
+    fn statement(&self, client_id: u16) -> Statement {
+        let mut entries: Vec<(u64, StatementLine)> = self
+            .ledger
+            .iter()
+            .map(|(&tx, entry)| match entry {
+                LedgerEntry::Deposit {
+                    seq,
+                    timestamp,
+                    amount,
+                    state,
+                    transitions,
+                    origin,
+                    ..
+                } => (
+                    *seq,
+                    StatementLine {
+                        tx,
+                        kind: EntryKind::Deposit,
+                        amount: *amount,
+                        timestamp: *timestamp,
+                        dispute_state: Some(*state),
+                        transitions: transitions.clone(),
+                        origin: *origin,
+                        refund_of: None,
+                    },
+                ),
+                LedgerEntry::Withdrawal {
+                    seq,
+                    timestamp,
+                    amount,
+                    state,
+                    transitions,
+                    origin,
+                } => (
+                    *seq,
+                    StatementLine {
+                        tx,
+                        kind: EntryKind::Withdrawal,
+                        amount: *amount,
+                        timestamp: *timestamp,
+                        // Only withdrawals that have been disputed show a state
+                        dispute_state: (!transitions.is_empty()).then_some(*state),
+                        transitions: transitions.clone(),
+                        origin: *origin,
+                        refund_of: None,
+                    },
+                ),
+                LedgerEntry::Refund {
+                    seq,
+                    timestamp,
+                    amount,
+                    original,
+                    origin,
+                } => (
+                    *seq,
+                    StatementLine {
+                        tx,
+                        kind: EntryKind::Refund,
+                        amount: *amount,
+                        timestamp: *timestamp,
+                        dispute_state: None,
+                        transitions: Vec::new(),
+                        origin: *origin,
+                        refund_of: *original,
+                    },
+                ),
+            })
+            .collect();
+        entries.sort_by_key(|(seq, _)| *seq);
+
+        Statement {
+            client: client_id,
+            available: self.available(),
+            held: self.held(),
+            total: self.total(),
+            state: self.state(),
+            lines: entries.into_iter().map(|(_, line)| line).collect(),
+            history: self.history.clone(),
+        }
+    }
+}
+
+// =============================================================================
+// DISPUTE CASES
+// =============================================================================
+
+/// Step of the dispute lifecycle performed by a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DisputeAction {
+    /// Funds moved to held
+    Dispute,
+    /// Funds released back to available
+    Resolve,
+    /// Held funds removed
+    Chargeback,
+    /// Chargeback reversed after representment
+    ChargebackReversal,
+}
+
+impl std::fmt::Display for DisputeAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            DisputeAction::Dispute => "dispute",
+            DisputeAction::Resolve => "resolve",
+            DisputeAction::Chargeback => "chargeback",
+            DisputeAction::ChargebackReversal => "chargeback_reversal",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A dispute-lifecycle transaction recorded against a case
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaseEvent {
+    /// Client the transaction was for
+    pub client: u16,
+    /// Transaction ID the action referenced
+    pub tx: u32,
+    /// What was done
+    pub action: DisputeAction,
+    /// When it happened, if known
+    pub timestamp: Option<Timestamp>,
+}
+
+// =============================================================================
+// APPROVALS
+// =============================================================================
+
+/// An operation that can be staged for approval
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Operation {
+    /// A transaction, applied as by [`Database::process_transaction_with`]
+    Transaction {
+        /// Transaction ID
+        tx: u32,
+        /// The transaction itself
+        transaction: Transaction,
+        /// Timestamp and case ID it was submitted with
+        meta: TransactionMeta,
+    },
+    /// An account state change, applied as by [`Database::set_account_state`]
+    SetState {
+        /// Requested state
+        state: AccountState,
+    },
+    /// An operator unlock, applied as by [`Database::unlock_account`]
+    Unlock {
+        /// Why the account is being unlocked
+        reason: String,
+    },
+}
+
+impl std::fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operation::Transaction {
+                tx, transaction, ..
+            } => match transaction.amount() {
+                Some(amount) => write!(f, "{} {} of {}", transaction.name(), tx, amount),
+                None => write!(f, "{} {}", transaction.name(), tx),
+            },
+            Operation::SetState { state } => write!(f, "set state to {}", state),
+            Operation::Unlock { reason } => write!(f, "unlock ({})", reason),
+        }
+    }
+}
+
+/// An operation waiting for [`Database::approve`] or [`Database::reject`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingApproval {
+    /// ID identifying the operation
+    pub id: u64,
+    /// Client the operation is for
+    pub client: u16,
+    /// What the operation does
+    pub operation: Operation,
+}
+
+// =============================================================================
+// STATEMENTS
+// =============================================================================
+
+/// Kind of transaction recorded in an account's ledger
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EntryKind {
+    /// Funds added to the account
+    Deposit,
+    /// Funds removed from the account
+    Withdrawal,
+    /// Funds returned to the payer of an earlier deposit, recorded as an entry of its
+    /// own that names the deposit and cannot be disputed
+    Refund,
+}
+
+impl std::fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EntryKind::Deposit => write!(f, "deposit"),
+            EntryKind::Withdrawal => write!(f, "withdrawal"),
+            EntryKind::Refund => write!(f, "refund"),
+        }
+    }
+}
+
+/// One ledger entry as shown on a [`Statement`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatementLine {
+    /// Transaction ID
+    pub tx: u32,
+    /// Whether this was a deposit, a withdrawal or a refund
+    pub kind: EntryKind,
+    /// Original transaction amount
+    pub amount: Fixed4,
+    /// When the transaction was made, if known
+    pub timestamp: Option<Timestamp>,
+    /// Current dispute state (deposits, and withdrawals that have been disputed)
+    pub dispute_state: Option<DisputeState>,
+    /// Dispute state changes, oldest first
+    pub transitions: Vec<StateTransition>,
+    /// Client and transaction ID the entry was originally recorded under, if it was
+    /// moved to this account by [`Database::merge_accounts`] or
+    /// [`Database::split_account`]
+    pub origin: Option<(u16, u32)>,
+    /// Deposit this entry refunds, for refunds that named one
+    pub refund_of: Option<u32>,
+}
+
+/// A transaction in an account's ledger, as listed by [`Account::ledger`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LedgerRecord {
+    /// Transaction ID
+    pub tx: u32,
+    /// Whether this was a deposit, a withdrawal or a refund
+    pub kind: EntryKind,
+    /// Original transaction amount
+    pub amount: Fixed4,
+    /// When the transaction was made, if known
+    pub timestamp: Option<Timestamp>,
+    /// Current dispute state (deposits, and withdrawals that have been disputed)
+    pub dispute_state: Option<DisputeState>,
+    /// Deposit this entry refunds, for refunds that named one
+    pub refund_of: Option<u32>,
+}
+
+/// Change to an account's balances, as listed in its balance history
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Activity {
+    /// Funds deposited
+    Deposit,
+    /// Funds withdrawn
+    Withdrawal,
+    /// Part or all of a deposit returned to the payer, debiting available funds
+    Refund,
+    /// Deposit or withdrawal disputed, moving its amount to held
+    Dispute,
+    /// Dispute resolved, releasing held funds
+    Resolve,
+    /// Held funds charged back
+    Chargeback,
+    /// Chargeback reversed after representment
+    ChargebackReversal,
+    /// Rolling reserve on a deposit released to available
+    ReserveRelease,
+    /// Balances moved between accounts by [`Database::merge_accounts`]
+    Merge,
+    /// Transactions moved to a new account by [`Database::split_account`]
+    Split,
+}
+
+impl std::fmt::Display for Activity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Activity::Deposit => "deposit",
+            Activity::Withdrawal => "withdrawal",
+            Activity::Refund => "refund",
+            Activity::Dispute => "dispute",
+            Activity::Resolve => "resolve",
+            Activity::Chargeback => "chargeback",
+            Activity::ChargebackReversal => "chargeback_reversal",
+            Activity::ReserveRelease => "reserve_release",
+            Activity::Merge => "merge",
+            Activity::Split => "split",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Account balances immediately after one change
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceRow {
+    /// Transaction ID the change relates to
+    pub tx: u32,
+    /// What changed the balances
+    pub activity: Activity,
+    /// When the change happened, if known
+    pub timestamp: Option<Timestamp>,
+    /// Who submitted the transaction, if recorded (see [`TransactionMeta::initiator`])
+    pub initiator: Option<String>,
+    /// Funds available after the change
+    pub available: Fixed4,
+    /// Funds held after the change
+    pub held: Fixed4,
+}
+
+/// Administrative event recorded against an account
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuditEvent {
+    /// Account state changed through [`Database::set_account_state`]
+    StateChanged {
+        /// State before the change
+        from: AccountState,
+        /// State after the change
+        to: AccountState,
+    },
+    /// A locked account was marked as reviewed
+    Reviewed {
+        /// Who reviewed the account
+        reviewer: String,
+    },
+    /// A locked account was unlocked by the automatic unlock policy
+    AutoUnlocked,
+    /// A locked account was unlocked by an operator through [`Database::unlock_account`]
+    AdminUnlock {
+        /// Why it was unlocked, e.g. a reference to the manually resolved dispute
+        reason: String,
+    },
+    /// This account was merged into another and closed
+    MergedInto {
+        /// Account that took over the balances and ledger
+        into: u16,
+    },
+    /// Another account was merged into this one
+    MergedFrom {
+        /// Account whose balances and ledger were taken over
+        from: u16,
+    },
+    /// Transactions were moved from this account to a new one
+    SplitTo {
+        /// Account the transactions were moved to
+        client: u16,
+        /// IDs of the moved transactions
+        txs: Vec<u32>,
+    },
+    /// This account was created from transactions moved out of another
+    SplitFrom {
+        /// Account the transactions were moved from
+        client: u16,
+        /// IDs of the moved transactions
+        txs: Vec<u32>,
+    },
+    /// An operation on this account was staged for approval
+    ApprovalRequested {
+        /// ID of the pending operation
+        id: u64,
+        /// What the operation does
+        operation: Operation,
+    },
+    /// A pending operation was approved and applied
+    Approved {
+        /// ID of the operation
+        id: u64,
+        /// Who approved it
+        approver: String,
+    },
+    /// A pending operation was rejected and discarded
+    Rejected {
+        /// ID of the operation
+        id: u64,
+        /// Who rejected it
+        approver: String,
+    },
+    /// Balances that had drifted from the ledger were reset to what it implies, by
+    /// [`Database::audit_and_repair`]
+    BalancesRepaired {
+        /// Available funds before the repair
+        available: Fixed4,
+        /// Held funds before the repair
+        held: Fixed4,
+    },
+    /// Chargebacks reached a [`ChargebackLimit`](crate::ChargebackLimit) of the policy
+    ChargebackLimitReached {
+        /// Chargebacks counted towards the limit
+        chargebacks: u32,
+        /// Amount they charged back
+        volume: Fixed4,
+        /// What was done to the account
+        action: LimitAction,
+    },
+}
+
+impl std::fmt::Display for AuditEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuditEvent::StateChanged { from, to } => {
+                write!(f, "state changed from {} to {}", from, to)
+            }
+            AuditEvent::Reviewed { reviewer } => write!(f, "reviewed by {}", reviewer),
+            AuditEvent::AutoUnlocked => write!(f, "automatically unlocked"),
+            AuditEvent::AdminUnlock { reason } => write!(f, "unlocked by an operator: {}", reason),
+            AuditEvent::MergedInto { into } => write!(f, "merged into client {}", into),
+            AuditEvent::MergedFrom { from } => write!(f, "merged from client {}", from),
+            AuditEvent::SplitTo { client, txs } => {
+                write!(f, "moved {} transactions to client {}", txs.len(), client)
+            }
+            AuditEvent::SplitFrom { client, txs } => {
+                write!(f, "moved {} transactions from client {}", txs.len(), client)
+            }
+            AuditEvent::ApprovalRequested { id, operation } => {
+                write!(f, "operation {} ({}) staged for approval", id, operation)
+            }
+            AuditEvent::Approved { id, approver } => {
+                write!(f, "operation {} approved by {}", id, approver)
+            }
+            AuditEvent::Rejected { id, approver } => {
+                write!(f, "operation {} rejected by {}", id, approver)
+            }
+            AuditEvent::BalancesRepaired { available, held } => write!(
+                f,
+                "balances repaired from available {}, held {}",
+                available, held
+            ),
+            AuditEvent::ChargebackLimitReached {
+                chargebacks,
+                volume,
+                action,
+            } => write!(
+                f,
+                "{} after reaching the chargeback limit ({} chargebacks totalling {})",
+                action, chargebacks, volume
+            ),
+        }
+    }
+}
+
+/// An [`AuditEvent`] and when it happened
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// When the event happened, if known
+    pub timestamp: Option<Timestamp>,
+    /// What happened
+    pub event: AuditEvent,
+}
+
+/// A difference between an account's stored balances and those its ledger implies
+///
+/// The engine keeps the two in step, so drift means the state was damaged outside it,
+/// e.g. by a hand-edited dump or a faulty storage backend. Found by
+/// [`Database::audit_balances`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BalanceDrift {
+    /// Client ID
+    pub client: u16,
+    /// Available funds as stored
+    pub available: Fixed4,
+    /// Held funds as stored
+    pub held: Fixed4,
+    /// Available funds the ledger implies
+    pub ledger_available: Fixed4,
+    /// Held funds the ledger implies
+    pub ledger_held: Fixed4,
+}
+
+impl std::fmt::Display for BalanceDrift {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "client {}: stored available {}, held {}; ledger implies available {}, held {}",
+            self.client, self.available, self.held, self.ledger_available, self.ledger_held
+        )
+    }
+}
+
+/// Snapshot of a client's balances and transaction history
+///
+/// Lines are listed in the order the transactions were processed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    /// Client the statement is for
+    pub client: u16,
+    /// Funds available for withdrawal
+    pub available: Fixed4,
+    /// Funds held due to disputes
+    pub held: Fixed4,
+    /// Available plus held funds
+    pub total: Fixed4,
+    /// Account lifecycle state
+    pub state: AccountState,
+    /// Ledger entries in processing order
+    pub lines: Vec<StatementLine>,
+    /// Every change to the balances, oldest first, including dispute holds and
+    /// chargebacks (see [`Account::balance_history`]); only the latest rows if the
+    /// policy limits the history
+    pub history: Vec<BalanceRow>,
+}
+
+// =============================================================================
+// DATABASE
+// =============================================================================
+
+/// In-memory database for managing client accounts and transactions
+///
+/// The Database manages multiple client accounts and processes financial transactions.
+/// It ensures data consistency, handles error conditions, and maintains audit trails
+/// for regulatory compliance.
+///
+/// # Features
+/// - Multi-client account management
+/// - Transaction processing with balance validation
+/// - Dispute resolution workflow (dispute → resolve/chargeback)
+/// - Account locking after chargebacks
+/// - Complete audit trail maintenance
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Database, Transaction};
+/// let mut db = Database::new();
+///
+/// // Process transactions for different clients
+/// let deposit1 = Transaction::deposit("100.00").unwrap();
+/// let deposit2 = Transaction::deposit("200.00").unwrap();
+///
+/// db.process_transaction(1, 1, deposit1).unwrap();
+/// db.process_transaction(2, 2, deposit2).unwrap();
+///
+/// // Check balances
+/// assert_eq!(db.get_account(1).unwrap().available().to_f64(), 100.00);
+/// assert_eq!(db.get_account(2).unwrap().available().to_f64(), 200.00);
+/// ```
+///
+/// Accounts are kept in a [`Storage`] backend, in memory unless the database was created
+/// [`with_storage`](Database::with_storage). Cloning or deserializing a database always
+/// gives one in memory. A clone of an in-memory database shares its accounts
+/// copy-on-write (see [`MemoryStorage`]), so checkpoints and snapshots are cheap to take.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Database {
+    /// Client IDs and their accounts
+    #[serde(
+        serialize_with = "storage::serialize",
+        deserialize_with = "storage::deserialize"
+    )]
+    accounts: Box<dyn Storage>,
+    /// Business rules applied while processing; configuration rather than state, so
+    /// left out of snapshots
+    #[serde(skip)]
+    policy: Policy,
+    /// Handlers for custom transaction types; configuration, like the policy
+    #[serde(skip)]
+    custom: CustomTransactions,
+    /// Dispute-lifecycle events grouped by case ID
+    #[serde(serialize_with = "sorted")]
+    cases: HashMap<String, Vec<CaseEvent>>,
+    /// Operations staged for approval, by ID
+    pending: BTreeMap<u64, PendingApproval>,
+    /// ID for the next staged operation
+    next_approval_id: u64,
+    /// Where accepted transactions are recorded before they are applied, if anywhere
+    #[serde(skip)]
+    journal: Option<Journal>,
+    /// In a clone, how much of the original's journal had been written when it was
+    /// taken, for [`roll_back`](Database::roll_back)
+    #[serde(skip)]
+    journal_mark: Option<u64>,
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for Database {
+    fn clone(&self) -> Self {
+        Self {
+            accounts: Box::new(self.accounts.fork()),
+            policy: self.policy.clone(),
+            custom: self.custom.clone(),
+            cases: self.cases.clone(),
+            pending: self.pending.clone(),
+            next_approval_id: self.next_approval_id,
+            // Two databases writing one journal would corrupt it
+            journal: None,
+            journal_mark: self.journal.as_ref().map(Journal::position),
+        }
+    }
+}
+
+impl Database {
+    /// Create a new empty database
+    pub fn new() -> Self {
+        Self::with_policy(Policy::default())
+    }
+
+    /// Create a new empty database that applies the given policy
+    ///
+    /// # Panics
+    /// Panics if the policy is [invalid](Policy::validate).
+    pub fn with_policy(policy: Policy) -> Self {
+        Self::with_storage(MemoryStorage::new(), policy)
+    }
+
+    /// Create a database that keeps its accounts in `storage` and applies the given
+    /// policy
+    ///
+    /// Accounts already in `storage` are picked up as they are. Call
+    /// [`flush`](Database::flush) to make changes durable.
+    ///
+    /// # Panics
+    /// Panics if the policy is [invalid](Policy::validate).
+    pub fn with_storage(storage: impl Storage + 'static, policy: Policy) -> Self {
+        check_policy(&policy);
+        Self {
+            accounts: Box::new(storage),
+            policy,
+            custom: CustomTransactions::default(),
+            cases: HashMap::new(),
+            pending: BTreeMap::new(),
+            next_approval_id: 0,
+            journal: None,
+            journal_mark: None,
+        }
+    }
+
+    /// Put the state back as it was when `checkpoint` was cloned from this database
+    ///
+    /// A clone keeps its accounts in memory and has no journal, so assigning it back
+    /// would leave the database without its storage backend and journal. Rolling back
+    /// instead replaces the accounts in the database's own storage, along with the
+    /// dispute cases and operations awaiting approval, and cuts the transactions
+    /// journaled since the clone off the journal, so they aren't replayed after a crash.
+    /// The policy and custom transaction types are kept.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Database, JsonFileStorage, Policy, Transaction};
+    /// let dir = tempfile::tempdir().unwrap();
+    /// let storage = JsonFileStorage::open(dir.path().join("accounts.json")).unwrap();
+    /// let mut db = Database::with_storage(storage, Policy::default());
+    /// db.process_transaction(1, 1, Transaction::deposit("10").unwrap()).unwrap();
+    ///
+    /// let checkpoint = db.clone();
+    /// db.process_transaction(1, 2, Transaction::deposit("99").unwrap()).unwrap();
+    /// db.roll_back(checkpoint).unwrap();
+    /// db.flush().unwrap();
+    ///
+    /// // Still backed by the file
+    /// let storage = JsonFileStorage::open(dir.path().join("accounts.json")).unwrap();
+    /// let reopened = Database::with_storage(storage, Policy::default());
+    /// assert_eq!(reopened.get_account(1).unwrap().total().to_string(), "10.0000");
+    /// ```
+    ///
+    /// # Errors
+    /// Returns an error if the journal can't be cut back. The accounts are rolled back
+    /// regardless.
+    pub fn roll_back(&mut self, checkpoint: Database) -> std::io::Result<()> {
+        let mark = checkpoint.journal_mark;
+        self.replace_state(checkpoint);
+        match (&mut self.journal, mark) {
+            (Some(journal), Some(mark)) => journal.truncate(mark),
+            _ => Ok(()),
+        }
+    }
+
+    /// Replace the accounts, cases and staged operations with those of `state`, keeping
+    /// the storage backend, journal, policy and custom transaction types
+    pub(crate) fn replace_state(&mut self, mut state: Database) {
+        let stale: Vec<u16> = self.accounts.iter().map(|(client, _)| client).collect();
+        for client in stale {
+            self.accounts.remove(client);
+        }
+        let clients: Vec<u16> = state.accounts.iter().map(|(client, _)| client).collect();
+        for client in clients {
+            if let Some(account) = state.accounts.remove(client) {
+                self.accounts.insert(client, account);
+            }
+        }
+        self.cases = state.cases;
+        self.pending = state.pending;
+        self.next_approval_id = state.next_approval_id;
+    }
+
+    /// Make every change to the accounts so far durable, if their storage is
+    ///
+    /// # Errors
+    /// Returns an error if the storage can't be written.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.accounts.flush()
+    }
+
+    /// Record every transaction accepted from now on in `journal`, before applying it
+    ///
+    /// A transaction that can't be journaled is rejected with [`EngineError::Io`] and
+    /// leaves the account untouched. Clones of the database don't inherit the journal.
+    pub fn set_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
+    /// The journal accepted transactions are recorded in, if any
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    /// Apply every transaction recorded in the journal at `path`, in order, returning
+    /// how many were applied
+    ///
+    /// Used after a crash to rebuild the state from the one the journal was started
+    /// on: an empty database, or one restored from a snapshot. Replayed transactions
+    /// are not journaled again, nor staged for approval, since they were accepted when
+    /// first applied. See [`Journal`] for an example.
+    ///
+    /// # Errors
+    /// Returns an error if the journal can't be read or holds an invalid entry, or if
+    /// an entry is rejected, which means the database isn't in the state the journal
+    /// was started on. Entries before the one that failed stay applied.
+    pub fn replay_journal(
+        &mut self,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let events = journal::read(path.as_ref())?;
+        let journal = self.journal.take();
+        let mut applied = 0;
+        let mut result = Ok(());
+        for event in events {
+            result = self.apply_transaction(event.client, event.tx, event.transaction, event.meta);
+            if result.is_err() {
+                break;
+            }
+            applied += 1;
+        }
+        self.journal = journal;
+        result?;
+        Ok(applied)
+    }
+
+    /// Business rules applied while processing
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    /// Dump every account as pretty-printed JSON, for debugging, support tickets, or
+    /// moving state between engine versions
+    ///
+    /// The dump is an object with these keys:
+    /// - `accounts` - each account by client ID, with its `available` and `held`
+    ///   balances (exact decimal strings), `state`, full `ledger` by transaction ID
+    ///   (including dispute states and transitions), `audit` trail, balance `history`
+    ///   and, if any labels are set, `metadata`
+    /// - `cases` - dispute-lifecycle events by case ID
+    /// - `pending` - operations awaiting approval, by ID
+    ///
+    /// The policy is configuration rather than state, so it is not included. Every map
+    /// is written in key order, so the same state always dumps to the same text.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Database, Transaction};
+    /// let mut db = Database::new();
+    /// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+    /// db.process_transaction(1, 1, Transaction::dispute()).unwrap();
+    ///
+    /// let json = db.to_json();
+    /// assert!(json.contains(r#""held": "100.0000""#));
+    ///
+    /// let loaded = Database::from_json(&json).unwrap();
+    /// assert_eq!(loaded.get_account(1).unwrap().held().to_string(), "100.0000");
+    /// ```
+    pub fn to_json(&self) -> String {
+        // Every key is a string or number and every value serializable, so this can't fail
+        serde_json::to_string_pretty(self).expect("database serializes to JSON")
+    }
+
+    /// SHA-256 of the complete state, as lowercase hex
+    ///
+    /// The state is serialized canonically, with every map sorted by key, so two runs
+    /// over the same input produce the same digest whatever order accounts were created
+    /// in. Comparing digests is a cheap way to check that two runs (or two engine
+    /// versions) agree on every balance, ledger entry and audit record.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Database, Transaction};
+    /// let mut a = Database::new();
+    /// a.process_transaction(1, 1, Transaction::deposit("10").unwrap()).unwrap();
+    /// a.process_transaction(2, 2, Transaction::deposit("20").unwrap()).unwrap();
+    ///
+    /// let mut b = Database::new();
+    /// b.process_transaction(1, 1, Transaction::deposit("10").unwrap()).unwrap();
+    /// b.process_transaction(2, 2, Transaction::deposit("20").unwrap()).unwrap();
+    /// assert_eq!(a.digest(), b.digest());
+    /// assert_eq!(a.digest().len(), 64);
+    ///
+    /// b.process_transaction(2, 3, Transaction::withdrawal("1").unwrap()).unwrap();
+    /// assert_ne!(a.digest(), b.digest());
+    /// ```
+    pub fn digest(&self) -> String {
+        let state = serde_json::to_value(self).expect("database serializes to JSON");
+        let hash = Sha256::digest(canonical(state).to_string().as_bytes());
+        hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// Load a database dumped by [`to_json`](Database::to_json), with the default policy
+    ///
+    /// # Errors
+    /// Returns an error if `json` is not a valid dump.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// The complete state, for saving and later resuming with
+    /// [`from_snapshot`](Database::from_snapshot)
+    ///
+    /// The [`Snapshot`] holds every account, with its balances, lock state and ledger,
+    /// along with the dispute cases and operations awaiting approval, and can be
+    /// serialized with any serde format. It is the same structure an
+    /// [`Engine::snapshot`](crate::Engine::snapshot) holds, without source offsets.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Database, Policy, Snapshot, Transaction};
+    /// let mut db = Database::new();
+    /// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+    /// db.process_transaction(1, 1, Transaction::dispute()).unwrap();
+    ///
+    /// let saved = serde_json::to_string(&db.to_snapshot()).unwrap();
+    ///
+    /// // After a restart, pick up where processing stopped
+    /// let snapshot: Snapshot = serde_json::from_str(&saved).unwrap();
+    /// let mut db = Database::from_snapshot(snapshot, Policy::default());
+    /// db.process_transaction(1, 1, Transaction::resolve()).unwrap();
+    /// assert_eq!(db.get_account(1).unwrap().available().to_string(), "100.0000");
+    /// ```
+    pub fn to_snapshot(&self) -> Snapshot {
+        Snapshot {
+            database: self.clone(),
+            offsets: Vec::new(),
+        }
+    }
+
+    /// Restore the state saved by [`to_snapshot`](Database::to_snapshot), applying
+    /// `policy` from now on
+    ///
+    /// The accounts are kept in memory; use [`with_storage`](Database::with_storage) to
+    /// keep them elsewhere. Source offsets in a snapshot taken from an
+    /// [`Engine`](crate::Engine) are dropped, as a database doesn't track them.
+    ///
+    /// # Panics
+    /// Panics if the policy is [invalid](Policy::validate).
+    pub fn from_snapshot(snapshot: Snapshot, policy: Policy) -> Self {
+        let mut database = snapshot.database;
+        database.set_policy(policy);
+        database
+    }
+
+    /// Replace the business rules, e.g. after restoring the accounts from a snapshot
+    pub(crate) fn set_policy(&mut self, policy: Policy) {
+        check_policy(&policy);
+        self.policy = policy;
+    }
+
+    /// Keep the accounts in `storage` from now on, dropping those kept so far
+    pub(crate) fn set_storage(&mut self, storage: Box<dyn Storage>) {
+        self.accounts = storage;
+    }
+
+    /// The account of `client_id`, opened if it has none yet
+    fn account_or_new(&mut self, client_id: u16) -> &mut Account {
+        if self.accounts.get(client_id).is_none() {
+            self.accounts.insert(client_id, Account::new());
+        }
+        self.accounts
+            .get_mut(client_id)
+            .expect("account was just opened")
+    }
+
+    /// Custom transaction types this database applies
+    pub fn custom_transactions(&self) -> &CustomTransactions {
+        &self.custom
+    }
+
+    /// Apply rows of the custom transaction types in `types` (see
+    /// [`CustomTransaction`](crate::CustomTransaction))
+    pub fn set_custom_transactions(&mut self, types: CustomTransactions) {
+        self.custom = types;
+    }
+
+    /// Process a financial transaction for a client
+    ///
+    /// Creates a new account if the client doesn't exist. Validates business rules
+    /// such as sufficient funds for withdrawals and account lock status.
+    ///
+    /// # Arguments
+    /// * `client_id` - Unique identifier for the client
+    /// * `txn_id` - Unique identifier for this transaction
+    /// * `transaction` - The transaction to process
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Database, Transaction};
+    /// let mut db = Database::new();
+    ///
+    /// // Process a deposit
+    /// let deposit = Transaction::deposit("100.00").unwrap();
+    /// db.process_transaction(1, 1, deposit).unwrap();
+    ///
+    /// // Process a withdrawal  
+    /// let withdrawal = Transaction::withdrawal("25.00").unwrap();
+    /// db.process_transaction(1, 2, withdrawal).unwrap();
+    ///
+    /// let account = db.get_account(1).unwrap();
+    /// assert_eq!(account.available().to_f64(), 75.00);
+    /// ```
+    ///
+    /// # Errors
+    /// Errors are returned as a [`TransactionError`] carrying the client, transaction ID
+    /// and amount alongside one of the following kinds:
+    /// - [`EngineError::InsufficientFunds`] - Withdrawal amount exceeds available balance
+    /// - [`EngineError::AccountLocked`] - Attempted deposit/withdrawal on locked account
+    /// - [`EngineError::TransactionNotFound`] - Dispute/resolve/chargeback on non-existent transaction
+    /// - [`EngineError::BalanceOverflow`] - A balance would exceed what a [`Fixed4`] holds
+    /// - Other transaction-specific errors (see [`EngineError`] for complete list)
+    ///
+    /// # Panics
+    /// Should applying the transaction panic, the account is left exactly as it was
+    /// before. Every change is staged
+    /// before any is made, so no account is ever left half-updated, and a database
+    /// behind a lock poisoned by such a panic can safely be recovered with
+    /// [`PoisonError::into_inner`](std::sync::PoisonError::into_inner).
+    pub fn process_transaction(
+        &mut self,
+        client_id: u16,
+        txn_id: u32,
+        transaction: Transaction,
+    ) -> Result<(), TransactionError> {
+        self.process_transaction_at(client_id, txn_id, transaction, None)
+    }
+
+    /// Process a financial transaction that happened at a known time
+    ///
+    /// Behaves exactly like [`Database::process_transaction`], additionally recording
+    /// the timestamp on new ledger entries and on dispute state transitions.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Database, DisputeState, Transaction};
+    /// let mut db = Database::new();
+    /// db.process_transaction_at(1, 1, Transaction::deposit("100").unwrap(), Some(1_000))
+    ///     .unwrap();
+    /// db.process_transaction_at(1, 1, Transaction::dispute(), Some(2_000)).unwrap();
+    ///
+    /// let statement = db.statement(1).unwrap();
+    /// let deposit = &statement.lines[0];
+    /// assert_eq!(deposit.timestamp, Some(1_000));
+    /// assert_eq!(deposit.dispute_state, Some(DisputeState::Disputed));
+    /// assert_eq!(deposit.transitions[0].timestamp, Some(2_000));
+    /// ```
+    pub fn process_transaction_at(
+        &mut self,
+        client_id: u16,
+        txn_id: u32,
+        transaction: Transaction,
+        timestamp: Option<Timestamp>,
+    ) -> Result<(), TransactionError> {
+        let meta = TransactionMeta {
+            timestamp,
+            ..TransactionMeta::default()
+        };
+        self.process_transaction_with(client_id, txn_id, transaction, meta)
+    }
+
+    /// Process a financial transaction along with its metadata
+    ///
+    /// Behaves exactly like [`Database::process_transaction`], additionally recording
+    /// everything supplied in `meta` (see [`TransactionMeta`]).
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Database, DisputeAction, Transaction, TransactionMeta};
+    /// let mut db = Database::new();
+    /// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+    ///
+    /// let case = TransactionMeta {
+    ///     case_id: Some("CASE-17".to_string()),
+    ///     ..TransactionMeta::default()
+    /// };
+    /// db.process_transaction_with(1, 1, Transaction::dispute(), case.clone()).unwrap();
+    /// db.process_transaction_with(1, 1, Transaction::resolve(), case).unwrap();
+    ///
+    /// let actions: Vec<_> = db.disputes_by_case("CASE-17").iter().map(|e| e.action).collect();
+    /// assert_eq!(actions, vec![DisputeAction::Dispute, DisputeAction::Resolve]);
+    /// ```
+    pub fn process_transaction_with(
+        &mut self,
+        client_id: u16,
+        txn_id: u32,
+        transaction: Transaction,
+        meta: TransactionMeta,
+    ) -> Result<(), TransactionError> {
+        self.submit_transaction(client_id, txn_id, transaction, meta, false)
+    }
+
+    /// Apply a transaction, or stage it for approval if the policy requires one
+    ///
+    /// Withdrawals, and with `manual` any adjustment, moving more than
+    /// [`Policy::approval_threshold`] are staged. They are validated first, so only an
+    /// operation that would apply right now is ever staged.
+    fn submit_transaction(
+        &mut self,
+        client_id: u16,
+        txn_id: u32,
+        transaction: Transaction,
+        meta: TransactionMeta,
+        manual: bool,
+    ) -> Result<(), TransactionError> {
+        let gated = match transaction {
+            Transaction::Withdrawal { amount } => Some(amount),
+            Transaction::Custom { .. } => None,
+            _ if manual => transaction.amount().map(|amount| amount.max(-amount)),
+            _ => None,
+        };
+        if let Some(amount) = gated
+            && self
+                .policy
+                .approval_threshold
+                .is_some_and(|threshold| amount > threshold)
+        {
+            let in_use = self
+                .accounts
+                .get(client_id)
+                .is_some_and(|account| account.ledger.contains_key(&txn_id))
+                || self.pending.values().any(|pending| {
+                    pending.client == client_id
+                        && matches!(pending.operation, Operation::Transaction { tx, .. } if tx == txn_id)
+                });
+            if in_use {
+                return Err(TransactionError {
+                    client: client_id,
+                    tx: txn_id,
+                    amount: transaction.amount(),
+                    kind: EngineError::TransactionIdInUse,
+                    lock: None,
+                });
+            }
+            self.plan(client_id, txn_id, transaction.clone(), meta.timestamp)?;
+            let amount = transaction.amount();
+            let id = self.stage(
+                client_id,
+                Operation::Transaction {
+                    tx: txn_id,
+                    transaction,
+                    meta,
+                },
+            );
+            return Err(TransactionError {
+                client: client_id,
+                tx: txn_id,
+                amount,
+                kind: EngineError::PendingApproval { id },
+                lock: None,
+            });
+        }
+        self.apply_transaction(client_id, txn_id, transaction, meta)
+    }
+
+    /// Apply a transaction, without staging it for approval
+    ///
+    /// The delta is planned first, without touching the account, and then committed in
+    /// one step. A panic while planning (an arithmetic overflow, say) leaves the account
+    /// exactly as it was, never with its balances updated but not its ledger, or one
+    /// balance updated but not the other.
+    fn apply_transaction(
+        &mut self,
+        client_id: u16,
+        txn_id: u32,
+        transaction: Transaction,
+        meta: TransactionMeta,
+    ) -> Result<(), TransactionError> {
+        if let Transaction::Custom { name, amount } = transaction {
+            return self.apply_custom(client_id, txn_id, name, amount, meta);
+        }
+        self.account_or_new(client_id);
+
+        let action = transaction.dispute_action();
+        let activity = transaction.activity();
+        let entry = self.journal.is_some().then(|| Event {
+            client: client_id,
+            tx: txn_id,
+            transaction: transaction.clone(),
+            meta: meta.clone(),
+        });
+        let delta = self.plan(client_id, txn_id, transaction, meta.timestamp)?;
+        if let (Some(journal), Some(entry)) = (&mut self.journal, entry) {
+            journal.append(&entry).map_err(|err| TransactionError {
+                client: client_id,
+                tx: txn_id,
+                amount: entry.transaction.amount(),
+                kind: EngineError::Io(err),
+                lock: None,
+            })?;
+        }
+        let account = self
+            .accounts
+            .get_mut(client_id)
+            .expect("account was opened");
+        account.commit(delta);
+        if action == Some(DisputeAction::Chargeback) {
+            account.apply_chargeback_limits(&self.policy, meta.timestamp);
+        }
+        account.record_balances(
+            txn_id,
+            activity,
+            meta.timestamp,
+            meta.initiator.clone(),
+            self.policy.balance_history_limit,
+        );
+
+        if let (Some(case_id), Some(action)) = (meta.case_id, action) {
+            self.cases.entry(case_id).or_default().push(CaseEvent {
+                client: client_id,
+                tx: txn_id,
+                action,
+                timestamp: meta.timestamp,
+            });
+        }
+        Ok(())
+    }
+
+    /// Work out what a transaction would change, without changing anything
+    ///
+    /// This is a dry run of [`process_transaction_at`](Self::process_transaction_at):
+    /// the same checks are made and the same arithmetic done, and the returned delta is
+    /// exactly what applying the transaction would commit. A client with no account is
+    /// treated as having a new, empty one.
+    ///
+    /// Transactions that need approval are planned as if they had it, and custom
+    /// transactions cannot be simulated, since their handlers act on the account
+    /// directly.
+    ///
+    /// # Errors
+    /// Returns the error applying the transaction would return, or
+    /// [`EngineError::UnknownTransactionType`] for a custom transaction.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Database, EngineError, Transaction};
+    /// let mut db = Database::new();
+    /// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+    ///
+    /// let delta = db.simulate(1, 2, Transaction::withdrawal("40").unwrap(), None).unwrap();
+    /// assert_eq!(delta.d_available.to_string(), "-40.0000");
+    /// // Nothing was applied
+    /// assert_eq!(db.get_account(1).unwrap().available().to_string(), "100.0000");
+    ///
+    /// let err = db.simulate(1, 2, Transaction::withdrawal("400").unwrap(), None).unwrap_err();
+    /// assert!(matches!(err.kind, EngineError::InsufficientFunds));
+    /// ```
+    pub fn simulate(
+        &self,
+        client_id: u16,
+        txn_id: u32,
+        transaction: Transaction,
+        timestamp: Option<Timestamp>,
+    ) -> Result<AccountDelta, TransactionError> {
+        if let Transaction::Custom { name, amount } = transaction {
+            return Err(TransactionError {
+                client: client_id,
+                tx: txn_id,
+                amount,
+                kind: EngineError::UnknownTransactionType(name),
+                lock: None,
+            });
+        }
+        self.plan(client_id, txn_id, transaction, timestamp)
+    }
+
+    /// Check a transaction against the account and work out its delta
+    fn plan(
+        &self,
+        client_id: u16,
+        txn_id: u32,
+        transaction: Transaction,
+        timestamp: Option<Timestamp>,
+    ) -> Result<AccountDelta, TransactionError> {
+        let new_account;
+        let account = match self.accounts.get(client_id) {
+            Some(account) => account,
+            None => {
+                new_account = Account::new();
+                &new_account
+            }
+        };
+
+        let amount = transaction
+            .amount()
+            .or_else(|| account.ledger_amount(txn_id));
+        let lock = account.lock_details();
+        let with_context = |kind| TransactionError {
+            client: client_id,
+            tx: txn_id,
+            amount,
+            lock: matches!(kind, EngineError::AccountLocked)
+                .then(|| lock.clone())
+                .flatten(),
+            kind,
+        };
+
+        // Only check the account state for deposit/withdrawal transactions
+        // Dispute, resolve, and chargeback operations are allowed in every state
+        match transaction {
+            Transaction::Deposit { amount } => {
+                // A negative deposit is a refund, paying funds out
+                let outgoing = amount < Fixed4::zero();
+                account
+                    .state()
+                    .check_accepts_funds(outgoing)
+                    .map_err(with_context)?;
+            }
+            Transaction::Withdrawal { .. } | Transaction::Refund { .. } => {
+                account
+                    .state()
+                    .check_accepts_funds(true)
+                    .map_err(with_context)?;
+            }
+            Transaction::Dispute
+            | Transaction::Resolve
+            | Transaction::Chargeback
+            | Transaction::ChargebackReversal
+            | Transaction::Custom { .. } => {
+                // These operations are allowed on locked, frozen and closed accounts
+            }
+        }
+
+        account
+            .delta(txn_id, transaction, timestamp, &self.policy)
+            .map_err(with_context)
+    }
+
+    /// Apply a custom transaction through its registered handler
+    fn apply_custom(
+        &mut self,
+        client_id: u16,
+        txn_id: u32,
+        name: String,
+        amount: Option<Fixed4>,
+        meta: TransactionMeta,
+    ) -> Result<(), TransactionError> {
+        let Some(handler) = self.custom.get(&name) else {
+            return Err(TransactionError {
+                client: client_id,
+                tx: txn_id,
+                amount,
+                kind: EngineError::UnknownTransactionType(name),
+                lock: None,
+            });
+        };
+        self.account_or_new(client_id);
+        let mut ops = AccountOps {
+            db: self,
+            client: client_id,
+            meta,
+        };
+        handler
+            .apply(&mut ops, txn_id, amount)
+            .map_err(|kind| TransactionError {
+                client: client_id,
+                tx: txn_id,
+                amount,
+                lock: matches!(kind, EngineError::AccountLocked)
+                    .then(|| self.accounts.get(client_id).and_then(Account::lock_details))
+                    .flatten(),
+                kind,
+            })
+    }
+
+    /// Dispute a set of transactions atomically: either every hold is placed or none is
+    ///
+    /// Each element is a `(client_id, txn_id)` pair. All of them are validated before any
+    /// funds move, so a single invalid reference leaves every account untouched. The same
+    /// metadata (e.g. a fraud case ID) is recorded for each dispute. A
+    /// [journal](Self::set_journal) records the bundle as a single entry, so a failure
+    /// to journal it, like a crash, never leaves it partly applied.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Database, EngineError, Transaction, TransactionMeta};
+    /// let mut db = Database::new();
+    /// db.process_transaction(1, 1, Transaction::deposit("10").unwrap()).unwrap();
+    /// db.process_transaction(2, 2, Transaction::deposit("20").unwrap()).unwrap();
+    ///
+    /// // Transaction 3 does not exist, so nothing is disputed
+    /// let err = db
+    ///     .dispute_bundle(&[(1, 1), (2, 2), (2, 3)], TransactionMeta::default())
+    ///     .unwrap_err();
+    /// assert_eq!((err.client, err.tx), (2, 3));
+    /// assert!(matches!(err.kind, EngineError::TransactionNotFound));
+    /// assert_eq!(db.get_account(1).unwrap().held().to_string(), "0.0000");
+    ///
+    /// db.dispute_bundle(&[(1, 1), (2, 2)], TransactionMeta::default()).unwrap();
+    /// assert_eq!(db.get_account(1).unwrap().held().to_string(), "10.0000");
+    /// assert_eq!(db.get_account(2).unwrap().held().to_string(), "20.0000");
+    /// ```
+    ///
+    /// # Errors
+    /// Returns the first invalid dispute as a [`TransactionError`]. A pair listed twice
+    /// is reported as [`EngineError::TransactionAlreadyDisputed`].
+    pub fn dispute_bundle(
+        &mut self,
+        transactions: &[(u16, u32)],
+        meta: TransactionMeta,
+    ) -> Result<(), TransactionError> {
+        let mut seen = std::collections::HashSet::new();
+        for &(client_id, txn_id) in transactions {
+            let account = self.accounts.get(client_id);
+            let check = match account {
+                Some(_) if !seen.insert((client_id, txn_id)) => {
+                    Err(EngineError::TransactionAlreadyDisputed)
+                }
+                Some(account) => account.check_dispute(txn_id, &self.policy),
+                None => Err(EngineError::TransactionNotFound),
+            };
+            check.map_err(|kind| TransactionError {
+                client: client_id,
+                tx: txn_id,
+                amount: account.and_then(|account| account.ledger_amount(txn_id)),
+                kind,
+                lock: None,
+            })?;
+        }
+
+        if let (Some(journal), Some(&(client_id, txn_id))) =
+            (&mut self.journal, transactions.first())
+        {
+            let events: Vec<Event> = transactions
+                .iter()
+                .map(|&(client, tx)| Event {
+                    client,
+                    tx,
+                    transaction: Transaction::dispute(),
+                    meta: meta.clone(),
+                })
+                .collect();
+            journal
+                .append_bundle(&events)
+                .map_err(|err| TransactionError {
+                    client: client_id,
+                    tx: txn_id,
+                    amount: None,
+                    kind: EngineError::Io(err),
+                    lock: None,
+                })?;
+        }
+
+        // Already journaled as a whole
+        let journal = self.journal.take();
+        let mut result = Ok(());
+        for &(client_id, txn_id) in transactions {
+            result =
+                self.apply_transaction(client_id, txn_id, Transaction::dispute(), meta.clone());
+            if result.is_err() {
+                break;
+            }
+        }
+        self.journal = journal;
+        result
+    }
+
+    /// All accepted dispute-lifecycle events tagged with a case ID, in processing order
+    ///
+    /// Returns an empty slice for unknown case IDs.
+    pub fn disputes_by_case(&self, case_id: &str) -> &[CaseEvent] {
+        self.cases.get(case_id).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Check whether a transaction repeats one that has already been applied
+    ///
+    /// Only deposits and withdrawals can be duplicates: they must match an existing
+    /// ledger entry for the same client in ID, kind and amount.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Database, Transaction};
+    /// let mut db = Database::new();
+    /// db.process_transaction(1, 1, Transaction::deposit("10.00").unwrap()).unwrap();
+    ///
+    /// assert!(db.is_duplicate(1, 1, &Transaction::deposit("10.00").unwrap()));
+    /// assert!(!db.is_duplicate(1, 1, &Transaction::deposit("12.00").unwrap()));
+    /// assert!(!db.is_duplicate(2, 1, &Transaction::deposit("10.00").unwrap()));
+    /// ```
+    pub fn is_duplicate(&self, client_id: u16, txn_id: u32, transaction: &Transaction) -> bool {
+        self.accounts
+            .get(client_id)
+            .is_some_and(|account| account.is_duplicate(txn_id, transaction))
+    }
+
+    /// Move a client's account to a new lifecycle state
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{AccountState, Database, EngineError, Transaction};
+    /// let mut db = Database::new();
+    /// db.process_transaction(1, 1, Transaction::deposit("10.00").unwrap()).unwrap();
+    ///
+    /// db.set_account_state(1, AccountState::Frozen).unwrap();
+    /// let err = db
+    ///     .process_transaction(1, 2, Transaction::deposit("5.00").unwrap())
+    ///     .unwrap_err();
+    /// assert!(matches!(err.kind, EngineError::AccountFrozen));
+    ///
+    /// db.set_account_state(1, AccountState::Active).unwrap();
+    /// // Accounts holding funds cannot be closed
+    /// assert!(matches!(
+    ///     db.set_account_state(1, AccountState::Closed),
+    ///     Err(EngineError::AccountNotSettled)
+    /// ));
+    /// ```
+    ///
+    /// # Errors
+    /// - [`EngineError::AccountNotFound`] - The client has no account
+    /// - [`EngineError::InvalidStateTransition`] - The transition is not allowed (see [`AccountState`])
+    /// - [`EngineError::AccountNotSettled`] - Closing an account whose balances are not all zero
+    pub fn set_account_state(
+        &mut self,
+        client_id: u16,
+        state: AccountState,
+    ) -> Result<(), EngineError> {
+        let account = self
+            .accounts
+            .get(client_id)
+            .ok_or(EngineError::AccountNotFound)?;
+        if self.policy.unlocks_require_approval
+            && account.state() == AccountState::Locked
+            && state == AccountState::Active
+        {
+            let id = self.stage(client_id, Operation::SetState { state });
+            return Err(EngineError::PendingApproval { id });
+        }
+        self.apply_account_state(client_id, state)
+    }
+
+    /// Change an account's state, without staging it for approval
+    #[allow(deprecated)]
+    fn apply_account_state(
+        &mut self,
+        client_id: u16,
+        state: AccountState,
+    ) -> Result<(), EngineError> {
+        let account = self
+            .accounts
+            .get_mut(client_id)
+            .ok_or(EngineError::AccountNotFound)?;
+
+        if !account.state().can_transition_to(state) {
+            return Err(EngineError::InvalidStateTransition {
+                from: account.state(),
+                to: state,
+            });
+        }
+        if state == AccountState::Closed
+            && (account.available() != Fixed4::zero() || account.held() != Fixed4::zero())
+        {
+            return Err(EngineError::AccountNotSettled);
+        }
+
+        let from = account.state();
+        if state == AccountState::Active && from == AccountState::Locked {
+            account.unlock();
+        } else {
+            account.state = state;
+            if state == AccountState::Locked {
+                account.lock_reason = Some(LockReason::Manual);
+            }
+        }
+        account.record(None, AuditEvent::StateChanged { from, to: state });
+        Ok(())
+    }
+
+    /// Merge two accounts found to belong to the same customer
+    ///
+    /// The balances and every ledger entry of `from` move to `into`. Entries are re-keyed
+    /// to consecutive transaction IDs starting at `new_tx_base`, in processing order, so
+    /// they cannot clash with `into`'s own; each keeps its original client and
+    /// transaction ID as [`StatementLine::origin`]. Dispute cases follow the entries.
+    /// Later disputes must use the new IDs.
+    ///
+    /// `from` is left empty and closed. If it was locked or frozen and `into` is active,
+    /// `into` takes over that state. Both accounts record the merge in their audit log
+    /// and balance history.
+    ///
+    /// Returns the `(old, new)` transaction ID pairs, in processing order.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{AccountState, Database, Transaction};
+    /// let mut db = Database::new();
+    /// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+    /// db.process_transaction(2, 7, Transaction::deposit("40").unwrap()).unwrap();
+    /// db.process_transaction(2, 8, Transaction::withdrawal("15").unwrap()).unwrap();
+    ///
+    /// let rekeyed = db.merge_accounts(2, 1, 1000).unwrap();
+    /// assert_eq!(rekeyed, vec![(7, 1000), (8, 1001)]);
+    ///
+    /// let merged = db.get_account(1).unwrap();
+    /// assert_eq!(merged.available().to_string(), "125.0000");
+    /// assert_eq!(db.statement(1).unwrap().lines[1].origin, Some((2, 7)));
+    /// assert_eq!(db.get_account(2).unwrap().state(), AccountState::Closed);
+    ///
+    /// // The merged deposit is disputed under its new ID
+    /// db.process_transaction(1, 1000, Transaction::dispute()).unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// - [`EngineError::SameAccount`] - `from` and `into` are the same client
+    /// - [`EngineError::AccountNotFound`] - Either client has no account
+    /// - [`EngineError::AccountClosed`] - Either account is closed
+    /// - [`EngineError::TransactionIdInUse`] - `into` already uses one of the new IDs, or
+    ///   they would overflow
+    /// - [`EngineError::BalanceOverflow`] - The combined balances would overflow
+    #[allow(deprecated)]
+    pub fn merge_accounts(
+        &mut self,
+        from: u16,
+        into: u16,
+        new_tx_base: u32,
+    ) -> Result<Vec<(u32, u32)>, EngineError> {
+        if from == into {
+            return Err(EngineError::SameAccount);
+        }
+        let source = self
+            .accounts
+            .get(from)
+            .ok_or(EngineError::AccountNotFound)?;
+        let target = self
+            .accounts
+            .get(into)
+            .ok_or(EngineError::AccountNotFound)?;
+        if source.state() == AccountState::Closed || target.state() == AccountState::Closed {
+            return Err(EngineError::AccountClosed);
+        }
+        target.check_balances(source.available(), source.held())?;
+
+        let mut old_ids: Vec<(u64, u32)> = source
+            .ledger
+            .iter()
+            .map(|(&tx, entry)| (entry.seq(), tx))
+            .collect();
+        old_ids.sort();
+        let mut rekeyed = Vec::with_capacity(old_ids.len());
+        for (offset, &(_, old)) in old_ids.iter().enumerate() {
+            let new = u32::try_from(offset)
+                .ok()
+                .and_then(|offset| new_tx_base.checked_add(offset))
+                .ok_or(EngineError::TransactionIdInUse)?;
+            if target.ledger.contains_key(&new) {
+                return Err(EngineError::TransactionIdInUse);
+            }
+            rekeyed.push((old, new));
+        }
+
+        // Validated: from here on nothing can fail
+        let source = self.accounts.get_mut(from).unwrap();
+        let mut ledger = std::mem::take(&mut source.ledger);
+        let (available, held) = (source.available(), source.held());
+        let (source_state, locked_at, reviewed) =
+            (source.state(), source.locked_at, source.reviewed);
+        // A chargeback lock names the transaction by its new ID
+        let lock_reason = source.lock_reason.take().map(|reason| match reason {
+            LockReason::Chargeback { tx } => LockReason::Chargeback {
+                tx: rekeyed
+                    .iter()
+                    .find(|&&(old, _)| old == tx)
+                    .map_or(tx, |&(_, new)| new),
+            },
+            reason => reason,
+        });
+        source.available = Fixed4::zero();
+        source.held = Fixed4::zero();
+        source.state = AccountState::Closed;
+        source.record(None, AuditEvent::MergedInto { into });
+        source.record_balances(
+            new_tx_base,
+            Activity::Merge,
+            None,
+            None,
+            self.policy.balance_history_limit,
+        );
+
+        let target = self.accounts.get_mut(into).unwrap();
+        for &(old, new) in &rekeyed {
+            if let Some(mut entry) = ledger.remove(&old) {
+                entry.rekey(target.take_seq(), from, old);
+                // A refund names the deposit it refunds by its new ID
+                if let LedgerEntry::Refund {
+                    original: Some(original),
+                    ..
+                } = &mut entry
+                    && let Some(&(_, renamed)) = rekeyed.iter().find(|&&(old, _)| old == *original)
+                {
+                    *original = renamed;
+                }
+                target.ledger.insert(new, entry);
+            }
+        }
+        target.available += available;
+        target.held += held;
+        if target.state() == AccountState::Active
+            && matches!(
+                source_state,
+                AccountState::Locked | AccountState::Frozen | AccountState::FrozenOutgoing
+            )
+        {
+            target.state = source_state;
+            target.locked_at = locked_at;
+            target.lock_reason = lock_reason;
+            target.reviewed = reviewed;
+        }
+        target.record(None, AuditEvent::MergedFrom { from });
+        target.record_balances(
+            new_tx_base,
+            Activity::Merge,
+            None,
+            None,
+            self.policy.balance_history_limit,
+        );
+
+        let new_ids: HashMap<u32, u32> = rekeyed.iter().copied().collect();
+        for event in self.cases.values_mut().flatten() {
+            if event.client == from
+                && let Some(&new) = new_ids.get(&event.tx)
+            {
+                event.client = into;
+                event.tx = new;
+            }
+        }
+
+        Ok(rekeyed)
+    }
+
+    /// Move selected transactions to a new client, e.g. to correct a mis-keyed feed
+    ///
+    /// Each listed ledger entry moves from `from` to a new account for `new_client`,
+    /// keeping its transaction ID, together with its effect on the balances: a
+    /// deposit's available (or, while disputed, held) funds and any rolling reserve, or
+    /// a withdrawal's debit. Entries are recorded with their original client as
+    /// [`StatementLine::origin`], and dispute cases follow them. Both accounts record the
+    /// move in their audit log and balance history.
+    ///
+    /// Charged back deposits can't be moved, since the chargeback locked `from`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{AuditEvent, Database, Transaction};
+    /// let mut db = Database::new();
+    /// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+    /// db.process_transaction(1, 2, Transaction::deposit("30").unwrap()).unwrap();
+    /// db.process_transaction(1, 3, Transaction::withdrawal("10").unwrap()).unwrap();
+    ///
+    /// // Transactions 2 and 3 were really client 5's
+    /// db.split_account(1, 5, &[2, 3]).unwrap();
+    ///
+    /// assert_eq!(db.get_account(1).unwrap().available().to_string(), "100.0000");
+    /// let moved = db.get_account(5).unwrap();
+    /// assert_eq!(moved.available().to_string(), "20.0000");
+    /// assert_eq!(
+    ///     moved.audit_log()[0].event,
+    ///     AuditEvent::SplitFrom { client: 1, txs: vec![2, 3] }
+    /// );
+    /// ```
+    ///
+    /// # Errors
+    /// - [`EngineError::AccountNotFound`] - `from` has no account
+    /// - [`EngineError::AccountAlreadyExists`] - `new_client` already has an account
+    /// - [`EngineError::AccountClosed`] - `from` is closed
+    /// - [`EngineError::TransactionNotFound`] - `from` has no entry for a listed ID
+    /// - [`EngineError::TransactionAlreadyChargedBack`] - A listed deposit is charged back
+    /// - [`EngineError::InsufficientFunds`] - The move would leave either account with a
+    ///   negative available or total balance (or, for `from`, a more negative one)
+    #[allow(deprecated)]
+    pub fn split_account(
+        &mut self,
+        from: u16,
+        new_client: u16,
+        txs: &[u32],
+    ) -> Result<(), EngineError> {
+        if self.accounts.get(new_client).is_some() {
+            return Err(EngineError::AccountAlreadyExists);
+        }
+        let source = self
+            .accounts
+            .get(from)
+            .ok_or(EngineError::AccountNotFound)?;
+        if source.state() == AccountState::Closed {
+            return Err(EngineError::AccountClosed);
+        }
+        let mut moved: Vec<(u64, u32)> = Vec::with_capacity(txs.len());
+        for &tx in txs {
+            let entry = source
+                .ledger
+                .get(&tx)
+                .ok_or(EngineError::TransactionNotFound)?;
+            if matches!(
+                entry,
+                LedgerEntry::Deposit {
+                    state: DisputeState::ChargedBack,
+                    ..
+                }
+            ) {
+                return Err(EngineError::TransactionAlreadyChargedBack);
+            }
+            moved.push((entry.seq(), tx));
+        }
+        // Move in processing order, ignoring repeated IDs
+        moved.sort();
+        moved.dedup();
+        let moved: Vec<u32> = moved.into_iter().map(|(_, tx)| tx).collect();
+
+        // Neither account may be left overdrawn by the move, though one already
+        // overdrawn (by a dispute after a withdrawal, say) may move entries that help
+        let (mut d_available, mut d_held) = (Fixed4::zero(), Fixed4::zero());
+        for &tx in &moved {
+            let (available, held) = source.ledger[&tx].balance_effect(source.refunded(tx));
+            d_available = d_available
+                .checked_add(available)
+                .ok_or(EngineError::BalanceOverflow)?;
+            d_held = d_held
+                .checked_add(held)
+                .ok_or(EngineError::BalanceOverflow)?;
+        }
+        let d_total = d_available
+            .checked_add(d_held)
+            .ok_or(EngineError::BalanceOverflow)?;
+        let overdrawn = |before: Fixed4, after: Option<Fixed4>| match after {
+            Some(after) => after < Fixed4::zero() && after < before,
+            None => true,
+        };
+        let zero = Fixed4::zero();
+        if overdrawn(
+            source.available(),
+            source.available().checked_sub(d_available),
+        ) || overdrawn(source.total(), source.total().checked_sub(d_total))
+            || overdrawn(zero, Some(d_available))
+            || overdrawn(zero, Some(d_total))
+        {
+            return Err(EngineError::InsufficientFunds);
+        }
+
+        // Validated: from here on nothing can fail
+        let source = self.accounts.get_mut(from).unwrap();
+        let mut target = Account::new();
+        for &tx in &moved {
+            let refunded = source.refunded(tx);
+            if let Some(mut entry) = source.ledger.remove(&tx) {
+                let (available, held) = entry.balance_effect(refunded);
+                source.available -= available;
+                source.held -= held;
+                target.available += available;
+                target.held += held;
+                entry.rekey(target.take_seq(), from, tx);
+                target.ledger.insert(tx, entry);
+            }
+        }
+        let first = moved.first().copied().unwrap_or_default();
+        source.record(
+            None,
+            AuditEvent::SplitTo {
+                client: new_client,
+                txs: moved.clone(),
+            },
+        );
+        source.record_balances(
+            first,
+            Activity::Split,
+            None,
+            None,
+            self.policy.balance_history_limit,
+        );
+        target.record(
+            None,
+            AuditEvent::SplitFrom {
+                client: from,
+                txs: moved.clone(),
+            },
+        );
+        target.record_balances(
+            first,
+            Activity::Split,
+            None,
+            None,
+            self.policy.balance_history_limit,
+        );
+        self.accounts.insert(new_client, target);
+
+        for event in self.cases.values_mut().flatten() {
+            if event.client == from && moved.contains(&event.tx) {
+                event.client = new_client;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Move every account, dispute case and pending approval of `other` into this
+    /// database, e.g. to consolidate the results of regional runs
+    ///
+    /// Accounts of clients only in `other` move as they are. A client in both has its
+    /// ledgers, balances, histories and audit logs combined, with `other`'s entries
+    /// after this database's; a lock on either side carries over. Pending approvals of
+    /// `other` are given new IDs, and its audit logs refer to them by those.
+    ///
+    /// Transaction IDs are unique across clients, so a transaction ID in both databases
+    /// is a conflict, settled by `policy`. The losing entry is dropped along with its
+    /// effect on its account's balances; a lock or dispute case it caused is kept.
+    /// Returns the conflicts settled, in transaction ID order.
+    ///
+    /// # Errors
+    /// Nothing is merged if this fails.
+    /// - [`MergeError::Conflict`] - With [`ConflictPolicy::Error`], the first conflict
+    /// - [`MergeError::BalanceOverflow`] - A client's combined balances would overflow
     ///
     /// # Examples
     /// ```
-    /// # use transaction_processor::{Database, Transaction};
-    /// let mut db = Database::new();
-    /// let deposit = Transaction::deposit("100.00").unwrap();
-    /// db.process_transaction(1, 1, deposit).unwrap();
+    /// # use transaction_processor::{ConflictPolicy, Database, MergeConflict, MergeError, Transaction};
+    /// let mut east = Database::new();
+    /// east.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+    /// let mut west = Database::new();
+    /// west.process_transaction(1, 2, Transaction::deposit("20").unwrap()).unwrap();
+    /// west.process_transaction(2, 1, Transaction::deposit("5").unwrap()).unwrap();
     ///
-    /// let account = db.get_account(1).unwrap();
-    /// assert_eq!(account.total().to_f64(), 100.00);
+    /// let conflict = MergeConflict { tx: 1, left: 1, right: 2 };
+    /// assert_eq!(
+    ///     east.clone().merge(west.clone(), ConflictPolicy::Error),
+    ///     Err(MergeError::Conflict(conflict.clone()))
+    /// );
+    ///
+    /// // Keep client 1's transaction 1, dropping client 2's
+    /// let settled = east.merge(west, ConflictPolicy::PreferLeft).unwrap();
+    /// assert_eq!(settled, [conflict]);
+    /// assert_eq!(east.get_account(1).unwrap().available().to_string(), "120.0000");
+    /// assert_eq!(east.get_account(2).unwrap().available().to_string(), "0.0000");
     /// ```
-    pub fn total(&self) -> Fixed4 {
-        self.available + self.held
-    }
+    pub fn merge(
+        &mut self,
+        mut other: Database,
+        policy: ConflictPolicy,
+    ) -> Result<Vec<MergeConflict>, MergeError> {
+        let owners: HashMap<u32, u16> = self
+            .accounts
+            .iter()
+            .flat_map(|(client, account)| account.ledger.keys().map(move |&tx| (tx, client)))
+            .collect();
+        let mut conflicts = Vec::new();
+        for (client, account) in other.accounts.iter() {
+            for tx in account.ledger.keys() {
+                if let Some(&left) = owners.get(tx) {
+                    conflicts.push(MergeConflict {
+                        tx: *tx,
+                        left,
+                        right: client,
+                    });
+                }
+            }
+        }
+        conflicts.sort_by_key(|conflict| conflict.tx);
 
-    /// Get transaction count for testing/audit purposes
-    ///
-    /// Returns the total number of transactions recorded in this account's ledger.
-    /// Useful for audit trails and testing transaction history completeness.
-    pub fn transaction_count(&self) -> usize {
-        self.ledger.len()
+        if let (ConflictPolicy::Error, Some(conflict)) = (policy, conflicts.first()) {
+            return Err(conflict.clone().into());
+        }
+        // Entries dropped from this database's accounts are put back if the merge fails
+        let mut originals: HashMap<u16, Account> = HashMap::new();
+        for conflict in &conflicts {
+            let (accounts, client) = match policy {
+                ConflictPolicy::Error => unreachable!("conflicts fail the merge"),
+                ConflictPolicy::PreferLeft => (&mut other.accounts, conflict.right),
+                ConflictPolicy::PreferRight => (&mut self.accounts, conflict.left),
+            };
+            if let Some(account) = accounts.get_mut(client) {
+                if policy == ConflictPolicy::PreferRight {
+                    originals.entry(client).or_insert_with(|| account.clone());
+                }
+                account.drop_entry(conflict.tx);
+            }
+        }
+        if let Err(err) = self.absorb(other) {
+            for (client, account) in originals {
+                self.accounts.insert(client, account);
+            }
+            return Err(err);
+        }
+        Ok(conflicts)
     }
 
-    /// Check if a transaction exists (for testing)
+    /// Move everything in `other` into this database, combining the accounts of clients
+    /// in both without checking their transaction IDs
     ///
-    /// # Arguments
-    /// * `txn_id` - Transaction ID to check
+    /// Approval IDs of `other` are shifted past this database's, in its pending
+    /// operations and audit logs alike, so every ID stays unique.
     ///
-    /// # Returns
-    /// `true` if the transaction exists in the account's ledger, `false` otherwise
-    pub fn has_transaction(&self, txn_id: u32) -> bool {
-        self.ledger.contains_key(&txn_id)
-    }
-
-    /// Process a transaction for this account
-    fn add_transaction(&mut self, txn_id: u32, txn: Transaction) -> Result<(), MyError> {
-        match txn {
-            Transaction::Deposit { amount } => {
-                self.available += amount;
-                self.ledger.insert(
-                    txn_id,
-                    LedgerEntry::Deposit {
-                        amount,
-                        state: DepositState::Normal,
-                    },
-                );
+    /// # Errors
+    /// - [`MergeError::BalanceOverflow`] - A client's combined balances would overflow;
+    ///   nothing is moved
+    pub(crate) fn absorb(&mut self, mut other: Database) -> Result<(), MergeError> {
+        for (client, account) in other.accounts.iter() {
+            if let Some(existing) = self.accounts.get(client)
+                && existing.combined_balances(account).is_none()
+            {
+                return Err(MergeError::BalanceOverflow { client });
             }
-            Transaction::Withdrawal { amount } => {
-                if self.available >= amount {
-                    self.available -= amount;
-                    self.ledger
-                        .insert(txn_id, LedgerEntry::Withdrawal { amount });
-                } else {
-                    return Err(MyError::InsufficientFunds);
+        }
+
+        let offset = self.next_approval_id;
+        let clients: Vec<u16> = other.accounts.iter().map(|(client, _)| client).collect();
+        for client in clients {
+            let mut account = other
+                .accounts
+                .remove(client)
+                .expect("client has an account");
+            account.shift_approval_ids(offset);
+            match self.accounts.get_mut(client) {
+                Some(existing) => existing
+                    .absorb(account)
+                    .expect("combined balances were checked"),
+                None => {
+                    self.accounts.insert(client, account);
                 }
             }
-            Transaction::Dispute => {
-                let entry = self
-                    .ledger
-                    .get_mut(&txn_id)
-                    .ok_or(MyError::TransactionNotFound)?;
-
-                match entry {
-                    LedgerEntry::Withdrawal { .. } => {
-                        return Err(MyError::TransactionIsWithdrawal);
-                    }
-                    LedgerEntry::Deposit { amount, state } => match state {
-                        DepositState::Normal => {
-                            self.available -= *amount;
-                            self.held += *amount;
-                            *state = DepositState::Disputed;
-                        }
-                        DepositState::Disputed => {
-                            return Err(MyError::TransactionAlreadyDisputed);
-                        }
-                        DepositState::ChargedBack => {
-                            return Err(MyError::TransactionAlreadyChargedBack);
-                        }
-                    },
-                }
+        }
+        for (case_id, events) in other.cases {
+            self.cases.entry(case_id).or_default().extend(events);
+        }
+        for (id, mut pending) in other.pending {
+            pending.id = id + offset;
+            self.pending.insert(pending.id, pending);
+        }
+        self.next_approval_id = offset + other.next_approval_id;
+        Ok(())
+    }
+
+    /// Stage an operation for approval, returning its ID
+    fn stage(&mut self, client_id: u16, operation: Operation) -> u64 {
+        let id = self.next_approval_id;
+        self.next_approval_id += 1;
+        let timestamp = match &operation {
+            Operation::Transaction { meta, .. } => meta.timestamp,
+            Operation::SetState { .. } | Operation::Unlock { .. } => None,
+        };
+        if let Some(account) = self.accounts.get_mut(client_id) {
+            account.record(
+                timestamp,
+                AuditEvent::ApprovalRequested {
+                    id,
+                    operation: operation.clone(),
+                },
+            );
+        }
+        self.pending.insert(
+            id,
+            PendingApproval {
+                id,
+                client: client_id,
+                operation,
+            },
+        );
+        id
+    }
+
+    /// Operations waiting for approval, oldest first
+    pub fn pending_approvals(&self) -> Vec<&PendingApproval> {
+        self.pending.values().collect()
+    }
+
+    /// Apply an operation that was staged for approval
+    ///
+    /// Operations are staged, and [`EngineError::PendingApproval`] returned, for withdrawals
+    /// and manual adjustments (see [`AccountOps::post_adjustment`]) above
+    /// [`Policy::approval_threshold`] and, with [`Policy::unlocks_require_approval`], for
+    /// unlocks. A transaction is only staged if it would apply at the time; otherwise it
+    /// is rejected straight away. The approval is recorded in the account's audit log
+    /// next to the request.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{AuditEvent, Database, EngineError, Policy, Transaction};
+    /// let mut db = Database::with_policy(Policy {
+    ///     approval_threshold: Some("1000".parse().unwrap()),
+    ///     ..Policy::default()
+    /// });
+    /// db.process_transaction(1, 1, Transaction::deposit("5000").unwrap()).unwrap();
+    ///
+    /// let withdrawal = Transaction::withdrawal("2500").unwrap();
+    /// let err = db.process_transaction(1, 2, withdrawal).unwrap_err();
+    /// let EngineError::PendingApproval { id } = err.kind else { panic!() };
+    /// assert_eq!(db.get_account(1).unwrap().available().to_string(), "5000.0000");
+    ///
+    /// db.approve(id, "supervisor").unwrap();
+    /// let account = db.get_account(1).unwrap();
+    /// assert_eq!(account.available().to_string(), "2500.0000");
+    /// assert_eq!(
+    ///     account.audit_log()[1].event,
+    ///     AuditEvent::Approved { id, approver: "supervisor".to_string() }
+    /// );
+    /// ```
+    ///
+    /// # Errors
+    /// - [`EngineError::ApprovalNotFound`] - Nothing is pending under `id`
+    /// - Any error from applying the operation, e.g. [`EngineError::InsufficientFunds`] if
+    ///   the balance changed in the meantime. The operation then stays pending.
+    pub fn approve(&mut self, id: u64, approver: &str) -> Result<(), EngineError> {
+        let pending = self
+            .pending
+            .get(&id)
+            .ok_or(EngineError::ApprovalNotFound)?
+            .clone();
+        let timestamp = match pending.operation {
+            Operation::Transaction {
+                tx,
+                transaction,
+                meta,
+            } => {
+                let timestamp = meta.timestamp;
+                self.apply_transaction(pending.client, tx, transaction, meta)
+                    .map_err(|err| err.kind)?;
+                timestamp
             }
-            Transaction::Resolve => {
-                let entry = self
-                    .ledger
-                    .get_mut(&txn_id)
-                    .ok_or(MyError::TransactionNotFound)?;
-                match entry {
-                    LedgerEntry::Withdrawal { .. } => {
-                        return Err(MyError::TransactionIsWithdrawal);
-                    }
-                    LedgerEntry::Deposit { amount, state } => match state {
-                        DepositState::Disputed => {
-                            self.held -= *amount;
-                            self.available += *amount;
-                            *state = DepositState::Normal;
-                        }
-                        DepositState::Normal => {
-                            return Err(MyError::TransactionNotDisputed);
-                        }
-                        DepositState::ChargedBack => {
-                            return Err(MyError::TransactionAlreadyChargedBack);
-                        }
-                    },
-                }
+            Operation::SetState { state } => {
+                self.apply_account_state(pending.client, state)?;
+                None
             }
-            Transaction::Chargeback => {
-                let entry = self
-                    .ledger
-                    .get_mut(&txn_id)
-                    .ok_or(MyError::TransactionNotFound)?;
-                match entry {
-                    LedgerEntry::Withdrawal { .. } => {
-                        return Err(MyError::TransactionIsWithdrawal);
-                    }
-                    LedgerEntry::Deposit { amount, state } => match state {
-                        DepositState::ChargedBack => {
-                            return Err(MyError::TransactionAlreadyChargedBack);
-                        }
-                        DepositState::Normal => {
-                            return Err(MyError::TransactionNotDisputed);
-                        }
-                        DepositState::Disputed => {
-                            self.held -= *amount;
-                            *state = DepositState::ChargedBack;
-                            self.locked = true;
-                        }
-                    },
-                }
+            Operation::Unlock { reason } => {
+                self.apply_unlock(pending.client, &reason)?;
+                None
             }
+        };
+        self.pending.remove(&id);
+        if let Some(account) = self.accounts.get_mut(pending.client) {
+            account.record(
+                timestamp,
+                AuditEvent::Approved {
+                    id,
+                    approver: approver.to_string(),
+                },
+            );
         }
         Ok(())
     }
-}
 
-// =============================================================================
-// DATABASE
-// =============================================================================
+    /// Discard an operation that was staged for approval, recording who rejected it
+    ///
+    /// # Errors
+    /// - [`EngineError::ApprovalNotFound`] - Nothing is pending under `id`
+    pub fn reject(&mut self, id: u64, approver: &str) -> Result<(), EngineError> {
+        let pending = self
+            .pending
+            .remove(&id)
+            .ok_or(EngineError::ApprovalNotFound)?;
+        if let Some(account) = self.accounts.get_mut(pending.client) {
+            account.record(
+                None,
+                AuditEvent::Rejected {
+                    id,
+                    approver: approver.to_string(),
+                },
+            );
+        }
+        Ok(())
+    }
 
-/// In-memory database for managing client accounts and transactions
-///
-/// The Database manages multiple client accounts and processes financial transactions.
-/// It ensures data consistency, handles error conditions, and maintains audit trails
-/// for regulatory compliance.
-///
-/// # Features
-/// - Multi-client account management
-/// - Transaction processing with balance validation
-/// - Dispute resolution workflow (dispute → resolve/chargeback)
-/// - Account locking after chargebacks
-/// - Complete audit trail maintenance
-///
-/// # Examples
-/// ```
-/// # use transaction_processor::{Database, Transaction};
-/// let mut db = Database::new();
-///
-/// // Process transactions for different clients
-/// let deposit1 = Transaction::deposit("100.00").unwrap();
-/// let deposit2 = Transaction::deposit("200.00").unwrap();
-///
-/// db.process_transaction(1, 1, deposit1).unwrap();
-/// db.process_transaction(2, 2, deposit2).unwrap();
-///
-/// // Check balances
-/// assert_eq!(db.get_account(1).unwrap().available.to_f64(), 100.00);
-/// assert_eq!(db.get_account(2).unwrap().available.to_f64(), 200.00);
-/// ```
-#[derive(Debug, Default)]
-pub struct Database {
-    /// Map of client IDs to their accounts
-    accounts: HashMap<u16, Account>,
-}
+    /// Mark a locked account as reviewed, so a review-gated unlock policy may release it
+    ///
+    /// # Errors
+    /// - [`EngineError::AccountNotFound`] - The client has no account
+    /// - [`EngineError::AccountNotLocked`] - The account is not locked
+    pub fn mark_reviewed(
+        &mut self,
+        client_id: u16,
+        reviewer: &str,
+        timestamp: Option<Timestamp>,
+    ) -> Result<(), EngineError> {
+        let account = self
+            .accounts
+            .get_mut(client_id)
+            .ok_or(EngineError::AccountNotFound)?;
+        if account.state() != AccountState::Locked {
+            return Err(EngineError::AccountNotLocked);
+        }
+        account.reviewed = true;
+        account.record(
+            timestamp,
+            AuditEvent::Reviewed {
+                reviewer: reviewer.to_string(),
+            },
+        );
+        Ok(())
+    }
 
-impl Database {
-    /// Create a new empty database
-    pub fn new() -> Self {
-        Self {
-            accounts: HashMap::new(),
+    /// Unlock a locked account, recording why in its audit log
+    ///
+    /// The sanctioned way for an operator to re-enable an account, e.g. once the dispute
+    /// that locked it has been resolved by hand. Unlike
+    /// [`set_account_state`](Database::set_account_state), it records the reason, as an
+    /// [`AuditEvent::AdminUnlock`], next to the lock's history. Any lock can be lifted,
+    /// including one set by a [`ChargebackLimit`](crate::ChargebackLimit). With
+    /// [`Policy::unlocks_require_approval`], the unlock is staged and only takes effect
+    /// once [approved](Database::approve).
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{AuditEvent, Database, EngineError, Transaction};
+    /// let mut db = Database::new();
+    /// db.process_transaction(1, 1, Transaction::deposit("10").unwrap()).unwrap();
+    /// db.process_transaction(1, 1, Transaction::dispute()).unwrap();
+    /// db.process_transaction(1, 1, Transaction::chargeback()).unwrap();
+    ///
+    /// let reason = "chargeback withdrawn by the issuer, case 4411";
+    /// db.unlock_account(1, reason).unwrap();
+    /// let account = db.get_account(1).unwrap();
+    /// assert!(!account.is_locked());
+    /// assert_eq!(
+    ///     account.audit_log()[0].event,
+    ///     AuditEvent::AdminUnlock { reason: reason.to_string() }
+    /// );
+    ///
+    /// // Only locked accounts can be unlocked
+    /// assert!(matches!(db.unlock_account(1, "again"), Err(EngineError::AccountNotLocked)));
+    /// ```
+    ///
+    /// # Errors
+    /// - [`EngineError::AccountNotFound`] - The client has no account
+    /// - [`EngineError::AccountNotLocked`] - The account is not locked
+    /// - [`EngineError::PendingApproval`] - The unlock was staged for approval
+    pub fn unlock_account(&mut self, client_id: u16, reason: &str) -> Result<(), EngineError> {
+        let account = self
+            .accounts
+            .get(client_id)
+            .ok_or(EngineError::AccountNotFound)?;
+        if account.state() != AccountState::Locked {
+            return Err(EngineError::AccountNotLocked);
+        }
+        if self.policy.unlocks_require_approval {
+            let id = self.stage(
+                client_id,
+                Operation::Unlock {
+                    reason: reason.to_string(),
+                },
+            );
+            return Err(EngineError::PendingApproval { id });
         }
+        self.apply_unlock(client_id, reason)
     }
 
-    /// Process a financial transaction for a client
+    /// Unlock a locked account, without staging it for approval
+    fn apply_unlock(&mut self, client_id: u16, reason: &str) -> Result<(), EngineError> {
+        let account = self
+            .accounts
+            .get_mut(client_id)
+            .ok_or(EngineError::AccountNotFound)?;
+        if account.state() != AccountState::Locked {
+            return Err(EngineError::AccountNotLocked);
+        }
+        account.unlock();
+        account.record(
+            None,
+            AuditEvent::AdminUnlock {
+                reason: reason.to_string(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Unlock every locked account that the automatic unlock policy allows to be released
     ///
-    /// Creates a new account if the client doesn't exist. Validates business rules
-    /// such as sufficient funds for withdrawals and account lock status.
+    /// An account qualifies once its cooling-off period (see [`Policy::auto_unlock_after`])
+    /// has elapsed since the locking chargeback and, if [`Policy::unlock_requires_review`]
+    /// is set, it has been [marked as reviewed](Database::mark_reviewed). Accounts whose
+    /// locking chargeback had no timestamp never qualify by time alone.
     ///
-    /// # Arguments
-    /// * `client_id` - Unique identifier for the client
-    /// * `txn_id` - Unique identifier for this transaction
-    /// * `transaction` - The transaction to process
+    /// Returns the unlocked client IDs in ascending order.
     ///
     /// # Examples
     /// ```
-    /// # use transaction_processor::{Database, Transaction};
-    /// let mut db = Database::new();
+    /// # use transaction_processor::{AuditEvent, Database, Policy, Transaction};
+    /// const DAY: u64 = 24 * 60 * 60;
+    /// let mut db = Database::with_policy(Policy {
+    ///     auto_unlock_after: Some(30 * DAY),
+    ///     ..Policy::default()
+    /// });
+    /// db.process_transaction_at(1, 1, Transaction::deposit("10").unwrap(), Some(0)).unwrap();
+    /// db.process_transaction_at(1, 1, Transaction::dispute(), Some(DAY)).unwrap();
+    /// db.process_transaction_at(1, 1, Transaction::chargeback(), Some(2 * DAY)).unwrap();
     ///
-    /// // Process a deposit
-    /// let deposit = Transaction::deposit("100.00").unwrap();
-    /// db.process_transaction(1, 1, deposit).unwrap();
+    /// assert!(db.apply_auto_unlocks(31 * DAY).is_empty());
+    /// assert_eq!(db.apply_auto_unlocks(32 * DAY), vec![1]);
     ///
-    /// // Process a withdrawal  
-    /// let withdrawal = Transaction::withdrawal("25.00").unwrap();
-    /// db.process_transaction(1, 2, withdrawal).unwrap();
+    /// let account = db.get_account(1).unwrap();
+    /// assert!(!account.is_locked());
+    /// assert_eq!(account.audit_log()[0].event, AuditEvent::AutoUnlocked);
+    /// ```
+    pub fn apply_auto_unlocks(&mut self, now: Timestamp) -> Vec<u16> {
+        let mut unlocked = Vec::new();
+        for (client_id, account) in self.accounts.iter_mut() {
+            if account.is_due_for_unlock(&self.policy, now) {
+                account.unlock();
+                account.record(Some(now), AuditEvent::AutoUnlocked);
+                unlocked.push(client_id);
+            }
+        }
+        unlocked.sort();
+        unlocked
+    }
+
+    /// Release every rolling reserve that has matured by `now`
+    ///
+    /// Reserved funds move from held to available. Reserves are released even on locked
+    /// accounts, since they remain the client's funds; only withdrawals are blocked.
+    ///
+    /// Returns the client IDs that had funds released, in ascending order.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Database, Policy, Transaction};
+    /// const DAY: u64 = 24 * 60 * 60;
+    /// let mut db = Database::with_policy(Policy {
+    ///     reserve_percent: 10,
+    ///     reserve_days: 90,
+    ///     ..Policy::default()
+    /// });
+    /// db.process_transaction_at(1, 1, Transaction::deposit("100").unwrap(), Some(0)).unwrap();
+    ///
+    /// let account = db.get_account(1).unwrap();
+    /// assert_eq!(account.available().to_string(), "90.0000");
+    /// assert_eq!(account.reserved().to_string(), "10.0000");
+    ///
+    /// assert!(db.release_matured_reserves(89 * DAY).is_empty());
+    /// assert_eq!(db.release_matured_reserves(90 * DAY), vec![1]);
     ///
     /// let account = db.get_account(1).unwrap();
-    /// assert_eq!(account.available.to_f64(), 75.00);
+    /// assert_eq!(account.available().to_string(), "100.0000");
+    /// assert_eq!(account.held().to_string(), "0.0000");
     /// ```
+    pub fn release_matured_reserves(&mut self, now: Timestamp) -> Vec<u16> {
+        let mut released = Vec::new();
+        for (client_id, account) in self.accounts.iter_mut() {
+            if account.release_reserves(now, self.policy.balance_history_limit) {
+                released.push(client_id);
+            }
+        }
+        released.sort();
+        released
+    }
+
+    /// Check every account's stored balances against those its ledger implies
     ///
-    /// # Errors
-    /// - [`MyError::InsufficientFunds`] - Withdrawal amount exceeds available balance
-    /// - [`MyError::AccountLocked`] - Attempted deposit/withdrawal on locked account
-    /// - [`MyError::TransactionNotFound`] - Dispute/resolve/chargeback on non-existent transaction
-    /// - Other transaction-specific errors (see [`MyError`] for complete list)
-    pub fn process_transaction(
-        &mut self,
-        client_id: u16,
-        txn_id: u32,
-        transaction: Transaction,
-    ) -> Result<(), MyError> {
-        self.accounts.entry(client_id).or_insert_with( Account::new);
-        let account = self.accounts.get_mut(&client_id).unwrap();
+    /// Returns the accounts whose balances have drifted, in client order, without
+    /// changing anything. See [`audit_and_repair`](Self::audit_and_repair) to fix them.
+    pub fn audit_balances(&self) -> Vec<BalanceDrift> {
+        let mut drifts: Vec<BalanceDrift> = self
+            .accounts
+            .iter()
+            .filter_map(|(client, account)| {
+                let (ledger_available, ledger_held) = account.recompute_balances();
+                let drift = BalanceDrift {
+                    client,
+                    available: account.available(),
+                    held: account.held(),
+                    ledger_available,
+                    ledger_held,
+                };
+                (drift.available != ledger_available || drift.held != ledger_held).then_some(drift)
+            })
+            .collect();
+        drifts.sort_by_key(|drift| drift.client);
+        drifts
+    }
 
-        // Only check if account is locked for deposit/withdrawal transactions
-        // Dispute, resolve, and chargeback operations should be allowed on locked accounts
-        match transaction {
-            Transaction::Deposit { .. } | Transaction::Withdrawal { .. } => {
-                if account.locked {
-                    return Err(MyError::AccountLocked);
-                }
-            }
-            Transaction::Dispute | Transaction::Resolve | Transaction::Chargeback => {
-                // These operations are allowed on locked accounts
+    /// Check every account's balances against its ledger, resetting any that have
+    /// drifted to what the ledger implies
+    ///
+    /// The ledger is taken as the source of truth. Each repair is recorded in the
+    /// account's audit log with the balances it replaced; the balance history is left
+    /// as it was. Returns the drift found, as [`audit_balances`](Self::audit_balances).
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{AuditEvent, Database, Transaction};
+    /// let mut db = Database::new();
+    /// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+    /// db.process_transaction(2, 2, Transaction::deposit("50").unwrap()).unwrap();
+    /// assert!(db.audit_balances().is_empty());
+    ///
+    /// // A damaged dump claims client 1 has less than its ledger says
+    /// let json = db
+    ///     .to_json()
+    ///     .replacen(r#""available": "100.0000""#, r#""available": "90.0000""#, 1);
+    /// let mut db = Database::from_json(&json).unwrap();
+    ///
+    /// let drift = db.audit_and_repair();
+    /// assert_eq!(drift.len(), 1);
+    /// assert_eq!(
+    ///     drift[0].to_string(),
+    ///     "client 1: stored available 90.0000, held 0.0000; \
+    ///      ledger implies available 100.0000, held 0.0000"
+    /// );
+    /// let account = db.get_account(1).unwrap();
+    /// assert_eq!(account.available().to_string(), "100.0000");
+    /// assert!(matches!(account.audit_log()[0].event, AuditEvent::BalancesRepaired { .. }));
+    /// assert!(db.audit_balances().is_empty());
+    /// ```
+    #[allow(deprecated)]
+    pub fn audit_and_repair(&mut self) -> Vec<BalanceDrift> {
+        let drifts = self.audit_balances();
+        for drift in &drifts {
+            if let Some(account) = self.accounts.get_mut(drift.client) {
+                account.available = drift.ledger_available;
+                account.held = drift.ledger_held;
+                account.record(
+                    None,
+                    AuditEvent::BalancesRepaired {
+                        available: drift.available,
+                        held: drift.held,
+                    },
+                );
             }
         }
+        drifts
+    }
 
-        account.add_transaction(txn_id, transaction)
+    /// Build a statement for a client, listing balances and every ledger entry
+    ///
+    /// # Returns
+    /// `Some(Statement)` if the client exists, `None` otherwise
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Database, DisputeState, EntryKind, Transaction};
+    /// let mut db = Database::new();
+    /// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+    /// db.process_transaction(1, 2, Transaction::withdrawal("40").unwrap()).unwrap();
+    /// db.process_transaction(1, 1, Transaction::dispute()).unwrap();
+    /// db.process_transaction(1, 1, Transaction::resolve()).unwrap();
+    ///
+    /// let statement = db.statement(1).unwrap();
+    /// assert_eq!(statement.total.to_string(), "60.0000");
+    /// assert_eq!(statement.lines[0].kind, EntryKind::Deposit);
+    /// assert_eq!(statement.lines[1].kind, EntryKind::Withdrawal);
+    ///
+    /// let states: Vec<_> = statement.lines[0].transitions.iter().map(|t| t.to).collect();
+    /// assert_eq!(states, vec![DisputeState::Disputed, DisputeState::Normal]);
+    /// ```
+    pub fn statement(&self, client_id: u16) -> Option<Statement> {
+        self.accounts
+            .get(client_id)
+            .map(|account| account.statement(client_id))
     }
 
     /// Get an account by client ID
@@ -507,10 +4830,55 @@ impl Database {
     ///
     /// // Now account exists
     /// let account = db.get_account(1).unwrap();
-    /// assert_eq!(account.available.to_f64(), 100.00);
+    /// assert_eq!(account.available().to_f64(), 100.00);
     /// ```
     pub fn get_account(&self, client_id: u16) -> Option<&Account> {
-        self.accounts.get(&client_id)
+        self.accounts.get(client_id)
+    }
+
+    /// Run `f` with guarded write access to a client's account
+    ///
+    /// Returns `None`, without calling `f`, if the client has no account.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Database, Transaction};
+    /// let mut db = Database::new();
+    /// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+    ///
+    /// db.with_account_mut(1, |account| {
+    ///     account.set_metadata("segment", "retail");
+    ///     account
+    ///         .post_adjustment(2, Transaction::withdrawal("2.50").unwrap(), "operator:jsmith")
+    ///         .unwrap();
+    /// })
+    /// .unwrap();
+    ///
+    /// let account = db.get_account(1).unwrap();
+    /// assert_eq!(account.available().to_string(), "97.5000");
+    /// assert_eq!(account.metadata()["segment"], "retail");
+    /// assert!(db.with_account_mut(2, |_| ()).is_none());
+    /// ```
+    pub fn with_account_mut<R>(
+        &mut self,
+        client_id: u16,
+        f: impl FnOnce(&mut AccountOps<'_>) -> R,
+    ) -> Option<R> {
+        self.accounts.get(client_id)?;
+        let mut ops = AccountOps {
+            db: self,
+            client: client_id,
+            meta: TransactionMeta::default(),
+        };
+        Some(f(&mut ops))
+    }
+
+    /// Get a read-only view of a client's balances and state
+    ///
+    /// Returns `None` if the client has no account.
+    pub fn view(&self, client_id: u16) -> Option<AccountView> {
+        self.get_account(client_id)
+            .map(|account| account.view(client_id))
     }
 
     /// Get all client IDs that have accounts
@@ -536,6 +4904,92 @@ impl Database {
     /// assert_eq!(client_ids, vec![1, 3]);
     /// ```
     pub fn get_all_client_ids(&self) -> Vec<u16> {
-        self.accounts.keys().copied().collect()
+        self.accounts
+            .iter()
+            .map(|(client_id, _)| client_id)
+            .collect()
+    }
+
+    /// Accounts selected and ordered for a summary report
+    ///
+    /// See [`SummaryQuery`] for the available filters.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Database, SummaryQuery, Transaction};
+    /// let mut db = Database::new();
+    /// db.process_transaction(1, 1, Transaction::deposit("10").unwrap()).unwrap();
+    /// db.process_transaction(2, 2, Transaction::deposit("30").unwrap()).unwrap();
+    /// db.process_transaction(3, 3, Transaction::deposit("20").unwrap()).unwrap();
+    ///
+    /// let query = SummaryQuery {
+    ///     sort: "total:desc".parse().unwrap(),
+    ///     ..SummaryQuery::default()
+    /// };
+    /// let clients: Vec<u16> = db.summaries(&query).iter().map(|(id, _)| *id).collect();
+    /// assert_eq!(clients, vec![2, 3, 1]);
+    /// ```
+    pub fn summaries(&self, query: &SummaryQuery) -> Vec<(u16, &Account)> {
+        let mut summaries: Vec<(u16, &Account)> = self
+            .accounts
+            .iter()
+            .filter(|(_, account)| query.matches(account))
+            .collect();
+        summaries.sort_by(|&a, &b| query.sort.compare(a, b));
+        if let Some(limit) = query.limit {
+            summaries.truncate(limit);
+        }
+        summaries
+    }
+
+    /// Aggregate accounts grouped by the metadata label `key`, such as a segment or tier
+    ///
+    /// Groups are listed in label order, followed by the accounts without the label, so
+    /// risk can compare portfolios without exporting per-account data. Labels are set
+    /// through [`AccountOps::set_metadata`].
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Database, Transaction};
+    /// let mut db = Database::new();
+    /// for (client, segment) in [(1, "retail"), (2, "retail"), (3, "business"), (4, "")] {
+    ///     let tx = client.into();
+    ///     db.process_transaction(client, tx, Transaction::deposit("100").unwrap()).unwrap();
+    ///     if !segment.is_empty() {
+    ///         db.with_account_mut(client, |account| account.set_metadata("segment", segment));
+    ///     }
+    /// }
+    /// db.process_transaction(2, 2, Transaction::dispute()).unwrap();
+    /// db.process_transaction(2, 2, Transaction::chargeback()).unwrap();
+    /// db.process_transaction(3, 3, Transaction::dispute()).unwrap();
+    ///
+    /// let segments = db.segment_summaries("segment");
+    /// let names: Vec<_> = segments.iter().map(|s| s.segment.as_deref()).collect();
+    /// assert_eq!(names, vec![Some("business"), Some("retail"), None]);
+    ///
+    /// let retail = &segments[1];
+    /// assert_eq!(retail.accounts, 2);
+    /// assert_eq!(retail.total.to_string(), "100.0000");
+    /// assert_eq!(retail.chargeback_rate(), 0.5);
+    /// assert_eq!(segments[0].held.to_string(), "100.0000");
+    /// ```
+    pub fn segment_summaries(&self, key: &str) -> Vec<SegmentSummary> {
+        let mut segments: BTreeMap<Option<&str>, SegmentSummary> = BTreeMap::new();
+        for (_, account) in self.accounts.iter() {
+            let segment = account.metadata().get(key).map(String::as_str);
+            segments
+                .entry(segment)
+                .or_insert_with(|| SegmentSummary::new(segment.map(str::to_string)))
+                .add(account);
+        }
+        // `None` sorts first in the map, but reads better as a trailing "other" group
+        let mut summaries: Vec<SegmentSummary> = segments.into_values().collect();
+        if summaries
+            .first()
+            .is_some_and(|summary| summary.segment.is_none())
+        {
+            summaries.rotate_left(1);
+        }
+        summaries
     }
 }
@@ -0,0 +1,1006 @@
+//! High-level processing facade
+//!
+//! An [`Engine`] bundles a [`Database`], its policy, the report settings and the errors,
+//! warnings and statistics gathered along the way, so embedders can process an input
+//! and produce the account summary with a single [`Engine::run`] call instead of
+//! reimplementing the orchestration in the binary.
+
+use crate::custom::CustomTransactions;
+use crate::db::{Database, DisputeAction, EngineError, Transaction};
+use crate::event_log::{Event, EventLog};
+use crate::fixed4::Fixed4;
+use crate::histogram::AmountHistogram;
+use crate::policy::Policy;
+use crate::record::Warning;
+use crate::rejection::{RejectedFields, Rejection};
+use crate::report::SummaryQuery;
+use crate::sample::SplitMix64;
+use crate::sink::SummarySink;
+use crate::source::{SourceError, SourceOffset, SourcedTransaction, TransactionSource};
+use crate::storage::Storage;
+use crate::throttle::{Throttled, TokenBucket};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Highest offset consumed in each (source, partition)
+pub(crate) type Offsets = HashMap<(String, u32), u64>;
+
+/// Counts of what happened to the input rows of a run
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunStats {
+    /// Rows read from the input
+    pub rows: usize,
+    /// Rows applied to the database
+    pub applied: usize,
+    /// Rows rejected with an error
+    pub rejected: usize,
+    /// Rejected rows that could not be decoded at all, as opposed to valid transactions
+    /// that were refused
+    pub malformed: usize,
+    /// Warnings raised, including for skipped duplicate rows
+    pub warnings: usize,
+    /// Rows skipped because their [source offset](crate::SourceOffset) had already been
+    /// applied
+    pub redelivered: usize,
+    /// Disputes, resolves and chargebacks held back under
+    /// [`Policy::defer_unmatched_disputes`] because the transaction they refer to hadn't
+    /// arrived yet, whether or not it arrived later
+    pub deferred: usize,
+    /// Rejected disputes, resolves and chargebacks of a transaction never seen, which
+    /// often means the input was truncated upstream (see [`UnmatchedReference`])
+    pub unmatched: usize,
+    /// Amounts of the deposits applied
+    pub deposit_amounts: AmountHistogram,
+    /// Amounts of the withdrawals applied
+    pub withdrawal_amounts: AmountHistogram,
+    /// Amounts of the refunds applied, including deposits with a negative amount under
+    /// [`Policy::negative_deposits_as_refunds`]
+    pub refund_amounts: AmountHistogram,
+    /// Errors of the run by kind, if the engine summarizes them (see
+    /// [`Engine::with_error_summary`])
+    pub error_summary: Option<ErrorSummary>,
+}
+
+impl std::ops::AddAssign for RunStats {
+    fn add_assign(&mut self, other: Self) {
+        self.rows += other.rows;
+        self.applied += other.applied;
+        self.rejected += other.rejected;
+        self.malformed += other.malformed;
+        self.warnings += other.warnings;
+        self.redelivered += other.redelivered;
+        self.deferred += other.deferred;
+        self.unmatched += other.unmatched;
+        self.deposit_amounts += other.deposit_amounts;
+        self.withdrawal_amounts += other.withdrawal_amounts;
+        self.refund_amounts += other.refund_amounts;
+        if let Some(other) = other.error_summary {
+            match &mut self.error_summary {
+                Some(summary) => summary.merge(&other),
+                None => self.error_summary = Some(other),
+            }
+        }
+    }
+}
+
+/// Identifies one processing run, for tying its outputs and logs together
+///
+/// Generated IDs mix the time, the process ID and a per-process counter, so runs in
+/// the same process, or on different machines at the same moment, get different IDs.
+/// Displays as 16 hex digits.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::RunId;
+/// let (first, second) = (RunId::generate(), RunId::generate());
+/// assert_ne!(first, second);
+/// assert_eq!(first.to_string().len(), 16);
+/// assert_eq!(RunId::from_raw(0xbeef).to_string(), "000000000000beef");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RunId(u64);
+
+impl RunId {
+    /// A new ID, different from every other generated by this process
+    pub fn generate() -> Self {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let mut random = SplitMix64(nanos ^ (u64::from(std::process::id()) << 32));
+        Self(random.next() ^ count)
+    }
+
+    /// The ID with the given value, e.g. one recorded by an earlier run
+    pub fn from_raw(raw: u64) -> Self {
+        Self(raw)
+    }
+
+    /// The ID's value
+    pub fn to_raw(self) -> u64 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for RunId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Errors counted by kind, with the first few rows of each kind as examples
+///
+/// Memory stays bounded however many rows fail, unlike keeping every error message:
+/// counts are exact, but only `examples_per_kind` messages of each kind are kept.
+/// Rejected transactions are grouped by their [`ErrorCode`](crate::ErrorCode) name,
+/// and rows that could not be decoded at all as `MALFORMED`.
+///
+/// Displays as the total followed by each kind, most frequent first, with its examples
+/// indented beneath it.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{CsvSource, Engine};
+/// let input = "type,client,tx,amount\n\
+///              withdrawal,1,1,5\n\
+///              withdrawal,1,2,5\n\
+///              withdrawal,1,3,5\n\
+///              deposit,1,x,5\n";
+///
+/// let mut engine = Engine::new().with_error_summary(2);
+/// engine.process(CsvSource::new("input", input.as_bytes()));
+///
+/// // Errors are summarized instead of kept one by one
+/// assert!(engine.errors().is_empty());
+/// let summary = engine.error_summary().unwrap();
+/// assert_eq!(summary.total(), 4);
+///
+/// let top = summary.top(1);
+/// assert_eq!(top[0].0, "INSUFFICIENT_FUNDS");
+/// assert_eq!(top[0].1.count, 3);
+/// assert_eq!(top[0].1.examples.len(), 2);
+/// assert!(top[0].1.examples[0].contains("input:2"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorSummary {
+    examples_per_kind: usize,
+    total: usize,
+    kinds: BTreeMap<String, ErrorKindSummary>,
+}
+
+/// Errors of one kind in an [`ErrorSummary`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorKindSummary {
+    /// Number of errors of this kind
+    pub count: usize,
+    /// The first errors of this kind, as they would have been reported
+    pub examples: Vec<String>,
+}
+
+impl ErrorSummary {
+    /// An empty summary keeping up to `examples_per_kind` messages of each kind
+    pub fn new(examples_per_kind: usize) -> Self {
+        Self {
+            examples_per_kind,
+            ..Self::default()
+        }
+    }
+
+    /// Count `error`, keeping its message if fewer than `examples_per_kind` of its kind
+    /// have been kept
+    pub fn record(&mut self, error: &SourceError) {
+        let kind = match error {
+            SourceError::Malformed { .. } => "MALFORMED",
+            SourceError::Invalid { error, .. } => error.code().name,
+            SourceError::Skipped { .. } => "SKIPPED",
+        };
+        self.total += 1;
+        let summary = self.kinds.entry(kind.to_string()).or_default();
+        summary.count += 1;
+        if summary.examples.len() < self.examples_per_kind {
+            summary.examples.push(error.to_string());
+        }
+    }
+
+    /// Add the counts of `other`, keeping its examples while fewer than
+    /// `examples_per_kind` of their kind have been kept
+    pub fn merge(&mut self, other: &ErrorSummary) {
+        self.total += other.total;
+        for (kind, theirs) in &other.kinds {
+            let summary = self.kinds.entry(kind.clone()).or_default();
+            summary.count += theirs.count;
+            let room = self
+                .examples_per_kind
+                .saturating_sub(summary.examples.len());
+            summary
+                .examples
+                .extend(theirs.examples.iter().take(room).cloned());
+        }
+    }
+
+    /// Number of errors counted
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// The `k` most frequent kinds, most frequent first; kinds as frequent as each other
+    /// are in name order
+    pub fn top(&self, k: usize) -> Vec<(&str, &ErrorKindSummary)> {
+        let mut kinds: Vec<_> = self
+            .kinds
+            .iter()
+            .map(|(kind, summary)| (kind.as_str(), summary))
+            .collect();
+        kinds.sort_by_key(|(_, summary)| std::cmp::Reverse(summary.count));
+        kinds.truncate(k);
+        kinds
+    }
+}
+
+impl std::fmt::Display for ErrorSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} errors", self.total)?;
+        for (kind, summary) in self.top(self.kinds.len()) {
+            write!(f, "\n  {}: {}", kind, summary.count)?;
+            for example in &summary.examples {
+                write!(f, "\n    {}", example)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A dispute, resolve or chargeback row whose transaction was never seen
+///
+/// These are reported apart from other errors: a feed truncated upstream typically
+/// shows up as a batch of them. Displays as `LOCATION: ACTION of tx TX for client
+/// CLIENT matched no transaction`.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{CsvSource, Engine};
+/// let input = "type,client,tx,amount\n\
+///              deposit,1,1,10\n\
+///              withdrawal,1,2,50\n\
+///              dispute,1,7,\n";
+///
+/// let mut engine = Engine::new();
+/// let stats = engine.process(CsvSource::new("input", input.as_bytes()));
+/// assert_eq!((stats.rejected, stats.unmatched), (2, 1));
+/// assert_eq!(engine.errors().len(), 1);
+/// assert_eq!(
+///     engine.unmatched()[0].to_string(),
+///     "input:4: dispute of tx 7 for client 1 matched no transaction"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnmatchedReference {
+    /// Where the row was found
+    pub location: String,
+    /// Client the row is for
+    pub client: u16,
+    /// Transaction the row refers to
+    pub tx: u32,
+    /// What the row would have done
+    pub action: DisputeAction,
+}
+
+impl std::fmt::Display for UnmatchedReference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} of tx {} for client {} matched no transaction",
+            self.location, self.action, self.tx, self.client
+        )
+    }
+}
+
+/// How far an [`Engine`] has got through an input, as reported to the callback of
+/// [`Engine::process_with_progress`]
+#[derive(Debug, Clone, Copy)]
+pub struct Progress<'a> {
+    /// The accounts, with every row so far applied
+    pub database: &'a Database,
+    /// Statistics for the input so far
+    pub stats: &'a RunStats,
+    /// The row just applied, if the last row read was applied rather than rejected or
+    /// skipped
+    pub applied: Option<AppliedRow>,
+}
+
+/// A row applied to the database, as reported in [`Progress`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppliedRow {
+    /// Client the row was for
+    pub client: u16,
+    /// Transaction ID of the row
+    pub tx: u32,
+    /// The dispute lifecycle step it performed, if it was a dispute, resolve,
+    /// chargeback or chargeback reversal
+    pub action: Option<DisputeAction>,
+}
+
+/// The state of an [`Engine`]: its accounts and the source offsets it has applied
+///
+/// Taken with [`Engine::snapshot`] and applied with [`Engine::restore`], or, for a
+/// database on its own, with [`Database::to_snapshot`] and [`Database::from_snapshot`].
+/// The policy is configuration rather than state, so it is not included.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub(crate) database: Database,
+    pub(crate) offsets: Vec<SourceOffset>,
+}
+
+impl Snapshot {
+    /// The accounts as they were when the snapshot was taken
+    pub fn database(&self) -> &Database {
+        &self.database
+    }
+
+    /// Highest offset applied in each source partition
+    pub fn offsets(&self) -> &[SourceOffset] {
+        &self.offsets
+    }
+}
+
+/// Processes transaction inputs and writes the account summary report
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{CsvSink, CsvSource, Engine};
+/// let input = "type,client,tx,amount\n\
+///              deposit,1,1,10.0\n\
+///              withdrawal,1,2,25.0\n";
+///
+/// let mut engine = Engine::new();
+/// let mut output = Vec::new();
+/// let sink = CsvSink::new(&mut output).with_columns("client,available".parse().unwrap());
+/// let stats = engine.run(CsvSource::new("input", input.as_bytes()), sink).unwrap();
+///
+/// assert_eq!(String::from_utf8(output).unwrap(), "client,available\n1,10.0000\n");
+/// assert_eq!((stats.rows, stats.applied, stats.rejected), (2, 1, 1));
+/// assert!(engine.errors()[0].starts_with("Error processing transaction at input:3"));
+/// ```
+///
+/// Cloning an engine takes a snapshot of its state, which can be restored with
+/// [`roll_back`](Engine::roll_back), e.g. to roll back an input that turned out to be
+/// corrupt.
+#[derive(Debug, Clone, Default)]
+pub struct Engine {
+    database: Database,
+    query: SummaryQuery,
+    errors: Vec<String>,
+    error_summary: Option<ErrorSummary>,
+    unmatched: Vec<UnmatchedReference>,
+    rejections: Option<Vec<Rejection>>,
+    warnings: Vec<String>,
+    stats: RunStats,
+    rate_limit: Option<TokenBucket>,
+    event_log: Option<EventLog>,
+    offsets: Offsets,
+}
+
+impl Engine {
+    /// Create an engine with the default policy and report settings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an engine whose database applies `policy`
+    ///
+    /// # Panics
+    /// Panics if the policy is [invalid](Policy::validate).
+    pub fn with_policy(policy: Policy) -> Self {
+        Self {
+            database: Database::with_policy(policy),
+            ..Self::default()
+        }
+    }
+
+    /// Keep the accounts in `storage`, picking up those already in it
+    ///
+    /// Call before processing anything: accounts processed so far are dropped. Call
+    /// [`flush`](Engine::flush) to make the accounts durable.
+    pub fn with_storage(mut self, storage: impl Storage + 'static) -> Self {
+        self.database.set_storage(Box::new(storage));
+        self
+    }
+
+    /// Select and order the accounts listed in the report
+    pub fn with_query(mut self, query: SummaryQuery) -> Self {
+        self.query = query;
+        self
+    }
+
+    /// Apply transactions no faster than `bucket` allows, across every run
+    pub fn with_rate_limit(mut self, bucket: TokenBucket) -> Self {
+        self.rate_limit = Some(bucket);
+        self
+    }
+
+    /// Apply rows of the custom transaction types in `types` (see
+    /// [`CustomTransaction`](crate::CustomTransaction))
+    pub fn with_custom_transactions(mut self, types: CustomTransactions) -> Self {
+        self.database.set_custom_transactions(types);
+        self
+    }
+
+    /// Count errors by kind in an [`ErrorSummary`], keeping up to `examples_per_kind`
+    /// messages of each, instead of keeping every message in [`errors`](Engine::errors)
+    ///
+    /// For inputs where millions of rows may fail.
+    pub fn with_error_summary(mut self, examples_per_kind: usize) -> Self {
+        self.error_summary = Some(ErrorSummary::new(examples_per_kind));
+        self
+    }
+
+    /// Keep every row rejected from now on as a [`Rejection`] too, with its error code
+    /// and fields, for writing out with [`write_rejections_json`](crate::write_rejections_json)
+    ///
+    /// Kept whether or not errors are summarized.
+    pub fn with_rejections(mut self) -> Self {
+        self.rejections = Some(Vec::new());
+        self
+    }
+
+    /// Record every transaction applied from now on in an [`EventLog`]
+    pub fn with_event_log(mut self) -> Self {
+        self.event_log = Some(EventLog::new());
+        self
+    }
+
+    /// Apply every transaction from `source`, then write the account summary to `sink`
+    ///
+    /// Rows that fail are recorded in [`errors`](Engine::errors) and skipped, so a run
+    /// only fails outright if the sink cannot be written. Runs accumulate: calling `run`
+    /// again applies further input to the same database.
+    ///
+    /// Returns the statistics for this run.
+    ///
+    /// # Errors
+    /// Returns an error if writing the report fails.
+    pub fn run<S: TransactionSource, K: SummarySink>(
+        &mut self,
+        source: S,
+        sink: K,
+    ) -> Result<RunStats, Box<dyn Error>> {
+        let stats = self.process(source);
+        self.report(sink)?;
+        Ok(stats)
+    }
+
+    /// Write the account summary for everything processed so far to `sink`
+    ///
+    /// # Errors
+    /// Returns an error if writing the report fails.
+    pub fn report<K: SummarySink>(&self, mut sink: K) -> Result<(), Box<dyn Error>> {
+        sink.write_summaries(&self.database.summaries(&self.query))
+    }
+
+    /// Apply every transaction from `source` without writing a report
+    ///
+    /// Returns the statistics for this input.
+    pub fn process<S: TransactionSource>(&mut self, source: S) -> RunStats {
+        self.process_reporting(source, None)
+    }
+
+    /// Apply every transaction from `source`, like [`process`](Engine::process), calling
+    /// `on_progress` before each row is read and once more at the end
+    ///
+    /// The callback sees the accounts and the statistics as they stand, and the row just
+    /// applied, if the last one was, e.g. to draw a progress display while a large file
+    /// or a stream is processed. It is called on every row, so it should be quick;
+    /// anything slow, like redrawing, is best done at most every so often.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{CsvSource, DisputeAction, Engine};
+    /// let input = "type,client,tx,amount\n\
+    ///              deposit,1,1,10\n\
+    ///              withdrawal,1,2,50\n\
+    ///              dispute,1,1,\n\
+    ///              chargeback,1,1,\n";
+    ///
+    /// let mut engine = Engine::new();
+    /// let mut chargebacks = Vec::new();
+    /// let mut rows_seen = 0;
+    /// engine.process_with_progress(CsvSource::new("input", input.as_bytes()), |progress| {
+    ///     if let Some(row) = progress.applied
+    ///         && row.action == Some(DisputeAction::Chargeback)
+    ///     {
+    ///         chargebacks.push((row.client, row.tx));
+    ///         assert!(progress.database.get_account(row.client).unwrap().is_locked());
+    ///     }
+    ///     rows_seen = progress.stats.rows;
+    /// });
+    /// assert_eq!(chargebacks, [(1, 1)]);
+    /// assert_eq!(rows_seen, 4);
+    /// ```
+    pub fn process_with_progress<S, F>(&mut self, source: S, mut on_progress: F) -> RunStats
+    where
+        S: TransactionSource,
+        F: FnMut(Progress<'_>),
+    {
+        self.process_reporting(source, Some(&mut on_progress))
+    }
+
+    fn process_reporting<S: TransactionSource>(
+        &mut self,
+        source: S,
+        progress: Option<&mut dyn FnMut(Progress<'_>)>,
+    ) -> RunStats {
+        // Errors of this run are summarized apart, then added to those of every run
+        let mut summary = self
+            .error_summary
+            .as_ref()
+            .map(|summary| ErrorSummary::new(summary.examples_per_kind));
+        let mut stats = match &mut self.rate_limit {
+            Some(bucket) => process_source(
+                &mut self.database,
+                Throttled::new(source, bucket),
+                Rejected {
+                    errors: &mut self.errors,
+                    summary: summary.as_mut(),
+                    unmatched: Some(&mut self.unmatched),
+                    rejections: self.rejections.as_mut(),
+                },
+                &mut self.warnings,
+                self.event_log.as_mut(),
+                Some(&mut self.offsets),
+                progress,
+            ),
+            None => process_source(
+                &mut self.database,
+                source,
+                Rejected {
+                    errors: &mut self.errors,
+                    summary: summary.as_mut(),
+                    unmatched: Some(&mut self.unmatched),
+                    rejections: self.rejections.as_mut(),
+                },
+                &mut self.warnings,
+                self.event_log.as_mut(),
+                Some(&mut self.offsets),
+                progress,
+            ),
+        };
+        if let (Some(total), Some(summary)) = (&mut self.error_summary, &summary) {
+            total.merge(summary);
+        }
+        stats.error_summary = summary;
+        self.stats += stats.clone();
+        stats
+    }
+
+    /// The database holding every account processed so far
+    pub fn database(&self) -> &Database {
+        &self.database
+    }
+
+    /// Make the accounts durable, if their storage is (see
+    /// [`with_storage`](Engine::with_storage))
+    ///
+    /// # Errors
+    /// Returns an error if the storage can't be written.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.database.flush()
+    }
+
+    /// Errors from every run so far, each with the input and line it relates to
+    ///
+    /// Empty if errors are being summarized (see
+    /// [`with_error_summary`](Engine::with_error_summary)).
+    pub fn errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    /// Errors from every run so far by kind, if enabled with
+    /// [`with_error_summary`](Engine::with_error_summary)
+    ///
+    /// The errors of each run alone are in the [`RunStats`] it returns.
+    pub fn error_summary(&self) -> Option<&ErrorSummary> {
+        self.error_summary.as_ref()
+    }
+
+    /// Disputes, resolves and chargebacks from every run so far whose transaction was
+    /// never seen, kept apart from [`errors`](Engine::errors)
+    pub fn unmatched(&self) -> &[UnmatchedReference] {
+        &self.unmatched
+    }
+
+    /// Rows rejected so far, if kept with [`with_rejections`](Engine::with_rejections),
+    /// in the order they were rejected
+    ///
+    /// Unlike [`errors`](Engine::errors), this includes the rows in
+    /// [`unmatched`](Engine::unmatched).
+    pub fn rejections(&self) -> &[Rejection] {
+        self.rejections.as_deref().unwrap_or_default()
+    }
+
+    /// Warnings from every run so far, each with the input and line it relates to
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Statistics accumulated over every run so far
+    pub fn stats(&self) -> RunStats {
+        self.stats.clone()
+    }
+
+    /// Transactions applied so far, if recording was enabled with
+    /// [`with_event_log`](Engine::with_event_log)
+    pub fn event_log(&self) -> Option<&EventLog> {
+        self.event_log.as_ref()
+    }
+
+    #[cfg(feature = "csv")]
+    pub(crate) fn event_log_mut(&mut self) -> Option<&mut EventLog> {
+        self.event_log.as_mut()
+    }
+
+    /// The accounts and committed source offsets, for persisting with a
+    /// [`SnapshotStore`](crate::SnapshotStore)
+    ///
+    /// With in-memory storage the accounts are shared with the engine copy-on-write, so
+    /// taking a snapshot doesn't copy ledgers or histories; an account is only copied
+    /// when the engine next changes it. Dispute cases and pending approvals are copied.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            database: self.database.clone(),
+            offsets: self.committed_offsets(),
+        }
+    }
+
+    /// Replace the accounts and committed source offsets with those in `snapshot`
+    ///
+    /// The engine keeps its own policy, custom transaction types, report settings, errors
+    /// and statistics.
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.database.replace_state(snapshot.database);
+        self.offsets = snapshot
+            .offsets
+            .into_iter()
+            .map(|offset| ((offset.source, offset.partition), offset.offset))
+            .collect();
+    }
+
+    /// Put the engine back as it was when `checkpoint` was cloned from it
+    ///
+    /// Everything the engine holds is restored from the clone, except that the accounts
+    /// are put back into the engine's own storage backend and its journal is cut back,
+    /// as [`Database::roll_back`] does, so a persistent engine stays persistent.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{CsvSource, Engine};
+    /// let mut engine = Engine::new();
+    /// engine.process(CsvSource::new("good", "type,client,tx,amount\ndeposit,1,1,10\n".as_bytes()));
+    ///
+    /// let checkpoint = engine.clone();
+    /// engine.process(CsvSource::new("bad", "type,client,tx,amount\ndeposit,1,2,5\n".as_bytes()));
+    /// engine.roll_back(checkpoint).unwrap();
+    /// assert_eq!(engine.database().get_account(1).unwrap().total().to_string(), "10.0000");
+    /// assert_eq!(engine.stats().rows, 1);
+    /// ```
+    ///
+    /// # Errors
+    /// Returns an error if the journal can't be cut back. Everything else is rolled back
+    /// regardless.
+    pub fn roll_back(&mut self, checkpoint: Engine) -> std::io::Result<()> {
+        let mut database = std::mem::take(&mut self.database);
+        *self = checkpoint;
+        std::mem::swap(&mut self.database, &mut database);
+        // `database` is now the checkpoint's
+        self.database.roll_back(database)
+    }
+
+    /// Highest offset consumed from `partition` of `source`, if any
+    ///
+    /// Every row read counts, whether it was applied, rejected or skipped as a
+    /// duplicate, so transactions delivered again at or below this offset are skipped.
+    /// An [`EventLog`] only holds applied transactions, so replaying one restores the
+    /// offsets of those alone: a consumer persists [`committed_offsets`] as well, and
+    /// restores them with [`commit_offsets`] after replaying the log.
+    ///
+    /// [`committed_offsets`]: Engine::committed_offsets
+    /// [`commit_offsets`]: Engine::commit_offsets
+    pub fn committed_offset(&self, source: &str, partition: u32) -> Option<u64> {
+        self.offsets.get(&(source.to_string(), partition)).copied()
+    }
+
+    /// Highest offset consumed from each source partition, by source and partition
+    pub fn committed_offsets(&self) -> Vec<SourceOffset> {
+        let mut offsets: Vec<SourceOffset> = self
+            .offsets
+            .iter()
+            .map(|((source, partition), offset)| SourceOffset::new(source, *partition, *offset))
+            .collect();
+        offsets.sort_by(|a, b| (&a.source, a.partition).cmp(&(&b.source, b.partition)));
+        offsets
+    }
+
+    /// Mark everything up to each of `offsets` as consumed, e.g. after a restart
+    ///
+    /// Offsets already committed further along are kept.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Engine, SourceOffset};
+    /// let mut engine = Engine::new();
+    /// engine.commit_offsets([SourceOffset::new("payments", 0, 7)]);
+    /// engine.commit_offsets([SourceOffset::new("payments", 0, 3)]);
+    /// assert_eq!(engine.committed_offset("payments", 0), Some(7));
+    /// ```
+    pub fn commit_offsets(&mut self, offsets: impl IntoIterator<Item = SourceOffset>) {
+        for offset in offsets {
+            commit_offset(
+                &mut self.offsets,
+                (offset.source, offset.partition),
+                offset.offset,
+            );
+        }
+    }
+}
+
+/// Apply every transaction from `source` to `database`
+///
+/// Errors and warnings are appended, formatted with the location they relate to, or
+/// errors counted in `summary` instead, if given.
+/// Rows repeating a deposit or withdrawal that was already applied are skipped with
+/// a warning. Applied transactions are appended to `log`, if given. If `offsets` are
+/// given, rows delivered at an offset already consumed are skipped, and the offsets of
+/// the rows consumed are recorded, whether they were applied, rejected or skipped as
+/// duplicates. `progress` is called before each row is read, and once at the end.
+pub(crate) fn process_source<S: TransactionSource>(
+    database: &mut Database,
+    mut source: S,
+    mut rejected: Rejected<'_>,
+    warnings: &mut Vec<String>,
+    mut log: Option<&mut EventLog>,
+    mut offsets: Option<&mut Offsets>,
+    mut progress: Option<&mut dyn FnMut(Progress<'_>)>,
+) -> RunStats {
+    let mut stats = RunStats::default();
+    let defer = database.policy().defer_unmatched_disputes;
+    // Rows held back until the transaction they refer to arrives, by (client, tx), each
+    // with its position in the input
+    let mut deferred: BTreeMap<(u16, u32), Vec<(usize, SourcedTransaction)>> = BTreeMap::new();
+    // Held-back rows to retry before reading on
+    let mut retries: VecDeque<SourcedTransaction> = VecDeque::new();
+    // Whether the input is exhausted, so retries are the last attempt
+    let mut last_attempt = false;
+    // The last row, if it was applied, for the progress callback
+    let mut applied = None;
+
+    loop {
+        if let Some(progress) = progress.as_deref_mut() {
+            progress(Progress {
+                database,
+                stats: &stats,
+                applied: applied.take(),
+            });
+        }
+        let next = match retries.pop_front() {
+            Some(item) => Some((Ok(item), true)),
+            None if last_attempt => None,
+            None => source.next_transaction().map(|result| (result, false)),
+        };
+        let Some((result, retry)) = next else {
+            if deferred.is_empty() {
+                break;
+            }
+            // End of input: whatever is still held back gets one last try
+            let mut rows: Vec<_> = std::mem::take(&mut deferred)
+                .into_values()
+                .flatten()
+                .collect();
+            rows.sort_by_key(|(position, _)| *position);
+            retries.extend(rows.into_iter().map(|(_, item)| item));
+            last_attempt = true;
+            continue;
+        };
+        if !retry {
+            stats.rows += 1;
+        }
+
+        let item = match result {
+            Ok(item) => item,
+            Err(e @ SourceError::Skipped { .. }) => {
+                warnings.push(e.to_string());
+                stats.warnings += 1;
+                continue;
+            }
+            Err(e) => {
+                if matches!(e, SourceError::Malformed { .. }) {
+                    stats.malformed += 1;
+                }
+                rejected.error(e, None);
+                stats.rejected += 1;
+                continue;
+            }
+        };
+
+        let position = item
+            .meta
+            .offset
+            .as_ref()
+            .map(|offset| ((offset.source.clone(), offset.partition), offset.offset));
+        if let (false, Some(offsets), Some((partition, offset))) =
+            (retry, offsets.as_deref(), &position)
+            && offsets
+                .get(partition)
+                .is_some_and(|committed| offset <= committed)
+        {
+            stats.redelivered += 1;
+            continue;
+        }
+
+        if database.is_duplicate(item.client, item.tx, &item.transaction) {
+            warnings.push(format!(
+                "Warning at {}: {}",
+                item.location,
+                Warning::DuplicateRow
+            ));
+            stats.warnings += 1;
+            if let (Some(offsets), Some((partition, offset))) = (offsets.as_deref_mut(), position) {
+                commit_offset(offsets, partition, offset);
+            }
+            continue;
+        }
+
+        let amounts = match item.transaction {
+            Transaction::Deposit { amount } if amount < Fixed4::zero() => {
+                Some((&mut stats.refund_amounts, amount))
+            }
+            Transaction::Deposit { amount } => Some((&mut stats.deposit_amounts, amount)),
+            Transaction::Withdrawal { amount } => Some((&mut stats.withdrawal_amounts, amount)),
+            Transaction::Refund { amount, .. } => Some((&mut stats.refund_amounts, amount)),
+            _ => None,
+        };
+        let event = log.is_some().then(|| Event {
+            client: item.client,
+            tx: item.tx,
+            transaction: item.transaction.clone(),
+            meta: item.meta.clone(),
+        });
+        let fields = rejected.rejections.is_some().then(|| RejectedFields {
+            transaction_type: Some(item.transaction.name().to_string()),
+            client: Some(item.client),
+            tx: Some(item.tx),
+            amount: item.transaction.amount(),
+        });
+        let action = item.transaction.dispute_action();
+        let refers_back = action.is_some();
+        // Kept to hold the row back if it refers to a transaction not seen yet
+        let copy = (defer && refers_back && !last_attempt).then(|| item.clone());
+        let key = (item.client, item.tx);
+        let result =
+            database.process_transaction_with(item.client, item.tx, item.transaction, item.meta);
+        // A row held back to retry is only consumed once it is retried
+        let held_back = copy.is_some()
+            && result
+                .as_ref()
+                .is_err_and(|error| matches!(error.kind, EngineError::TransactionNotFound));
+        if !held_back
+            && let (Some(offsets), Some((partition, offset))) = (offsets.as_deref_mut(), position)
+        {
+            commit_offset(offsets, partition, offset);
+        }
+        match result {
+            Ok(()) => {
+                stats.applied += 1;
+                applied = Some(AppliedRow {
+                    client: key.0,
+                    tx: key.1,
+                    action,
+                });
+                if !refers_back && let Some(rows) = deferred.remove(&key) {
+                    retries.extend(rows.into_iter().map(|(_, item)| item));
+                }
+                if let Some((histogram, amount)) = amounts {
+                    histogram.record(amount);
+                }
+                if let (Some(log), Some(event)) = (log.as_deref_mut(), event) {
+                    log.push(event);
+                }
+                if let Some(warning) = item.warning {
+                    warnings.push(format!("Warning at {}: {}", item.location, warning));
+                    stats.warnings += 1;
+                }
+            }
+            Err(error) => {
+                if let Some(copy) = copy
+                    && matches!(error.kind, EngineError::TransactionNotFound)
+                {
+                    if !retry {
+                        stats.deferred += 1;
+                    }
+                    deferred.entry(key).or_default().push((stats.rows, copy));
+                    continue;
+                }
+                stats.rejected += 1;
+                if let Some(action) = action
+                    && matches!(error.kind, EngineError::TransactionNotFound)
+                {
+                    stats.unmatched += 1;
+                    if rejected.unmatched.is_some() {
+                        rejected.record(
+                            &SourceError::Invalid {
+                                location: item.location.clone(),
+                                error,
+                            },
+                            fields,
+                        );
+                        if let Some(unmatched) = rejected.unmatched.as_deref_mut() {
+                            unmatched.push(UnmatchedReference {
+                                location: item.location,
+                                client: item.client,
+                                tx: item.tx,
+                                action,
+                            });
+                        }
+                        continue;
+                    }
+                }
+                rejected.error(
+                    SourceError::Invalid {
+                        location: item.location,
+                        error,
+                    },
+                    fields,
+                );
+            }
+        }
+    }
+
+    if let Some(progress) = progress {
+        progress(Progress {
+            database,
+            stats: &stats,
+            applied,
+        });
+    }
+    stats
+}
+
+/// Record `offset` as consumed in `partition`, unless a later one already is
+fn commit_offset(offsets: &mut Offsets, partition: (String, u32), offset: u64) {
+    let committed = offsets.entry(partition).or_insert(offset);
+    *committed = offset.max(*committed);
+}
+
+/// Where [`process_source`] reports the rows it rejects
+pub(crate) struct Rejected<'a> {
+    /// Error messages, unless they are summarized
+    pub(crate) errors: &'a mut Vec<String>,
+    /// Errors counted by kind instead of kept one by one
+    pub(crate) summary: Option<&'a mut ErrorSummary>,
+    /// Rows referring to a transaction never seen, kept apart from the errors; if
+    /// `None`, they are reported as errors
+    pub(crate) unmatched: Option<&'a mut Vec<UnmatchedReference>>,
+    /// Every rejected row, if kept, whether reported as an error or as unmatched
+    pub(crate) rejections: Option<&'a mut Vec<Rejection>>,
+}
+
+impl Rejected<'_> {
+    /// Report `error` in the summary if there is one, or append it to the errors, and
+    /// keep it as a rejection of a row of `fields`
+    fn error(&mut self, error: SourceError, fields: Option<RejectedFields>) {
+        self.record(&error, fields);
+        match self.summary.as_deref_mut() {
+            Some(summary) => summary.record(&error),
+            None => self.errors.push(error.to_string()),
+        }
+    }
+
+    /// Keep `error` as a rejection of a row of `fields`, if rejections are kept
+    fn record(&mut self, error: &SourceError, fields: Option<RejectedFields>) {
+        if let Some(rejections) = self.rejections.as_deref_mut() {
+            rejections.push(Rejection::new(error, fields));
+        }
+    }
+}
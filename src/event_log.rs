@@ -0,0 +1,420 @@
+//! Persisted log of applied transactions
+//!
+//! An [`Engine`](crate::Engine) can record every transaction it applies, in order, into
+//! an [`EventLog`]. Rejected rows and skipped duplicates are left out, so replaying the
+//! log rebuilds exactly the state it was recorded from. Logs are stored as CSV in the
+//! usual input format, and can be replayed in full or only up to a [`ReplayPoint`] to
+//! see what the accounts looked like at that moment.
+
+use crate::db::{Account, Database, Timestamp, Transaction, TransactionMeta};
+use crate::source::{SourceError, SourcedTransaction, TransactionSource};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+#[cfg(feature = "csv")]
+use {
+    crate::csv_processor::CsvSource,
+    crate::record::{RecordAmount, TransactionRecord},
+    std::error::Error,
+    std::fs::OpenOptions,
+    std::io::{BufWriter, Read, Write},
+    std::path::Path,
+};
+
+/// A transaction that was applied
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    /// Client the transaction was for
+    pub client: u16,
+    /// Transaction ID
+    pub tx: u32,
+    /// The transaction itself
+    pub transaction: Transaction,
+    /// Timestamp and case ID it was applied with
+    #[serde(default, skip_serializing_if = "is_unknown")]
+    pub meta: TransactionMeta,
+}
+
+/// Whether nothing is known about a transaction beyond the transaction itself
+fn is_unknown(meta: &TransactionMeta) -> bool {
+    *meta == TransactionMeta::default()
+}
+
+impl Event {
+    /// The event as a row in the input format
+    #[cfg(feature = "csv")]
+    pub(crate) fn to_record(&self) -> TransactionRecord {
+        TransactionRecord {
+            transaction_type: self.transaction.name().to_string(),
+            client: self.client,
+            tx: self.tx,
+            amount: self.transaction.amount().map(RecordAmount::Valid),
+            timestamp: self.meta.timestamp,
+            case_id: self.meta.case_id.clone(),
+            effective_at: self.meta.effective_at,
+            initiator: self.meta.initiator.clone(),
+            offset: self.meta.offset.as_ref().map(ToString::to_string),
+            original_tx: match self.transaction {
+                Transaction::Refund { original_tx, .. } => Some(original_tx),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Where to stop replaying an event log
+///
+/// Parses from `tx:ID`, `time:TIMESTAMP`, or a bare transaction ID.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::ReplayPoint;
+/// assert_eq!("123456".parse(), Ok(ReplayPoint::Tx(123456)));
+/// assert_eq!("tx:7".parse(), Ok(ReplayPoint::Tx(7)));
+/// assert_eq!("time:1700000000".parse(), Ok(ReplayPoint::Time(1700000000)));
+/// assert!("yesterday".parse::<ReplayPoint>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayPoint {
+    /// Up to and including the first event with this transaction ID, i.e. the deposit
+    /// or withdrawal itself rather than any later dispute of it
+    Tx(u32),
+    /// Up to and including the last event before one timestamped after this time
+    Time(Timestamp),
+}
+
+impl ReplayPoint {
+    /// Whether the event comes after this point, ending the replay
+    fn is_after(&self, event: &Event) -> bool {
+        match self {
+            Self::Tx(_) => false,
+            Self::Time(time) => event.meta.timestamp.is_some_and(|t| t > *time),
+        }
+    }
+
+    /// Whether the event is the last one to replay
+    fn is_last(&self, event: &Event) -> bool {
+        matches!(self, Self::Tx(tx) if event.tx == *tx)
+    }
+}
+
+impl FromStr for ReplayPoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = s.split_once(':').unwrap_or(("tx", s));
+        match kind {
+            "tx" => value
+                .parse()
+                .map(Self::Tx)
+                .map_err(|_| format!("Invalid transaction ID: {}", value)),
+            "time" => value
+                .parse()
+                .map(Self::Time)
+                .map_err(|_| format!("Invalid timestamp: {}", value)),
+            _ => Err(format!("Expected tx:ID or time:TIMESTAMP, got {}", s)),
+        }
+    }
+}
+
+/// Every transaction applied, in the order it was applied
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{CsvSource, Engine, EventLog, ReplayPoint};
+/// let input = "type,client,tx,amount\n\
+///              deposit,7,1,100.0\n\
+///              withdrawal,7,2,500.0\n\
+///              withdrawal,7,3,30.0\n";
+///
+/// let mut engine = Engine::new().with_event_log();
+/// engine.process(CsvSource::new("input", input.as_bytes()));
+///
+/// // The refused withdrawal isn't part of the log
+/// let mut persisted = Vec::new();
+/// engine.event_log().unwrap().write_csv(&mut persisted).unwrap();
+/// let log = EventLog::read_csv("log", persisted.as_slice()).unwrap();
+/// assert_eq!(log.events().len(), 2);
+///
+/// // Client 7 before the second withdrawal
+/// let mut replayed = Engine::new();
+/// replayed.process(log.replay(Some(ReplayPoint::Tx(1))));
+/// let account = replayed.database().get_account(7).unwrap();
+/// assert_eq!(account.available().to_string(), "100.0000");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventLog {
+    events: Vec<Event>,
+}
+
+impl EventLog {
+    /// Create an empty log
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append an applied transaction
+    pub fn push(&mut self, event: Event) {
+        self.events.push(event);
+    }
+
+    /// The events, oldest first
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Remove every event, e.g. once a snapshot covers them
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    /// Read a log written by [`write_csv`](EventLog::write_csv)
+    ///
+    /// # Errors
+    /// Returns the first record that cannot be read, located by `name` and line number.
+    #[cfg(feature = "csv")]
+    pub fn read_csv<R: Read>(name: &str, reader: R) -> Result<Self, SourceError> {
+        let mut source = CsvSource::new(name, reader);
+        let mut log = Self::new();
+        while let Some(item) = source.next_transaction() {
+            let item = item?;
+            log.push(Event {
+                client: item.client,
+                tx: item.tx,
+                transaction: item.transaction,
+                meta: item.meta,
+            });
+        }
+        Ok(log)
+    }
+
+    /// Write the log as CSV in the input format, with timestamp, case_id,
+    /// effective_at, initiator, offset and original_tx columns
+    ///
+    /// # Errors
+    /// Returns an error if writing fails.
+    #[cfg(feature = "csv")]
+    pub fn write_csv<W: Write>(&self, writer: W) -> Result<(), csv::Error> {
+        let mut writer = csv::Writer::from_writer(writer);
+        for event in &self.events {
+            writer.serialize(event.to_record())?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Every account as it stood at `point`
+    ///
+    /// Events are replayed under the default [`Policy`](crate::Policy); to replay under
+    /// another, process [`replay`](EventLog::replay) with an
+    /// [`Engine::with_policy`](crate::Engine::with_policy) instead.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Event, EventLog, ReplayPoint, Transaction, TransactionMeta};
+    /// let mut log = EventLog::new();
+    /// for (client, tx, transaction, timestamp) in [
+    ///     (1, 1, Transaction::deposit("100").unwrap(), 1000),
+    ///     (2, 2, Transaction::deposit("40").unwrap(), 2000),
+    ///     (1, 3, Transaction::withdrawal("25").unwrap(), 3000),
+    /// ] {
+    ///     let meta = TransactionMeta { timestamp: Some(timestamp), ..TransactionMeta::default() };
+    ///     log.push(Event { client, tx, transaction, meta });
+    /// }
+    ///
+    /// let db = log.state_at(ReplayPoint::Time(2500));
+    /// assert_eq!(db.get_account(1).unwrap().available().to_string(), "100.0000");
+    /// assert_eq!(db.get_account(2).unwrap().available().to_string(), "40.0000");
+    ///
+    /// let account = log.account_at(1, ReplayPoint::Tx(3)).unwrap();
+    /// assert_eq!(account.available().to_string(), "75.0000");
+    /// assert!(log.account_at(2, ReplayPoint::Tx(1)).is_none());
+    /// ```
+    pub fn state_at(&self, point: ReplayPoint) -> Database {
+        self.replay_where(point, |_| true)
+    }
+
+    /// One client's account as it stood at `point`, or `None` if it didn't exist yet
+    ///
+    /// Only that client's events are replayed, so this is cheaper than
+    /// [`state_at`](EventLog::state_at) on a log with many clients.
+    pub fn account_at(&self, client: u16, point: ReplayPoint) -> Option<Account> {
+        let database = self.replay_where(point, |event| event.client == client);
+        database.get_account(client).cloned()
+    }
+
+    /// Every account as it stood at `as_of`, in both time dimensions
+    ///
+    /// Answers "what were the balances effective at `as_of.effective`, as known at
+    /// `as_of.recorded`": corrections recorded later are left out, and backdated
+    /// corrections recorded earlier are applied at their effective time rather than
+    /// when they were posted. See [`replay_as_of`](EventLog::replay_as_of).
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{AsOf, Event, EventLog, Transaction, TransactionMeta};
+    /// let mut log = EventLog::new();
+    /// let at = |timestamp, effective_at| TransactionMeta {
+    ///     timestamp: Some(timestamp),
+    ///     effective_at,
+    ///     ..TransactionMeta::default()
+    /// };
+    /// let deposit = Transaction::deposit("100").unwrap();
+    /// log.push(Event { client: 1, tx: 1, transaction: deposit, meta: at(1000, None) });
+    /// // Posted at 5000, correcting a fee that should have been taken at 2000
+    /// let fee = Transaction::withdrawal("10").unwrap();
+    /// log.push(Event { client: 1, tx: 2, transaction: fee, meta: at(5000, Some(2000)) });
+    ///
+    /// let balance = |effective, recorded| {
+    ///     let db = log.state_as_of(AsOf { effective: Some(effective), recorded: Some(recorded) });
+    ///     db.get_account(1).unwrap().available().to_string()
+    /// };
+    /// // Balance effective at 3000, as reported at 4000, before the correction was posted
+    /// assert_eq!(balance(3000, 4000), "100.0000");
+    /// // The same effective time, as known today
+    /// assert_eq!(balance(3000, 9000), "90.0000");
+    /// ```
+    pub fn state_as_of(&self, as_of: AsOf) -> Database {
+        apply(self.replay_as_of(as_of), |_| true)
+    }
+
+    /// Replay the events up to `point` that pass `filter` into a fresh database
+    fn replay_where(
+        &self,
+        point: ReplayPoint,
+        filter: impl Fn(&SourcedTransaction) -> bool,
+    ) -> Database {
+        apply(self.replay(Some(point)), filter)
+    }
+
+    /// Source replaying the events up to `until`, or all of them
+    ///
+    /// Errors are located by event number, e.g. `event 3`.
+    pub fn replay(&self, until: Option<ReplayPoint>) -> Replay<'_> {
+        Replay {
+            events: self
+                .events
+                .iter()
+                .enumerate()
+                .collect::<Vec<_>>()
+                .into_iter(),
+            until,
+            finished: false,
+        }
+    }
+
+    /// Source replaying the events visible at `as_of`, in effective-time order
+    ///
+    /// Events without a timestamp are taken to have been recorded, and to take effect,
+    /// with the event before them. Events effective at the same time keep their log
+    /// order. Reordering can make a transaction fail that succeeded when it was posted,
+    /// e.g. a backdated withdrawal that was only covered by a later deposit; such
+    /// transactions are rejected like any other.
+    pub fn replay_as_of(&self, as_of: AsOf) -> Replay<'_> {
+        let mut recorded = 0;
+        let mut visible = Vec::new();
+        for (index, event) in self.events.iter().enumerate() {
+            recorded = event.meta.timestamp.unwrap_or(recorded);
+            let effective = event.meta.effective_at.unwrap_or(recorded);
+            if as_of.recorded.is_none_or(|limit| recorded <= limit)
+                && as_of.effective.is_none_or(|limit| effective <= limit)
+            {
+                visible.push((effective, index, event));
+            }
+        }
+        visible.sort_by_key(|&(effective, index, _)| (effective, index));
+
+        Replay {
+            events: visible
+                .into_iter()
+                .map(|(_, index, event)| (index, event))
+                .collect::<Vec<_>>()
+                .into_iter(),
+            until: None,
+            finished: false,
+        }
+    }
+}
+
+/// Apply the replayed events that pass `filter` to a fresh database
+///
+/// Events that fail to apply are skipped, as they were when first processed.
+fn apply(mut replay: Replay<'_>, filter: impl Fn(&SourcedTransaction) -> bool) -> Database {
+    let mut database = Database::new();
+    while let Some(item) = replay.next_transaction() {
+        let Ok(item) = item else { continue };
+        if filter(&item) {
+            // Only applied transactions were logged, so this fails only if the log was
+            // recorded under a different policy or the events were reordered
+            let _ = database.process_transaction_with(
+                item.client,
+                item.tx,
+                item.transaction,
+                item.meta,
+            );
+        }
+    }
+    database
+}
+
+/// A point in both time dimensions of an event log
+///
+/// Every event has a recorded time, its `timestamp`, when it was posted. Its effective
+/// time is the same, unless it is a backdated correction posted with an earlier
+/// [`effective_at`](TransactionMeta::effective_at).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AsOf {
+    /// Only events that took effect at or before this time (`None` for no limit)
+    pub effective: Option<Timestamp>,
+    /// Only events recorded at or before this time, i.e. what was known then (`None`
+    /// for no limit)
+    pub recorded: Option<Timestamp>,
+}
+
+/// Source yielding events of an [`EventLog`], e.g. up to a [`ReplayPoint`]
+pub struct Replay<'a> {
+    events: std::vec::IntoIter<(usize, &'a Event)>,
+    until: Option<ReplayPoint>,
+    finished: bool,
+}
+impl TransactionSource for Replay<'_> {
+    fn next_transaction(&mut self) -> Option<Result<SourcedTransaction, SourceError>> {
+        if self.finished {
+            return None;
+        }
+        let (index, event) = self.events.next()?;
+        if let Some(until) = &self.until {
+            if until.is_after(event) {
+                self.finished = true;
+                return None;
+            }
+            self.finished = until.is_last(event);
+        }
+
+        Some(Ok(SourcedTransaction {
+            location: format!("event {}", index + 1),
+            client: event.client,
+            tx: event.tx,
+            transaction: event.transaction.clone(),
+            meta: event.meta.clone(),
+            warning: None,
+        }))
+    }
+}
+
+/// Append `events` to the CSV log at `path`, creating it (with a header) if needed
+#[cfg(feature = "csv")]
+pub(crate) fn append_csv(path: &Path, events: &[Event]) -> Result<(), Box<dyn Error>> {
+    if events.is_empty() {
+        return Ok(());
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let is_new = file.metadata()?.len() == 0;
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(is_new)
+        .from_writer(BufWriter::new(file));
+    for event in events {
+        writer.serialize(event.to_record())?;
+    }
+    writer.flush()?;
+    Ok(())
+}
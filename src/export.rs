@@ -0,0 +1,583 @@
+//! CSV and JSON exports of processed data
+
+use crate::db::{Account, Database};
+use crate::engine::UnmatchedReference;
+use crate::sink::SummarySink;
+use std::error::Error;
+use std::io::Write;
+use std::str::FromStr;
+
+/// A column of the account summary report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SummaryColumn {
+    /// Client ID
+    Client,
+    /// Funds available for withdrawal
+    Available,
+    /// Funds held
+    Held,
+    /// Available plus held funds
+    Total,
+    /// Whether the account is locked
+    Locked,
+    /// Account lifecycle state
+    State,
+    /// Number of deposits currently under dispute
+    OpenDisputes,
+    /// Number of deposits and withdrawals in the ledger
+    TxCount,
+    /// Latest timestamp of any balance change, blank if unknown
+    LastActivity,
+    /// Funds held in the rolling reserve
+    Reserved,
+}
+
+impl SummaryColumn {
+    /// Every column, in the order they are documented
+    pub const ALL: [SummaryColumn; 10] = [
+        SummaryColumn::Client,
+        SummaryColumn::Available,
+        SummaryColumn::Held,
+        SummaryColumn::Total,
+        SummaryColumn::Locked,
+        SummaryColumn::State,
+        SummaryColumn::OpenDisputes,
+        SummaryColumn::TxCount,
+        SummaryColumn::LastActivity,
+        SummaryColumn::Reserved,
+    ];
+
+    /// Column header name
+    pub fn name(self) -> &'static str {
+        match self {
+            SummaryColumn::Client => "client",
+            SummaryColumn::Available => "available",
+            SummaryColumn::Held => "held",
+            SummaryColumn::Total => "total",
+            SummaryColumn::Locked => "locked",
+            SummaryColumn::State => "state",
+            SummaryColumn::OpenDisputes => "open_disputes",
+            SummaryColumn::TxCount => "tx_count",
+            SummaryColumn::LastActivity => "last_activity",
+            SummaryColumn::Reserved => "reserved",
+        }
+    }
+
+    /// This column's value for an account
+    pub(crate) fn value(self, client_id: u16, account: &Account) -> String {
+        match self {
+            SummaryColumn::Client => client_id.to_string(),
+            SummaryColumn::Available => account.available().to_string(),
+            SummaryColumn::Held => account.held().to_string(),
+            SummaryColumn::Total => account.total().to_string(),
+            SummaryColumn::Locked => account.is_locked().to_string(),
+            SummaryColumn::State => account.state().to_string(),
+            SummaryColumn::OpenDisputes => account.open_disputes().to_string(),
+            SummaryColumn::TxCount => account.transaction_count().to_string(),
+            SummaryColumn::LastActivity => account
+                .last_activity()
+                .map(|t| t.to_string())
+                .unwrap_or_default(),
+            SummaryColumn::Reserved => account.reserved().to_string(),
+        }
+    }
+
+    /// This column's value for an account in JSON: amounts as decimal strings, so they
+    /// stay exact, and everything else as its JSON type
+    pub(crate) fn json_value(self, client_id: u16, account: &Account) -> serde_json::Value {
+        use serde_json::Value;
+        match self {
+            SummaryColumn::Client => Value::from(client_id),
+            SummaryColumn::Locked => Value::from(account.is_locked()),
+            SummaryColumn::OpenDisputes => Value::from(account.open_disputes()),
+            SummaryColumn::TxCount => Value::from(account.transaction_count()),
+            SummaryColumn::LastActivity => Value::from(account.last_activity()),
+            _ => Value::from(self.value(client_id, account)),
+        }
+    }
+}
+
+impl FromStr for SummaryColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SummaryColumn::ALL
+            .into_iter()
+            .find(|column| column.name() == s)
+            .ok_or_else(|| format!("Unknown column: {}", s))
+    }
+}
+
+/// The columns of the account summary report, in output order
+///
+/// The default is the original report: `client,available,held,total,locked,state`.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{SummaryColumn, SummaryColumns};
+/// let columns: SummaryColumns = "client, total, open_disputes".parse().unwrap();
+/// assert_eq!(
+///     columns.columns(),
+///     &[SummaryColumn::Client, SummaryColumn::Total, SummaryColumn::OpenDisputes]
+/// );
+///
+/// assert!("client,balance".parse::<SummaryColumns>().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SummaryColumns(Vec<SummaryColumn>);
+
+impl SummaryColumns {
+    /// Use the given columns, in order
+    pub fn new(columns: Vec<SummaryColumn>) -> Self {
+        Self(columns)
+    }
+
+    /// The selected columns, in output order
+    pub fn columns(&self) -> &[SummaryColumn] {
+        &self.0
+    }
+}
+
+impl SummaryColumns {
+    /// The balance components: `available,held,total,locked,reserved`, without the
+    /// client, for the [long](SummaryLayout::Long) layout
+    pub fn balances() -> Self {
+        Self(vec![
+            SummaryColumn::Available,
+            SummaryColumn::Held,
+            SummaryColumn::Total,
+            SummaryColumn::Locked,
+            SummaryColumn::Reserved,
+        ])
+    }
+}
+
+impl Default for SummaryColumns {
+    fn default() -> Self {
+        Self(vec![
+            SummaryColumn::Client,
+            SummaryColumn::Available,
+            SummaryColumn::Held,
+            SummaryColumn::Total,
+            SummaryColumn::Locked,
+            SummaryColumn::State,
+        ])
+    }
+}
+
+impl FromStr for SummaryColumns {
+    type Err = String;
+
+    /// Parse a comma-separated list of column names
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|name| name.trim().parse())
+            .collect::<Result<Vec<_>, _>>()
+            .map(Self)
+    }
+}
+
+/// How the account summary report is laid out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SummaryLayout {
+    /// One row per account, with a column for each field
+    #[default]
+    Wide,
+    /// One `client,metric,value` row per field of each account, as loaded by warehouse
+    /// and BI pipelines (see [`write_account_summaries_long`])
+    Long,
+}
+
+/// Format of the account summary report
+///
+/// Parses from and displays as `csv` or `json`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// CSV, with a header row (see [`write_account_summaries`])
+    #[default]
+    Csv,
+    /// A JSON array with an object per account (see [`write_account_summaries_json`])
+    Json,
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Csv => write!(f, "csv"),
+            Self::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            other => Err(format!("Expected csv or json, got {}", other)),
+        }
+    }
+}
+
+/// Write one summary row per account, in the given order, with the chosen columns
+///
+/// The rows usually come from [`Database::summaries`].
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{
+/// #     Database, SummaryColumns, SummaryQuery, Transaction, write_account_summaries,
+/// # };
+/// let mut db = Database::new();
+/// db.process_transaction(1, 1, Transaction::deposit("10").unwrap()).unwrap();
+/// db.process_transaction(1, 1, Transaction::dispute()).unwrap();
+///
+/// let summaries = db.summaries(&SummaryQuery::default());
+/// let columns: SummaryColumns = "client,held,open_disputes,tx_count".parse().unwrap();
+/// let mut output = Vec::new();
+/// write_account_summaries(&summaries, &columns, &mut output).unwrap();
+/// assert_eq!(
+///     String::from_utf8(output).unwrap(),
+///     "client,held,open_disputes,tx_count\n1,10.0000,1,1\n"
+/// );
+/// ```
+///
+/// # Errors
+/// Returns an error if writing to `writer` fails.
+pub fn write_account_summaries<W: Write>(
+    summaries: &[(u16, &Account)],
+    columns: &SummaryColumns,
+    writer: W,
+) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(columns.columns().iter().map(|column| column.name()))?;
+
+    for &(client_id, account) in summaries {
+        writer.write_record(
+            columns
+                .columns()
+                .iter()
+                .map(|column| column.value(client_id, account)),
+        )?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write the account summaries in long format: one `client,metric,value` row for each
+/// of the chosen columns of each account
+///
+/// Accounts are written in the given order, each with its metrics in column order. A
+/// `client` column is left out, as every row starts with the client.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{
+/// #     Database, SummaryColumns, SummaryQuery, Transaction, write_account_summaries_long,
+/// # };
+/// let mut db = Database::new();
+/// db.process_transaction(1, 1, Transaction::deposit("10").unwrap()).unwrap();
+/// db.process_transaction(1, 1, Transaction::dispute()).unwrap();
+///
+/// let summaries = db.summaries(&SummaryQuery::default());
+/// let mut output = Vec::new();
+/// write_account_summaries_long(&summaries, &SummaryColumns::balances(), &mut output).unwrap();
+/// assert_eq!(
+///     String::from_utf8(output).unwrap(),
+///     "client,metric,value\n\
+///      1,available,0.0000\n\
+///      1,held,10.0000\n\
+///      1,total,10.0000\n\
+///      1,locked,false\n\
+///      1,reserved,0.0000\n"
+/// );
+/// ```
+///
+/// # Errors
+/// Returns an error if writing to `writer` fails.
+pub fn write_account_summaries_long<W: Write>(
+    summaries: &[(u16, &Account)],
+    columns: &SummaryColumns,
+    writer: W,
+) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["client", "metric", "value"])?;
+
+    for &(client_id, account) in summaries {
+        for &column in columns.columns() {
+            if column == SummaryColumn::Client {
+                continue;
+            }
+            writer.write_record([
+                client_id.to_string(),
+                column.name().to_string(),
+                column.value(client_id, account),
+            ])?;
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write the account summaries as a JSON array: an object per account, in the given
+/// order, with a member for each of the chosen columns
+///
+/// Amounts are decimal strings, as in the CSV report, so they stay exact; counts,
+/// timestamps and the client are numbers, and `locked` is a boolean. Each account is on
+/// its own line.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{
+/// #     Database, SummaryColumns, SummaryQuery, Transaction, write_account_summaries_json,
+/// # };
+/// let mut db = Database::new();
+/// db.process_transaction(1, 1, Transaction::deposit("10").unwrap()).unwrap();
+/// db.process_transaction(2, 2, Transaction::deposit("2.5").unwrap()).unwrap();
+///
+/// let summaries = db.summaries(&SummaryQuery::default());
+/// let columns: SummaryColumns = "client,total,locked,tx_count".parse().unwrap();
+/// let mut output = Vec::new();
+/// write_account_summaries_json(&summaries, &columns, &mut output).unwrap();
+/// assert_eq!(
+///     String::from_utf8(output).unwrap(),
+///     "[\n\
+///      {\"client\":1,\"total\":\"10.0000\",\"locked\":false,\"tx_count\":1},\n\
+///      {\"client\":2,\"total\":\"2.5000\",\"locked\":false,\"tx_count\":1}\n\
+///      ]\n"
+/// );
+/// ```
+///
+/// # Errors
+/// Returns an error if writing to `writer` fails.
+pub fn write_account_summaries_json<W: Write>(
+    summaries: &[(u16, &Account)],
+    columns: &SummaryColumns,
+    mut writer: W,
+) -> std::io::Result<()> {
+    writer.write_all(b"[")?;
+    for (index, &(client_id, account)) in summaries.iter().enumerate() {
+        let members: Vec<String> = columns
+            .columns()
+            .iter()
+            .map(|column| {
+                // Values display as compact JSON; a map would sort the members by name
+                format!(
+                    "{}:{}",
+                    serde_json::Value::from(column.name()),
+                    column.json_value(client_id, account)
+                )
+            })
+            .collect();
+        let separator = if index == 0 { "" } else { "," };
+        write!(writer, "{}\n{{{}}}", separator, members.join(","))?;
+    }
+    writer.write_all(b"\n]\n")?;
+    writer.flush()
+}
+
+/// CSV summary report output, with a configurable column set
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{CsvSink, Database, SummaryQuery, SummarySink, Transaction};
+/// let mut db = Database::new();
+/// db.process_transaction(1, 1, Transaction::deposit("10").unwrap()).unwrap();
+///
+/// let mut output = Vec::new();
+/// CsvSink::new(&mut output)
+///     .with_columns("client,total".parse().unwrap())
+///     .write_summaries(&db.summaries(&SummaryQuery::default()))
+///     .unwrap();
+/// assert_eq!(String::from_utf8(output).unwrap(), "client,total\n1,10.0000\n");
+/// ```
+pub struct CsvSink<W> {
+    writer: W,
+    columns: SummaryColumns,
+    layout: SummaryLayout,
+}
+
+impl<W: Write> CsvSink<W> {
+    /// Write the report to `writer` with the default columns
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            columns: SummaryColumns::default(),
+            layout: SummaryLayout::default(),
+        }
+    }
+
+    /// Choose the report columns
+    pub fn with_columns(mut self, columns: SummaryColumns) -> Self {
+        self.columns = columns;
+        self
+    }
+
+    /// Lay the report out as given, e.g. long with [`SummaryColumns::balances`]
+    pub fn with_layout(mut self, layout: SummaryLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+}
+
+impl<W: Write> SummarySink for CsvSink<W> {
+    fn write_summaries(&mut self, summaries: &[(u16, &Account)]) -> Result<(), Box<dyn Error>> {
+        match self.layout {
+            SummaryLayout::Wide => {
+                write_account_summaries(summaries, &self.columns, &mut self.writer)?
+            }
+            SummaryLayout::Long => {
+                write_account_summaries_long(summaries, &self.columns, &mut self.writer)?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// JSON summary report output, with a configurable column set (see
+/// [`write_account_summaries_json`])
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{CsvSource, Engine, JsonSink};
+/// let input = "type,client,tx,amount\ndeposit,1,1,10\n";
+/// let mut engine = Engine::new();
+/// engine.process(CsvSource::new("input", input.as_bytes()));
+///
+/// let mut output = Vec::new();
+/// engine
+///     .report(JsonSink::new(&mut output).with_columns("client,available".parse().unwrap()))
+///     .unwrap();
+/// assert_eq!(
+///     String::from_utf8(output).unwrap(),
+///     "[\n{\"client\":1,\"available\":\"10.0000\"}\n]\n"
+/// );
+/// ```
+pub struct JsonSink<W> {
+    writer: W,
+    columns: SummaryColumns,
+}
+
+impl<W: Write> JsonSink<W> {
+    /// Write the report to `writer` with the default columns
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            columns: SummaryColumns::default(),
+        }
+    }
+
+    /// Choose the report columns
+    pub fn with_columns(mut self, columns: SummaryColumns) -> Self {
+        self.columns = columns;
+        self
+    }
+}
+
+impl<W: Write> SummarySink for JsonSink<W> {
+    fn write_summaries(&mut self, summaries: &[(u16, &Account)]) -> Result<(), Box<dyn Error>> {
+        write_account_summaries_json(summaries, &self.columns, &mut self.writer)?;
+        Ok(())
+    }
+}
+
+/// Write every client's balance history as CSV, with the running balances after each row
+///
+/// Columns are `client,tx,type,timestamp,available,held`. Clients are listed in
+/// ascending order and each client's rows in the order they were applied, so an
+/// auditor can find the exact row where a balance stopped matching expectations.
+/// Rejected transactions don't change balances and are not listed.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Database, Transaction, write_running_balances};
+/// let mut db = Database::new();
+/// db.process_transaction_at(1, 1, Transaction::deposit("100").unwrap(), Some(10)).unwrap();
+/// db.process_transaction(1, 2, Transaction::withdrawal("30").unwrap()).unwrap();
+/// db.process_transaction(1, 1, Transaction::dispute()).unwrap();
+///
+/// let mut output = Vec::new();
+/// write_running_balances(&db, &mut output).unwrap();
+/// assert_eq!(
+///     String::from_utf8(output).unwrap(),
+///     "client,tx,type,timestamp,available,held\n\
+///      1,1,deposit,10,100.0000,0.0000\n\
+///      1,2,withdrawal,,70.0000,0.0000\n\
+///      1,1,dispute,,-30.0000,100.0000\n"
+/// );
+/// ```
+///
+/// # Errors
+/// Returns an error if writing to `writer` fails.
+pub fn write_running_balances<W: Write>(database: &Database, writer: W) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["client", "tx", "type", "timestamp", "available", "held"])?;
+
+    let mut client_ids = database.get_all_client_ids();
+    client_ids.sort();
+
+    for client_id in client_ids {
+        if let Some(account) = database.get_account(client_id) {
+            for row in account.balance_history() {
+                writer.write_record([
+                    client_id.to_string(),
+                    row.tx.to_string(),
+                    row.activity.to_string(),
+                    row.timestamp.map(|t| t.to_string()).unwrap_or_default(),
+                    row.available.to_string(),
+                    row.held.to_string(),
+                ])?;
+            }
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write disputes, resolves and chargebacks that matched no transaction as CSV
+///
+/// Columns are `location,client,tx,type`, in the order the rows were read, so the
+/// references can be checked against the upstream feed for truncation.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{CsvSource, Engine, write_unmatched_references};
+/// let input = "type,client,tx,amount\n\
+///              deposit,1,1,10\n\
+///              chargeback,1,4,\n";
+///
+/// let mut engine = Engine::new();
+/// engine.process(CsvSource::new("input", input.as_bytes()));
+///
+/// let mut output = Vec::new();
+/// write_unmatched_references(engine.unmatched(), &mut output).unwrap();
+/// assert_eq!(
+///     String::from_utf8(output).unwrap(),
+///     "location,client,tx,type\ninput:3,1,4,chargeback\n"
+/// );
+/// ```
+///
+/// # Errors
+/// Returns an error if writing to `writer` fails.
+pub fn write_unmatched_references<W: Write>(
+    references: &[UnmatchedReference],
+    writer: W,
+) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["location", "client", "tx", "type"])?;
+    for reference in references {
+        writer.write_record([
+            reference.location.clone(),
+            reference.client.to_string(),
+            reference.tx.to_string(),
+            reference.action.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
@@ -1,16 +1,40 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::str::FromStr;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Fixed4(i64);
 
 impl Fixed4 {
-    const SCALE: i64 = 10_000;
+    pub(crate) const SCALE: i64 = 10_000;
 }
 
 impl Fixed4 {
     pub fn zero() -> Self {
         Self(0)
     }
+
+    /// An amount given in hundredths, e.g. cents, or `None` if it is too large
+    ///
+    /// # Examples
+    /// ```
+    /// use transaction_processor::Fixed4;
+    ///
+    /// assert_eq!(Fixed4::from_cents(12345).unwrap().to_string(), "123.4500");
+    /// assert_eq!(Fixed4::from_cents(i64::MAX), None);
+    /// ```
+    pub fn from_cents(cents: i64) -> Option<Self> {
+        cents.checked_mul(Self::SCALE / 100).map(Self)
+    }
+
+    /// The amount in ten-thousandths
+    pub(crate) fn to_raw(self) -> i64 {
+        self.0
+    }
+
+    /// An amount of `raw` ten-thousandths
+    pub(crate) fn from_raw(raw: i64) -> Self {
+        Self(raw)
+    }
 }
 
 impl FromStr for Fixed4 {
@@ -103,7 +127,7 @@ impl FromStr for Fixed4 {
 
 impl Fixed4 {
     /// Convert to f64 for compatibility (may lose precision for very large values)
-    /// 
+    ///
     /// Note: For display purposes, prefer using `to_string()` or the `Display` trait
     /// to avoid any potential floating-point precision issues.
     pub fn to_f64(self) -> f64 {
@@ -111,6 +135,102 @@ impl Fixed4 {
     }
 }
 
+impl Fixed4 {
+    /// The given whole percentage of this amount, rounded toward zero
+    ///
+    /// # Examples
+    /// ```
+    /// use transaction_processor::Fixed4;
+    ///
+    /// let amount: Fixed4 = "100.01".parse().unwrap();
+    /// assert_eq!(amount.percentage(10).to_string(), "10.0010");
+    /// assert_eq!(amount.percentage(0).to_string(), "0.0000");
+    /// ```
+    pub fn percentage(self, percent: u8) -> Self {
+        Self((i128::from(self.0) * i128::from(percent) / 100) as i64)
+    }
+}
+
+impl Fixed4 {
+    /// `self + other`, or `None` if the sum is too large to hold
+    ///
+    /// # Examples
+    /// ```
+    /// use transaction_processor::Fixed4;
+    ///
+    /// let amount: Fixed4 = "900000000000000".parse().unwrap();
+    /// assert_eq!(amount.checked_add(amount), None);
+    /// assert_eq!(amount.checked_add(Fixed4::zero()), Some(amount));
+    /// ```
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    /// `self - other`, or `None` if the difference is too large to hold
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Self)
+    }
+
+    /// `self * factor`, or `None` if the product is too large to hold
+    ///
+    /// # Examples
+    /// ```
+    /// use transaction_processor::Fixed4;
+    ///
+    /// let amount: Fixed4 = "900000000000000".parse().unwrap();
+    /// assert_eq!(amount.checked_mul(2), None);
+    /// assert_eq!(amount.checked_mul(-1), Some(-amount));
+    /// ```
+    pub fn checked_mul(self, factor: i64) -> Option<Self> {
+        self.0.checked_mul(factor).map(Self)
+    }
+
+    /// `self / divisor`, rounded to the nearest ten-thousandth, halves away from zero,
+    /// or `None` if `divisor` is zero or the quotient is too large to hold
+    ///
+    /// # Examples
+    /// ```
+    /// use transaction_processor::Fixed4;
+    ///
+    /// let amount: Fixed4 = "100".parse().unwrap();
+    /// assert_eq!(amount.checked_div(0), None);
+    /// assert_eq!(amount.checked_div(3).unwrap().to_string(), "33.3333");
+    /// ```
+    pub fn checked_div(self, divisor: i64) -> Option<Self> {
+        if divisor == 0 {
+            return None;
+        }
+        let (numerator, divisor) = (i128::from(self.0), i128::from(divisor));
+        let quotient = numerator / divisor;
+        let remainder = numerator % divisor;
+        // Round the magnitude up if the remainder is at least half the divisor
+        let rounded = if 2 * remainder.abs() >= divisor.abs() {
+            quotient
+                + if (numerator < 0) == (divisor < 0) {
+                    1
+                } else {
+                    -1
+                }
+        } else {
+            quotient
+        };
+        i64::try_from(rounded).ok().map(Self)
+    }
+
+    /// `self + other`, limited to the largest or smallest amount a `Fixed4` holds
+    ///
+    /// # Examples
+    /// ```
+    /// use transaction_processor::Fixed4;
+    ///
+    /// let amount: Fixed4 = "900000000000000".parse().unwrap();
+    /// assert_eq!(amount.saturating_add(amount).to_string(), "922337203685477.5807");
+    /// ```
+    pub fn saturating_add(self, other: Self) -> Self {
+        Self(self.0.saturating_add(other.0))
+    }
+}
+
 impl std::fmt::Display for Fixed4 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if self.0 < 0 {
@@ -126,22 +246,185 @@ impl std::fmt::Display for Fixed4 {
     }
 }
 
+/// # Panics
+/// Panics if the sum is too large to hold, in every build; use [`Fixed4::checked_add`]
+/// to handle that instead.
 impl std::ops::Add for Fixed4 {
     type Output = Self;
 
     fn add(self, other: Self) -> Self {
-        Fixed4(self.0 + other.0)
+        self.checked_add(other).expect("Fixed4 addition overflowed")
     }
 }
 
+/// # Panics
+/// Panics if the sum is too large to hold, as [`Add`](std::ops::Add) does.
 impl std::ops::AddAssign for Fixed4 {
     fn add_assign(&mut self, other: Self) {
-        self.0 += other.0;
+        *self = *self + other;
     }
 }
 
+/// # Panics
+/// Panics if the difference is too large to hold, as [`Sub`](std::ops::Sub) does.
 impl std::ops::SubAssign for Fixed4 {
     fn sub_assign(&mut self, other: Self) {
-        self.0 -= other.0;
+        *self = *self - other;
+    }
+}
+
+/// # Panics
+/// Panics if the difference is too large to hold, in every build; use
+/// [`Fixed4::checked_sub`] to handle that instead.
+impl std::ops::Sub for Fixed4 {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        self.checked_sub(other)
+            .expect("Fixed4 subtraction overflowed")
+    }
+}
+
+/// # Panics
+/// Panics on the most negative amount, whose negation is too large to hold, in every
+/// build.
+impl std::ops::Neg for Fixed4 {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Fixed4(self.0.checked_neg().expect("Fixed4 negation overflowed"))
+    }
+}
+
+/// Multiplied by a whole number, which is exact
+///
+/// # Examples
+/// ```
+/// use transaction_processor::Fixed4;
+///
+/// let fee: Fixed4 = "0.0125".parse().unwrap();
+/// assert_eq!((fee * 12).to_string(), "0.1500");
+/// assert_eq!((fee * -3).to_string(), "-0.0375");
+/// ```
+///
+/// # Panics
+/// Panics if the product is too large to hold, in every build; use
+/// [`Fixed4::checked_mul`] to handle that instead.
+impl std::ops::Mul<i64> for Fixed4 {
+    type Output = Self;
+
+    fn mul(self, factor: i64) -> Self {
+        self.checked_mul(factor)
+            .expect("Fixed4 multiplication overflowed")
+    }
+}
+
+/// Divided by a whole number, rounded to the nearest ten-thousandth, halves away from
+/// zero
+///
+/// # Examples
+/// ```
+/// use transaction_processor::Fixed4;
+///
+/// let amount: Fixed4 = "100".parse().unwrap();
+/// assert_eq!((amount / 3).to_string(), "33.3333");
+/// assert_eq!((amount / 6).to_string(), "16.6667");
+/// assert_eq!((-amount / 6).to_string(), "-16.6667");
+/// // 0.0001 / 2 is exactly half way, so it rounds away from zero
+/// assert_eq!(("0.0001".parse::<Fixed4>().unwrap() / 2).to_string(), "0.0001");
+/// ```
+///
+/// # Panics
+/// Panics if `divisor` is zero, or the quotient is too large to hold (the smallest
+/// amount divided by -1); use [`Fixed4::checked_div`] to handle those instead.
+impl std::ops::Div<i64> for Fixed4 {
+    type Output = Self;
+
+    fn div(self, divisor: i64) -> Self {
+        assert!(divisor != 0, "Fixed4 division by zero");
+        self.checked_div(divisor)
+            .expect("Fixed4 division overflowed")
+    }
+}
+
+/// Serialized as an exact decimal string, e.g. `"12.5000"`
+///
+/// # Examples
+/// ```
+/// use transaction_processor::Fixed4;
+///
+/// #[derive(serde::Serialize, serde::Deserialize)]
+/// struct Payout {
+///     client: u16,
+///     amount: Fixed4,
+/// }
+///
+/// let payout = Payout { client: 1, amount: "12.5".parse().unwrap() };
+/// assert_eq!(
+///     serde_json::to_string(&payout).unwrap(),
+///     r#"{"client":1,"amount":"12.5000"}"#
+/// );
+/// ```
+impl Serialize for Fixed4 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Deserialized from a decimal string, as serialized, or from a number
+///
+/// Strings are read exactly. Integers are read as whole amounts. Other numbers are
+/// read from the shortest decimal that gives back the same `f64`, which is exact for
+/// amounts with up to 15 significant digits; formats that hand over every number as
+/// an `f64` can't do better, so prefer strings for amounts that may be larger.
+///
+/// # Examples
+/// ```
+/// use transaction_processor::Fixed4;
+///
+/// let amounts: Vec<Fixed4> = serde_json::from_str(r#"["1.2345", 7, 0.1, -2.5]"#).unwrap();
+/// let amounts: Vec<String> = amounts.iter().map(Fixed4::to_string).collect();
+/// assert_eq!(amounts, ["1.2345", "7.0000", "0.1000", "-2.5000"]);
+///
+/// // More than four decimal places can't be held exactly
+/// assert!(serde_json::from_str::<Fixed4>("0.00001").is_err());
+/// assert!(serde_json::from_str::<Fixed4>(r#""12.34567""#).is_err());
+/// ```
+impl<'de> Deserialize<'de> for Fixed4 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(Fixed4Visitor)
+    }
+}
+
+/// Reads a [`Fixed4`] from whichever of a string or number the input holds
+struct Fixed4Visitor;
+
+impl serde::de::Visitor<'_> for Fixed4Visitor {
+    type Value = Fixed4;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "a decimal amount with at most 4 decimal places")
+    }
+
+    fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Fixed4, E> {
+        value.parse().map_err(E::custom)
+    }
+
+    fn visit_i64<E: serde::de::Error>(self, value: i64) -> Result<Fixed4, E> {
+        value
+            .checked_mul(Fixed4::SCALE)
+            .map(Fixed4)
+            .ok_or_else(|| E::custom(format!("{} is too large for an amount", value)))
+    }
+
+    fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<Fixed4, E> {
+        i64::try_from(value)
+            .map_err(|_| E::custom(format!("{} is too large for an amount", value)))
+            .and_then(|value| self.visit_i64(value))
+    }
+
+    fn visit_f64<E: serde::de::Error>(self, value: f64) -> Result<Fixed4, E> {
+        // Display gives the shortest decimal that reads back as the same f64
+        self.visit_str(&value.to_string())
     }
 }
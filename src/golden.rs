@@ -0,0 +1,215 @@
+//! Golden-file regression tests (`testing` feature)
+//!
+//! A golden test runs an input CSV through the engine and compares the account summary
+//! with an expected summary CSV kept alongside it. Rather than a pass/fail, the result
+//! is a [`GoldenDiff`] listing every client and field that differs, so a failing
+//! fixture shows exactly what changed. Teams maintaining their own fixture suites can
+//! run them with a loop over a directory of input/expected pairs.
+
+use crate::csv_processor::CsvSource;
+use crate::engine::Engine;
+use crate::export::{SummaryColumn, SummaryColumns};
+use crate::fixed4::Fixed4;
+use crate::policy::Policy;
+use crate::report::SummaryQuery;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::path::PathBuf;
+
+/// An input CSV and the account summary it should produce
+///
+/// The expected file's header selects the columns compared (see [`SummaryColumns`]); it
+/// must include `client`. Amounts are compared by value, so `10.0` matches `10.0000`.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::GoldenTest;
+/// let dir = tempfile::tempdir().unwrap();
+/// let input = dir.path().join("input.csv");
+/// let expected = dir.path().join("expected.csv");
+/// std::fs::write(&input, "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,2,2,5\n").unwrap();
+/// std::fs::write(&expected, "client,available,locked\n1,10.0,false\n2,5.0,false\n").unwrap();
+///
+/// let diff = GoldenTest::new(&input, &expected).run().unwrap();
+/// assert!(!diff.is_empty());
+/// assert_eq!(
+///     diff.to_string(),
+///     "client 2: available: expected 5.0, got 0.0000\n"
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct GoldenTest {
+    input: PathBuf,
+    expected: PathBuf,
+    policy: Policy,
+}
+
+impl GoldenTest {
+    /// Compare the summary produced from `input` with the one in `expected`
+    pub fn new(input: impl Into<PathBuf>, expected: impl Into<PathBuf>) -> Self {
+        Self {
+            input: input.into(),
+            expected: expected.into(),
+            policy: Policy::default(),
+        }
+    }
+
+    /// Process the input under `policy`
+    pub fn with_policy(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Process the input and compare the result with the expected summary
+    ///
+    /// # Errors
+    /// Returns an error if either file cannot be read, or the expected file's header
+    /// names an unknown column or lacks `client`.
+    pub fn run(&self) -> Result<GoldenDiff, Box<dyn Error>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(File::open(&self.expected)?);
+        let header: Vec<String> = reader.headers()?.iter().map(str::to_string).collect();
+        let columns: SummaryColumns = header.join(",").parse()?;
+        let client_index = columns
+            .columns()
+            .iter()
+            .position(|&column| column == SummaryColumn::Client)
+            .ok_or("The expected summary has no client column")?;
+
+        let mut expected = BTreeMap::new();
+        for record in reader.records() {
+            let record = record?;
+            let client: u16 = record[client_index].parse()?;
+            expected.insert(
+                client,
+                record.iter().map(str::to_string).collect::<Vec<_>>(),
+            );
+        }
+
+        let mut engine = Engine::with_policy(self.policy.clone());
+        engine.process(CsvSource::new(
+            self.input.display().to_string(),
+            File::open(&self.input)?,
+        ));
+        let mut actual = BTreeMap::new();
+        for (client, account) in engine.database().summaries(&SummaryQuery::default()) {
+            let values: Vec<String> = columns
+                .columns()
+                .iter()
+                .map(|column| column.value(client, account))
+                .collect();
+            actual.insert(client, values);
+        }
+
+        let mut differences = Vec::new();
+        for (&client, expected_values) in &expected {
+            let Some(actual_values) = actual.remove(&client) else {
+                differences.push(Difference::MissingClient { client });
+                continue;
+            };
+            for ((name, expected), actual) in header.iter().zip(expected_values).zip(actual_values)
+            {
+                if !values_match(expected, &actual) {
+                    differences.push(Difference::Field {
+                        client,
+                        field: name.clone(),
+                        expected: expected.clone(),
+                        actual,
+                    });
+                }
+            }
+        }
+        differences.extend(
+            actual
+                .into_keys()
+                .map(|client| Difference::UnexpectedClient { client }),
+        );
+        Ok(GoldenDiff { differences })
+    }
+}
+
+/// Whether two summary values agree, comparing amounts by value
+fn values_match(expected: &str, actual: &str) -> bool {
+    match (expected.parse::<Fixed4>(), actual.parse::<Fixed4>()) {
+        (Ok(expected), Ok(actual)) => expected == actual,
+        _ => expected == actual,
+    }
+}
+
+/// One way the produced summary differs from the expected one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// An expected client has no account
+    MissingClient {
+        /// Client ID
+        client: u16,
+    },
+    /// An account exists that the expected summary doesn't list
+    UnexpectedClient {
+        /// Client ID
+        client: u16,
+    },
+    /// A field has a different value
+    Field {
+        /// Client ID
+        client: u16,
+        /// Column name
+        field: String,
+        /// Value in the expected summary
+        expected: String,
+        /// Value produced
+        actual: String,
+    },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Difference::MissingClient { client } => write!(f, "client {}: missing", client),
+            Difference::UnexpectedClient { client } => {
+                write!(f, "client {}: not expected", client)
+            }
+            Difference::Field {
+                client,
+                field,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "client {}: {}: expected {}, got {}",
+                client, field, expected, actual
+            ),
+        }
+    }
+}
+
+/// Every difference between a produced and an expected summary, by client
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GoldenDiff {
+    differences: Vec<Difference>,
+}
+
+impl GoldenDiff {
+    /// Whether the summaries match
+    pub fn is_empty(&self) -> bool {
+        self.differences.is_empty()
+    }
+
+    /// The differences, expected clients in ID order followed by unexpected ones
+    pub fn differences(&self) -> &[Difference] {
+        &self.differences
+    }
+}
+
+/// One difference per line
+impl fmt::Display for GoldenDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for difference in &self.differences {
+            writeln!(f, "{}", difference)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+//! Graph of dispute relationships, for visualizing complex cases
+//!
+//! [`write_dispute_graph`] writes the accounts' disputes, chargebacks and refunds as a
+//! Graphviz DOT graph: each client links to its transactions that were disputed or
+//! refunded, each such transaction to its dispute lifecycle in order, and each refund
+//! to the deposit it refunds. Render it with e.g. `dot -Tsvg graph.dot > graph.svg`.
+
+use crate::db::{Activity, Database, DisputeState};
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+/// Write the dispute relationships of every account as a DOT graph
+///
+/// Only the transactions that take part in a dispute or a refund are drawn, and only
+/// the clients that have any, so the graph stays readable for a whole run. Clients are
+/// boxes, red if locked. Transactions are labelled with their type, ID, amount and
+/// dispute state, and filled by that state: orange while disputed, red once charged
+/// back, blue once represented. Each dispute, resolve, chargeback and reversal is a
+/// node of its own, chained from its transaction in the order it was applied. Dashed
+/// edges run from refunds to the deposits they refund.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Database, Transaction, write_dispute_graph};
+/// let mut db = Database::new();
+/// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+/// db.process_transaction(1, 2, Transaction::deposit("5").unwrap()).unwrap();
+/// db.process_transaction(1, 1, Transaction::dispute()).unwrap();
+/// db.process_transaction(1, 1, Transaction::chargeback()).unwrap();
+///
+/// let mut output = Vec::new();
+/// write_dispute_graph(&db, &mut output).unwrap();
+/// let dot = String::from_utf8(output).unwrap();
+/// assert!(dot.starts_with("digraph disputes {\n"));
+/// assert!(dot.contains("\"c1\" -> \"c1 tx1\";\n"));
+/// assert!(dot.contains("\"c1 tx1\" -> \"c1 tx1 #1\";\n"));
+/// assert!(dot.contains("\"c1 tx1 #1\" -> \"c1 tx1 #2\";\n"));
+/// // The undisputed deposit is left out
+/// assert!(!dot.contains("tx2"));
+/// ```
+///
+/// # Errors
+/// Returns an error if writing to `writer` fails.
+pub fn write_dispute_graph<W: Write>(database: &Database, mut writer: W) -> io::Result<()> {
+    let mut client_ids = database.get_all_client_ids();
+    client_ids.sort();
+
+    writeln!(writer, "digraph disputes {{")?;
+    writeln!(writer, "rankdir=LR;")?;
+    writeln!(writer, "node [fontname=\"Helvetica\"];")?;
+    for client_id in client_ids {
+        let Some(account) = database.get_account(client_id) else {
+            continue;
+        };
+        let ledger: Vec<_> = account.ledger().collect();
+        let refunded: BTreeSet<u32> = ledger.iter().filter_map(|entry| entry.refund_of).collect();
+        let lifecycle = |tx: u32| {
+            account.balance_history().iter().filter(move |row| {
+                row.tx == tx
+                    && matches!(
+                        row.activity,
+                        Activity::Dispute
+                            | Activity::Resolve
+                            | Activity::Chargeback
+                            | Activity::ChargebackReversal
+                    )
+            })
+        };
+        let drawn: Vec<_> = ledger
+            .iter()
+            .filter(|entry| {
+                entry.refund_of.is_some()
+                    || refunded.contains(&entry.tx)
+                    || lifecycle(entry.tx).next().is_some()
+            })
+            .collect();
+        if drawn.is_empty() {
+            continue;
+        }
+
+        let client = format!("c{}", client_id);
+        let color = if account.is_locked() { "red" } else { "black" };
+        writeln!(
+            writer,
+            "\"{}\" [shape=box, color={}, label=\"client {}\\n{}\"];",
+            client,
+            color,
+            client_id,
+            account.state()
+        )?;
+        for entry in drawn {
+            let node = format!("{} tx{}", client, entry.tx);
+            let (state, fill) = match entry.dispute_state {
+                Some(DisputeState::Disputed) => ("\\ndisputed", "orange"),
+                Some(DisputeState::ChargedBack) => ("\\ncharged_back", "tomato"),
+                Some(DisputeState::Represented) => ("\\nrepresented", "lightblue"),
+                Some(DisputeState::Normal) | None => ("", "white"),
+            };
+            writeln!(
+                writer,
+                "\"{}\" [style=filled, fillcolor={}, label=\"{} {}\\n{}{}\"];",
+                node, fill, entry.kind, entry.tx, entry.amount, state
+            )?;
+            writeln!(writer, "\"{}\" -> \"{}\";", client, node)?;
+
+            let mut previous = node.clone();
+            for (step, row) in lifecycle(entry.tx).enumerate() {
+                let event = format!("{} #{}", node, step + 1);
+                let time = row
+                    .timestamp
+                    .map_or(String::new(), |time| format!("\\n@{}", time));
+                writeln!(
+                    writer,
+                    "\"{}\" [shape=diamond, label=\"{}{}\"];",
+                    event, row.activity, time
+                )?;
+                writeln!(writer, "\"{}\" -> \"{}\";", previous, event)?;
+                previous = event;
+            }
+
+            if let Some(deposit) = entry.refund_of {
+                writeln!(
+                    writer,
+                    "\"{}\" -> \"{} tx{}\" [style=dashed, label=\"refunds\"];",
+                    node, client, deposit
+                )?;
+            }
+        }
+    }
+    writeln!(writer, "}}")?;
+    writer.flush()
+}
@@ -0,0 +1,177 @@
+//! Streaming distribution of transaction amounts
+//!
+//! An [`AmountHistogram`] counts amounts in buckets whose width grows with the amount,
+//! in the style of HdrHistogram: each power of two is split into eight buckets, so any
+//! amount, from 0.0001 up to the largest a [`Fixed4`] holds, is placed within 12.5% of
+//! its value in a fixed few kilobytes. That is plenty to spot an anomaly such as a feed
+//! in cents where dollars were expected, which moves every percentile a hundredfold.
+
+use crate::fixed4::Fixed4;
+
+/// Buckets per power of two
+const SUB_BUCKETS: usize = 8;
+/// log2 of [`SUB_BUCKETS`]
+const SUB_BUCKET_BITS: u32 = 3;
+/// Amounts below [`SUB_BUCKETS`] ten-thousandths get a bucket each; above, every power
+/// of two up to 2^62 gets [`SUB_BUCKETS`]
+const BUCKETS: usize = SUB_BUCKETS + (63 - SUB_BUCKET_BITS as usize) * SUB_BUCKETS;
+
+/// Distribution of amounts, with percentiles
+///
+/// Percentiles are reported as the largest amount recorded in their bucket, so they are
+/// never below the true value and at most 12.5% above it, and exact when the amounts
+/// near them repeat. Negative amounts are recorded by their magnitude.
+///
+/// Displays as `count=N p50=.. p95=.. p99=.. max=..`.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{AmountHistogram, Fixed4};
+/// let mut histogram = AmountHistogram::new();
+/// for amount in 1..=100 {
+///     histogram.record(amount.to_string().parse().unwrap());
+/// }
+///
+/// assert_eq!(histogram.count(), 100);
+/// assert_eq!(histogram.max().unwrap().to_string(), "100.0000");
+/// let p50 = histogram.percentile(50.0).unwrap().to_f64();
+/// assert!((50.0..=50.0 * 1.125).contains(&p50));
+/// assert_eq!(histogram.percentile(100.0), histogram.max());
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct AmountHistogram {
+    counts: [u64; BUCKETS],
+    /// Largest value recorded in each bucket
+    highest: [i64; BUCKETS],
+    count: u64,
+    /// Amounts with no fractional part
+    whole: u64,
+    min: i64,
+    max: i64,
+}
+
+impl Default for AmountHistogram {
+    fn default() -> Self {
+        Self {
+            counts: [0; BUCKETS],
+            highest: [0; BUCKETS],
+            count: 0,
+            whole: 0,
+            min: i64::MAX,
+            max: 0,
+        }
+    }
+}
+
+impl AmountHistogram {
+    /// An empty histogram
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count `amount`
+    pub fn record(&mut self, amount: Fixed4) {
+        let value = amount.to_raw().saturating_abs();
+        let index = bucket(value);
+        self.counts[index] += 1;
+        self.highest[index] = self.highest[index].max(value);
+        self.count += 1;
+        if value % Fixed4::SCALE == 0 {
+            self.whole += 1;
+        }
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    /// Number of amounts recorded
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Number of amounts recorded with no fractional part, e.g. 25 but not 25.5
+    pub fn whole_count(&self) -> u64 {
+        self.whole
+    }
+
+    /// Smallest amount recorded, if any
+    pub fn min(&self) -> Option<Fixed4> {
+        (self.count > 0).then(|| Fixed4::from_raw(self.min))
+    }
+
+    /// Largest amount recorded, if any
+    pub fn max(&self) -> Option<Fixed4> {
+        (self.count > 0).then(|| Fixed4::from_raw(self.max))
+    }
+
+    /// The amount that `percentile` percent of the amounts recorded are at or below, if
+    /// any were recorded
+    ///
+    /// `percentile` is clamped to between 0 and 100.
+    pub fn percentile(&self, percentile: f64) -> Option<Fixed4> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((percentile.clamp(0.0, 100.0) / 100.0 * self.count as f64).ceil() as u64)
+            .clamp(1, self.count);
+        let mut seen = 0;
+        for (index, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(Fixed4::from_raw(self.highest[index]));
+            }
+        }
+        self.max()
+    }
+}
+
+impl std::ops::AddAssign for AmountHistogram {
+    fn add_assign(&mut self, other: Self) {
+        for (count, other) in self.counts.iter_mut().zip(other.counts) {
+            *count += other;
+        }
+        for (highest, other) in self.highest.iter_mut().zip(other.highest) {
+            *highest = (*highest).max(other);
+        }
+        self.count += other.count;
+        self.whole += other.whole;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+}
+
+impl std::fmt::Debug for AmountHistogram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AmountHistogram")
+            .field("count", &self.count)
+            .field("min", &self.min())
+            .field("max", &self.max())
+            .finish_non_exhaustive()
+    }
+}
+
+impl std::fmt::Display for AmountHistogram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "count={}", self.count)?;
+        if let Some(max) = self.max() {
+            for (name, percentile) in [("p50", 50.0), ("p95", 95.0), ("p99", 99.0)] {
+                if let Some(value) = self.percentile(percentile) {
+                    write!(f, " {}={}", name, value)?;
+                }
+            }
+            write!(f, " max={}", max)?;
+        }
+        Ok(())
+    }
+}
+
+/// Bucket holding `value` ten-thousandths, which is not negative
+fn bucket(value: i64) -> usize {
+    let value = value as u64;
+    if value < SUB_BUCKETS as u64 {
+        return value as usize;
+    }
+    let exponent = 63 - value.leading_zeros();
+    let shift = exponent - SUB_BUCKET_BITS;
+    let sub_bucket = (value >> shift) as usize - SUB_BUCKETS;
+    SUB_BUCKETS + shift as usize * SUB_BUCKETS + sub_bucket
+}
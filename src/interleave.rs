@@ -0,0 +1,295 @@
+//! Deterministic interleaving tests of the parallel modes (`testing` feature)
+//!
+//! Processing clients in parallel (see [`Router`] and
+//! [`process_csv_files_parallel`](crate::process_csv_files_parallel)) is only correct
+//! because accounts are independent: as long as each client's transactions are applied
+//! in their original order, it must not matter how clients are interleaved with each
+//! other. [`Interleavings`] checks that this holds for a given input. Each seed draws an
+//! interleaving that keeps every client's order, applies it to a fresh database, and
+//! compares every account, and every rejected transaction, with a sequential run.
+//!
+//! Interleavings are drawn from a seeded generator rather than left to the thread
+//! scheduler, so a failure names the seed that caused it and [`interleave`] reproduces
+//! the exact order.
+
+use crate::db::{Database, ErrorCode, Transaction};
+use crate::policy::Policy;
+use crate::router::Router;
+use crate::sample::SplitMix64;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+
+/// A transaction for a client, as `(client, tx, transaction)`
+pub type Row = (u16, u32, Transaction);
+
+/// Rejected transactions by client, as `(tx, code)` in the order they were rejected
+type Rejections = BTreeMap<u16, Vec<(u32, ErrorCode)>>;
+
+/// Reorder `rows` at random, keeping the rows of each client in their original order
+///
+/// Every such order is equally likely, and the same seed always gives the same order.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Transaction, interleave};
+/// let rows: Vec<_> = (1..=6)
+///     .map(|tx| (tx as u16 % 2, tx, Transaction::deposit("1").unwrap()))
+///     .collect();
+///
+/// let shuffled = interleave(&rows, 3);
+/// assert_eq!(shuffled, interleave(&rows, 3));
+/// let txs = |client| -> Vec<u32> {
+///     shuffled.iter().filter(|row| row.0 == client).map(|row| row.1).collect()
+/// };
+/// assert_eq!(txs(0), [2, 4, 6]);
+/// assert_eq!(txs(1), [1, 3, 5]);
+/// ```
+pub fn interleave(rows: &[Row], seed: u64) -> Vec<Row> {
+    // Rows of each client, in reverse so the next one can be popped
+    let mut queues: BTreeMap<u16, Vec<&Row>> = BTreeMap::new();
+    for row in rows.iter().rev() {
+        queues.entry(row.0).or_default().push(row);
+    }
+    let mut random = SplitMix64(seed);
+    let mut remaining = rows.len();
+    let mut order = Vec::with_capacity(rows.len());
+    while remaining > 0 {
+        // Picking a client in proportion to its rows left makes every order equally likely
+        let mut pick = random.below(remaining as u64) as usize;
+        let queue = queues
+            .values_mut()
+            .find(|queue| {
+                if pick < queue.len() {
+                    return true;
+                }
+                pick -= queue.len();
+                false
+            })
+            .expect("a row is left");
+        order.push(queue.pop().expect("queue is not empty").clone());
+        remaining -= 1;
+    }
+    order
+}
+
+/// Which run of an [`Interleavings`] check went wrong
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Run {
+    /// The interleaving drawn with this seed
+    Seed(u64),
+    /// Processing through a [`Router`] with this many shards
+    Sharded(usize),
+}
+
+impl std::fmt::Display for Run {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Seed(seed) => write!(f, "interleaving with seed {}", seed),
+            Self::Sharded(shards) => write!(f, "run over {} shards", shards),
+        }
+    }
+}
+
+/// An account that came out differently from the sequential run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterleavingFailure {
+    /// The run that went wrong
+    pub run: Run,
+    /// The client whose account differs
+    pub client: u16,
+    /// What differs
+    pub problem: String,
+}
+
+impl std::fmt::Display for InterleavingFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: client {}: {}", self.run, self.client, self.problem)
+    }
+}
+
+impl std::error::Error for InterleavingFailure {}
+
+/// Checks that an input gives the same accounts however its clients are interleaved
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Interleavings, Transaction};
+/// let rows = vec![
+///     (1, 1, Transaction::deposit("100").unwrap()),
+///     (2, 2, Transaction::deposit("50").unwrap()),
+///     (1, 3, Transaction::withdrawal("30").unwrap()),
+///     (2, 2, Transaction::dispute()),
+///     (1, 1, Transaction::dispute()),
+///     (2, 2, Transaction::chargeback()),
+///     (2, 4, Transaction::deposit("5").unwrap()),
+/// ];
+///
+/// let checked = Interleavings::new(rows).with_seeds(0..50).with_shards(3).check();
+/// assert_eq!(checked, Ok(51));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Interleavings {
+    rows: Vec<Row>,
+    seeds: Range<u64>,
+    shards: Option<usize>,
+    policy: Policy,
+}
+
+impl Interleavings {
+    /// Check `rows`, in the order a sequential run would apply them, with seeds 0 to 99
+    pub fn new(rows: Vec<Row>) -> Self {
+        Self {
+            rows,
+            seeds: 0..100,
+            shards: None,
+            policy: Policy::default(),
+        }
+    }
+
+    /// Draw one interleaving from each of `seeds`
+    pub fn with_seeds(mut self, seeds: Range<u64>) -> Self {
+        self.seeds = seeds;
+        self
+    }
+
+    /// Also process the rows on `shards` worker threads through a [`Router`]
+    pub fn with_shards(mut self, shards: usize) -> Self {
+        self.shards = Some(shards);
+        self
+    }
+
+    /// Apply `policy` in every run
+    pub fn with_policy(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Run every check, returning how many runs were compared with the sequential one
+    ///
+    /// # Errors
+    /// Returns the first account that came out differently.
+    pub fn check(&self) -> Result<usize, InterleavingFailure> {
+        let mut expected = Database::with_policy(self.policy.clone());
+        let expected_rejections = apply(&mut expected, self.rows.iter().cloned());
+
+        let mut runs = 0;
+        for seed in self.seeds.clone() {
+            let mut actual = Database::with_policy(self.policy.clone());
+            let rejections = apply(&mut actual, interleave(&self.rows, seed));
+            let clients = expected.get_all_client_ids().into_iter().collect();
+            compare(
+                &expected,
+                &expected_rejections,
+                &actual,
+                &rejections,
+                clients,
+            )
+            .map_err(|(client, problem)| InterleavingFailure {
+                run: Run::Seed(seed),
+                client,
+                problem,
+            })?;
+            runs += 1;
+        }
+
+        if let Some(shards) = self.shards {
+            let policy = self.policy.clone();
+            let pool = Router::new(shards).spawn(self.rows.len().max(1), move |_, rows| {
+                let mut database = Database::with_policy(policy.clone());
+                let rejections = apply(&mut database, rows);
+                (database, rejections)
+            });
+            for row in &self.rows {
+                pool.send(row.0, row.clone())
+                    .expect("shard workers run until joined");
+            }
+            let mut seen = BTreeSet::new();
+            for (actual, rejections) in pool.join() {
+                let clients: BTreeSet<u16> = actual.get_all_client_ids().into_iter().collect();
+                seen.extend(clients.iter().copied());
+                compare(
+                    &expected,
+                    &expected_rejections,
+                    &actual,
+                    &rejections,
+                    clients,
+                )
+                .map_err(|(client, problem)| InterleavingFailure {
+                    run: Run::Sharded(shards),
+                    client,
+                    problem,
+                })?;
+            }
+            if let Some(client) = expected
+                .get_all_client_ids()
+                .into_iter()
+                .find(|client| !seen.contains(client))
+            {
+                return Err(InterleavingFailure {
+                    run: Run::Sharded(shards),
+                    client,
+                    problem: "account missing".to_string(),
+                });
+            }
+            runs += 1;
+        }
+        Ok(runs)
+    }
+}
+
+/// Apply `rows` to `database`, returning the rejected transactions
+fn apply(database: &mut Database, rows: impl IntoIterator<Item = Row>) -> Rejections {
+    let mut rejections = Rejections::new();
+    for (client, tx, transaction) in rows {
+        if let Err(error) = database.process_transaction(client, tx, transaction) {
+            rejections
+                .entry(client)
+                .or_default()
+                .push((tx, error.code()));
+        }
+    }
+    rejections
+}
+
+/// Compare the accounts of `clients` in `actual` with those in `expected`, returning
+/// the first client that differs and how
+fn compare(
+    expected: &Database,
+    expected_rejections: &Rejections,
+    actual: &Database,
+    rejections: &Rejections,
+    clients: BTreeSet<u16>,
+) -> Result<(), (u16, String)> {
+    if let Some(drift) = actual.audit_balances().into_iter().next() {
+        return Err((
+            drift.client,
+            format!("balances disagree with ledger: {}", drift),
+        ));
+    }
+    for client in clients {
+        let (Some(want), Some(got)) = (expected.get_account(client), actual.get_account(client))
+        else {
+            return Err((client, "account missing or unexpected".to_string()));
+        };
+        if want.summary() != got.summary() {
+            return Err((
+                client,
+                format!("expected {}, got {}", want.summary(), got.summary()),
+            ));
+        }
+        if expected.statement(client) != actual.statement(client) {
+            return Err((client, "ledger differs".to_string()));
+        }
+        if expected_rejections.get(&client) != rejections.get(&client) {
+            return Err((
+                client,
+                format!(
+                    "expected rejections {:?}, got {:?}",
+                    expected_rejections.get(&client),
+                    rejections.get(&client)
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,217 @@
+//! Write-ahead journal of accepted transactions, for crash recovery
+//!
+//! A [`Database`](crate::Database) given a [`Journal`] appends every transaction it
+//! accepts to the journal file before changing any balance. Should the process die
+//! part way through a run, [`Database::replay_journal`](crate::Database::replay_journal)
+//! applies the journal to a fresh database (or to the snapshot the journal was started
+//! after) and rebuilds exactly the state at the crash.
+//!
+//! Each line of the file is one [`Event`](crate::Event) as JSON, or an array of the
+//! events of a [dispute bundle](crate::Database::dispute_bundle), which are applied all
+//! together or not at all. Only whole lines are ever relied on: a last line cut short
+//! by the crash is ignored on replay, since the transaction it would have recorded was
+//! never applied, and cut off when the journal is opened again, so new entries don't
+//! follow it onto the same line.
+
+use crate::event_log::Event;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// An append-only file of accepted transactions
+///
+/// Entries are written straight to the operating system, so they survive the process
+/// crashing. [`with_sync`](Journal::with_sync) also syncs each one to disk, so they
+/// survive the machine failing too, at the cost of a disk write per transaction.
+///
+/// An entry that fails to be written or synced is cut back off the file, so a
+/// transaction rejected with [`EngineError::Io`](crate::EngineError::Io) is never
+/// replayed. Should that fail too, the journal refuses every further entry.
+///
+/// Only transactions are journaled. Account operations such as
+/// [`set_account_state`](crate::Database::set_account_state) or merges are not, so
+/// take a [snapshot](crate::Database::to_snapshot) and start a new journal after them.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Database, Journal, Transaction};
+/// let path = std::env::temp_dir().join(format!("journal-{}.jsonl", std::process::id()));
+///
+/// let mut db = Database::new();
+/// db.set_journal(Journal::open(&path).unwrap());
+/// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+/// db.process_transaction(1, 1, Transaction::dispute()).unwrap();
+/// // Rejected, so not journaled
+/// assert!(db.process_transaction(1, 2, Transaction::withdrawal("500").unwrap()).is_err());
+/// drop(db); // the process crashes
+///
+/// let mut recovered = Database::new();
+/// assert_eq!(recovered.replay_journal(&path).unwrap(), 2);
+/// assert_eq!(recovered.get_account(1).unwrap().held().to_string(), "100.0000");
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    /// Length of the file up to the end of the last whole entry
+    len: u64,
+    sync: bool,
+    /// Whether a failed entry could not be cut back off the file
+    poisoned: bool,
+}
+
+impl Journal {
+    /// Append to the journal at `path`, creating it if it doesn't exist
+    ///
+    /// A last entry cut short by a crash is cut off the file first.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be opened for reading and appending, or if a
+    /// partial last entry can't be cut off.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)?;
+        let len = whole_entries_len(&mut file)?;
+        if len < file.metadata()?.len() {
+            file.set_len(len)?;
+            file.sync_all()?;
+        }
+        Ok(Self {
+            path,
+            file,
+            len,
+            sync: false,
+            poisoned: false,
+        })
+    }
+
+    /// Sync every entry to disk before its transaction is applied
+    pub fn with_sync(mut self, sync: bool) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    /// File the journal is written to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Length of the file up to the end of the last whole entry
+    pub(crate) fn position(&self) -> u64 {
+        self.len
+    }
+
+    /// Cut every entry written after `position` off the file
+    pub(crate) fn truncate(&mut self, position: u64) -> io::Result<()> {
+        if position < self.len {
+            self.file.set_len(position)?;
+            self.sync_if_enabled()?;
+            self.len = position;
+        }
+        Ok(())
+    }
+
+    /// Append `event`, returning once it is written (and synced, if enabled)
+    pub(crate) fn append(&mut self, event: &Event) -> io::Result<()> {
+        self.write_entry(event)
+    }
+
+    /// Append `events` as one entry, replayed all together or not at all
+    pub(crate) fn append_bundle(&mut self, events: &[Event]) -> io::Result<()> {
+        self.write_entry(&events)
+    }
+
+    fn write_entry<T: Serialize + ?Sized>(&mut self, entry: &T) -> io::Result<()> {
+        if self.poisoned {
+            return Err(io::Error::other(format!(
+                "{}: journal unusable after a failed write that could not be undone",
+                self.path.display()
+            )));
+        }
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        let written = self
+            .file
+            .write_all(&line)
+            .and_then(|()| self.sync_if_enabled());
+        match written {
+            Ok(()) => {
+                self.len += line.len() as u64;
+                Ok(())
+            }
+            Err(err) => {
+                // Part of the entry may have reached the file; cut it off, so the rejected
+                // transaction is never replayed and no partial line ends up mid-file
+                let undone = self
+                    .file
+                    .set_len(self.len)
+                    .and_then(|()| self.sync_if_enabled());
+                if undone.is_err() {
+                    self.poisoned = true;
+                }
+                Err(err)
+            }
+        }
+    }
+
+    fn sync_if_enabled(&self) -> io::Result<()> {
+        if self.sync {
+            self.file.sync_data()
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Length of `file` up to and including its last newline, i.e. its whole entries
+fn whole_entries_len(file: &mut File) -> io::Result<u64> {
+    const CHUNK: u64 = 4096;
+    let mut buf = vec![0; CHUNK as usize];
+    let mut end = file.metadata()?.len();
+    while end > 0 {
+        let start = end.saturating_sub(CHUNK);
+        let chunk = &mut buf[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+        if let Some(at) = chunk.iter().rposition(|&byte| byte == b'\n') {
+            return Ok(start + at as u64 + 1);
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+/// Read every whole entry of the journal at `path`, in the order they were written
+///
+/// # Errors
+/// Returns an error if the file can't be read, or if a line other than a last one cut
+/// short isn't a valid entry.
+pub(crate) fn read(path: &Path) -> io::Result<Vec<Event>> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut lines: Vec<&str> = contents.split('\n').collect();
+    // The last element is whatever follows the last newline: empty, or an entry the
+    // crash cut short
+    lines.pop();
+    let mut events = Vec::new();
+    for (index, line) in lines.into_iter().enumerate() {
+        let parsed = if line.starts_with('[') {
+            serde_json::from_str::<Vec<Event>>(line).map(|bundle| events.extend(bundle))
+        } else {
+            serde_json::from_str::<Event>(line).map(|event| events.push(event))
+        };
+        parsed.map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: {}", path.display(), index + 1, err),
+            )
+        })?;
+    }
+    Ok(events)
+}
@@ -3,7 +3,7 @@
 //! A robust financial transaction processing system with support for:
 //! - Precise decimal arithmetic using fixed-point numbers
 //! - Multi-client account management  
-//! - Dispute resolution workflows (dispute → resolve/chargeback)
+//! - Dispute resolution workflows (dispute → resolve/chargeback → reversal)
 //! - CSV transaction file processing
 //! - Comprehensive error handling and audit trails
 //!
@@ -24,19 +24,185 @@
 //!
 //! // Check account balance
 //! let account = db.get_account(1).unwrap();
-//! assert_eq!(account.available.to_f64(), 75.25);
-//! # Ok::<(), transaction_processor::MyError>(())
+//! assert_eq!(account.available().to_f64(), 75.25);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
 //! ```
 //!
 //! ## Modules
 //!
+//! - [`access`] - API key roles for services embedding the engine
+//! - [`analytics`] - Canned reports such as suspicious patterns and held-funds aging
+//! - [`engine`] - High-level facade: process an input and write the report in one call
 //! - [`db`] - Core transaction processing and account management
 //! - [`fixed4`] - Fixed-point decimal arithmetic with 4 decimal places
-//! - [`csv_processor`] - CSV file processing utilities
+//! - [`graph`] - DOT graph of disputes, chargebacks and refunds for fraud analysts
+//! - [`histogram`] - Streaming distribution of transaction amounts
+//! - `golden` - Golden-file regression tests of the account summary (`testing` and `csv` features)
+//! - `csv_processor` - CSV file processing utilities (`csv` feature)
+//! - [`custom`] - Domain-specific transaction types registered by name
+//! - `dashboard` - Live terminal dashboard of a run (`tui` feature)
+//! - [`record`] - Raw input rows, with hooks and decoders run before decoding
+//! - [`rejection`] - Rejected rows with their error codes and fields, as JSON
+//! - [`event_log`] - Persisted log of applied transactions, replayable to any point
+//! - [`journal`] - Write-ahead journal of accepted transactions, for crash recovery
+//! - `export` - CSV and JSON exports such as per-transaction running balances (`csv` feature)
+//! - `avro` - Avro exports of the summary and balance history (`avro` feature)
+//! - [`location`] - Local and object storage (`cloud` feature) input and output locations
+//! - [`messages`] - Customizable (e.g. translated) error messages
+//! - `outbox` - Applied transactions persisted for at-least-once publication (`csv` feature)
+//! - `partition` - Splitting inputs by client range for runs across machines (`csv` feature)
+//! - [`policy`] - Configurable business rules
+//! - [`prelude`] - The types most programs need, for `use transaction_processor::prelude::*`
+//! - `watch` - Drop-folder processing of incoming CSV files (`csv` feature)
+//! - `schedule` - Reports written at fixed times of day by long-running modes (`csv` feature)
+//! - `selftest` - Built-in end-to-end scenarios for validating a deployment (`csv` feature)
+//! - `snapshot` - Engine state persisted as a snapshot plus the event log since (`csv` feature)
+//! - [`throttle`] - Token-bucket rate limiting of ingestion
+//! - [`source`] and [`sink`] - Pluggable transaction inputs and report outputs
+//! - [`sql`] - SQL script loading the accounts and ledgers into a reporting database
+//! - [`storage`] - Where the database keeps its accounts, in memory or in a file
+//! - [`sample`] - Processing a sample of a huge input for a quick look
+//! - [`reorder`] - Each client's rows in transaction ID order, for out-of-order feeds
+//! - [`report`] - Selecting and ordering accounts for summary reports
+//! - [`router`] - Sharding clients over parallel workers with per-client ordering
+//! - `render` - HTML and PDF client statements (`render` and `pdf` features)
+//! - `chaos` - Fault injection for testing recovery logic (`testing` feature)
+//! - `interleave` - Deterministic interleaving tests of the parallel modes (`testing` feature)
 
+pub mod access;
+pub mod analytics;
+#[cfg(feature = "avro")]
+pub mod avro;
+#[cfg(feature = "testing")]
+pub mod chaos;
+#[cfg(feature = "csv")]
 pub mod csv_processor;
+pub mod custom;
+#[cfg(feature = "tui")]
+pub mod dashboard;
 pub mod db;
+pub mod engine;
+pub mod event_log;
+#[cfg(feature = "csv")]
+pub mod export;
 pub mod fixed4;
-pub use csv_processor::*;
-pub use db::*;
-pub use fixed4::*;
+#[cfg(all(feature = "testing", feature = "csv"))]
+pub mod golden;
+pub mod graph;
+pub mod histogram;
+pub mod journal;
+#[cfg(feature = "testing")]
+pub mod interleave;
+pub mod location;
+pub mod messages;
+#[cfg(feature = "csv")]
+pub mod outbox;
+#[cfg(feature = "csv")]
+pub mod partition;
+pub mod policy;
+pub mod prelude;
+#[cfg(feature = "render")]
+pub mod render;
+pub mod record;
+pub mod rejection;
+pub mod reorder;
+pub mod report;
+pub mod router;
+pub mod sample;
+#[cfg(feature = "csv")]
+pub mod schedule;
+#[cfg(feature = "csv")]
+pub mod selftest;
+pub mod sink;
+#[cfg(feature = "csv")]
+pub mod snapshot;
+pub mod source;
+pub mod sql;
+pub mod storage;
+pub mod throttle;
+#[cfg(feature = "csv")]
+pub mod watch;
+pub use access::{AccessError, Action, ApiKeys, Role};
+pub use analytics::{
+    DisputeAfterWithdrawal, HeldFundsAging, disputes_after_withdrawal, held_funds_aging,
+};
+#[cfg(feature = "csv")]
+pub use analytics::{write_disputes_after_withdrawal, write_held_funds_aging};
+#[cfg(feature = "avro")]
+pub use avro::{
+    AvroSink, LEDGER_SCHEMA, SUMMARY_SCHEMA, write_ledger_avro, write_summaries_avro,
+};
+#[cfg(feature = "testing")]
+pub use chaos::{Faults, FaultySink, FaultySource, FaultyWriter};
+#[cfg(feature = "csv")]
+pub use csv_processor::{
+    AmountUnit, CsvDialect, CsvProcessor, CsvProcessorBuilder, CsvSource, HeaderReport,
+    ProcessingOutcome, ProcessingResult, UnknownTypes, process_csv_file, process_csv_file_parallel,
+    process_csv_files_parallel, process_csv_outcome, process_csv_reader, process_csv_sample,
+    process_csv_str, suspect_amount_unit,
+};
+pub use custom::{CustomTransaction, CustomTransactions};
+#[cfg(feature = "tui")]
+pub use dashboard::{Chargeback, Dashboard};
+#[allow(deprecated)]
+pub use db::MyError;
+pub use db::{
+    Account, AccountDelta, AccountOps, AccountState, AccountSummary, AccountView, Activity,
+    AuditEntry, AuditEvent, BalanceDrift, BalanceRow, CaseEvent, ConflictPolicy, Database,
+    DisputeAction, DisputeState, EngineError, EntryKind, ErrorClass, ErrorCode, LedgerOp,
+    LedgerRecord, LockChange, LockDetails, LockReason, MergeConflict, MergeError, Operation,
+    PendingApproval, StateTransition, Statement, StatementLine, Timestamp, Transaction,
+    TransactionError, TransactionMeta,
+};
+pub use engine::{
+    AppliedRow, Engine, ErrorKindSummary, ErrorSummary, Progress, RunId, RunStats, Snapshot,
+    UnmatchedReference,
+};
+pub use event_log::{AsOf, Event, EventLog, Replay, ReplayPoint};
+#[cfg(feature = "csv")]
+pub use export::{
+    CsvSink, JsonSink, OutputFormat, SummaryColumn, SummaryColumns, SummaryLayout,
+    write_account_summaries, write_account_summaries_json, write_account_summaries_long,
+    write_running_balances, write_unmatched_references,
+};
+pub use fixed4::Fixed4;
+#[cfg(all(feature = "testing", feature = "csv"))]
+pub use golden::{Difference, GoldenDiff, GoldenTest};
+pub use graph::write_dispute_graph;
+pub use histogram::AmountHistogram;
+pub use journal::Journal;
+#[cfg(feature = "testing")]
+pub use interleave::{InterleavingFailure, Interleavings, Row, Run, interleave};
+pub use location::{LocationWriter, is_object_store_uri, open_location};
+pub use messages::MessageCatalog;
+#[cfg(feature = "csv")]
+pub use outbox::Outbox;
+#[cfg(feature = "csv")]
+pub use partition::{ClientRanges, combine_summaries, split_csv};
+pub use policy::{ChargebackLimit, LimitAction, Policy, PolicyError};
+pub use record::{
+    RecordAction, RecordAmount, RecordHook, RecordRewrite, TransactionDecoder, TransactionRecord,
+    Warning,
+};
+#[cfg(feature = "render")]
+pub use render::statement_html;
+#[cfg(all(feature = "render", feature = "pdf"))]
+pub use render::statement_pdf;
+pub use rejection::{RejectedFields, Rejection, write_rejections_json};
+pub use reorder::Reordered;
+pub use report::{SegmentSummary, SortKey, SummaryQuery, SummarySort};
+pub use router::{Router, ShardPool};
+pub use sample::{Sample, Sampled};
+#[cfg(feature = "csv")]
+pub use schedule::{ReportSchedule, ScheduledReports};
+#[cfg(feature = "csv")]
+pub use selftest::{SCENARIOS, Scenario, selftest};
+pub use sink::SummarySink;
+#[cfg(feature = "csv")]
+pub use snapshot::{SNAPSHOT_VERSION, SnapshotError, SnapshotStore};
+pub use source::{SourceError, SourceOffset, SourcedTransaction, TransactionSource};
+pub use sql::write_sql;
+pub use storage::{JsonFileStorage, MemoryStorage, Storage};
+pub use throttle::{Throttled, TokenBucket};
+#[cfg(feature = "csv")]
+pub use watch::{DropFolder, FileOutcome, ProcessedFile};
@@ -0,0 +1,231 @@
+//! Input and output locations
+//!
+//! A location is either a local file path or, with the `cloud` feature, an object
+//! storage URI such as `s3://bucket/key` or `gs://bucket/key`. Credentials and region
+//! come from the usual environment variables (`AWS_ACCESS_KEY_ID`, `AWS_REGION`,
+//! `GOOGLE_SERVICE_ACCOUNT`, ...).
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+
+/// Whether a location refers to object storage rather than a local path
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::is_object_store_uri;
+/// assert!(is_object_store_uri("s3://feeds/2024-01-01.csv"));
+/// assert!(is_object_store_uri("gs://feeds/2024-01-01.csv"));
+/// assert!(!is_object_store_uri("feeds/2024-01-01.csv"));
+/// ```
+pub fn is_object_store_uri(location: &str) -> bool {
+    location.starts_with("s3://") || location.starts_with("gs://")
+}
+
+/// Open a location for reading
+///
+/// Objects are streamed chunk by chunk rather than downloaded up front.
+///
+/// # Errors
+/// Returns an error if the file or object cannot be opened, or if `location` is an
+/// object storage URI and the `cloud` feature is disabled.
+pub fn open_location(location: &str) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    if is_object_store_uri(location) {
+        #[cfg(feature = "cloud")]
+        return Ok(Box::new(cloud::ObjectReader::open(location)?));
+        #[cfg(not(feature = "cloud"))]
+        return Err(cloud_disabled(location));
+    }
+    Ok(Box::new(File::open(location)?))
+}
+
+/// Writer for an output location
+///
+/// Local files are written as data arrives. Objects are buffered and uploaded by
+/// [`finish`](LocationWriter::finish), which must be called to complete the output.
+pub struct LocationWriter {
+    target: Target,
+}
+
+enum Target {
+    File(BufWriter<File>),
+    #[cfg(feature = "cloud")]
+    Object(cloud::ObjectWriter),
+}
+
+impl LocationWriter {
+    /// Create (or replace) the file or object at `location`
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be created, or if `location` is an object
+    /// storage URI and the `cloud` feature is disabled.
+    pub fn create(location: &str) -> Result<Self, Box<dyn Error>> {
+        if is_object_store_uri(location) {
+            #[cfg(feature = "cloud")]
+            return Ok(Self {
+                target: Target::Object(cloud::ObjectWriter::create(location)?),
+            });
+            #[cfg(not(feature = "cloud"))]
+            return Err(cloud_disabled(location));
+        }
+        Ok(Self {
+            target: Target::File(BufWriter::new(File::create(location)?)),
+        })
+    }
+
+    /// Flush everything written and, for objects, upload it
+    ///
+    /// # Errors
+    /// Returns an error if the data cannot be written or uploaded.
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self.target {
+            Target::File(mut file) => file.flush()?,
+            #[cfg(feature = "cloud")]
+            Target::Object(object) => object.finish()?,
+        }
+        Ok(())
+    }
+}
+
+impl Write for LocationWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match &mut self.target {
+            Target::File(file) => file.write(buf),
+            #[cfg(feature = "cloud")]
+            Target::Object(object) => object.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.target {
+            Target::File(file) => file.flush(),
+            #[cfg(feature = "cloud")]
+            Target::Object(object) => object.flush(),
+        }
+    }
+}
+
+#[cfg(not(feature = "cloud"))]
+fn cloud_disabled(location: &str) -> Box<dyn Error> {
+    format!(
+        "{} is an object storage location; rebuild with the cloud feature to use it",
+        location
+    )
+    .into()
+}
+
+#[cfg(feature = "cloud")]
+mod cloud {
+    use futures::StreamExt;
+    use futures::stream::BoxStream;
+    use object_store::aws::AmazonS3Builder;
+    use object_store::gcp::GoogleCloudStorageBuilder;
+    use object_store::path::Path;
+    use object_store::{ObjectStore, PutPayload};
+    use std::error::Error;
+    use std::io::{Read, Write};
+    use tokio::runtime::Runtime;
+
+    /// Store and object path for an `s3://` or `gs://` URI
+    fn resolve(uri: &str) -> Result<(Box<dyn ObjectStore>, Path), Box<dyn Error>> {
+        let (scheme, rest) = uri.split_once("://").ok_or("Invalid object storage URI")?;
+        let (_, key) = rest
+            .split_once('/')
+            .ok_or_else(|| format!("{} does not name an object", uri))?;
+        let store: Box<dyn ObjectStore> = match scheme {
+            "s3" => Box::new(AmazonS3Builder::from_env().with_url(uri).build()?),
+            "gs" => Box::new(
+                GoogleCloudStorageBuilder::from_env()
+                    .with_url(uri)
+                    .build()?,
+            ),
+            _ => return Err(format!("Unsupported object storage scheme: {}", scheme).into()),
+        };
+        Ok((store, Path::parse(key)?))
+    }
+
+    /// Runtime for driving object_store's async API from blocking code
+    fn runtime() -> std::io::Result<Runtime> {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+    }
+
+    /// Streaming reader over an object's contents
+    pub(super) struct ObjectReader {
+        runtime: Runtime,
+        stream: BoxStream<'static, object_store::Result<Vec<u8>>>,
+        chunk: Vec<u8>,
+        position: usize,
+    }
+
+    impl ObjectReader {
+        pub(super) fn open(uri: &str) -> Result<Self, Box<dyn Error>> {
+            let (store, path) = resolve(uri)?;
+            let runtime = runtime()?;
+            let result = runtime.block_on(store.get(&path))?;
+            let stream = result
+                .into_stream()
+                .map(|chunk| chunk.map(|bytes| bytes.to_vec()))
+                .boxed();
+            Ok(Self {
+                runtime,
+                stream,
+                chunk: Vec::new(),
+                position: 0,
+            })
+        }
+    }
+
+    impl Read for ObjectReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            while self.position == self.chunk.len() {
+                match self.runtime.block_on(self.stream.next()) {
+                    Some(chunk) => {
+                        self.chunk = chunk.map_err(std::io::Error::other)?;
+                        self.position = 0;
+                    }
+                    None => return Ok(0),
+                }
+            }
+            let count = buf.len().min(self.chunk.len() - self.position);
+            buf[..count].copy_from_slice(&self.chunk[self.position..self.position + count]);
+            self.position += count;
+            Ok(count)
+        }
+    }
+
+    /// Buffered writer that uploads an object when finished
+    pub(super) struct ObjectWriter {
+        store: Box<dyn ObjectStore>,
+        path: Path,
+        buffer: Vec<u8>,
+    }
+
+    impl ObjectWriter {
+        pub(super) fn create(uri: &str) -> Result<Self, Box<dyn Error>> {
+            let (store, path) = resolve(uri)?;
+            Ok(Self {
+                store,
+                path,
+                buffer: Vec::new(),
+            })
+        }
+
+        pub(super) fn finish(self) -> Result<(), Box<dyn Error>> {
+            runtime()?.block_on(self.store.put(&self.path, PutPayload::from(self.buffer)))?;
+            Ok(())
+        }
+    }
+
+    impl Write for ObjectWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.buffer.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -1,20 +1,419 @@
-use clap::Parser;
+use clap::{CommandFactory, Parser, Subcommand};
 use std::error::Error;
+use std::io::{Read, Write};
+use std::path::PathBuf;
 use std::process;
-use transaction_processor::{Database, process_csv_file};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use transaction_processor::{
+    AmountUnit, AsOf, ClientRanges, CsvSink, CsvSource, Database, DropFolder, Engine, EventLog,
+    Fixed4, JsonFileStorage, JsonSink, LocationWriter, Outbox, OutputFormat, Policy, RecordRewrite,
+    Reordered, ReplayPoint, ReportSchedule, RunStats, Sample, Sampled, ScheduledReports,
+    SnapshotStore, SummaryColumns, SummaryLayout, SummaryQuery, SummarySink, SummarySort,
+    Timestamp, TokenBucket, TransactionSource, UnknownTypes, combine_summaries, open_location,
+    selftest, split_csv, suspect_amount_unit, write_dispute_graph, write_disputes_after_withdrawal,
+    write_held_funds_aging, write_rejections_json, write_running_balances, write_sql,
+    write_unmatched_references,
+};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 #[command(
     about = "A transaction processing engine that processes CSV files containing financial transactions"
 )]
+#[command(args_conflicts_with_subcommands = true, arg_required_else_help = true)]
 struct Args {
-    /// Input CSV file containing transactions
-    csv_file: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Input CSV file containing transactions (or an s3:// or gs:// URI with the cloud feature),
+    /// or - to read from stdin
+    csv_file: Option<String>,
 
     /// Print detailed error messages to stderr
     #[arg(short, long)]
     verbose: bool,
+
+    /// Print errors to stderr summarized by kind, with counts and the first N rows of each
+    /// kind, rather than one by one; memory use stays bounded however many rows fail
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "3")]
+    error_summary: Option<usize>,
+
+    /// Print warnings (ignored amounts, skipped duplicate rows) to stderr
+    #[arg(short, long)]
+    warnings: bool,
+
+    /// Skip rows with an unrecognised transaction type (e.g. fee) with a warning instead
+    /// of rejecting them
+    #[arg(long)]
+    ignore_unknown_types: bool,
+
+    /// Read rows of type ALIAS as TYPE, e.g. `credit=deposit` (repeatable)
+    #[arg(long, value_name = "ALIAS=TYPE", value_parser = parse_rename)]
+    type_alias: Vec<(String, String)>,
+
+    /// Read the input's column FROM as column TO, e.g. `client_id=client` (repeatable)
+    #[arg(long, value_name = "FROM=TO", value_parser = parse_rename)]
+    column_map: Vec<(String, String)>,
+
+    /// Process only the first N rows, printing every error and warning found, for a quick
+    /// look at a large file
+    #[arg(long, value_name = "N")]
+    sample: Option<usize>,
+
+    /// With --sample, draw the N rows at random from the whole file using this seed,
+    /// rather than taking the first N
+    #[arg(long, value_name = "SEED", requires = "sample")]
+    sample_seed: Option<u64>,
+
+    /// Skip the first N rows after the header, e.g. to reprocess a known range of lines
+    #[arg(long, value_name = "N")]
+    skip_rows: Option<usize>,
+
+    /// Process no more than N rows, after any skipped
+    #[arg(long, value_name = "N")]
+    limit_rows: Option<usize>,
+
+    /// Apply each client's rows in transaction ID order, holding back up to N rows, for
+    /// feeds whose IDs are assigned in order but whose rows may arrive out of order
+    #[arg(long, value_name = "N")]
+    order_by_tx: Option<usize>,
+
+    /// Print the distribution of deposit and withdrawal amounts (percentiles and
+    /// maximum) to stderr, e.g. to spot a feed in the wrong unit
+    #[arg(long)]
+    amount_stats: bool,
+
+    /// Unit of the amount column: units (e.g. 12.34) or cents (e.g. 1234)
+    #[arg(long, value_name = "UNIT", default_value_t = AmountUnit::Units)]
+    amount_unit: AmountUnit,
+
+    /// Apply deposits with a negative amount as refunds instead of rejecting them
+    #[arg(long)]
+    negative_deposits_as_refunds: bool,
+
+    /// Hold back disputes, resolves and chargebacks of transactions not seen yet, and
+    /// retry them when the transaction arrives or at the end of the input
+    #[arg(long)]
+    defer_disputes: bool,
+
+    /// Allow withdrawals to be disputed; charging one back returns the funds
+    #[arg(long)]
+    withdrawal_disputes: bool,
+
+    /// After processing, check every account's balances against those its ledger
+    /// implies, failing if any disagree
+    #[arg(long)]
+    verify_ledger: bool,
+
+    /// Read rows for client FROM as client TO (repeatable)
+    #[arg(long, value_name = "FROM=TO", value_parser = parse_client_map)]
+    client_map: Vec<(u16, u16)>,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    /// Write the summary to this file (or s3:// or gs:// URI) instead of stdout
+    #[arg(short, long, value_name = "LOCATION")]
+    output: Option<String>,
+
+    /// Also write each client's running balances after every transaction to this CSV file
+    /// (or s3:// or gs:// URI)
+    #[arg(long, value_name = "LOCATION")]
+    running_balances: Option<String>,
+
+    /// Also write the disputes, resolves and chargebacks that matched no transaction to
+    /// this CSV file (or s3:// or gs:// URI)
+    #[arg(long, value_name = "LOCATION")]
+    unmatched_report: Option<String>,
+
+    /// Also write every rejected row to this file (or s3:// or gs:// URI) as JSON Lines,
+    /// with its line, error code and fields, for reprocessing
+    #[arg(long, value_name = "LOCATION")]
+    errors_json: Option<String>,
+
+    /// Also write the accounts and their ledgers to this file (or s3:// or gs:// URI) as
+    /// a SQL script of INSERT statements, for loading into a reporting database
+    #[arg(long, value_name = "LOCATION")]
+    sql: Option<String>,
+
+    /// Also write the disputes, chargebacks and refunds of every account to this file (or
+    /// s3:// or gs:// URI) as a Graphviz DOT graph
+    #[arg(long, value_name = "LOCATION")]
+    dispute_graph: Option<String>,
+
+    /// Also write every applied transaction to this event log (or s3:// or gs:// URI),
+    /// for later use with `replay`
+    #[arg(long, value_name = "LOCATION")]
+    event_log: Option<String>,
+
+    /// Keep the accounts in this JSON file between runs: carry on from the accounts in
+    /// it, if it exists, and save them back after processing
+    #[arg(long, value_name = "FILE")]
+    state: Option<PathBuf>,
+
+    /// Also dump the full state of every account, including ledgers, as JSON to this
+    /// file (or s3:// or gs:// URI)
+    #[arg(long, value_name = "LOCATION")]
+    dump_state: Option<String>,
+
+    /// Print a SHA-256 digest of the final state to stderr, for comparing runs
+    #[arg(long)]
+    digest: bool,
+
+    /// Guarantee byte-identical output for the same input and options, for audit
+    /// submissions. Rows are always applied in input order on one thread; this also
+    /// measures --held-aging as of the latest timestamp in the input rather than the
+    /// current time, and refuses options that depend on the terminal
+    #[arg(long)]
+    deterministic: bool,
+
+    /// Also write deposits disputed after their funds were withdrawn, a common
+    /// first-party fraud pattern, as CSV to this file (or s3:// or gs:// URI)
+    #[arg(long, value_name = "LOCATION")]
+    disputes_after_withdrawal: Option<String>,
+
+    /// Also write disputed funds per client, bucketed by days held (0-30, 31-60, 61-90,
+    /// 90+), as CSV to this file (or s3:// or gs:// URI); needs timestamped disputes
+    #[arg(long, value_name = "LOCATION")]
+    held_aging: Option<String>,
+
+    /// Also write the account summary as an Avro container file to this file (or s3://
+    /// or gs:// URI)
+    #[cfg(feature = "avro")]
+    #[arg(long, value_name = "LOCATION")]
+    avro_summary: Option<String>,
+
+    /// Also write each client's running balances after every transaction as an Avro
+    /// container file to this file (or s3:// or gs:// URI)
+    #[cfg(feature = "avro")]
+    #[arg(long, value_name = "LOCATION")]
+    avro_running_balances: Option<String>,
+
+    /// Show a live dashboard of throughput, error rates, the accounts holding the most
+    /// funds and recent chargebacks while processing; press q to stop the run
+    #[cfg(feature = "tui")]
+    #[arg(long, conflicts_with = "deterministic")]
+    dashboard: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Process CSV files dropped into a directory, reporting on each in done/failed
+    ///
+    /// With --state-dir, processed files are moved to done/failed too. Without it the
+    /// accounts are only kept in memory, so files stay in DIR to be applied again after
+    /// a restart.
+    Watch {
+        /// Directory to watch for new CSV files
+        dir: PathBuf,
+
+        /// Where to write reports on processed files, and move them with --state-dir
+        /// (default: DIR/done)
+        #[arg(long, value_name = "DIR")]
+        done: Option<PathBuf>,
+
+        /// Where to write reports on corrupt files, which are rolled back, and move them
+        /// with --state-dir (default: DIR/failed)
+        #[arg(long, value_name = "DIR")]
+        failed: Option<PathBuf>,
+
+        /// Seconds between checks for new files
+        #[arg(long, value_name = "SECONDS", default_value_t = 2)]
+        interval: u64,
+
+        /// Also write the account summary at these times of day, UTC (e.g. 02:00,14:00)
+        #[arg(long, value_name = "HH:MM,...")]
+        report_at: Option<ReportSchedule>,
+
+        /// Where scheduled reports are written (default: DIR/reports)
+        #[arg(long, value_name = "DIR")]
+        report_dir: Option<PathBuf>,
+
+        /// Apply at most this many transactions per second
+        #[arg(long, value_name = "TPS")]
+        max_rate: Option<u32>,
+
+        /// Keep an event log of every applied transaction at this location, rewritten
+        /// after each batch of files. Not with --state-dir, whose log is compacted away.
+        #[arg(long, value_name = "LOCATION", conflicts_with = "state_dir")]
+        event_log: Option<String>,
+
+        /// Append every applied transaction to the outbox in this directory, for
+        /// publication by a separate process (see `Outbox`)
+        #[arg(long, value_name = "DIR")]
+        outbox: Option<PathBuf>,
+
+        /// Persist the accounts in this directory, resuming from them on restart
+        #[arg(long, value_name = "DIR")]
+        state_dir: Option<PathBuf>,
+
+        /// With --state-dir, snapshot the accounts and discard the log after this many
+        /// transactions
+        #[arg(long, value_name = "N", default_value_t = SnapshotStore::DEFAULT_COMPACT_EVERY)]
+        compact_every: usize,
+
+        #[command(flatten)]
+        report: ReportArgs,
+    },
+    /// Rebuild the accounts from an event log, optionally stopping at a transaction or time
+    Replay {
+        /// Event log written with --event-log (or an s3:// or gs:// URI)
+        log: String,
+
+        /// Stop after this transaction (tx:ID, or just ID) or at this time (time:TIMESTAMP)
+        #[arg(long, value_name = "POINT")]
+        until: Option<ReplayPoint>,
+
+        /// Replay backdated corrections at the time they took effect, and only events
+        /// effective by this time; combine with --until time:TIMESTAMP to see what was
+        /// known at the time
+        #[arg(long, value_name = "TIMESTAMP")]
+        effective: Option<Timestamp>,
+
+        /// Apply at most this many transactions per second
+        #[arg(long, value_name = "TPS")]
+        max_rate: Option<u32>,
+
+        /// Write the summary to this file (or s3:// or gs:// URI) instead of stdout
+        #[arg(short, long, value_name = "LOCATION")]
+        output: Option<String>,
+
+        #[command(flatten)]
+        report: ReportArgs,
+    },
+    /// Split an input into one CSV file per client range, for processing on separate
+    /// machines
+    Split {
+        /// Input CSV file (or an s3:// or gs:// URI with the cloud feature)
+        input: String,
+
+        /// Split into this many ranges of equal size
+        #[arg(long, value_name = "N", required_unless_present = "ranges")]
+        shards: Option<usize>,
+
+        /// Split into these client ranges, covering 0-65535 (e.g. 0-999,1000-65535)
+        #[arg(long, value_name = "FIRST-LAST,...", conflicts_with = "shards")]
+        ranges: Option<ClientRanges>,
+
+        /// Directory to write shard-0.csv, shard-1.csv, ... to
+        #[arg(long, value_name = "DIR")]
+        output_dir: PathBuf,
+    },
+    /// Combine the summaries of runs over separate client ranges into one
+    Combine {
+        /// Summaries to combine, in client range order (or s3:// or gs:// URIs)
+        #[arg(required = true)]
+        summaries: Vec<String>,
+
+        /// Write the combined summary to this file (or s3:// or gs:// URI) instead of
+        /// stdout
+        #[arg(short, long, value_name = "LOCATION")]
+        output: Option<String>,
+    },
+    /// Run the built-in end-to-end scenarios and report which pass, to validate a
+    /// deployment
+    Selftest,
+    /// Print a shell completion script, for packaging
+    Completions {
+        /// Shell to complete in: bash, elvish, fish, powershell or zsh
+        shell: clap_complete::Shell,
+    },
+    /// Print the manual page in roff, for packaging
+    Manpage {
+        /// Write the page, and a page per subcommand, to this directory instead of stdout
+        #[arg(long, value_name = "DIR")]
+        output_dir: Option<PathBuf>,
+    },
+}
+
+/// Options controlling the account summary report
+#[derive(clap::Args)]
+struct ReportArgs {
+    /// Comma-separated summary columns: client, available, held, total, locked, state,
+    /// open_disputes, tx_count, last_activity, reserved
+    #[arg(long, value_name = "COLUMNS")]
+    columns: Option<SummaryColumns>,
+
+    /// Sort the summary by client, available, held or total, optionally suffixed with
+    /// :asc or :desc (e.g. total:desc)
+    #[arg(long, value_name = "KEY[:asc|:desc]")]
+    sort: Option<SummarySort>,
+
+    /// Only list accounts whose total is at least this amount
+    #[arg(long, value_name = "AMOUNT")]
+    min_total: Option<Fixed4>,
+
+    /// Only list locked accounts
+    #[arg(long)]
+    only_locked: bool,
+
+    /// Only list accounts with deposits under dispute
+    #[arg(long)]
+    only_disputed: bool,
+
+    /// Only list the first N accounts, after sorting
+    #[arg(long, value_name = "N")]
+    top: Option<usize>,
+
+    /// Write the summary in long format, one client,metric,value row per column; the
+    /// columns default to available, held, total, locked and reserved
+    #[arg(long)]
+    long: bool,
+
+    /// Write the summary as csv or json (an array with an object per account)
+    #[arg(long, value_name = "FORMAT", default_value_t = OutputFormat::Csv)]
+    format: OutputFormat,
+}
+
+impl ReportArgs {
+    /// Columns chosen, or the default for the layout
+    fn columns(&self) -> SummaryColumns {
+        match (&self.columns, self.long) {
+            (Some(columns), _) => columns.clone(),
+            (None, false) => SummaryColumns::default(),
+            (None, true) => SummaryColumns::balances(),
+        }
+    }
+
+    /// Layout chosen
+    fn layout(&self) -> SummaryLayout {
+        if self.long {
+            SummaryLayout::Long
+        } else {
+            SummaryLayout::Wide
+        }
+    }
+
+    /// The summary report, written to `writer`
+    fn sink<'a, W: std::io::Write + 'a>(
+        &self,
+        writer: W,
+    ) -> Result<Box<dyn SummarySink + 'a>, Box<dyn Error>> {
+        match (self.format, self.layout()) {
+            (OutputFormat::Csv, layout) => Ok(Box::new(
+                CsvSink::new(writer)
+                    .with_columns(self.columns())
+                    .with_layout(layout),
+            )),
+            (OutputFormat::Json, SummaryLayout::Wide) => {
+                Ok(Box::new(JsonSink::new(writer).with_columns(self.columns())))
+            }
+            (OutputFormat::Json, SummaryLayout::Long) => {
+                Err("--long is only supported with --format csv".into())
+            }
+        }
+    }
+
+    fn query(&self) -> SummaryQuery {
+        SummaryQuery {
+            sort: self.sort.unwrap_or_default(),
+            min_total: self.min_total,
+            only_locked: self.only_locked,
+            only_disputed: self.only_disputed,
+            limit: self.top,
+        }
+    }
 }
 
 fn main() {
@@ -27,35 +426,609 @@ fn main() {
 fn run() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
 
-    let (database, errors) = process_csv_file(&args.csv_file)?;
+    match args.command {
+        Some(Command::Watch {
+            dir,
+            done,
+            failed,
+            interval,
+            report_at,
+            report_dir,
+            max_rate,
+            event_log,
+            outbox,
+            state_dir,
+            compact_every,
+            report,
+        }) => {
+            let columns = report.columns();
+            let mut folder = DropFolder::new(&dir)
+                .with_columns(columns.clone())
+                .with_layout(report.layout());
+            if let Some(done) = done {
+                folder = folder.with_done_dir(done);
+            }
+            if let Some(failed) = failed {
+                folder = folder.with_failed_dir(failed);
+            }
+            if state_dir.is_none() {
+                folder = folder.with_retained_files();
+            }
+            let schedule = report_at.map(|schedule| {
+                let report_dir = report_dir.unwrap_or_else(|| dir.join("reports"));
+                ScheduledReports::new(schedule, report_dir, now())
+                    .with_columns(columns)
+                    .with_layout(report.layout())
+            });
+            let mut engine = Engine::new().with_query(report.query());
+            if let Some(max_rate) = max_rate {
+                engine = engine.with_rate_limit(TokenBucket::new(max_rate));
+            }
+            if event_log.is_some() || outbox.is_some() || state_dir.is_some() {
+                engine = engine.with_event_log();
+            }
+            let store = match state_dir {
+                Some(dir) => Some(
+                    SnapshotStore::open(dir, &mut engine)?
+                        .with_compact_every(compact_every)
+                        .with_background_writes(),
+                ),
+                None => None,
+            };
+            let outbox = outbox.map(Outbox::open).transpose()?;
+            return watch(engine, folder, interval, schedule, event_log, outbox, store);
+        }
+        Some(Command::Replay {
+            log,
+            until,
+            effective,
+            max_rate,
+            output,
+            report,
+        }) => {
+            let events = EventLog::read_csv(&log, open_location(&log)?)?;
+            let mut engine = Engine::new().with_query(report.query());
+            if let Some(max_rate) = max_rate {
+                engine = engine.with_rate_limit(TokenBucket::new(max_rate));
+            }
+            match (effective, until) {
+                (None, until) => engine.process(events.replay(until)),
+                (Some(effective), None | Some(ReplayPoint::Time(_))) => {
+                    let recorded = match until {
+                        Some(ReplayPoint::Time(time)) => Some(time),
+                        _ => None,
+                    };
+                    engine.process(events.replay_as_of(AsOf {
+                        effective: Some(effective),
+                        recorded,
+                    }))
+                }
+                (Some(_), Some(ReplayPoint::Tx(_))) => {
+                    return Err(
+                        "--effective can only be combined with --until time:TIMESTAMP".into(),
+                    );
+                }
+            };
+            for error in engine.errors() {
+                eprintln!("{}", error);
+            }
+            for reference in engine.unmatched() {
+                eprintln!("{}", reference);
+            }
+            return write_summary(&engine, output.as_deref(), &report);
+        }
+        Some(Command::Split {
+            input,
+            shards,
+            ranges,
+            output_dir,
+        }) => {
+            let ranges = match (ranges, shards) {
+                (Some(ranges), _) => ranges,
+                (None, Some(shards)) if (1..=65536).contains(&shards) => ClientRanges::even(shards),
+                (None, _) => return Err("--shards must be between 1 and 65536".into()),
+            };
+            std::fs::create_dir_all(&output_dir)?;
+            let paths: Vec<PathBuf> = (0..ranges.len())
+                .map(|shard| output_dir.join(format!("shard-{}.csv", shard)))
+                .collect();
+            let mut outputs = paths
+                .iter()
+                .map(|path| std::fs::File::create(path).map(std::io::BufWriter::new))
+                .collect::<Result<Vec<_>, _>>()?;
+            let rows = split_csv(open_location(&input)?, &ranges, &mut outputs)?;
+            for (shard, path) in paths.iter().enumerate() {
+                eprintln!(
+                    "{}: clients {}-{}, {} rows",
+                    path.display(),
+                    ranges.range(shard).start(),
+                    ranges.range(shard).end(),
+                    rows[shard]
+                );
+            }
+            return Ok(());
+        }
+        Some(Command::Combine { summaries, output }) => {
+            let inputs = summaries
+                .iter()
+                .map(|summary| open_location(summary))
+                .collect::<Result<Vec<_>, _>>()?;
+            match output {
+                Some(location) => {
+                    let mut output = LocationWriter::create(&location)?;
+                    combine_summaries(inputs, &mut output)?;
+                    output.finish()?;
+                }
+                None => {
+                    combine_summaries(inputs, std::io::stdout().lock())?;
+                }
+            }
+            return Ok(());
+        }
+        Some(Command::Selftest) => {
+            let results = selftest();
+            let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+            for (scenario, result) in &results {
+                match result {
+                    Ok(()) => println!("ok    {}", scenario.name),
+                    Err(e) => println!("FAIL  {}: {}", scenario.name, e),
+                }
+            }
+            println!(
+                "{} scenarios: {} passed, {} failed",
+                results.len(),
+                results.len() - failed,
+                failed
+            );
+            if failed > 0 {
+                return Err(format!("{} self-test scenarios failed", failed).into());
+            }
+            return Ok(());
+        }
+        Some(Command::Completions { shell }) => {
+            let mut command = Args::command();
+            let name = command.get_name().to_string();
+            // Written out here, as generate panics if it can't write
+            let mut script = Vec::new();
+            clap_complete::generate(shell, &mut command, name, &mut script);
+            std::io::stdout().write_all(&script)?;
+            return Ok(());
+        }
+        Some(Command::Manpage { output_dir }) => {
+            match output_dir {
+                Some(dir) => {
+                    std::fs::create_dir_all(&dir)?;
+                    clap_mangen::generate_to(Args::command(), &dir)?;
+                }
+                None => clap_mangen::Man::new(Args::command()).render(&mut std::io::stdout())?,
+            }
+            return Ok(());
+        }
+        None => {}
+    }
 
-    if args.verbose {
-        for error in errors {
+    let csv_file = args.csv_file.ok_or("No input file given")?;
+    let (csv_file, input): (String, Box<dyn Read>) = if csv_file == "-" {
+        ("stdin".to_string(), Box::new(std::io::stdin().lock()))
+    } else {
+        let input = open_location(&csv_file)?;
+        (csv_file, input)
+    };
+    let policy = Policy {
+        negative_deposits_as_refunds: args.negative_deposits_as_refunds,
+        defer_unmatched_disputes: args.defer_disputes,
+        withdrawal_disputes: args.withdrawal_disputes,
+        ..Policy::default()
+    };
+    let mut engine = Engine::with_policy(policy).with_query(args.report.query());
+    if let Some(path) = &args.state {
+        engine = engine.with_storage(JsonFileStorage::open(path)?);
+    }
+    if let Some(examples) = args.error_summary {
+        engine = engine.with_error_summary(examples);
+    }
+    if args.event_log.is_some() {
+        engine = engine.with_event_log();
+    }
+    if args.errors_json.is_some() {
+        engine = engine.with_rejections();
+    }
+    let unknown_types = if args.ignore_unknown_types {
+        UnknownTypes::Ignore
+    } else {
+        UnknownTypes::Reject
+    };
+    let mut rewrite = RecordRewrite::new();
+    for (alias, transaction_type) in &args.type_alias {
+        rewrite = rewrite.with_type_alias(alias, transaction_type);
+    }
+    for &(from, to) in &args.client_map {
+        rewrite = rewrite.with_client(from, to);
+    }
+    let mut source = CsvSource::new(&csv_file, input)
+        .with_unknown_types(unknown_types)
+        .with_record_hook(rewrite)
+        .with_amount_unit(args.amount_unit);
+    for (from, to) in &args.column_map {
+        source = source.with_column_map(from, to);
+    }
+    if let Some(rows) = args.skip_rows {
+        source = source.with_skip_rows(rows);
+    }
+    if let Some(rows) = args.limit_rows {
+        source = source.with_limit_rows(rows);
+    }
+    // A sample is for finding problems, so report all of them
+    let verbose = args.verbose || args.sample.is_some();
+    let warnings = args.warnings || args.sample.is_some();
+    let header = source.header_report()?;
+    if !header.columns.is_empty() && !header.is_valid() {
+        return Err(format!("Invalid header in {}: {}", csv_file, header).into());
+    }
+    if warnings && !header.is_clean() {
+        eprintln!("Warning at {}:1: {}", csv_file, header);
+    }
+    let sample = args.sample.map(|rows| match args.sample_seed {
+        Some(seed) => Sample::Random { rows, seed },
+        None => Sample::First(rows),
+    });
+    let window = args.order_by_tx.unwrap_or(0);
+    #[cfg(feature = "tui")]
+    let dashboard = args.dashboard;
+    #[cfg(not(feature = "tui"))]
+    let dashboard = false;
+    let stats = match sample {
+        Some(sample) => process(
+            &mut engine,
+            Reordered::new(Sampled::new(source, sample), window),
+            dashboard,
+        )?,
+        None => process(&mut engine, Reordered::new(source, window), dashboard)?,
+    };
+    engine.flush()?;
+    write_summary(&engine, args.output.as_deref(), &args.report)?;
+
+    if args.verify_ledger {
+        let drift = engine.database().audit_balances();
+        for account in &drift {
+            eprintln!("Ledger mismatch: {}", account);
+        }
+        if !drift.is_empty() {
+            return Err(format!(
+                "{} accounts' balances disagree with their ledgers",
+                drift.len()
+            )
+            .into());
+        }
+    }
+
+    if sample.is_some() {
+        eprintln!(
+            "Sampled {} rows: {} applied, {} rejected ({} malformed), {} warnings",
+            stats.rows, stats.applied, stats.rejected, stats.malformed, stats.warnings
+        );
+    }
+
+    if verbose {
+        for error in engine.errors() {
             eprintln!("{}", error);
         }
     }
 
-    print_account_summaries(&database);
+    if !engine.unmatched().is_empty() {
+        eprintln!(
+            "{} disputes, resolves or chargebacks matched no transaction; the input may be truncated upstream",
+            engine.unmatched().len()
+        );
+        if verbose {
+            for reference in engine.unmatched() {
+                eprintln!("{}", reference);
+            }
+        }
+    }
+
+    if args.amount_stats {
+        eprintln!("Deposit amounts: {}", stats.deposit_amounts);
+        eprintln!("Withdrawal amounts: {}", stats.withdrawal_amounts);
+        if stats.refund_amounts.count() > 0 {
+            eprintln!("Refund amounts: {}", stats.refund_amounts);
+        }
+    }
+
+    let mut amounts = stats.deposit_amounts;
+    amounts += stats.withdrawal_amounts;
+    if let Some(suspected) = suspect_amount_unit(&amounts, args.amount_unit) {
+        eprintln!(
+            "Warning: amounts in {} look like {} rather than {}; check --amount-unit",
+            csv_file, suspected, args.amount_unit
+        );
+    }
+
+    if let Some(summary) = engine.error_summary() {
+        eprintln!("{}", summary);
+    }
+
+    if warnings {
+        for warning in engine.warnings() {
+            eprintln!("{}", warning);
+        }
+    }
+
+    if let Some(location) = &args.running_balances {
+        let mut output = LocationWriter::create(location)?;
+        write_running_balances(engine.database(), &mut output)?;
+        output.finish()?;
+    }
+
+    if let Some(location) = &args.unmatched_report {
+        let mut output = LocationWriter::create(location)?;
+        write_unmatched_references(engine.unmatched(), &mut output)?;
+        output.finish()?;
+    }
+
+    if let Some(location) = &args.errors_json {
+        let mut output = LocationWriter::create(location)?;
+        write_rejections_json(engine.rejections(), &mut output)?;
+        output.finish()?;
+    }
+
+    if let Some(location) = &args.sql {
+        let mut output = LocationWriter::create(location)?;
+        write_sql(engine.database(), &mut output)?;
+        output.finish()?;
+    }
+
+    if let Some(location) = &args.dispute_graph {
+        let mut output = LocationWriter::create(location)?;
+        write_dispute_graph(engine.database(), &mut output)?;
+        output.finish()?;
+    }
+
+    if let (Some(location), Some(events)) = (&args.event_log, engine.event_log()) {
+        write_event_log(events, location)?;
+    }
+
+    if args.digest {
+        eprintln!("digest: {}", engine.database().digest());
+    }
+
+    if let Some(location) = &args.dump_state {
+        let mut output = LocationWriter::create(location)?;
+        output.write_all(engine.database().to_json().as_bytes())?;
+        output.finish()?;
+    }
+
+    if let Some(location) = &args.disputes_after_withdrawal {
+        let mut output = LocationWriter::create(location)?;
+        write_disputes_after_withdrawal(engine.database(), &mut output)?;
+        output.finish()?;
+    }
+
+    if let Some(location) = &args.held_aging {
+        let mut output = LocationWriter::create(location)?;
+        let as_of = if args.deterministic {
+            latest_activity(engine.database())
+        } else {
+            now()
+        };
+        write_held_funds_aging(engine.database(), as_of, &mut output)?;
+        output.finish()?;
+    }
+
+    #[cfg(feature = "avro")]
+    if let Some(location) = &args.avro_summary {
+        let mut output = LocationWriter::create(location)?;
+        engine.report(transaction_processor::AvroSink::new(&mut output))?;
+        output.finish()?;
+    }
+
+    #[cfg(feature = "avro")]
+    if let Some(location) = &args.avro_running_balances {
+        let mut output = LocationWriter::create(location)?;
+        transaction_processor::write_ledger_avro(engine.database(), &mut output)?;
+        output.finish()?;
+    }
 
     Ok(())
 }
 
-fn print_account_summaries(database: &Database) {
-    println!("client,available,held,total,locked");
+/// Apply every transaction from `source`, drawing the live dashboard if `dashboard`
+#[cfg(feature = "tui")]
+fn process<S: TransactionSource>(
+    engine: &mut Engine,
+    source: S,
+    dashboard: bool,
+) -> Result<RunStats, Box<dyn Error>> {
+    use ratatui::crossterm::event::{self, Event, KeyCode, KeyModifiers};
+    use std::time::Instant;
+    use transaction_processor::Dashboard;
 
-    let mut client_ids = database.get_all_client_ids();
-    client_ids.sort(); // Sort for consistent output
+    if !dashboard {
+        return Ok(engine.process(source));
+    }
+    let mut terminal = start_dashboard()?;
+    let mut view = Dashboard::new(Instant::now());
+    let mut failed = None;
+    let stats = engine.process_with_progress(source, |progress| {
+        if failed.is_some() || !view.update(progress, Instant::now()) {
+            return;
+        }
+        if let Err(e) = terminal.draw(|frame| view.render(frame)) {
+            failed = Some(e);
+            return;
+        }
+        // Raw mode turns Ctrl-C into a key press, so it is handled here along with q
+        while let Ok(true) = event::poll(Duration::ZERO) {
+            if let Ok(Event::Key(key)) = event::read()
+                && (key.code == KeyCode::Char('q')
+                    || (key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL)))
+            {
+                stop_dashboard();
+                eprintln!("Stopped at row {}; nothing was written", view.stats().rows);
+                process::exit(130);
+            }
+        }
+    });
+    let drawn = match failed {
+        Some(e) => Err(e),
+        None => {
+            view.refresh(engine.database(), &stats, Instant::now());
+            terminal.draw(|frame| view.render(frame)).map(|_| ())
+        }
+    };
+    stop_dashboard();
+    drawn?;
+    Ok(stats)
+}
 
-    for client_id in client_ids {
-        if let Some(account) = database.get_account(client_id) {
-            println!(
-                "{},{},{},{},{}",
-                client_id,
-                account.available,
-                account.held,
-                account.total(),
-                account.locked
+/// Terminal the dashboard is drawn on: stderr, so the summary can still go to stdout
+#[cfg(feature = "tui")]
+type DashboardTerminal = ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stderr>>;
+
+/// Switch stderr to a full-screen terminal for the dashboard
+#[cfg(feature = "tui")]
+fn start_dashboard() -> std::io::Result<DashboardTerminal> {
+    use ratatui::crossterm::terminal::{EnterAlternateScreen, enable_raw_mode};
+    use ratatui::crossterm::{ExecutableCommand, cursor::Hide};
+
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        stop_dashboard();
+        hook(info);
+    }));
+    enable_raw_mode()?;
+    std::io::stderr()
+        .execute(EnterAlternateScreen)?
+        .execute(Hide)?;
+    ratatui::Terminal::new(ratatui::backend::CrosstermBackend::new(std::io::stderr()))
+}
+
+/// Put the terminal back as it was before the dashboard
+#[cfg(feature = "tui")]
+fn stop_dashboard() {
+    use ratatui::crossterm::terminal::{LeaveAlternateScreen, disable_raw_mode};
+    use ratatui::crossterm::{ExecutableCommand, cursor::Show};
+
+    // Best effort: the terminal may already be restored, or gone
+    let _ = disable_raw_mode();
+    let _ = std::io::stderr()
+        .execute(LeaveAlternateScreen)
+        .and_then(|stderr| stderr.execute(Show));
+}
+
+/// Apply every transaction from `source`
+#[cfg(not(feature = "tui"))]
+fn process<S: TransactionSource>(
+    engine: &mut Engine,
+    source: S,
+    _dashboard: bool,
+) -> Result<RunStats, Box<dyn Error>> {
+    Ok(engine.process(source))
+}
+
+/// Write the account summary to `output`, or to stdout if not given
+fn write_summary(
+    engine: &Engine,
+    output: Option<&str>,
+    report: &ReportArgs,
+) -> Result<(), Box<dyn Error>> {
+    match output {
+        Some(location) => {
+            let mut output = LocationWriter::create(location)?;
+            engine.report(report.sink(&mut output)?)?;
+            output.finish()
+        }
+        None => {
+            let stdout = std::io::stdout().lock();
+            engine.report(report.sink(stdout)?)
+        }
+    }
+}
+
+fn write_event_log(events: &EventLog, location: &str) -> Result<(), Box<dyn Error>> {
+    let mut output = LocationWriter::create(location)?;
+    events.write_csv(&mut output)?;
+    output.finish()
+}
+
+fn watch(
+    mut engine: Engine,
+    mut folder: DropFolder,
+    interval: u64,
+    mut schedule: Option<ScheduledReports>,
+    event_log: Option<String>,
+    mut outbox: Option<Outbox>,
+    mut store: Option<SnapshotStore>,
+) -> Result<(), Box<dyn Error>> {
+    // Events restored from the state directory were already outboxed before the restart
+    let mut outboxed = engine.event_log().map_or(0, |log| log.events().len());
+    loop {
+        let processed = folder.process_pending(&mut engine)?;
+        for file in &processed {
+            eprintln!(
+                "{:?} {}: {} rows, {} applied, {} rejected",
+                file.outcome,
+                file.path.display(),
+                file.stats.rows,
+                file.stats.applied,
+                file.stats.rejected
             );
         }
+        if !processed.is_empty()
+            && let (Some(location), Some(events)) = (&event_log, engine.event_log())
+        {
+            write_event_log(events, location)?;
+        }
+        if let (Some(outbox), Some(events)) = (&mut outbox, engine.event_log()) {
+            outbox.append(&events.events()[outboxed..])?;
+            outboxed = events.events().len();
+        }
+        if let Some(store) = &mut store {
+            store.save(&mut engine)?;
+            // Compaction drops the events it covers, which were all outboxed already
+            outboxed = engine.event_log().map_or(0, |log| log.events().len());
+        }
+        if let Some(schedule) = &mut schedule
+            && let Some(path) = schedule.poll(&engine, now())?
+        {
+            eprintln!("Wrote scheduled report {}", path.display());
+        }
+        thread::sleep(Duration::from_secs(interval));
+    }
+}
+
+/// Parse a `--type-alias` or `--column-map` value
+fn parse_rename(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((from, to)) if !from.is_empty() && !to.is_empty() => {
+            Ok((from.to_string(), to.to_string()))
+        }
+        _ => Err(format!("Expected FROM=TO, got '{}'", value)),
     }
 }
+
+/// Parse a `--client-map` value
+fn parse_client_map(value: &str) -> Result<(u16, u16), String> {
+    value
+        .split_once('=')
+        .and_then(|(from, to)| Some((from.trim().parse().ok()?, to.trim().parse().ok()?)))
+        .ok_or_else(|| format!("Expected FROM=TO client IDs, got '{}'", value))
+}
+
+/// Latest timestamp of any change to any account, or 0 if none carried one
+fn latest_activity(database: &Database) -> Timestamp {
+    database
+        .get_all_client_ids()
+        .into_iter()
+        .filter_map(|client| database.get_account(client)?.last_activity())
+        .max()
+        .unwrap_or(0)
+}
+
+/// Current time in seconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
@@ -0,0 +1,129 @@
+//! Customizable error message catalog
+//!
+//! Error `Display` strings are English. A [`MessageCatalog`] lets deployments replace them
+//! with translations or brand-specific wording, keyed by the stable [`ErrorCode`] names.
+//! Any code missing from the catalog falls back to the built-in English message.
+
+use crate::db::{EngineError, ErrorCode, TransactionError};
+use std::collections::HashMap;
+
+/// Message templates keyed by error code
+///
+/// Templates may use the following placeholders:
+/// - `{detail}` - the offending value for errors that carry one (e.g. the unknown type name)
+/// - `{client}`, `{tx}`, `{amount}` - transaction context, when rendering a [`TransactionError`]
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{ErrorCode, MessageCatalog, EngineError};
+/// let catalog = MessageCatalog::parse(
+///     "# French messages\n\
+///      INSUFFICIENT_FUNDS = Fonds insuffisants\n\
+///      UNKNOWN_TRANSACTION_TYPE = Type de transaction inconnu : {detail}",
+/// )
+/// .unwrap();
+///
+/// assert_eq!(catalog.render(&EngineError::InsufficientFunds), "Fonds insuffisants");
+/// assert_eq!(
+///     catalog.render(&EngineError::UnknownTransactionType("fee".to_string())),
+///     "Type de transaction inconnu : fee"
+/// );
+/// // Codes without a template keep the default English message
+/// assert_eq!(catalog.render(&EngineError::AccountLocked), "Account is locked");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MessageCatalog {
+    templates: HashMap<ErrorCode, String>,
+}
+
+impl MessageCatalog {
+    /// Create an empty catalog, which renders every error with its default message
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add or replace the template for an error code
+    pub fn with_message(mut self, code: ErrorCode, template: impl Into<String>) -> Self {
+        self.templates.insert(code, template.into());
+        self
+    }
+
+    /// Parse a catalog from `NAME = template` lines
+    ///
+    /// Blank lines and lines starting with `#` are ignored. Names must be known
+    /// error code names such as `INSUFFICIENT_FUNDS`.
+    ///
+    /// # Errors
+    /// Returns a message naming the offending line if a line is malformed or uses
+    /// an unknown error code name.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut catalog = Self::new();
+        for (index, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, template) = line
+                .split_once('=')
+                .ok_or_else(|| format!("Line {}: expected NAME = message", index + 1))?;
+            let code = ErrorCode::from_name(name.trim())
+                .ok_or_else(|| format!("Line {}: unknown error code {}", index + 1, name.trim()))?;
+            catalog.templates.insert(code, template.trim().to_string());
+        }
+        Ok(catalog)
+    }
+
+    /// Render an error kind using the catalog
+    pub fn render(&self, error: &EngineError) -> String {
+        match self.templates.get(&error.code()) {
+            Some(template) => template.replace("{detail}", detail(error)),
+            None => error.to_string(),
+        }
+    }
+
+    /// Render an error with its transaction context using the catalog
+    ///
+    /// A template replaces the whole message, so it should include whichever of
+    /// `{client}`, `{tx}` and `{amount}` the deployment wants to show.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Database, ErrorCode, MessageCatalog, Transaction};
+    /// let catalog = MessageCatalog::new().with_message(
+    ///     ErrorCode::INSUFFICIENT_FUNDS,
+    ///     "Client {client} cannot withdraw {amount} (tx {tx})",
+    /// );
+    ///
+    /// let mut db = Database::new();
+    /// let err = db
+    ///     .process_transaction(3, 9, Transaction::withdrawal("5").unwrap())
+    ///     .unwrap_err();
+    /// assert_eq!(
+    ///     catalog.render_transaction_error(&err),
+    ///     "Client 3 cannot withdraw 5.0000 (tx 9)"
+    /// );
+    /// ```
+    pub fn render_transaction_error(&self, error: &TransactionError) -> String {
+        match self.templates.get(&error.code()) {
+            Some(template) => template
+                .replace("{detail}", detail(&error.kind))
+                .replace("{client}", &error.client.to_string())
+                .replace("{tx}", &error.tx.to_string())
+                .replace(
+                    "{amount}",
+                    &error.amount.map(|a| a.to_string()).unwrap_or_default(),
+                ),
+            None => error.to_string(),
+        }
+    }
+}
+
+/// The value carried by an error kind, or an empty string if it has none
+fn detail(error: &EngineError) -> &str {
+    match error {
+        EngineError::InvalidAmountFormat(detail) | EngineError::UnknownTransactionType(detail) => {
+            detail
+        }
+        _ => "",
+    }
+}
@@ -0,0 +1,149 @@
+//! Outbox of applied transactions awaiting publication
+//!
+//! Downstream consumers (webhooks, message queues) are told about every applied
+//! transaction through an [`Outbox`]: events are appended to `events.csv` in its
+//! directory as soon as they are applied, and a `delivered` marker file counts how many
+//! of them have been published. After a crash, publication resumes from the marker
+//! instead of regenerating events from a full replay.
+//!
+//! Delivery is at-least-once: an event published just before a crash, but not yet
+//! marked, is published again. Consumers should deduplicate on client and transaction
+//! ID.
+
+use crate::event_log::{Event, EventLog, append_csv};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const EVENTS_FILE: &str = "events.csv";
+const DELIVERED_FILE: &str = "delivered";
+
+/// Persisted queue of events, with a marker of how many have been delivered
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{CsvSource, Engine, Outbox};
+/// let dir = tempfile::tempdir().unwrap();
+/// let mut engine = Engine::new().with_event_log();
+/// engine.process(CsvSource::new(
+///     "input",
+///     "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,2,5.0\n".as_bytes(),
+/// ));
+///
+/// let mut outbox = Outbox::open(dir.path()).unwrap();
+/// outbox.append(engine.event_log().unwrap().events()).unwrap();
+///
+/// // The webhook accepts the first event, then goes down
+/// let mut published = Vec::new();
+/// let result = outbox.deliver(|event| {
+///     if event.tx == 2 {
+///         return Err("webhook unavailable".into());
+///     }
+///     published.push(event.tx);
+///     Ok(())
+/// });
+/// assert!(result.is_err());
+/// assert_eq!(published, vec![1]);
+///
+/// // After a restart, only the second event is still pending
+/// let mut outbox = Outbox::open(dir.path()).unwrap();
+/// let delivered = outbox.deliver(|event| {
+///     published.push(event.tx);
+///     Ok(())
+/// });
+/// assert_eq!(delivered.unwrap(), 1);
+/// assert_eq!(published, vec![1, 2]);
+/// assert!(outbox.pending().is_empty());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Outbox {
+    dir: PathBuf,
+    events: EventLog,
+    delivered: usize,
+}
+
+impl Outbox {
+    /// Open the outbox in `dir`, creating it if needed
+    ///
+    /// # Errors
+    /// Returns an error if the directory cannot be created, or its events or marker
+    /// cannot be read.
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, Box<dyn Error>> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        let events_path = dir.join(EVENTS_FILE);
+        let events = if events_path.exists() {
+            let name = events_path.display().to_string();
+            EventLog::read_csv(&name, File::open(&events_path)?)?
+        } else {
+            EventLog::new()
+        };
+        let delivered = match fs::read_to_string(dir.join(DELIVERED_FILE)) {
+            Ok(marker) => marker.trim().parse()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self {
+            dir,
+            events,
+            delivered,
+        })
+    }
+
+    /// Directory holding the outbox files
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Persist `events` at the end of the outbox
+    ///
+    /// # Errors
+    /// Returns an error if the events cannot be written.
+    pub fn append(&mut self, events: &[Event]) -> Result<(), Box<dyn Error>> {
+        append_csv(&self.dir.join(EVENTS_FILE), events)?;
+        for event in events {
+            self.events.push(event.clone());
+        }
+        Ok(())
+    }
+
+    /// Events not yet delivered, oldest first
+    pub fn pending(&self) -> &[Event] {
+        &self.events.events()[self.delivered.min(self.events.events().len())..]
+    }
+
+    /// Publish pending events in order, marking each one delivered once `publish` accepts
+    /// it
+    ///
+    /// Returns how many events were delivered.
+    ///
+    /// # Errors
+    /// Stops at the first event `publish` refuses and returns its error, leaving that
+    /// event and the ones after it pending. Also fails if the marker cannot be written.
+    pub fn deliver<F>(&mut self, mut publish: F) -> Result<usize, Box<dyn Error>>
+    where
+        F: FnMut(&Event) -> Result<(), Box<dyn Error>>,
+    {
+        let mut delivered = 0;
+        while let Some(event) = self.pending().first() {
+            publish(event)?;
+            self.mark_delivered(self.delivered + 1)?;
+            delivered += 1;
+        }
+        Ok(delivered)
+    }
+
+    /// Persist the delivered count, replacing the marker atomically
+    fn mark_delivered(&mut self, delivered: usize) -> std::io::Result<()> {
+        let temp = self.dir.join(format!("{}.tmp", DELIVERED_FILE));
+        let mut file = File::create(&temp)?;
+        writeln!(file, "{}", delivered)?;
+        file.sync_all()?;
+        fs::rename(temp, self.dir.join(DELIVERED_FILE))?;
+        self.delivered = delivered;
+        Ok(())
+    }
+}
@@ -0,0 +1,269 @@
+//! Splitting inputs by client range, for runs distributed across machines
+//!
+//! Accounts are independent of each other, so a very large input can be split into one
+//! file per range of client IDs with [`split_csv`], each file processed on a different
+//! machine, and the account summaries put back together with [`combine_summaries`].
+//! Every row of a client lands in the same file, in its original order, so each machine
+//! produces exactly the accounts a single run would have.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+/// Contiguous ranges of client IDs, together covering every ID
+///
+/// Parses from and displays as a comma-separated list of `FIRST-LAST` ranges in
+/// ascending order, e.g. `0-999,1000-65535`.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::ClientRanges;
+/// let ranges = ClientRanges::even(2);
+/// assert_eq!(ranges.to_string(), "0-32767,32768-65535");
+/// assert_eq!(ranges.shard(40000), 1);
+///
+/// let ranges: ClientRanges = "0-999,1000-65535".parse().unwrap();
+/// assert_eq!(ranges.len(), 2);
+/// assert_eq!(ranges.range(0), 0..=999);
+/// assert!("0-999,2000-65535".parse::<ClientRanges>().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientRanges {
+    /// First client ID of each range, ascending, starting with 0
+    starts: Vec<u16>,
+}
+
+impl ClientRanges {
+    /// `shards` ranges of (nearly) equal size
+    ///
+    /// # Panics
+    /// Panics if `shards` is zero or more than there are client IDs.
+    pub fn even(shards: usize) -> Self {
+        let ids = usize::from(u16::MAX) + 1;
+        assert!(
+            (1..=ids).contains(&shards),
+            "shards must be between 1 and {}",
+            ids
+        );
+        let starts = (0..shards)
+            .map(|shard| (shard * ids / shards) as u16)
+            .collect();
+        Self { starts }
+    }
+
+    /// Number of ranges
+    pub fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    /// Always false: the ranges cover every client ID
+    pub fn is_empty(&self) -> bool {
+        self.starts.is_empty()
+    }
+
+    /// Index of the range holding `client`
+    pub fn shard(&self, client: u16) -> usize {
+        self.starts.partition_point(|&start| start <= client) - 1
+    }
+
+    /// Client IDs of range `shard`
+    ///
+    /// # Panics
+    /// Panics if `shard` is not below [`len`](Self::len).
+    pub fn range(&self, shard: usize) -> std::ops::RangeInclusive<u16> {
+        let end = self.starts.get(shard + 1).map_or(u16::MAX, |next| next - 1);
+        self.starts[shard]..=end
+    }
+}
+
+impl std::fmt::Display for ClientRanges {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for shard in 0..self.len() {
+            if shard > 0 {
+                write!(f, ",")?;
+            }
+            let range = self.range(shard);
+            write!(f, "{}-{}", range.start(), range.end())?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ClientRanges {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut starts = Vec::new();
+        // First client ID the next range must start at, or None after u16::MAX
+        let mut next = Some(0u16);
+        for range in s.split(',') {
+            let invalid = || format!("Expected FIRST-LAST, got '{}'", range.trim());
+            let (first, last) = range.trim().split_once('-').ok_or_else(invalid)?;
+            let first: u16 = first.parse().map_err(|_| invalid())?;
+            let last: u16 = last.parse().map_err(|_| invalid())?;
+            match next {
+                Some(expected) if first == expected && last >= first => {}
+                Some(expected) => {
+                    return Err(format!(
+                        "Range '{}' should start at {} and not end before it starts",
+                        range.trim(),
+                        expected
+                    ));
+                }
+                None => return Err(format!("Range '{}' is past 65535", range.trim())),
+            }
+            starts.push(first);
+            next = last.checked_add(1);
+        }
+        match next {
+            None => Ok(Self { starts }),
+            Some(missing) => Err(format!("Client IDs from {} are not covered", missing)),
+        }
+    }
+}
+
+/// Split a CSV input into one output per client range
+///
+/// Every output gets the input's header, followed by the rows whose `client` is in its
+/// range, verbatim and in their original order. Rows whose client can't be read go to
+/// the first output, so they are reported when it is processed; errors then give their
+/// line in that output rather than in the input.
+///
+/// Returns the number of rows written to each output.
+///
+/// # Errors
+/// Returns an error if the input can't be read or has no `client` column, if there
+/// isn't one output per range, or if writing fails.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{ClientRanges, split_csv};
+/// let input = "type,client,tx,amount\n\
+///              deposit,1,1,10\n\
+///              deposit,40000,2,5\n\
+///              withdrawal,1,3,4\n";
+///
+/// let mut outputs = vec![Vec::new(), Vec::new()];
+/// let rows = split_csv(input.as_bytes(), &ClientRanges::even(2), &mut outputs).unwrap();
+/// assert_eq!(rows, [2, 1]);
+/// assert_eq!(
+///     String::from_utf8(outputs[0].clone()).unwrap(),
+///     "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,3,4\n"
+/// );
+/// ```
+pub fn split_csv<R: Read, W: Write>(
+    input: R,
+    ranges: &ClientRanges,
+    outputs: &mut [W],
+) -> Result<Vec<usize>, Box<dyn Error>> {
+    if outputs.len() != ranges.len() {
+        return Err(format!(
+            "{} outputs given for {} client ranges",
+            outputs.len(),
+            ranges.len()
+        )
+        .into());
+    }
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(input);
+    let headers = reader.headers()?.clone();
+    let client_column = headers
+        .iter()
+        .position(|name| name.trim().eq_ignore_ascii_case("client"))
+        .ok_or("Input has no client column")?;
+
+    let mut writers: Vec<_> = outputs
+        .iter_mut()
+        .map(|output| csv::WriterBuilder::new().flexible(true).from_writer(output))
+        .collect();
+    for writer in &mut writers {
+        writer.write_record(&headers)?;
+    }
+    let mut rows = vec![0; writers.len()];
+    for record in reader.records() {
+        let record = record?;
+        let shard = record
+            .get(client_column)
+            .and_then(|client| client.trim().parse().ok())
+            .map_or(0, |client| ranges.shard(client));
+        writers[shard].write_record(&record)?;
+        rows[shard] += 1;
+    }
+    for writer in &mut writers {
+        writer.flush()?;
+    }
+    Ok(rows)
+}
+
+/// Combine the account summaries of runs over separate client ranges into one
+///
+/// The summaries must have the same columns. Rows are written in the order of the
+/// inputs, so summaries sorted by client and given in range order combine into one
+/// sorted by client. Returns the number of accounts written.
+///
+/// # Errors
+/// Returns an error if an input can't be read, if the inputs' columns differ, if a
+/// client is in more than one input (the inputs weren't split by client), or if
+/// writing fails.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::combine_summaries;
+/// let low = "client,available\n1,10.0000\n2,5.0000\n";
+/// let high = "client,available\n40000,1.0000\n";
+///
+/// let mut output = Vec::new();
+/// let accounts = combine_summaries(vec![low.as_bytes(), high.as_bytes()], &mut output);
+/// assert_eq!(accounts.unwrap(), 3);
+/// assert_eq!(
+///     String::from_utf8(output).unwrap(),
+///     "client,available\n1,10.0000\n2,5.0000\n40000,1.0000\n"
+/// );
+///
+/// let again = combine_summaries(vec![low.as_bytes(), low.as_bytes()], Vec::new());
+/// assert!(again.unwrap_err().to_string().contains("client 1"));
+/// ```
+pub fn combine_summaries<R: Read, W: Write>(
+    inputs: Vec<R>,
+    output: W,
+) -> Result<usize, Box<dyn Error>> {
+    let mut writer = csv::Writer::from_writer(output);
+    let mut headers: Option<csv::StringRecord> = None;
+    let mut clients = HashSet::new();
+    let mut accounts = 0;
+    for (index, input) in inputs.into_iter().enumerate() {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input);
+        let input_headers = reader.headers()?.clone();
+        match &headers {
+            None => {
+                writer.write_record(&input_headers)?;
+                headers = Some(input_headers.clone());
+            }
+            Some(headers) if *headers != input_headers => {
+                return Err(format!(
+                    "Summary {} has columns {}, not {}",
+                    index + 1,
+                    input_headers.iter().collect::<Vec<_>>().join(","),
+                    headers.iter().collect::<Vec<_>>().join(",")
+                )
+                .into());
+            }
+            Some(_) => {}
+        }
+        let client_column = input_headers.iter().position(|name| name == "client");
+        for record in reader.records() {
+            let record = record?;
+            if let Some(client) = client_column.and_then(|column| record.get(column))
+                && !clients.insert(client.to_string())
+            {
+                return Err(format!("client {} is in more than one summary", client).into());
+            }
+            writer.write_record(&record)?;
+            accounts += 1;
+        }
+    }
+    writer.flush()?;
+    Ok(accounts)
+}
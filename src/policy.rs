@@ -0,0 +1,188 @@
+//! Configurable processing policy
+//!
+//! A [`Policy`] collects the business rules that differ between programs, so a single
+//! engine can serve them all. Every option defaults to the engine's original behavior.
+
+use crate::fixed4::Fixed4;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Business rules applied by a [`Database`](crate::Database) while processing transactions
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Database, Policy};
+/// let policy = Policy {
+///     unlock_on_chargeback_reversal: true,
+///     ..Policy::default()
+/// };
+/// let db = Database::with_policy(policy);
+/// assert!(db.policy().unlock_on_chargeback_reversal);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Policy {
+    /// Unlock a locked account when a chargeback reversal leaves it with no
+    /// outstanding chargebacks
+    pub unlock_on_chargeback_reversal: bool,
+    /// Cooling-off period, in seconds after the locking chargeback, after which
+    /// [`Database::apply_auto_unlocks`](crate::Database::apply_auto_unlocks) may unlock
+    /// an account. `None` disables time-based unlocking.
+    pub auto_unlock_after: Option<u64>,
+    /// Only unlock accounts automatically once they have been
+    /// [marked as reviewed](crate::Database::mark_reviewed)
+    pub unlock_requires_review: bool,
+    /// Percentage of each timestamped deposit kept in a rolling reserve (held) rather
+    /// than made available straight away, at most 100. `0` disables the reserve.
+    pub reserve_percent: u8,
+    /// Days a deposit's reserve is held before
+    /// [`Database::release_matured_reserves`](crate::Database::release_matured_reserves)
+    /// makes it available
+    pub reserve_days: u64,
+    /// Withdrawals, and manual adjustments through
+    /// [`AccountOps::post_adjustment`](crate::AccountOps::post_adjustment), larger than
+    /// this are staged for approval instead of applied (see
+    /// [`Database::approve`](crate::Database::approve)). `None` applies them straight
+    /// away.
+    pub approval_threshold: Option<Fixed4>,
+    /// Unlocking a locked account through
+    /// [`Database::set_account_state`](crate::Database::set_account_state) is staged for
+    /// approval
+    pub unlocks_require_approval: bool,
+    /// Apply a deposit with a negative amount as a refund of that amount, debiting
+    /// available funds and recorded as [`EntryKind::Refund`](crate::EntryKind::Refund),
+    /// rather than rejecting it, as some processors encode refunds that way
+    pub negative_deposits_as_refunds: bool,
+    /// Allow withdrawals to be disputed, as erroneous debits, rather than rejecting
+    /// their disputes with [`EngineError::TransactionIsWithdrawal`](crate::EngineError::TransactionIsWithdrawal)
+    ///
+    /// A disputed withdrawal is credited back to held funds. Resolving it lets the
+    /// withdrawal stand; charging it back returns the funds to available, without
+    /// locking the account, since the client was the one wronged.
+    pub withdrawal_disputes: bool,
+    /// Lock or freeze an account once its chargebacks reach any of these limits
+    pub chargeback_limits: Vec<ChargebackLimit>,
+    /// When processing an input, hold back a dispute, resolve or chargeback of a
+    /// transaction not seen yet and retry it once the transaction arrives, or at the end
+    /// of the input, rather than rejecting it straight away; only those that still don't
+    /// match are rejected
+    pub defer_unmatched_disputes: bool,
+    /// Keep at most this many of the latest rows of each account's
+    /// [balance history](crate::Account::balance_history), dropping the oldest. `None`
+    /// keeps every row.
+    ///
+    /// Statements, running balance exports, the Avro ledger export, the dispute graph
+    /// and [`disputes_after_withdrawal`](crate::disputes_after_withdrawal) are built from
+    /// the history, so they only cover the rows kept. Balances and the ledger itself are
+    /// unaffected.
+    pub balance_history_limit: Option<usize>,
+}
+
+impl Policy {
+    /// Check that the rules make sense together
+    ///
+    /// Databases check the policy they are given, so this is only needed to report a
+    /// bad policy, e.g. one read from configuration, before building one.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Policy, PolicyError};
+    /// assert_eq!(Policy::default().validate(), Ok(()));
+    ///
+    /// let policy = Policy {
+    ///     reserve_percent: 150,
+    ///     ..Policy::default()
+    /// };
+    /// assert_eq!(policy.validate(), Err(PolicyError::ReservePercentOver100(150)));
+    /// ```
+    ///
+    /// # Errors
+    /// Returns the first rule that is out of range.
+    pub fn validate(&self) -> Result<(), PolicyError> {
+        if self.reserve_percent > 100 {
+            return Err(PolicyError::ReservePercentOver100(self.reserve_percent));
+        }
+        Ok(())
+    }
+}
+
+/// Why a [`Policy`] is invalid
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum PolicyError {
+    /// [`Policy::reserve_percent`] would hold back more than the whole deposit
+    #[error("Reserve percentage {0} is over 100")]
+    ReservePercentOver100(u8),
+}
+
+/// A limit on an account's chargebacks, past which it is locked or frozen
+///
+/// Checked after every chargeback. The limit is reached once the account has had
+/// `max_chargebacks` chargebacks, or once more than `max_volume` has been charged back,
+/// counting only chargebacks in the `period` seconds up to the latest one if a period
+/// is set (which then needs timestamped input). Chargebacks later reversed still count.
+///
+/// An account locked by a limit is only unlocked through
+/// [`Database::set_account_state`](crate::Database::set_account_state), never by
+/// [`Policy::auto_unlock_after`] or [`Policy::unlock_on_chargeback_reversal`]. Reaching a
+/// limit is recorded in the account's [audit log](crate::Account::audit_log).
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{
+/// #     AccountState, AuditEvent, ChargebackLimit, Database, LimitAction, Policy, Transaction,
+/// # };
+/// // Freeze accounts with two chargebacks within a day
+/// let policy = Policy {
+///     chargeback_limits: vec![ChargebackLimit {
+///         max_chargebacks: Some(2),
+///         max_volume: None,
+///         period: Some(24 * 60 * 60),
+///         action: LimitAction::Freeze,
+///     }],
+///     ..Policy::default()
+/// };
+/// let mut db = Database::with_policy(policy);
+/// for tx in 1..=2 {
+///     db.process_transaction(1, tx, Transaction::deposit("10").unwrap()).unwrap();
+///     db.process_transaction(1, tx, Transaction::dispute()).unwrap();
+/// }
+/// db.process_transaction_at(1, 1, Transaction::chargeback(), Some(1000)).unwrap();
+/// assert_eq!(db.get_account(1).unwrap().state(), AccountState::Locked);
+/// db.process_transaction_at(1, 2, Transaction::chargeback(), Some(2000)).unwrap();
+///
+/// let account = db.get_account(1).unwrap();
+/// assert_eq!(account.state(), AccountState::Frozen);
+/// assert!(matches!(
+///     account.audit_log().last().unwrap().event,
+///     AuditEvent::ChargebackLimitReached { chargebacks: 2, action: LimitAction::Freeze, .. }
+/// ));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChargebackLimit {
+    /// Number of chargebacks that reaches the limit
+    pub max_chargebacks: Option<u32>,
+    /// Amount charged back that, once exceeded, reaches the limit
+    pub max_volume: Option<Fixed4>,
+    /// Seconds before the latest chargeback to count chargebacks over; `None` counts
+    /// every chargeback
+    pub period: Option<u64>,
+    /// What happens to the account when the limit is reached
+    pub action: LimitAction,
+}
+
+/// What a [`ChargebackLimit`] does to an account that reaches it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LimitAction {
+    /// Lock the account, with [`LockReason::ChargebackLimit`](crate::LockReason::ChargebackLimit)
+    Lock,
+    /// Freeze the account
+    Freeze,
+}
+
+impl std::fmt::Display for LimitAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Lock => write!(f, "locked"),
+            Self::Freeze => write!(f, "frozen"),
+        }
+    }
+}
@@ -0,0 +1,33 @@
+//! The types most programs need, for a single glob import
+//!
+//! ```
+//! use transaction_processor::prelude::*;
+//!
+//! let mut db = Database::new();
+//! db.process_transaction(1, 1, Transaction::deposit("10")?)?;
+//! let err = db.process_transaction(1, 2, Transaction::withdrawal("15")?).unwrap_err();
+//! assert!(matches!(err.kind, EngineError::InsufficientFunds));
+//! assert_eq!(db.get_account(1).unwrap().available(), "10".parse::<Fixed4>()?);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Everything here is also exported from the crate root. Items are only added to the
+//! prelude when they are needed by most users of the crate, and never removed or
+//! renamed without a deprecation first, so a glob import of it stays safe across
+//! upgrades. Reach for the crate root, or the module, for anything more specialized.
+
+#[allow(deprecated)]
+pub use crate::db::MyError;
+pub use crate::db::{
+    Account, AccountSummary, Database, DisputeAction, EngineError, ErrorClass, ErrorCode,
+    Transaction, TransactionError,
+};
+pub use crate::engine::{Engine, RunStats};
+pub use crate::fixed4::Fixed4;
+pub use crate::policy::Policy;
+pub use crate::sink::SummarySink;
+pub use crate::source::TransactionSource;
+pub use crate::storage::Storage;
+
+#[cfg(feature = "csv")]
+pub use crate::csv_processor::{CsvProcessor, CsvSource, ProcessingResult, process_csv_file};
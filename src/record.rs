@@ -0,0 +1,313 @@
+//! Raw transaction records, before they are decoded into transactions
+//!
+//! A [`TransactionRecord`] is one input row as read, with every field but the amount
+//! still in its input form. [`RecordHook`]s may rewrite or skip records before decoding, and a
+//! [`TransactionDecoder`] decodes types the built-in decoder doesn't know. None of this
+//! depends on the input format, so it is available without the `csv` feature that
+//! `CsvSource` needs.
+
+use crate::Fixed4;
+use crate::db::{EngineError, Timestamp, Transaction};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+
+/// A raw CSV row, before it is decoded into a transaction
+///
+/// Passed to [`RecordHook`]s, which may rewrite any field.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionRecord {
+    #[serde(rename = "type")]
+    pub transaction_type: String,
+    pub client: u16,
+    pub tx: u32,
+    /// Optional because dispute, resolve, chargeback don't have amounts
+    pub amount: Option<RecordAmount>,
+    #[serde(default)]
+    pub timestamp: Option<Timestamp>, // Optional column; seconds since the Unix epoch
+    #[serde(default)]
+    pub case_id: Option<String>, // Optional column; links dispute-lifecycle rows to a case
+    #[serde(default)]
+    pub effective_at: Option<Timestamp>, // Optional column; backdates a correction
+    #[serde(default)]
+    pub initiator: Option<String>, // Optional column; who submitted the row
+    #[serde(default)]
+    pub offset: Option<String>, // Optional column; SOURCE/PARTITION@OFFSET the row was delivered at
+    #[serde(default)]
+    pub original_tx: Option<u32>, // Optional column; deposit a refund row refunds
+}
+
+/// A record's amount, parsed from the field's text as the row is read
+///
+/// The text is parsed as a decimal directly, never read as a number first, so large
+/// amounts keep every digit. Text that doesn't parse is kept rather than failing the
+/// read, so that only a row whose type needs its amount is rejected for it, as
+/// [`EngineError::InvalidAmountFormat`].
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{EngineError, RecordAmount};
+/// let amount = RecordAmount::parse("123456789012.3456");
+/// assert_eq!(amount.value().unwrap().to_string(), "123456789012.3456");
+///
+/// let invalid = RecordAmount::parse("abc");
+/// assert!(matches!(invalid.value(), Err(EngineError::InvalidAmountFormat(_))));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordAmount {
+    /// A decimal with at most 4 places
+    Valid(Fixed4),
+    /// Text that isn't one, and why
+    Invalid { text: String, error: String },
+}
+
+impl RecordAmount {
+    /// Parse an amount field's text
+    pub fn parse(text: &str) -> Self {
+        match text.parse() {
+            Ok(amount) => Self::Valid(amount),
+            Err(error) => Self::Invalid {
+                text: text.to_string(),
+                error,
+            },
+        }
+    }
+
+    /// The amount, if the text was valid
+    ///
+    /// # Errors
+    /// Returns [`EngineError::InvalidAmountFormat`] if it wasn't
+    pub fn value(&self) -> Result<Fixed4, EngineError> {
+        match self {
+            Self::Valid(amount) => Ok(*amount),
+            Self::Invalid { error, .. } => Err(EngineError::InvalidAmountFormat(error.clone())),
+        }
+    }
+}
+
+impl From<Fixed4> for RecordAmount {
+    fn from(amount: Fixed4) -> Self {
+        Self::Valid(amount)
+    }
+}
+
+/// Written as the amount, or as the text read if that wasn't valid
+impl Serialize for RecordAmount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Valid(amount) => amount.serialize(serializer),
+            Self::Invalid { text, .. } => serializer.serialize_str(text),
+        }
+    }
+}
+
+/// Read from the field's text, whatever it looks like
+impl<'de> Deserialize<'de> for RecordAmount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(RecordAmountVisitor)
+    }
+}
+
+/// Reads a [`RecordAmount`] from a field's text
+struct RecordAmountVisitor;
+
+impl serde::de::Visitor<'_> for RecordAmountVisitor {
+    type Value = RecordAmount;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "an amount field")
+    }
+
+    fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<RecordAmount, E> {
+        Ok(RecordAmount::parse(value))
+    }
+}
+
+/// Non-fatal issues noticed while processing input
+///
+/// Warnings are reported separately from errors so that real failures aren't
+/// drowned out by noise from otherwise valid input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// A dispute-lifecycle row (dispute, resolve, chargeback, reversal) carried an amount,
+    /// which was ignored
+    IgnoredAmount,
+    /// Row repeats a deposit or withdrawal that was already applied, so it was skipped
+    DuplicateRow,
+    /// Row has a transaction type that isn't recognised, so it was skipped (see
+    /// [`UnknownTypes::Ignore`])
+    UnknownType(String),
+    /// A [`RecordHook`] skipped the row, for the reason given
+    SkippedByHook(String),
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IgnoredAmount => write!(f, "Amount ignored on dispute-lifecycle row"),
+            Self::DuplicateRow => write!(f, "Duplicate row skipped"),
+            Self::UnknownType(kind) => write!(f, "Row of unknown type '{}' skipped", kind),
+            Self::SkippedByHook(reason) => write!(f, "Row skipped: {}", reason),
+        }
+    }
+}
+
+/// Decodes CSV rows of types the built-in decoder doesn't recognise, such as `fee` or
+/// `bonus`
+///
+/// Closures taking the row's type and amount implement this trait.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{CsvSource, Engine, EngineError, RecordAmount, Transaction};
+/// // Fees are withdrawals and bonuses deposits
+/// let decoder = |kind: &str, amount: Option<&RecordAmount>| {
+///     let amount = amount
+///         .ok_or(EngineError::MissingAmount)
+///         .and_then(RecordAmount::value);
+///     match kind {
+///         "fee" => Some(amount.map(|amount| Transaction::Withdrawal { amount })),
+///         "bonus" => Some(amount.map(|amount| Transaction::Deposit { amount })),
+///         _ => None,
+///     }
+/// };
+/// let input = "type,client,tx,amount\n\
+///              deposit,1,1,100\n\
+///              fee,1,2,2.5\n\
+///              bonus,1,3,10\n\
+///              interest,1,4,1\n";
+/// let source = CsvSource::new("input", input.as_bytes()).with_decoder(decoder);
+///
+/// let mut engine = Engine::new();
+/// engine.process(source);
+/// let account = engine.database().get_account(1).unwrap();
+/// assert_eq!(account.available().to_string(), "107.5000");
+/// assert!(engine.errors()[0].contains("interest"));
+/// ```
+pub trait TransactionDecoder: Send + Sync {
+    /// Decode a row of type `transaction_type`, lower-cased, with the given amount
+    ///
+    /// Returns `None` if this decoder doesn't recognise the type either.
+    fn decode(
+        &self,
+        transaction_type: &str,
+        amount: Option<&RecordAmount>,
+    ) -> Option<Result<Transaction, EngineError>>;
+}
+
+impl<F> TransactionDecoder for F
+where
+    F: Fn(&str, Option<&RecordAmount>) -> Option<Result<Transaction, EngineError>> + Send + Sync,
+{
+    fn decode(
+        &self,
+        transaction_type: &str,
+        amount: Option<&RecordAmount>,
+    ) -> Option<Result<Transaction, EngineError>> {
+        self(transaction_type, amount)
+    }
+}
+
+/// What a [`RecordHook`] does with a row
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordAction {
+    /// Decode the row, with any changes the hook made
+    Keep,
+    /// Skip the row, reporting the reason as a warning
+    Skip(String),
+}
+
+/// Inspects, and may rewrite or skip, each raw CSV row before it is decoded
+///
+/// Hooks handle small feed quirks, such as renamed types or remapped client IDs,
+/// without a separate pre-processing step. Closures taking a mutable
+/// [`TransactionRecord`] implement this trait.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{CsvSource, Engine, RecordAction, TransactionRecord};
+/// let input = "type,client,tx,amount\nDEP,1,1,10\ntest,1,2,5\ndeposit,1,3,1\n";
+/// let source = CsvSource::new("input", input.as_bytes()).with_record_hook(
+///     |record: &mut TransactionRecord| match record.transaction_type.as_str() {
+///         "test" => RecordAction::Skip("test row".to_string()),
+///         "DEP" => {
+///             record.transaction_type = "deposit".to_string();
+///             RecordAction::Keep
+///         }
+///         _ => RecordAction::Keep,
+///     },
+/// );
+///
+/// let mut engine = Engine::new();
+/// let stats = engine.process(source);
+/// assert_eq!((stats.applied, stats.warnings), (2, 1));
+/// assert_eq!(engine.warnings()[0], "Warning at input:3: Row skipped: test row");
+/// ```
+pub trait RecordHook: Send + Sync {
+    /// Inspect or rewrite `record`, and say whether to decode it
+    fn preprocess(&self, record: &mut TransactionRecord) -> RecordAction;
+}
+
+impl<F> RecordHook for F
+where
+    F: Fn(&mut TransactionRecord) -> RecordAction + Send + Sync,
+{
+    fn preprocess(&self, record: &mut TransactionRecord) -> RecordAction {
+        self(record)
+    }
+}
+
+/// A [`RecordHook`] that renames transaction types and client IDs
+///
+/// Covers the most common feed quirks from configuration alone, e.g. the CLI's
+/// `--type-alias` and `--client-map` options. Type aliases match case-insensitively.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{CsvSource, Engine, RecordRewrite};
+/// let rewrite = RecordRewrite::new()
+///     .with_type_alias("credit", "deposit")
+///     .with_client(1001, 1);
+/// let input = "type,client,tx,amount\nCredit,1001,1,10\n";
+///
+/// let mut engine = Engine::new();
+/// engine.process(CsvSource::new("input", input.as_bytes()).with_record_hook(rewrite));
+/// let account = engine.database().get_account(1).unwrap();
+/// assert_eq!(account.available().to_string(), "10.0000");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecordRewrite {
+    types: HashMap<String, String>,
+    clients: HashMap<u16, u16>,
+}
+
+impl RecordRewrite {
+    /// Rename nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read rows of type `alias` as `transaction_type`
+    pub fn with_type_alias(mut self, alias: &str, transaction_type: &str) -> Self {
+        self.types
+            .insert(alias.to_lowercase(), transaction_type.to_string());
+        self
+    }
+
+    /// Read rows for client `from` as client `to`
+    pub fn with_client(mut self, from: u16, to: u16) -> Self {
+        self.clients.insert(from, to);
+        self
+    }
+}
+
+impl RecordHook for RecordRewrite {
+    fn preprocess(&self, record: &mut TransactionRecord) -> RecordAction {
+        if let Some(transaction_type) = self.types.get(&record.transaction_type.to_lowercase()) {
+            record.transaction_type = transaction_type.clone();
+        }
+        if let Some(&client) = self.clients.get(&record.client) {
+            record.client = client;
+        }
+        RecordAction::Keep
+    }
+}
@@ -0,0 +1,143 @@
+//! Rejected rows in machine-readable form, for reconciliation jobs
+//!
+//! [`Engine::errors`](crate::Engine::errors) holds each rejection as a message for
+//! people to read. An engine built [`with_rejections`](crate::Engine::with_rejections)
+//! also keeps each one as a [`Rejection`]: where the row was, its error code and the
+//! fields it was read with. [`write_rejections_json`] writes them out one JSON object
+//! per line, so a downstream job can fix and resubmit failed rows without scraping
+//! stderr.
+
+use crate::fixed4::Fixed4;
+use crate::source::SourceError;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+
+/// A row that was rejected, and why
+///
+/// Serializes as
+/// `{"location":..,"line":..,"code":..,"message":..,"fields":{"type":..,"client":..,"tx":..,"amount":..}}`,
+/// with `null` for anything not known.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{CsvSource, Engine};
+/// let input = "type,client,tx,amount\n\
+///              deposit,1,1,10\n\
+///              withdrawal,1,2,50\n";
+///
+/// let mut engine = Engine::new().with_rejections();
+/// engine.process(CsvSource::new("input", input.as_bytes()));
+///
+/// let rejection = &engine.rejections()[0];
+/// assert_eq!(rejection.line, Some(3));
+/// assert_eq!(rejection.code, "INSUFFICIENT_FUNDS");
+/// assert_eq!(rejection.fields.transaction_type.as_deref(), Some("withdrawal"));
+/// assert_eq!(rejection.fields.amount.unwrap().to_string(), "50.0000");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rejection {
+    /// Where the row was found, e.g. `input.csv:7`
+    pub location: String,
+    /// Line of the input the row was on, if its location ends in one
+    pub line: Option<u64>,
+    /// Name of the [`ErrorCode`](crate::ErrorCode) it was rejected with, or `MALFORMED`
+    /// if it could not be decoded at all
+    pub code: String,
+    /// What was wrong, as in [`Engine::errors`](crate::Engine::errors) but without the
+    /// location
+    pub message: String,
+    /// The row's fields, as far as they were read
+    pub fields: RejectedFields,
+}
+
+/// The fields of a rejected row
+///
+/// A row that decoded into a transaction has all of them, apart from an amount for
+/// types without one. A row whose fields didn't make a valid transaction has its
+/// client and transaction ID, and a row that couldn't be decoded at all has none.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RejectedFields {
+    /// Transaction type, as written in the `type` column
+    #[serde(rename = "type")]
+    pub transaction_type: Option<String>,
+    /// Client the row is for
+    pub client: Option<u16>,
+    /// Transaction ID
+    pub tx: Option<u32>,
+    /// Amount given on the row
+    pub amount: Option<Fixed4>,
+}
+
+impl Rejection {
+    /// The rejection reported as `error`, with the row's `fields` if they are known
+    /// beyond what the error records
+    pub(crate) fn new(error: &SourceError, fields: Option<RejectedFields>) -> Self {
+        let (location, code, message, recorded) = match error {
+            SourceError::Malformed { location, message } => (
+                location,
+                "MALFORMED",
+                message.clone(),
+                RejectedFields::default(),
+            ),
+            SourceError::Invalid { location, error } => (
+                location,
+                error.code().name,
+                error.to_string(),
+                RejectedFields {
+                    client: Some(error.client),
+                    tx: Some(error.tx),
+                    ..RejectedFields::default()
+                },
+            ),
+            SourceError::Skipped { location, warning } => (
+                location,
+                "SKIPPED",
+                warning.to_string(),
+                RejectedFields::default(),
+            ),
+        };
+        Self {
+            location: location.clone(),
+            line: location
+                .rsplit_once(':')
+                .and_then(|(_, line)| line.parse().ok()),
+            code: code.to_string(),
+            message,
+            fields: fields.unwrap_or(recorded),
+        }
+    }
+}
+
+/// Write `rejections` as JSON Lines: one [`Rejection`] object per line, in order
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{CsvSource, Engine, write_rejections_json};
+/// let input = "type,client,tx,amount\n\
+///              deposit,1,1,10\n\
+///              withdrawal,1,2,50\n\
+///              deposit,x,3,1\n";
+///
+/// let mut engine = Engine::new().with_rejections();
+/// engine.process(CsvSource::new("input", input.as_bytes()));
+///
+/// let mut output = Vec::new();
+/// write_rejections_json(engine.rejections(), &mut output).unwrap();
+/// let lines: Vec<&str> = std::str::from_utf8(&output).unwrap().lines().collect();
+/// assert_eq!(lines.len(), 2);
+/// assert!(lines[0].starts_with(r#"{"location":"input:3","line":3,"code":"INSUFFICIENT_FUNDS""#));
+/// assert!(lines[0].ends_with(
+///     r#""fields":{"type":"withdrawal","client":1,"tx":2,"amount":"50.0000"}}"#
+/// ));
+/// assert!(lines[1].contains(r#""code":"MALFORMED""#));
+/// ```
+///
+/// # Errors
+/// Returns an error if writing to `writer` fails.
+pub fn write_rejections_json<W: Write>(rejections: &[Rejection], mut writer: W) -> io::Result<()> {
+    for rejection in rejections {
+        serde_json::to_writer(&mut writer, rejection)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
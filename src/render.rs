@@ -0,0 +1,198 @@
+//! Formatted client statements
+//!
+//! Turns a [`Statement`] into a document that can be sent to a client: an HTML page
+//! with the account balances and one row per change to them, from its
+//! [balance history](Statement::history), with the available and held balances after
+//! it. Dispute holds, resolves and chargebacks get rows of their own, so the running
+//! balances always add up to the totals. A transaction's row also shows its dispute
+//! status. With the `pdf` feature the same statement can also be rendered as a PDF.
+
+use crate::db::{Activity, BalanceRow, DisputeState, Statement, StatementLine};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Table cells for each balance change: tx, activity, date, amount, status, available
+/// and held
+fn rows(statement: &Statement) -> Vec<[String; 7]> {
+    let lines: HashMap<u32, &StatementLine> =
+        statement.lines.iter().map(|line| (line.tx, line)).collect();
+    statement
+        .history
+        .iter()
+        .map(|row| {
+            let line = lines.get(&row.tx).filter(|_| refers_to_entry(row));
+            [
+                row.tx.to_string(),
+                row.activity.to_string(),
+                date(row),
+                line.map(|line| line.amount.to_string()).unwrap_or_default(),
+                line.filter(|_| applies_entry(row))
+                    .map(|line| status(line))
+                    .unwrap_or_default(),
+                row.available.to_string(),
+                row.held.to_string(),
+            ]
+        })
+        .collect()
+}
+
+/// Whether `row` is about the ledger entry with its transaction ID, rather than an
+/// account-wide change such as a merge
+fn refers_to_entry(row: &BalanceRow) -> bool {
+    !matches!(
+        row.activity,
+        Activity::ReserveRelease | Activity::Merge | Activity::Split
+    )
+}
+
+/// Whether `row` is the transaction itself being applied, rather than a later step of
+/// its dispute
+fn applies_entry(row: &BalanceRow) -> bool {
+    matches!(
+        row.activity,
+        Activity::Deposit | Activity::Withdrawal | Activity::Refund
+    )
+}
+
+/// Dispute status text for a line; withdrawals have none
+fn status(line: &StatementLine) -> String {
+    match line.dispute_state {
+        Some(DisputeState::Normal) | None => String::new(),
+        Some(state) => state.to_string(),
+    }
+}
+
+/// Timestamp text for a row, or `-` when unknown
+fn date(row: &BalanceRow) -> String {
+    row.timestamp
+        .map_or_else(|| "-".to_string(), |timestamp| timestamp.to_string())
+}
+
+/// Render a statement as a standalone HTML document
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Database, Transaction, statement_html};
+/// let mut db = Database::new();
+/// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+/// db.process_transaction(1, 2, Transaction::withdrawal("40").unwrap()).unwrap();
+/// db.process_transaction(1, 1, Transaction::dispute()).unwrap();
+///
+/// let html = statement_html(&db.statement(1).unwrap());
+/// assert!(html.contains("<h1>Statement for client 1</h1>"));
+/// assert!(html.contains("<td>1</td><td>deposit</td><td>-</td><td>100.0000</td><td>disputed</td>"));
+/// assert!(html.contains("<td>2</td><td>withdrawal</td><td>-</td><td>40.0000</td><td></td><td>60.0000</td><td>0.0000</td>"));
+/// // The hold moves the running balances too
+/// assert!(html.contains("<td>1</td><td>dispute</td><td>-</td><td>100.0000</td><td></td><td>-40.0000</td><td>100.0000</td>"));
+/// ```
+pub fn statement_html(statement: &Statement) -> String {
+    let mut html = String::new();
+    // Writing to a String cannot fail
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n\
+         <html>\n\
+         <head><meta charset=\"utf-8\"><title>Statement for client {client}</title></head>\n\
+         <body>\n\
+         <h1>Statement for client {client}</h1>\n\
+         <table class=\"balances\">\n\
+         <tr><th>Available</th><td>{available}</td></tr>\n\
+         <tr><th>Held</th><td>{held}</td></tr>\n\
+         <tr><th>Total</th><td>{total}</td></tr>\n\
+         <tr><th>State</th><td>{state}</td></tr>\n\
+         </table>\n\
+         <table class=\"transactions\">\n\
+         <tr><th>Tx</th><th>Activity</th><th>Date</th><th>Amount</th><th>Status</th><th>Available</th><th>Held</th></tr>\n",
+        client = statement.client,
+        available = statement.available,
+        held = statement.held,
+        total = statement.total,
+        state = statement.state,
+    );
+    for cells in rows(statement) {
+        html.push_str("<tr>");
+        for cell in cells {
+            let _ = write!(html, "<td>{}</td>", cell);
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+/// Render a statement as a PDF document
+///
+/// Uses the built-in Helvetica font, so no font files are needed. Long statements
+/// continue onto further A4 pages.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Database, Transaction, statement_pdf};
+/// let mut db = Database::new();
+/// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+///
+/// let pdf = statement_pdf(&db.statement(1).unwrap()).unwrap();
+/// assert!(pdf.starts_with(b"%PDF"));
+/// ```
+///
+/// # Errors
+/// Returns an error if the PDF document cannot be written.
+#[cfg(feature = "pdf")]
+pub fn statement_pdf(statement: &Statement) -> Result<Vec<u8>, printpdf::Error> {
+    use printpdf::{BuiltinFont, Mm, PdfDocument};
+
+    const PAGE_WIDTH: f32 = 210.0;
+    const PAGE_HEIGHT: f32 = 297.0;
+    const MARGIN: f32 = 20.0;
+    const LINE_HEIGHT: f32 = 6.0;
+    const COLUMNS: [f32; 7] = [20.0, 32.0, 62.0, 87.0, 112.0, 140.0, 168.0];
+
+    let title = format!("Statement for client {}", statement.client);
+    let (doc, page, layer) = PdfDocument::new(&title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica)?;
+    let bold = doc.add_builtin_font(BuiltinFont::HelveticaBold)?;
+    let mut layer = doc.get_page(page).get_layer(layer);
+    let mut y = PAGE_HEIGHT - MARGIN;
+
+    layer.use_text(&title, 16.0, Mm(MARGIN), Mm(y), &bold);
+    y -= 2.0 * LINE_HEIGHT;
+    for (label, value) in [
+        ("Available", statement.available.to_string()),
+        ("Held", statement.held.to_string()),
+        ("Total", statement.total.to_string()),
+        ("State", statement.state.to_string()),
+    ] {
+        layer.use_text(label, 10.0, Mm(MARGIN), Mm(y), &bold);
+        layer.use_text(value, 10.0, Mm(MARGIN + 30.0), Mm(y), &font);
+        y -= LINE_HEIGHT;
+    }
+    y -= LINE_HEIGHT;
+
+    let headings = [
+        "Tx",
+        "Activity",
+        "Date",
+        "Amount",
+        "Status",
+        "Available",
+        "Held",
+    ];
+    for (heading, x) in headings.iter().zip(COLUMNS) {
+        layer.use_text(*heading, 10.0, Mm(x), Mm(y), &bold);
+    }
+    y -= LINE_HEIGHT;
+
+    for cells in rows(statement) {
+        if y < MARGIN {
+            let (page, next) = doc.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+            layer = doc.get_page(page).get_layer(next);
+            y = PAGE_HEIGHT - MARGIN;
+        }
+        for (cell, x) in cells.iter().zip(COLUMNS) {
+            layer.use_text(cell.as_str(), 10.0, Mm(x), Mm(y), &font);
+        }
+        y -= LINE_HEIGHT;
+    }
+
+    doc.save_to_bytes()
+}
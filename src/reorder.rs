@@ -0,0 +1,117 @@
+//! Putting each client's transactions back in transaction ID order
+//!
+//! Some feeds assign transaction IDs in order but deliver rows out of order, e.g. when
+//! merged from several upstream systems. A dispute can then arrive before the deposit
+//! it disputes and be rejected, although both are in the input. [`Reordered`] holds a
+//! bounded window of rows and releases each client's rows in transaction ID order, so
+//! rows that arrive up to the window's size too early are applied in the right place.
+//!
+//! A dispute, resolve or chargeback has the ID of the transaction it refers to, so rows
+//! with the same ID are released in lifecycle order: the transaction itself, then its
+//! dispute, then the resolve or chargeback, then any chargeback reversal. Rows of
+//! different clients are never held back for each other beyond the window.
+
+use crate::db::DisputeAction;
+use crate::source::{SourceError, SourcedTransaction, TransactionSource};
+use std::collections::{BTreeMap, HashMap};
+
+/// Order of a row among the rows with its client and transaction ID
+type Key = (u32, u8, u64);
+
+/// A source yielding another source's rows in transaction ID order per client, within
+/// a bounded window
+///
+/// Up to `window` rows are held back. Once more arrive, a row of the client whose row
+/// has been held longest is released: the one with that client's lowest transaction
+/// ID, which may have arrived later. Rows that fail to decode are passed on as soon as
+/// they are read. A window of 0 passes every row on as it comes.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{CsvSource, Engine, Reordered};
+/// // The dispute and the withdrawal arrive before the deposit they depend on
+/// let input = "type,client,tx,amount\n\
+///              dispute,1,1,\n\
+///              withdrawal,1,2,30\n\
+///              deposit,2,3,5\n\
+///              deposit,1,1,100\n";
+///
+/// let mut engine = Engine::new();
+/// let stats = engine.process(Reordered::new(CsvSource::new("input", input.as_bytes()), 10));
+/// assert_eq!((stats.applied, stats.rejected), (3, 1));
+/// let account = engine.database().get_account(1).unwrap();
+/// // Applied as deposit, dispute, withdrawal: with 100 held, the withdrawal fails
+/// assert_eq!(account.held().to_string(), "100.0000");
+/// assert!(engine.errors()[0].contains("input:3"));
+/// ```
+pub struct Reordered<S> {
+    source: S,
+    window: usize,
+    /// Rows held back, by client
+    held: HashMap<u16, BTreeMap<Key, SourcedTransaction>>,
+    /// Client of each row held back, by arrival
+    arrivals: BTreeMap<u64, u16>,
+    /// Arrival number of the next row read
+    next_arrival: u64,
+    /// Whether the source is exhausted
+    done: bool,
+}
+
+impl<S: TransactionSource> Reordered<S> {
+    /// Yield the rows of `source`, holding back up to `window` of them to put each
+    /// client's rows in transaction ID order
+    pub fn new(source: S, window: usize) -> Self {
+        Self {
+            source,
+            window,
+            held: HashMap::new(),
+            arrivals: BTreeMap::new(),
+            next_arrival: 0,
+            done: false,
+        }
+    }
+
+    /// Release the lowest row of the client whose row has been held longest
+    fn release(&mut self) -> Option<SourcedTransaction> {
+        let (_, &client) = self.arrivals.first_key_value()?;
+        let rows = self.held.get_mut(&client)?;
+        let ((_, _, arrival), row) = rows.pop_first()?;
+        if rows.is_empty() {
+            self.held.remove(&client);
+        }
+        self.arrivals.remove(&arrival);
+        Some(row)
+    }
+}
+
+impl<S: TransactionSource> TransactionSource for Reordered<S> {
+    fn next_transaction(&mut self) -> Option<Result<SourcedTransaction, SourceError>> {
+        while !self.done && self.arrivals.len() <= self.window {
+            let row = match self.source.next_transaction() {
+                None => {
+                    self.done = true;
+                    break;
+                }
+                Some(Err(err)) => return Some(Err(err)),
+                Some(Ok(row)) => row,
+            };
+            if self.window == 0 {
+                return Some(Ok(row));
+            }
+            let arrival = self.next_arrival;
+            self.next_arrival += 1;
+            let stage = match row.transaction.dispute_action() {
+                None => 0,
+                Some(DisputeAction::Dispute) => 1,
+                Some(DisputeAction::Resolve | DisputeAction::Chargeback) => 2,
+                Some(DisputeAction::ChargebackReversal) => 3,
+            };
+            self.arrivals.insert(arrival, row.client);
+            self.held
+                .entry(row.client)
+                .or_default()
+                .insert((row.tx, stage, arrival), row);
+        }
+        self.release().map(Ok)
+    }
+}
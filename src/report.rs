@@ -0,0 +1,212 @@
+//! Selecting and ordering accounts for summary reports
+//!
+//! A [`SummaryQuery`] describes which accounts a report lists and in what order.
+//! [`Database::summaries`](crate::Database::summaries) applies it at the source, so the
+//! report writers in [`export`](crate::export) only ever see the rows they will output.
+//! [`SegmentSummary`] aggregates accounts by a metadata label instead.
+
+use crate::db::Account;
+use crate::fixed4::Fixed4;
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+/// Value that summary rows can be sorted by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum SortKey {
+    /// Client ID
+    #[default]
+    Client,
+    /// Funds available for withdrawal
+    Available,
+    /// Funds held
+    Held,
+    /// Available plus held funds
+    Total,
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "client" => Ok(SortKey::Client),
+            "available" => Ok(SortKey::Available),
+            "held" => Ok(SortKey::Held),
+            "total" => Ok(SortKey::Total),
+            _ => Err(format!("Unknown sort key: {}", s)),
+        }
+    }
+}
+
+/// Sort order for summary rows
+///
+/// Parses from `KEY`, `KEY:asc` or `KEY:desc`, where `KEY` is one of `client`,
+/// `available`, `held` or `total`. Rows that compare equal are listed in ascending
+/// client order. The default is ascending by client.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{SortKey, SummarySort};
+/// let sort: SummarySort = "total:desc".parse().unwrap();
+/// assert_eq!(sort, SummarySort { key: SortKey::Total, descending: true });
+///
+/// let sort: SummarySort = "held".parse().unwrap();
+/// assert!(!sort.descending);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SummarySort {
+    /// Value to sort by
+    pub key: SortKey,
+    /// Largest values first
+    pub descending: bool,
+}
+
+impl SummarySort {
+    /// Compare two summary rows according to this order
+    pub(crate) fn compare(&self, a: (u16, &Account), b: (u16, &Account)) -> Ordering {
+        let ordering = match self.key {
+            SortKey::Client => a.0.cmp(&b.0),
+            SortKey::Available => a.1.available().cmp(&b.1.available()),
+            SortKey::Held => a.1.held().cmp(&b.1.held()),
+            SortKey::Total => a.1.total().cmp(&b.1.total()),
+        };
+        let ordering = if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        };
+        // Ties always fall back to ascending client ID
+        ordering.then(a.0.cmp(&b.0))
+    }
+}
+
+impl FromStr for SummarySort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, direction) = s.split_once(':').unwrap_or((s, "asc"));
+        let descending = match direction {
+            "asc" => false,
+            "desc" => true,
+            _ => return Err(format!("Unknown sort direction: {}", direction)),
+        };
+        Ok(Self {
+            key: key.parse()?,
+            descending,
+        })
+    }
+}
+
+/// Which accounts a summary report lists, and in what order
+///
+/// Filters are applied first, then the rows are sorted, then truncated to
+/// [`limit`](SummaryQuery::limit). The default lists every account by client ID.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Database, SummaryQuery, Transaction};
+/// let mut db = Database::new();
+/// for (client, amount) in [(1, "500"), (2, "2000"), (3, "1500"), (4, "3000")] {
+///     db.process_transaction(client, client.into(), Transaction::deposit(amount).unwrap())
+///         .unwrap();
+/// }
+/// db.process_transaction(4, 4, Transaction::dispute()).unwrap();
+///
+/// // The two largest accounts holding at least 1000
+/// let query = SummaryQuery {
+///     sort: "total:desc".parse().unwrap(),
+///     min_total: Some("1000".parse().unwrap()),
+///     limit: Some(2),
+///     ..SummaryQuery::default()
+/// };
+/// let clients: Vec<u16> = db.summaries(&query).iter().map(|(id, _)| *id).collect();
+/// assert_eq!(clients, vec![4, 2]);
+///
+/// let query = SummaryQuery {
+///     only_disputed: true,
+///     ..SummaryQuery::default()
+/// };
+/// assert_eq!(db.summaries(&query).len(), 1);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SummaryQuery {
+    /// Order of the rows
+    pub sort: SummarySort,
+    /// Only accounts whose total is at least this amount
+    pub min_total: Option<Fixed4>,
+    /// Only locked accounts
+    pub only_locked: bool,
+    /// Only accounts with at least one deposit under dispute
+    pub only_disputed: bool,
+    /// At most this many rows, taken from the top of the sorted list
+    pub limit: Option<usize>,
+}
+
+impl SummaryQuery {
+    /// Whether an account passes every filter
+    pub(crate) fn matches(&self, account: &Account) -> bool {
+        self.min_total.is_none_or(|min| account.total() >= min)
+            && (!self.only_locked || account.is_locked())
+            && (!self.only_disputed || account.open_disputes() > 0)
+    }
+}
+
+/// Aggregates over the accounts sharing a metadata label, such as a segment or tier
+///
+/// Produced by [`Database::segment_summaries`](crate::Database::segment_summaries).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentSummary {
+    /// Value of the label, or `None` for the accounts without it
+    pub segment: Option<String>,
+    /// Number of accounts
+    pub accounts: usize,
+    /// Funds available for withdrawal
+    pub available: Fixed4,
+    /// Funds held
+    pub held: Fixed4,
+    /// Available plus held funds
+    pub total: Fixed4,
+    /// Number of locked accounts
+    pub locked: usize,
+    /// Number of deposits
+    pub deposits: usize,
+    /// Number of deposits that have been charged back
+    pub chargebacks: usize,
+}
+
+impl SegmentSummary {
+    /// An empty group labelled `segment`
+    pub(crate) fn new(segment: Option<String>) -> Self {
+        Self {
+            segment,
+            accounts: 0,
+            available: Fixed4::zero(),
+            held: Fixed4::zero(),
+            total: Fixed4::zero(),
+            locked: 0,
+            deposits: 0,
+            chargebacks: 0,
+        }
+    }
+
+    /// Add an account to the group
+    pub(crate) fn add(&mut self, account: &Account) {
+        let (deposits, chargebacks) = account.chargeback_counts();
+        self.accounts += 1;
+        // Saturate rather than panic, should the accounts together exceed what Fixed4 holds
+        self.available = self.available.saturating_add(account.available());
+        self.held = self.held.saturating_add(account.held());
+        self.total = self.total.saturating_add(account.total());
+        self.locked += usize::from(account.is_locked());
+        self.deposits += deposits;
+        self.chargebacks += chargebacks;
+    }
+
+    /// Share of deposits that were charged back, between `0.0` and `1.0`
+    pub fn chargeback_rate(&self) -> f64 {
+        if self.deposits == 0 {
+            return 0.0;
+        }
+        self.chargebacks as f64 / self.deposits as f64
+    }
+}
@@ -0,0 +1,163 @@
+//! Spreading clients over parallel workers without breaking per-client ordering
+//!
+//! Accounts are independent of each other, so a pipeline can apply transactions on
+//! several threads at once, as long as every transaction of a client goes to the same
+//! worker, in the order it was read. A [`Router`] maps each client to one of N shards
+//! with a stable hash, and a [`ShardPool`] runs one worker thread per shard, fed
+//! through a bounded channel.
+//!
+//! The hash depends only on the client ID and the number of shards, never on the
+//! process or the Rust version, so shard assignments can be relied on across restarts
+//! and between services.
+
+use std::panic;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, SendError, SyncSender};
+use std::thread::{self, JoinHandle};
+
+/// Maps client IDs to a fixed number of shards
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::Router;
+/// let router = Router::new(4);
+/// assert_eq!(router.shards(), 4);
+/// assert!(router.shard(17) < 4);
+/// // Always the same shard for the same client
+/// assert_eq!(router.shard(17), Router::new(4).shard(17));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Router {
+    shards: usize,
+}
+
+impl Router {
+    /// A router over `shards` shards
+    ///
+    /// # Panics
+    /// Panics if `shards` is zero.
+    pub fn new(shards: usize) -> Self {
+        assert!(shards > 0, "a router needs at least one shard");
+        Self { shards }
+    }
+
+    /// Number of shards
+    pub fn shards(&self) -> usize {
+        self.shards
+    }
+
+    /// Shard that `client` belongs to, below [`shards`](Self::shards)
+    pub fn shard(&self, client: u16) -> usize {
+        // Fibonacci hashing spreads consecutive IDs evenly over the shards
+        let hash = u64::from(client).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32;
+        (hash % self.shards as u64) as usize
+    }
+
+    /// Start one worker thread per shard
+    ///
+    /// Each worker is called with its shard number and the receiving end of its
+    /// channel, and runs until the channel is closed by [`ShardPool::join`]. Channels
+    /// hold up to `capacity` messages, after which sending blocks until the worker
+    /// catches up.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{Database, Router, Transaction};
+    /// let pool = Router::new(3).spawn(16, |_shard, rows| {
+    ///     let mut db = Database::new();
+    ///     for (client, tx, transaction) in rows {
+    ///         let _ = db.process_transaction(client, tx, transaction);
+    ///     }
+    ///     db
+    /// });
+    ///
+    /// for client in 1..=10 {
+    ///     let tx = u32::from(client);
+    ///     pool.send(client, (client, tx, Transaction::deposit("10").unwrap())).unwrap();
+    ///     // Always after the deposit, since both go to the same worker
+    ///     pool.send(client, (client, tx, Transaction::dispute())).unwrap();
+    /// }
+    ///
+    /// let databases = pool.join();
+    /// assert_eq!(databases.len(), 3);
+    /// let clients: usize = databases.iter().map(|db| db.get_all_client_ids().len()).sum();
+    /// assert_eq!(clients, 10);
+    /// for db in &databases {
+    ///     for client in db.get_all_client_ids() {
+    ///         assert_eq!(db.get_account(client).unwrap().held().to_string(), "10.0000");
+    ///     }
+    /// }
+    /// ```
+    pub fn spawn<M, T, F>(&self, capacity: usize, worker: F) -> ShardPool<M, T>
+    where
+        M: Send + 'static,
+        T: Send + 'static,
+        F: Fn(usize, Receiver<M>) -> T + Send + Sync + 'static,
+    {
+        let worker = Arc::new(worker);
+        let (senders, handles) = (0..self.shards)
+            .map(|shard| {
+                let (sender, receiver) = mpsc::sync_channel(capacity);
+                let worker = Arc::clone(&worker);
+                let handle = thread::spawn(move || worker(shard, receiver));
+                (sender, handle)
+            })
+            .unzip();
+        ShardPool {
+            router: *self,
+            senders,
+            handles,
+        }
+    }
+}
+
+/// Worker threads started by [`Router::spawn`], one per shard
+pub struct ShardPool<M, T> {
+    router: Router,
+    senders: Vec<SyncSender<M>>,
+    handles: Vec<JoinHandle<T>>,
+}
+
+impl<M, T> ShardPool<M, T> {
+    /// The router assigning clients to workers
+    pub fn router(&self) -> Router {
+        self.router
+    }
+
+    /// Send `message` to the worker for `client`
+    ///
+    /// Messages for the same client are received in the order they were sent. Blocks
+    /// while the worker's channel is full.
+    ///
+    /// # Errors
+    /// Returns the message back if the worker has stopped, e.g. because it panicked.
+    pub fn send(&self, client: u16, message: M) -> Result<(), SendError<M>> {
+        self.senders[self.router.shard(client)].send(message)
+    }
+
+    /// Close every channel and wait for the workers to finish
+    ///
+    /// Returns each worker's result, in shard order.
+    ///
+    /// # Panics
+    /// Panics with the same payload if a worker panicked.
+    pub fn join(self) -> Vec<T> {
+        drop(self.senders);
+        self.handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|payload| panic::resume_unwind(payload))
+            })
+            .collect()
+    }
+}
+
+impl<M, T> std::fmt::Debug for ShardPool<M, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShardPool")
+            .field("shards", &self.router.shards)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,140 @@
+//! Processing a sample of an input, for a quick look at a huge file
+//!
+//! Sampling the first rows of a file takes seconds however large it is, and shows
+//! whether it decodes and what kind of accounts it produces. A random sample is more
+//! representative but reads the whole input, applying only the rows drawn. Either way
+//! rows are applied in their original order, so a dispute whose deposit wasn't sampled
+//! is rejected like any other dispute of an unknown transaction.
+
+use crate::source::{SourceError, SourcedTransaction, TransactionSource};
+use std::collections::VecDeque;
+
+/// Which rows of an input to sample
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sample {
+    /// The first rows, up to this many
+    First(usize),
+    /// This many rows drawn uniformly at random, using `seed` so a sample can be
+    /// repeated
+    Random {
+        /// Number of rows to draw
+        rows: usize,
+        /// Seed for the random draw
+        seed: u64,
+    },
+}
+
+/// A source yielding only a [`Sample`] of another source's rows
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{CsvSource, Engine, Sample, Sampled, TransactionSource};
+/// let input = "type,client,tx,amount\n\
+///              deposit,1,1,1\n\
+///              deposit,2,2,1\n\
+///              deposit,3,3,1\n\
+///              deposit,4,4,1\n";
+///
+/// let mut engine = Engine::new();
+/// let source = CsvSource::new("input", input.as_bytes());
+/// let stats = engine.process(Sampled::new(source, Sample::First(2)));
+/// assert_eq!(stats.rows, 2);
+/// assert_eq!(engine.database().get_all_client_ids().len(), 2);
+///
+/// // A random sample keeps the rows in their original order
+/// let source = CsvSource::new("input", input.as_bytes());
+/// let mut sampled = Sampled::new(source, Sample::Random { rows: 3, seed: 7 });
+/// let mut clients = Vec::new();
+/// while let Some(Ok(row)) = sampled.next_transaction() {
+///     clients.push(row.client);
+/// }
+/// assert_eq!(clients.len(), 3);
+/// assert!(clients.is_sorted());
+/// ```
+pub struct Sampled<S> {
+    source: S,
+    sample: Sample,
+    /// Rows left to yield from the source, for [`Sample::First`]
+    remaining: usize,
+    /// The rows drawn, once a [`Sample::Random`] has read the whole source
+    drawn: Option<VecDeque<Result<SourcedTransaction, SourceError>>>,
+}
+
+impl<S: TransactionSource> Sampled<S> {
+    /// Yield only `sample` of the rows of `source`
+    pub fn new(source: S, sample: Sample) -> Self {
+        let remaining = match sample {
+            Sample::First(rows) => rows,
+            Sample::Random { .. } => 0,
+        };
+        Self {
+            source,
+            sample,
+            remaining,
+            drawn: None,
+        }
+    }
+
+    /// Draw `rows` of the source's rows by reservoir sampling, in their original order
+    fn draw(
+        &mut self,
+        rows: usize,
+        seed: u64,
+    ) -> VecDeque<Result<SourcedTransaction, SourceError>> {
+        let mut random = SplitMix64(seed);
+        let mut reservoir = Vec::with_capacity(rows);
+        let mut seen = 0u64;
+        while let Some(row) = self.source.next_transaction() {
+            seen += 1;
+            if reservoir.len() < rows {
+                reservoir.push((seen, row));
+            } else {
+                let slot = random.below(seen);
+                if slot < rows as u64 {
+                    reservoir[slot as usize] = (seen, row);
+                }
+            }
+        }
+        reservoir.sort_by_key(|(position, _)| *position);
+        reservoir.into_iter().map(|(_, row)| row).collect()
+    }
+}
+
+impl<S: TransactionSource> TransactionSource for Sampled<S> {
+    fn next_transaction(&mut self) -> Option<Result<SourcedTransaction, SourceError>> {
+        match self.sample {
+            Sample::First(_) => {
+                if self.remaining == 0 {
+                    return None;
+                }
+                self.remaining -= 1;
+                self.source.next_transaction()
+            }
+            Sample::Random { rows, seed } => {
+                if self.drawn.is_none() {
+                    self.drawn = Some(self.draw(rows, seed));
+                }
+                self.drawn.as_mut()?.pop_front()
+            }
+        }
+    }
+}
+
+/// The splitmix64 generator, enough for drawing sample rows
+pub(crate) struct SplitMix64(pub(crate) u64);
+
+impl SplitMix64 {
+    /// The next number, uniformly drawn from every `u64`
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number below `bound`, which must be non-zero
+    pub(crate) fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
@@ -0,0 +1,167 @@
+//! Scheduled report emission
+//!
+//! Long-running modes such as [`watch`](crate::watch) keep ingesting continuously; a
+//! [`ScheduledReports`] writes the account summary at fixed times of day in between
+//! batches, e.g. a nightly report at `02:00`.
+
+use crate::db::Timestamp;
+use crate::engine::Engine;
+use crate::export::{CsvSink, SummaryColumns, SummaryLayout};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Times of day (UTC) at which reports are due
+///
+/// Parses from a comma-separated list of `HH:MM` times.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::ReportSchedule;
+/// const DAY: u64 = 24 * 60 * 60;
+/// let schedule: ReportSchedule = "14:30, 02:00".parse().unwrap();
+///
+/// // 01:00 on day 10: next due at 02:00 the same day
+/// assert_eq!(schedule.next_after(10 * DAY + 3600), Some(10 * DAY + 2 * 3600));
+/// // 15:00: next due at 02:00 the following day
+/// assert_eq!(schedule.next_after(10 * DAY + 15 * 3600), Some(11 * DAY + 2 * 3600));
+///
+/// assert!("25:00".parse::<ReportSchedule>().is_err());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReportSchedule {
+    /// Seconds after midnight, ascending and without duplicates
+    times: Vec<u64>,
+}
+
+impl ReportSchedule {
+    /// The first due time strictly after `now`, or `None` if the schedule is empty
+    pub fn next_after(&self, now: Timestamp) -> Option<Timestamp> {
+        let midnight = now - now % SECONDS_PER_DAY;
+        let time_of_day = now % SECONDS_PER_DAY;
+        match self.times.iter().find(|&&time| time > time_of_day) {
+            Some(time) => Some(midnight + time),
+            None => self
+                .times
+                .first()
+                .map(|time| midnight + SECONDS_PER_DAY + time),
+        }
+    }
+}
+
+impl FromStr for ReportSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut times = Vec::new();
+        for time in s.split(',') {
+            let time = time.trim();
+            let (hours, minutes) = time
+                .split_once(':')
+                .ok_or_else(|| format!("Expected HH:MM, got {}", time))?;
+            let hours: u64 = hours
+                .parse()
+                .map_err(|_| format!("Invalid hour in {}", time))?;
+            let minutes: u64 = minutes
+                .parse()
+                .map_err(|_| format!("Invalid minute in {}", time))?;
+            if hours > 23 || minutes > 59 {
+                return Err(format!("Time of day out of range: {}", time));
+            }
+            times.push(hours * 3600 + minutes * 60);
+        }
+        times.sort();
+        times.dedup();
+        Ok(Self { times })
+    }
+}
+
+/// Writes the account summary into a directory whenever the schedule comes due
+///
+/// Reports are named `summary-<timestamp>.csv` after the time they were due.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Engine, ScheduledReports};
+/// let dir = tempfile::tempdir().unwrap();
+/// let mut reports = ScheduledReports::new("02:00".parse().unwrap(), dir.path(), 0);
+/// let engine = Engine::new();
+///
+/// // Not due yet at 01:00
+/// assert!(reports.poll(&engine, 3600).unwrap().is_none());
+/// // Due at 02:00
+/// let written = reports.poll(&engine, 7200).unwrap().unwrap();
+/// assert!(written.ends_with("summary-7200.csv"));
+/// // Not due again until the next day
+/// assert!(reports.poll(&engine, 7300).unwrap().is_none());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ScheduledReports {
+    schedule: ReportSchedule,
+    dir: PathBuf,
+    columns: SummaryColumns,
+    layout: SummaryLayout,
+    next_due: Option<Timestamp>,
+}
+
+impl ScheduledReports {
+    /// Write reports into `dir` on `schedule`, starting with the first time due after `now`
+    pub fn new(schedule: ReportSchedule, dir: impl Into<PathBuf>, now: Timestamp) -> Self {
+        Self {
+            next_due: schedule.next_after(now),
+            schedule,
+            dir: dir.into(),
+            columns: SummaryColumns::default(),
+            layout: SummaryLayout::default(),
+        }
+    }
+
+    /// Columns of the reports
+    pub fn with_columns(mut self, columns: SummaryColumns) -> Self {
+        self.columns = columns;
+        self
+    }
+
+    /// Layout of the reports
+    pub fn with_layout(mut self, layout: SummaryLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// When the next report is due, if the schedule has any times
+    pub fn next_due(&self) -> Option<Timestamp> {
+        self.next_due
+    }
+
+    /// Write a report if one is due at `now`, returning its path
+    ///
+    /// If several due times have passed since the last poll, a single report is written.
+    ///
+    /// # Errors
+    /// Returns an error if the report cannot be written.
+    pub fn poll(
+        &mut self,
+        engine: &Engine,
+        now: Timestamp,
+    ) -> Result<Option<PathBuf>, Box<dyn Error>> {
+        let Some(due) = self.next_due.filter(|&due| now >= due) else {
+            return Ok(None);
+        };
+
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(format!("summary-{}.csv", due));
+        let file = BufWriter::new(File::create(&path)?);
+        engine.report(
+            CsvSink::new(file)
+                .with_columns(self.columns.clone())
+                .with_layout(self.layout),
+        )?;
+
+        self.next_due = self.schedule.next_after(now);
+        Ok(Some(path))
+    }
+}
@@ -0,0 +1,208 @@
+//! Built-in end-to-end scenarios for validating a build (`csv` feature)
+//!
+//! [`SCENARIOS`] are small CSV inputs, embedded in the crate, each with the account
+//! summary and the number of rejected rows and warnings it must produce. [`selftest`]
+//! runs them all through the same engine, source and sink as the binary, so a
+//! deployment can be checked where no test suite or fixtures can be brought along;
+//! the binary runs them with `transaction_processor selftest`.
+
+use crate::csv_processor::CsvSource;
+use crate::engine::Engine;
+use crate::export::CsvSink;
+use std::panic::{self, AssertUnwindSafe};
+
+/// An input and what processing it with the default policy must produce
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scenario {
+    /// What the scenario checks
+    pub name: &'static str,
+    /// Input CSV
+    pub input: &'static str,
+    /// Account summary CSV, with the default columns, exactly as written
+    pub expected: &'static str,
+    /// Rows that must be rejected
+    pub rejected: usize,
+    /// Warnings that must be raised
+    pub warnings: usize,
+}
+
+/// The built-in scenarios: deposits, withdrawals, the dispute lifecycle and error cases
+pub const SCENARIOS: &[Scenario] = &[
+    Scenario {
+        name: "deposits and withdrawals",
+        input: "type,client,tx,amount\n\
+                deposit,1,1,10.5\n\
+                withdrawal,1,2,3.25\n\
+                deposit,2,3,1.0001\n",
+        expected: "client,available,held,total,locked,state\n\
+                   1,7.2500,0.0000,7.2500,false,active\n\
+                   2,1.0001,0.0000,1.0001,false,active\n",
+        rejected: 0,
+        warnings: 0,
+    },
+    Scenario {
+        name: "withdrawal beyond the available funds",
+        input: "type,client,tx,amount\n\
+                deposit,1,1,5\n\
+                withdrawal,1,2,10\n",
+        expected: "client,available,held,total,locked,state\n\
+                   1,5.0000,0.0000,5.0000,false,active\n",
+        rejected: 1,
+        warnings: 0,
+    },
+    Scenario {
+        name: "dispute holds funds, resolve releases them",
+        input: "type,client,tx,amount\n\
+                deposit,1,1,20\n\
+                deposit,1,2,5\n\
+                dispute,1,1,\n\
+                resolve,1,1,\n\
+                deposit,2,3,7\n\
+                dispute,2,3,\n",
+        expected: "client,available,held,total,locked,state\n\
+                   1,25.0000,0.0000,25.0000,false,active\n\
+                   2,0.0000,7.0000,7.0000,false,active\n",
+        rejected: 0,
+        warnings: 0,
+    },
+    Scenario {
+        name: "chargeback removes the funds and locks the account",
+        input: "type,client,tx,amount\n\
+                deposit,1,1,10\n\
+                deposit,1,2,5\n\
+                dispute,1,1,\n\
+                chargeback,1,1,\n\
+                deposit,1,3,3\n",
+        expected: "client,available,held,total,locked,state\n\
+                   1,5.0000,0.0000,5.0000,true,locked\n",
+        rejected: 1,
+        warnings: 0,
+    },
+    Scenario {
+        name: "disputes of unknown or undisputed transactions",
+        input: "type,client,tx,amount\n\
+                deposit,1,1,10\n\
+                dispute,1,9,\n\
+                resolve,1,1,\n\
+                chargeback,1,1,\n\
+                dispute,2,1,\n",
+        expected: "client,available,held,total,locked,state\n\
+                   1,10.0000,0.0000,10.0000,false,active\n\
+                   2,0.0000,0.0000,0.0000,false,active\n",
+        rejected: 4,
+        warnings: 0,
+    },
+    Scenario {
+        name: "malformed and invalid rows",
+        input: "type,client,tx,amount\n\
+                deposit,1,1,abc\n\
+                fee,1,2,1\n\
+                deposit,1,3,-4\n\
+                deposit,1,4,1.00001\n\
+                deposit,x,5,1\n\
+                deposit,1,6,2\n",
+        expected: "client,available,held,total,locked,state\n\
+                   1,2.0000,0.0000,2.0000,false,active\n",
+        rejected: 5,
+        warnings: 0,
+    },
+    Scenario {
+        name: "duplicate rows",
+        input: "type,client,tx,amount\n\
+                deposit,1,1,10\n\
+                deposit,1,1,10\n\
+                withdrawal,1,2,1\n",
+        expected: "client,available,held,total,locked,state\n\
+                   1,9.0000,0.0000,9.0000,false,active\n",
+        rejected: 0,
+        warnings: 1,
+    },
+    Scenario {
+        name: "four decimal places and padded fields",
+        input: "type,client,tx,amount\n\
+                deposit,1,1,0.0001\n\
+                deposit,1,2,0.0001\n\
+                deposit,1,3,0.0001\n\
+                withdrawal,1,4,0.0002\n  \
+                deposit , 2 , 5 , 2.5 \n",
+        expected: "client,available,held,total,locked,state\n\
+                   1,0.0001,0.0000,0.0001,false,active\n\
+                   2,2.5000,0.0000,2.5000,false,active\n",
+        rejected: 0,
+        warnings: 0,
+    },
+];
+
+impl Scenario {
+    /// Process the input with a new engine and check the outcome
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{SCENARIOS, Scenario};
+    /// assert_eq!(SCENARIOS[0].run(), Ok(()));
+    ///
+    /// let broken = Scenario { rejected: 3, ..SCENARIOS[0] };
+    /// assert_eq!(broken.run(), Err("expected 3 rejected rows, got 0".to_string()));
+    /// ```
+    ///
+    /// # Errors
+    /// Returns what differed from the expected outcome, or why processing failed.
+    pub fn run(&self) -> Result<(), String> {
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut engine = Engine::new();
+            let mut output = Vec::new();
+            let source = CsvSource::new(self.name, self.input.as_bytes());
+            let stats = engine.run(source, CsvSink::new(&mut output));
+            (stats.map_err(|e| e.to_string()), output)
+        }));
+        let (stats, output) = match outcome {
+            Ok((Ok(stats), output)) => (stats, output),
+            Ok((Err(e), _)) => return Err(format!("processing failed: {}", e)),
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_default();
+                return Err(format!("panicked: {}", message));
+            }
+        };
+
+        let summary = String::from_utf8_lossy(&output);
+        if summary != self.expected {
+            return Err(format!(
+                "expected summary\n{}got\n{}",
+                self.expected, summary
+            ));
+        }
+        if stats.rejected != self.rejected {
+            return Err(format!(
+                "expected {} rejected rows, got {}",
+                self.rejected, stats.rejected
+            ));
+        }
+        if stats.warnings != self.warnings {
+            return Err(format!(
+                "expected {} warnings, got {}",
+                self.warnings, stats.warnings
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Run every built-in scenario, returning each with its outcome, in order
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::selftest;
+/// let results = selftest();
+/// assert!(results.len() >= 8);
+/// assert!(results.iter().all(|(_, result)| result.is_ok()));
+/// ```
+pub fn selftest() -> Vec<(&'static Scenario, Result<(), String>)> {
+    SCENARIOS
+        .iter()
+        .map(|scenario| (scenario, scenario.run()))
+        .collect()
+}
@@ -0,0 +1,53 @@
+//! Pluggable report outputs
+//!
+//! A [`SummarySink`] receives the account summaries at the end of an
+//! [`Engine`](crate::Engine) run, so reports can go somewhere other than a CSV stream
+//! (an HTTP endpoint, object storage). [`CsvSink`](crate::CsvSink) is the CSV implementation.
+
+use crate::db::Account;
+use std::error::Error;
+
+/// Destination for the account summary report
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Account, CsvSource, Engine, SummarySink};
+/// /// Collects the client IDs in report order
+/// struct Clients(Vec<u16>);
+///
+/// impl SummarySink for Clients {
+///     fn write_summaries(
+///         &mut self,
+///         summaries: &[(u16, &Account)],
+///     ) -> Result<(), Box<dyn std::error::Error>> {
+///         self.0.extend(summaries.iter().map(|(client, _)| *client));
+///         Ok(())
+///     }
+/// }
+///
+/// let input = "type,client,tx,amount\ndeposit,2,1,1.0\ndeposit,1,2,1.0\n";
+/// let mut clients = Clients(Vec::new());
+/// Engine::new()
+///     .run(CsvSource::new("input", input.as_bytes()), &mut clients)
+///     .unwrap();
+/// assert_eq!(clients.0, vec![1, 2]);
+/// ```
+pub trait SummarySink {
+    /// Write one summary per account, in the order given
+    ///
+    /// # Errors
+    /// Returns an error if the report cannot be written.
+    fn write_summaries(&mut self, summaries: &[(u16, &Account)]) -> Result<(), Box<dyn Error>>;
+}
+
+impl<S: SummarySink + ?Sized> SummarySink for &mut S {
+    fn write_summaries(&mut self, summaries: &[(u16, &Account)]) -> Result<(), Box<dyn Error>> {
+        (**self).write_summaries(summaries)
+    }
+}
+
+impl<S: SummarySink + ?Sized> SummarySink for Box<S> {
+    fn write_summaries(&mut self, summaries: &[(u16, &Account)]) -> Result<(), Box<dyn Error>> {
+        (**self).write_summaries(summaries)
+    }
+}
@@ -0,0 +1,431 @@
+//! Snapshots and event log compaction
+//!
+//! A long-lived [`Engine`] can persist its state to a [`SnapshotStore`]: applied
+//! transactions are appended to an event log as they happen, and every so often the
+//! whole state is written as a snapshot and the log before it is discarded. Reopening
+//! the store loads the latest snapshot and replays only the log written since, so disk
+//! usage and restart time stay bounded however long the deployment runs.
+//!
+//! Each snapshot starts a new log generation (`events-<generation>.csv`). The snapshot
+//! is streamed to a temporary file, synced, and renamed into place before the previous
+//! log is deleted, so a crash at any point leaves either the new snapshot, or the old
+//! snapshot with the complete log since. Writes can run on a background thread (see
+//! [`SnapshotStore::with_background_writes`]) so large snapshots don't stall ingestion.
+//!
+//! The log only holds applied transactions, so the source offsets consumed, including
+//! those of rejected rows, are kept in `offsets.json` next to it, rewritten whenever they
+//! move on.
+//!
+//! # Format
+//!
+//! A snapshot file is a one-line header followed by the state as JSON:
+//!
+//! ```text
+//! TPSNAP 0001 1c291ca3 00000000000000004096
+//! {"generation":3,"snapshot":{...}}
+//! ```
+//!
+//! The header gives the format version, the CRC-32 of the JSON and its length, so a
+//! truncated or corrupted snapshot is refused with a [`SnapshotError`] rather than
+//! loaded. When the state structs change, [`SNAPSHOT_VERSION`] is bumped and a
+//! migration is added that rewrites the previous version's JSON, so existing snapshots
+//! stay loadable. Files without a header predate versioning and are read as version 0.
+
+use crate::engine::{Engine, Snapshot};
+use crate::event_log::{EventLog, append_csv};
+use crate::source::SourceOffset;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use thiserror::Error;
+
+const SNAPSHOT_FILE: &str = "snapshot.json";
+
+const OFFSETS_FILE: &str = "offsets.json";
+
+/// Format version of the snapshots written by this build
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+const MAGIC: &str = "TPSNAP";
+
+/// Upgrades from each older format version, in order: `MIGRATIONS[v]` turns the JSON of
+/// version `v` into that of version `v + 1`
+const MIGRATIONS: [fn(Value) -> Result<Value, String>; SNAPSHOT_VERSION as usize] = [
+    // 0 → 1: only the header was added
+    Ok,
+];
+
+/// Failure to load a snapshot
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    /// The snapshot file could not be read
+    #[error("Snapshot could not be read: {0}")]
+    Io(#[from] std::io::Error),
+    /// The snapshot was written in a format version newer than this build understands
+    #[error("Snapshot format version {0} is newer than supported version {SNAPSHOT_VERSION}")]
+    UnsupportedVersion(u32),
+    /// The snapshot's contents don't match its checksum, e.g. after a partial write
+    #[error("Snapshot is corrupt: checksum mismatch")]
+    ChecksumMismatch,
+    /// The header or JSON could not be parsed, or a migration failed
+    #[error("Snapshot is corrupt: {0}")]
+    Malformed(String),
+}
+
+/// A snapshot as stored on disk, with the log generation that follows it
+#[derive(Serialize, Deserialize)]
+struct SnapshotFile {
+    generation: u64,
+    snapshot: Snapshot,
+}
+
+/// Directory holding an engine's latest snapshot and the event log written since
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{CsvSource, Engine, SnapshotStore};
+/// let dir = tempfile::tempdir().unwrap();
+///
+/// let mut engine = Engine::new().with_event_log();
+/// let mut store = SnapshotStore::open(dir.path(), &mut engine)
+///     .unwrap()
+///     .with_compact_every(2);
+/// for batch in ["deposit,1,1,10.0", "deposit,1,2,5.0", "withdrawal,1,3,1.0"] {
+///     let input = format!("type,client,tx,amount\n{}\n", batch);
+///     engine.process(CsvSource::new("input", input.as_bytes()));
+///     store.save(&mut engine).unwrap();
+/// }
+/// // The first two transactions were compacted into a snapshot, and dropped from the
+/// // engine's event log
+/// assert_eq!(store.generation(), 1);
+/// assert!(!dir.path().join("events-0.csv").exists());
+/// assert_eq!(engine.event_log().unwrap().events().len(), 1);
+///
+/// // After a restart, the snapshot and the rest of the log are loaded
+/// let mut restarted = Engine::new().with_event_log();
+/// SnapshotStore::open(dir.path(), &mut restarted).unwrap();
+/// let account = restarted.database().get_account(1).unwrap();
+/// assert_eq!(account.available().to_string(), "14.0000");
+/// ```
+#[derive(Debug)]
+pub struct SnapshotStore {
+    dir: PathBuf,
+    generation: u64,
+    saved: usize,
+    since_snapshot: usize,
+    compact_every: usize,
+    background: bool,
+    writing: Option<JoinHandle<Result<(), WriteError>>>,
+    /// Source offsets as last written to the offsets file
+    offsets: Vec<SourceOffset>,
+}
+
+/// Failure of a snapshot write, which may happen on a background thread
+type WriteError = Box<dyn Error + Send + Sync>;
+
+impl SnapshotStore {
+    /// Log length after which [`save`](SnapshotStore::save) compacts by default
+    pub const DEFAULT_COMPACT_EVERY: usize = 10_000;
+
+    /// Open the store in `dir`, creating it if needed, and load its state into `engine`
+    ///
+    /// `engine` must record an event log (see [`Engine::with_event_log`]) and should not
+    /// have processed anything yet. Its accounts are replaced by the latest snapshot,
+    /// and the log written since is replayed on top.
+    ///
+    /// # Errors
+    /// Returns an error if the engine has no event log, or the snapshot or log cannot
+    /// be read.
+    pub fn open(dir: impl Into<PathBuf>, engine: &mut Engine) -> Result<Self, Box<dyn Error>> {
+        if engine.event_log().is_none() {
+            return Err("The engine must record an event log to be persisted".into());
+        }
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        let snapshot_path = dir.join(SNAPSHOT_FILE);
+        let generation = if snapshot_path.exists() {
+            let file = read_snapshot(File::open(&snapshot_path)?)?;
+            engine.restore(file.snapshot);
+            file.generation
+        } else {
+            0
+        };
+        // Left behind if a crash interrupted the snapshot's removal of the log before it
+        if generation > 0 {
+            remove_if_exists(&events_path(&dir, generation - 1))?;
+        }
+
+        let mut store = Self {
+            dir,
+            generation,
+            saved: 0,
+            since_snapshot: 0,
+            compact_every: Self::DEFAULT_COMPACT_EVERY,
+            background: false,
+            writing: None,
+            offsets: Vec::new(),
+        };
+        // If a crash interrupted a snapshot write, the next generation's log was
+        // already started: replay it too
+        loop {
+            let path = events_path(&store.dir, store.generation);
+            if path.exists() {
+                let name = path.display().to_string();
+                let log = EventLog::read_csv(&name, File::open(&path)?)?;
+                engine.process(log.replay(None));
+                store.since_snapshot += log.events().len();
+            }
+            if !events_path(&store.dir, store.generation + 1).exists() {
+                break;
+            }
+            store.generation += 1;
+        }
+        store.saved = engine.event_log().map_or(0, |log| log.events().len());
+
+        let offsets_path = store.dir.join(OFFSETS_FILE);
+        if offsets_path.exists() {
+            let offsets: Vec<SourceOffset> = serde_json::from_reader(File::open(&offsets_path)?)?;
+            engine.commit_offsets(offsets);
+        }
+        store.offsets = engine.committed_offsets();
+        Ok(store)
+    }
+
+    /// Compact once this many transactions have been logged since the last snapshot
+    pub fn with_compact_every(mut self, events: usize) -> Self {
+        self.compact_every = events.max(1);
+        self
+    }
+
+    /// Write snapshots on a background thread, so ingestion carries on meanwhile
+    ///
+    /// The snapshot taken on the ingest thread shares the accounts copy-on-write (see
+    /// [`MemoryStorage`](crate::MemoryStorage)), costing a reference count per account;
+    /// serializing and writing it happens on the background thread. The writer gets a
+    /// consistent view while new transactions keep arriving; they go to the next log
+    /// generation. Only one snapshot is written at a time.
+    pub fn with_background_writes(mut self) -> Self {
+        self.background = true;
+        self
+    }
+
+    /// Directory holding the snapshot and log
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Number of snapshots taken; also names the current log, `events-<generation>.csv`
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Append the transactions `engine` applied since the last save to the log,
+    /// compacting if the log has grown long enough
+    ///
+    /// # Errors
+    /// Returns an error if the log or snapshot cannot be written, including a background
+    /// snapshot write that failed since the last call.
+    pub fn save(&mut self, engine: &mut Engine) -> Result<(), Box<dyn Error>> {
+        if self.writing.as_ref().is_some_and(JoinHandle::is_finished) {
+            self.wait()?;
+        }
+
+        let events = engine.event_log().map_or(&[][..], |log| log.events());
+        let new = events.get(self.saved..).unwrap_or_default();
+        append_csv(&events_path(&self.dir, self.generation), new)?;
+        self.saved = events.len();
+        self.since_snapshot += new.len();
+
+        let offsets = engine.committed_offsets();
+        if offsets != self.offsets {
+            write_offsets(&self.dir, &offsets)?;
+            self.offsets = offsets;
+        }
+
+        if self.since_snapshot >= self.compact_every && self.writing.is_none() {
+            self.compact(engine)?;
+        }
+        Ok(())
+    }
+
+    /// Write a snapshot of `engine` and discard the log before it
+    ///
+    /// The events the snapshot covers are also dropped from the engine's
+    /// [event log](Engine::event_log), so its memory stays bounded too.
+    ///
+    /// With [background writes](SnapshotStore::with_background_writes), this only
+    /// starts the write, after waiting for any previous one to finish.
+    ///
+    /// # Errors
+    /// Returns an error if the snapshot cannot be written or the old log removed.
+    pub fn compact(&mut self, engine: &mut Engine) -> Result<(), Box<dyn Error>> {
+        self.wait()?;
+
+        let dir = self.dir.clone();
+        let previous = self.generation;
+        let file = SnapshotFile {
+            generation: previous + 1,
+            snapshot: engine.snapshot(),
+        };
+        self.generation = previous + 1;
+        if let Some(log) = engine.event_log_mut() {
+            log.clear();
+        }
+        self.saved = 0;
+        self.since_snapshot = 0;
+
+        if self.background {
+            self.writing = Some(thread::spawn(move || write_snapshot(&dir, &file)));
+            Ok(())
+        } else {
+            write_snapshot(&dir, &file).map_err(|e| e as Box<dyn Error>)
+        }
+    }
+
+    /// Wait for a background snapshot write to finish
+    ///
+    /// # Errors
+    /// Returns the write's error if it failed.
+    pub fn wait(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.writing.take() {
+            Some(writing) => match writing.join() {
+                Ok(result) => result.map_err(|e| e as Box<dyn Error>),
+                Err(_) => Err("The snapshot writer panicked".into()),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+/// Write `file` durably in place of the current snapshot, then delete the log it
+/// supersedes
+///
+/// The snapshot is streamed to a temporary file, synced, and renamed over the old one,
+/// so readers only ever see a complete snapshot.
+fn write_snapshot(dir: &Path, file: &SnapshotFile) -> Result<(), WriteError> {
+    let temp = dir.join(format!("{}.tmp", SNAPSHOT_FILE));
+    let mut writer = BufWriter::new(File::create(&temp)?);
+
+    // The checksum is only known once the body is written, so the fixed-width header is
+    // written as a placeholder first and filled in afterwards
+    writer.write_all(header(0, 0).as_bytes())?;
+    let mut body = Checksummed {
+        inner: &mut writer,
+        hasher: crc32fast::Hasher::new(),
+        len: 0,
+    };
+    serde_json::to_writer(&mut body, file)?;
+    let (crc, len) = (body.hasher.finalize(), body.len);
+    writer.seek(SeekFrom::Start(0))?;
+    writer.write_all(header(crc, len).as_bytes())?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+
+    fs::rename(&temp, dir.join(SNAPSHOT_FILE))?;
+    // Make the rename itself durable before the log it replaces goes away
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+
+    remove_if_exists(&events_path(dir, file.generation - 1))?;
+    Ok(())
+}
+
+/// Write `offsets` durably in place of the offsets file
+fn write_offsets(dir: &Path, offsets: &[SourceOffset]) -> std::io::Result<()> {
+    let temp = dir.join(format!("{}.tmp", OFFSETS_FILE));
+    let mut writer = BufWriter::new(File::create(&temp)?);
+    serde_json::to_writer(&mut writer, offsets)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    fs::rename(&temp, dir.join(OFFSETS_FILE))
+}
+
+/// Read a snapshot file, checking its header and migrating it to the current version
+fn read_snapshot(mut reader: impl Read) -> Result<SnapshotFile, SnapshotError> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    let (version, body) = if bytes.starts_with(MAGIC.as_bytes()) {
+        let end = bytes
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or_else(|| SnapshotError::Malformed("Truncated header".to_string()))?;
+        let header = String::from_utf8_lossy(&bytes[..end]);
+        let (version, crc, len) =
+            parse_header(&header).ok_or_else(|| SnapshotError::Malformed(header.to_string()))?;
+        let body = &bytes[end + 1..];
+        if body.len() as u64 != len || crc32fast::hash(body) != crc {
+            return Err(SnapshotError::ChecksumMismatch);
+        }
+        (version, body)
+    } else {
+        (0, &bytes[..])
+    };
+    if version > SNAPSHOT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+
+    let malformed = |e: serde_json::Error| SnapshotError::Malformed(e.to_string());
+    let mut value: Value = serde_json::from_slice(body).map_err(malformed)?;
+    for migrate in &MIGRATIONS[version as usize..] {
+        value = migrate(value).map_err(SnapshotError::Malformed)?;
+    }
+    serde_json::from_value(value).map_err(malformed)
+}
+
+/// Header line for the current version; fixed-width, so the placeholder written
+/// before the body is exactly overwritten
+fn header(crc: u32, len: u64) -> String {
+    format!(
+        "{} {:04} {:08x} {:020}\n",
+        MAGIC, SNAPSHOT_VERSION, crc, len
+    )
+}
+
+/// Version, checksum and body length from a header line
+fn parse_header(header: &str) -> Option<(u32, u32, u64)> {
+    let mut fields = header.split(' ');
+    if fields.next()? != MAGIC {
+        return None;
+    }
+    let version = fields.next()?.parse().ok()?;
+    let crc = u32::from_str_radix(fields.next()?, 16).ok()?;
+    let len = fields.next()?.parse().ok()?;
+    Some((version, crc, len))
+}
+
+/// Writer that checksums and counts everything written through it
+struct Checksummed<W> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+    len: u64,
+}
+
+impl<W: Write> Write for Checksummed<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn events_path(dir: &Path, generation: u64) -> PathBuf {
+    dir.join(format!("events-{}.csv", generation))
+}
+
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
@@ -0,0 +1,197 @@
+//! Pluggable transaction inputs
+//!
+//! A [`TransactionSource`] yields transactions one at a time, so inputs other than CSV
+//! files (databases, message queues) can feed the same [`Engine`](crate::Engine) loop.
+//! [`CsvSource`](crate::CsvSource) is the CSV implementation.
+
+use crate::db::{Transaction, TransactionError, TransactionMeta};
+use crate::record::Warning;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// A transaction read from a source, ready to apply
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourcedTransaction {
+    /// Where the transaction came from (e.g. `input.csv:7`), used in errors and warnings
+    pub location: String,
+    /// Client the transaction is for
+    pub client: u16,
+    /// Transaction ID
+    pub tx: u32,
+    /// The transaction itself
+    pub transaction: Transaction,
+    /// Optional timestamp and case ID
+    pub meta: TransactionMeta,
+    /// Anything suspicious the source noticed while decoding the transaction
+    pub warning: Option<Warning>,
+}
+
+/// Position of a message in a queue or stream, e.g. a Kafka topic partition
+///
+/// Sources that can redeliver messages after a crash attach the offset of each one in
+/// [`TransactionMeta::offset`]. An [`Engine`](crate::Engine) remembers the highest
+/// offset it has applied in each partition and skips anything at or below it, and the
+/// offsets are recorded in its [`EventLog`](crate::EventLog), so replaying the log
+/// after a restart restores them along with the accounts.
+///
+/// Parses from and displays as `SOURCE/PARTITION@OFFSET`.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{
+/// #     Engine, SourceError, SourceOffset, SourcedTransaction, Transaction, TransactionMeta,
+/// #     TransactionSource,
+/// # };
+/// /// Deposits of 1.0 into client 1, one per offset of partition 0
+/// struct Queue(std::ops::Range<u64>);
+///
+/// impl TransactionSource for Queue {
+///     fn next_transaction(&mut self) -> Option<Result<SourcedTransaction, SourceError>> {
+///         let offset = self.0.next()?;
+///         let meta = TransactionMeta {
+///             offset: Some(SourceOffset::new("deposits", 0, offset)),
+///             ..TransactionMeta::default()
+///         };
+///         Some(Ok(SourcedTransaction {
+///             location: format!("deposits/0@{}", offset),
+///             client: 1,
+///             tx: offset as u32,
+///             transaction: Transaction::deposit("1.0").unwrap(),
+///             meta,
+///             warning: None,
+///         }))
+///     }
+/// }
+///
+/// let mut engine = Engine::new();
+/// engine.process(Queue(0..3));
+/// // After a crash, the queue redelivers from offset 1
+/// let stats = engine.process(Queue(1..5));
+///
+/// assert_eq!((stats.rows, stats.applied, stats.redelivered), (4, 2, 2));
+/// assert_eq!(engine.committed_offset("deposits", 0), Some(4));
+/// assert_eq!(engine.database().get_account(1).unwrap().available().to_string(), "5.0000");
+///
+/// assert_eq!("deposits/0@4".parse(), Ok(SourceOffset::new("deposits", 0, 4)));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SourceOffset {
+    /// Queue, topic or stream name
+    pub source: String,
+    /// Partition within the source
+    pub partition: u32,
+    /// Position within the partition; offsets increase in delivery order
+    pub offset: u64,
+}
+
+impl SourceOffset {
+    /// Offset `offset` of `partition` in `source`
+    pub fn new(source: impl Into<String>, partition: u32, offset: u64) -> Self {
+        Self {
+            source: source.into(),
+            partition,
+            offset,
+        }
+    }
+}
+
+impl std::fmt::Display for SourceOffset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}@{}", self.source, self.partition, self.offset)
+    }
+}
+
+impl FromStr for SourceOffset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Expected SOURCE/PARTITION@OFFSET, got {}", s);
+        let (position, offset) = s.rsplit_once('@').ok_or_else(invalid)?;
+        let (source, partition) = position.rsplit_once('/').ok_or_else(invalid)?;
+        Ok(Self {
+            source: source.to_string(),
+            partition: partition.parse().map_err(|_| invalid())?,
+            offset: offset.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Failure to read a transaction from a source
+#[derive(Debug)]
+pub enum SourceError {
+    /// The input could not be read or decoded into a record
+    Malformed {
+        /// Where the problem was found
+        location: String,
+        /// What went wrong
+        message: String,
+    },
+    /// The record was read but does not describe a valid transaction
+    Invalid {
+        /// Where the record was found
+        location: String,
+        /// What was wrong with it
+        error: TransactionError,
+    },
+    /// The record was deliberately skipped, and is reported as a warning rather than an
+    /// error
+    Skipped {
+        /// Where the record was found
+        location: String,
+        /// Why it was skipped
+        warning: Warning,
+    },
+}
+
+impl std::fmt::Display for SourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Malformed { location, message } => {
+                write!(f, "Error parsing CSV at {}: {}", location, message)
+            }
+            Self::Invalid { location, error } => {
+                write!(f, "Error processing transaction at {}: {}", location, error)
+            }
+            Self::Skipped { location, warning } => {
+                write!(f, "Warning at {}: {}", location, warning)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+/// A stream of transactions to process
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{
+/// #     Engine, SourceError, SourcedTransaction, Transaction, TransactionMeta, TransactionSource,
+/// # };
+/// /// Deposits 1.0 into each of the given clients
+/// struct Deposits(std::vec::IntoIter<u16>);
+///
+/// impl TransactionSource for Deposits {
+///     fn next_transaction(&mut self) -> Option<Result<SourcedTransaction, SourceError>> {
+///         let client = self.0.next()?;
+///         Some(Ok(SourcedTransaction {
+///             location: format!("client {}", client),
+///             client,
+///             tx: client.into(),
+///             transaction: Transaction::deposit("1.0").unwrap(),
+///             meta: TransactionMeta::default(),
+///             warning: None,
+///         }))
+///     }
+/// }
+///
+/// let mut engine = Engine::new();
+/// let stats = engine.process(Deposits(vec![1, 2, 3].into_iter()));
+/// assert_eq!(stats.applied, 3);
+/// ```
+pub trait TransactionSource {
+    /// Read the next transaction, or `None` once the input is exhausted
+    ///
+    /// An error skips only the offending record; processing continues with the next.
+    fn next_transaction(&mut self) -> Option<Result<SourcedTransaction, SourceError>>;
+}
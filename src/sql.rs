@@ -0,0 +1,142 @@
+//! SQL export of the final accounts and their ledgers
+//!
+//! [`write_sql`] writes a script that creates the `accounts` and `ledger` tables, if
+//! they don't exist, and inserts every account and ledger entry in one transaction, so
+//! a reporting database can be loaded with e.g. `psql -f run.sql`. Amounts are
+//! `NUMERIC(19, 4)`, so they load exactly. The script sticks to standard SQL, and runs
+//! as is on PostgreSQL and SQLite.
+
+use crate::db::Database;
+use std::io::{self, Write};
+
+/// Rows per `INSERT` statement; large enough for a fast load, small enough for any
+/// server's statement size limit
+const ROWS_PER_INSERT: usize = 500;
+
+/// Tables written by [`write_sql`]
+const SCHEMA: &str = "\
+CREATE TABLE IF NOT EXISTS accounts (
+    client INTEGER PRIMARY KEY,
+    available NUMERIC(19, 4) NOT NULL,
+    held NUMERIC(19, 4) NOT NULL,
+    total NUMERIC(19, 4) NOT NULL,
+    locked BOOLEAN NOT NULL,
+    state TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS ledger (
+    client INTEGER NOT NULL,
+    tx BIGINT NOT NULL,
+    type TEXT NOT NULL,
+    amount NUMERIC(19, 4) NOT NULL,
+    timestamp BIGINT,
+    dispute_state TEXT,
+    refund_of BIGINT,
+    PRIMARY KEY (client, tx)
+);
+";
+
+/// Write every account and every ledger entry as a SQL script
+///
+/// The script creates the tables if needed, then inserts the rows inside a single
+/// `BEGIN`/`COMMIT`, so a failed load leaves the tables as they were. Accounts are in
+/// client order, and each client's ledger entries in the order they were processed,
+/// as listed by [`Account::ledger`](crate::Account::ledger).
+///
+/// The rows are inserted, not upserted: load each run into empty tables, or truncate
+/// them first.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Database, Transaction, write_sql};
+/// let mut db = Database::new();
+/// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+/// db.process_transaction(1, 1, Transaction::dispute()).unwrap();
+///
+/// let mut output = Vec::new();
+/// write_sql(&db, &mut output).unwrap();
+/// let script = String::from_utf8(output).unwrap();
+/// assert!(script.contains(
+///     "INSERT INTO accounts (client, available, held, total, locked, state) VALUES\n\
+///      (1, 0.0000, 100.0000, 100.0000, FALSE, 'active');\n"
+/// ));
+/// assert!(script.contains("(1, 1, 'deposit', 100.0000, NULL, 'disputed', NULL);\n"));
+/// assert!(script.ends_with("COMMIT;\n"));
+/// ```
+///
+/// # Errors
+/// Returns an error if writing to `writer` fails.
+pub fn write_sql<W: Write>(database: &Database, mut writer: W) -> io::Result<()> {
+    let mut client_ids = database.get_all_client_ids();
+    client_ids.sort();
+
+    writer.write_all(SCHEMA.as_bytes())?;
+    writer.write_all(b"BEGIN;\n")?;
+
+    let accounts: Vec<String> = client_ids
+        .iter()
+        .filter_map(|&client_id| {
+            let account = database.get_account(client_id)?;
+            Some(format!(
+                "({}, {}, {}, {}, {}, {})",
+                client_id,
+                account.available(),
+                account.held(),
+                account.total(),
+                if account.is_locked() { "TRUE" } else { "FALSE" },
+                quote(&account.state().to_string()),
+            ))
+        })
+        .collect();
+    write_inserts(
+        &mut writer,
+        "accounts (client, available, held, total, locked, state)",
+        &accounts,
+    )?;
+
+    let entries: Vec<String> = client_ids
+        .iter()
+        .filter_map(|&client_id| Some((client_id, database.get_account(client_id)?)))
+        .flat_map(|(client_id, account)| {
+            account.ledger().map(move |entry| {
+                format!(
+                    "({}, {}, {}, {}, {}, {}, {})",
+                    client_id,
+                    entry.tx,
+                    quote(&entry.kind.to_string()),
+                    entry.amount,
+                    entry
+                        .timestamp
+                        .map_or("NULL".to_string(), |time| time.to_string()),
+                    entry
+                        .dispute_state
+                        .map_or("NULL".to_string(), |state| quote(&state.to_string())),
+                    entry
+                        .refund_of
+                        .map_or("NULL".to_string(), |tx| tx.to_string()),
+                )
+            })
+        })
+        .collect();
+    write_inserts(
+        &mut writer,
+        "ledger (client, tx, type, amount, timestamp, dispute_state, refund_of)",
+        &entries,
+    )?;
+
+    writer.write_all(b"COMMIT;\n")?;
+    writer.flush()
+}
+
+/// Write `rows` into `table` as multi-row `INSERT` statements
+fn write_inserts<W: Write>(writer: &mut W, table: &str, rows: &[String]) -> io::Result<()> {
+    for chunk in rows.chunks(ROWS_PER_INSERT) {
+        writeln!(writer, "INSERT INTO {} VALUES", table)?;
+        writeln!(writer, "{};", chunk.join(",\n"))?;
+    }
+    Ok(())
+}
+
+/// `value` as a SQL string literal
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
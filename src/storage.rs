@@ -0,0 +1,258 @@
+//! Where a [`Database`](crate::Database) keeps its accounts
+//!
+//! Accounts, with their ledgers and audit trails, live in a [`Storage`] backend. The
+//! default, [`MemoryStorage`], keeps them in a map for the life of the process, as the
+//! database always has. [`JsonFileStorage`] loads them from a file when opened and
+//! writes them back on [`flush`](Storage::flush), so the state survives between runs
+//! of the CLI. Callers of [`process_transaction`](crate::Database::process_transaction)
+//! see no difference: pick a backend with [`Database::with_storage`](crate::Database::with_storage)
+//! and flush when done.
+//!
+//! Other backends, such as an embedded key-value store, implement [`Storage`] the same
+//! way. Dispute cases and operations awaiting approval are kept in memory whatever the
+//! backend; use a [`SnapshotStore`](crate::SnapshotStore) to persist those too.
+
+use crate::db::Account;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Accounts by client ID
+///
+/// Accounts are handed out by reference and changed in place, so a backend keeps those
+/// it has handed out in memory and writes them back on [`flush`](Storage::flush).
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Database, MemoryStorage, Policy, Transaction};
+/// let mut db = Database::with_storage(MemoryStorage::new(), Policy::default());
+/// db.process_transaction(1, 1, Transaction::deposit("10").unwrap()).unwrap();
+/// db.flush().unwrap();
+/// assert_eq!(db.get_account(1).unwrap().available().to_string(), "10.0000");
+/// ```
+pub trait Storage: std::fmt::Debug + Send + Sync {
+    /// The account of `client`, if it has one
+    fn get(&self, client: u16) -> Option<&Account>;
+
+    /// The account of `client` for changing in place, if it has one
+    fn get_mut(&mut self, client: u16) -> Option<&mut Account>;
+
+    /// Store `account` as the account of `client`, returning the one it replaces
+    fn insert(&mut self, client: u16, account: Account) -> Option<Account>;
+
+    /// Remove and return the account of `client`
+    fn remove(&mut self, client: u16) -> Option<Account>;
+
+    /// Every account, in no particular order
+    fn iter(&self) -> Box<dyn Iterator<Item = (u16, &Account)> + '_>;
+
+    /// Every account for changing in place, in no particular order
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item = (u16, &mut Account)> + '_>;
+
+    /// Make every change so far durable
+    ///
+    /// Backends without durable state have nothing to do.
+    ///
+    /// # Errors
+    /// Returns an error if the accounts can't be written.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// An in-memory copy of every account, for a snapshot or checkpoint
+    ///
+    /// The default copies each account in full. [`MemoryStorage`] shares them with the
+    /// copy instead, so forking is cheap and an account is only copied when one side
+    /// next changes it.
+    fn fork(&self) -> MemoryStorage {
+        self.iter()
+            .map(|(client, account)| (client, account.clone()))
+            .collect()
+    }
+}
+
+/// Accounts kept in memory only, lost when the process exits
+///
+/// Clones share their accounts copy-on-write: cloning costs a reference count per
+/// account, and an account is copied the first time either clone changes it.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    accounts: HashMap<u16, Arc<Account>>,
+}
+
+impl MemoryStorage {
+    /// Storage with no accounts
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl FromIterator<(u16, Account)> for MemoryStorage {
+    fn from_iter<I: IntoIterator<Item = (u16, Account)>>(accounts: I) -> Self {
+        Self {
+            accounts: accounts
+                .into_iter()
+                .map(|(client, account)| (client, Arc::new(account)))
+                .collect(),
+        }
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, client: u16) -> Option<&Account> {
+        self.accounts.get(&client).map(|account| &**account)
+    }
+
+    fn get_mut(&mut self, client: u16) -> Option<&mut Account> {
+        self.accounts.get_mut(&client).map(Arc::make_mut)
+    }
+
+    fn insert(&mut self, client: u16, account: Account) -> Option<Account> {
+        self.accounts
+            .insert(client, Arc::new(account))
+            .map(Arc::unwrap_or_clone)
+    }
+
+    fn remove(&mut self, client: u16) -> Option<Account> {
+        self.accounts.remove(&client).map(Arc::unwrap_or_clone)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (u16, &Account)> + '_> {
+        Box::new(
+            self.accounts
+                .iter()
+                .map(|(&client, account)| (client, &**account)),
+        )
+    }
+
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item = (u16, &mut Account)> + '_> {
+        Box::new(
+            self.accounts
+                .iter_mut()
+                .map(|(&client, account)| (client, Arc::make_mut(account))),
+        )
+    }
+
+    fn fork(&self) -> MemoryStorage {
+        self.clone()
+    }
+}
+
+/// Accounts persisted as a JSON file between runs
+///
+/// The file holds each account by client ID, in the same form as the `accounts` of
+/// [`Database::to_json`](crate::Database::to_json). It is read once when opened; while
+/// processing, the accounts are served from memory, and [`flush`](Storage::flush)
+/// writes them to a temporary file, syncs it and renames it over the old one, so a
+/// crash leaves either the previous state or the new one, never a mix.
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{Database, JsonFileStorage, Policy, Transaction};
+/// let path = std::env::temp_dir().join(format!("accounts-{}.json", std::process::id()));
+///
+/// let mut db = Database::with_storage(JsonFileStorage::open(&path).unwrap(), Policy::default());
+/// db.process_transaction(1, 1, Transaction::deposit("100").unwrap()).unwrap();
+/// db.flush().unwrap();
+///
+/// // A later run picks up where the first left off
+/// let mut db = Database::with_storage(JsonFileStorage::open(&path).unwrap(), Policy::default());
+/// db.process_transaction(1, 1, Transaction::dispute()).unwrap();
+/// assert_eq!(db.get_account(1).unwrap().held().to_string(), "100.0000");
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct JsonFileStorage {
+    path: PathBuf,
+    accounts: MemoryStorage,
+}
+
+impl JsonFileStorage {
+    /// Open the accounts stored at `path`, starting with none if it doesn't exist
+    ///
+    /// # Errors
+    /// Returns an error if the file exists but can't be read or isn't valid.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let accounts: HashMap<u16, Account> = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(Self {
+            path,
+            accounts: accounts.into_iter().collect(),
+        })
+    }
+
+    /// File the accounts are stored in
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Storage for JsonFileStorage {
+    fn get(&self, client: u16) -> Option<&Account> {
+        self.accounts.get(client)
+    }
+
+    fn get_mut(&mut self, client: u16) -> Option<&mut Account> {
+        self.accounts.get_mut(client)
+    }
+
+    fn insert(&mut self, client: u16, account: Account) -> Option<Account> {
+        self.accounts.insert(client, account)
+    }
+
+    fn remove(&mut self, client: u16) -> Option<Account> {
+        self.accounts.remove(client)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (u16, &Account)> + '_> {
+        self.accounts.iter()
+    }
+
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item = (u16, &mut Account)> + '_> {
+        self.accounts.iter_mut()
+    }
+
+    fn fork(&self) -> MemoryStorage {
+        self.accounts.fork()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let mut writer = BufWriter::new(File::create(&temp)?);
+        let accounts: BTreeMap<_, _> = self.accounts.iter().collect();
+        serde_json::to_writer(&mut writer, &accounts)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        drop(writer);
+        fs::rename(&temp, &self.path)
+    }
+}
+
+/// Serialize `accounts` as a map by client ID, in client order
+pub(crate) fn serialize<T, S>(accounts: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: std::ops::Deref<Target = dyn Storage>,
+    S: Serializer,
+{
+    accounts
+        .iter()
+        .collect::<BTreeMap<_, _>>()
+        .serialize(serializer)
+}
+
+/// Deserialize a map of accounts by client ID into memory
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Box<dyn Storage>, D::Error> {
+    let accounts = HashMap::<u16, Account>::deserialize(deserializer)?;
+    Ok(Box::new(accounts.into_iter().collect::<MemoryStorage>()))
+}
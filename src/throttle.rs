@@ -0,0 +1,105 @@
+//! Ingestion rate limiting
+//!
+//! A [`TokenBucket`] caps how many transactions per second an [`Engine`](crate::Engine)
+//! applies, so long-running modes don't flood downstream consumers of the results and
+//! replays can be run at a controlled pace. Short bursts up to the bucket's capacity
+//! go through at full speed; after that, rows are admitted at the configured rate.
+
+use crate::source::{SourceError, SourcedTransaction, TransactionSource};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Token bucket admitting at most `rate` transactions per second on average
+///
+/// # Examples
+/// ```
+/// # use std::time::{Duration, Instant};
+/// # use transaction_processor::TokenBucket;
+/// let start = Instant::now();
+/// let mut bucket = TokenBucket::new(10).with_burst(2);
+///
+/// // The burst goes through immediately...
+/// assert!(bucket.try_acquire(start).is_ok());
+/// assert!(bucket.try_acquire(start).is_ok());
+/// // ...then each token takes a tenth of a second to refill
+/// assert_eq!(bucket.try_acquire(start), Err(Duration::from_millis(100)));
+/// assert!(bucket.try_acquire(start + Duration::from_millis(100)).is_ok());
+/// ```
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    refilled_at: Option<Instant>,
+}
+
+impl TokenBucket {
+    /// Admit `per_second` transactions per second, with bursts of up to one second's worth
+    ///
+    /// A rate of zero is treated as one transaction per second.
+    pub fn new(per_second: u32) -> Self {
+        let rate = f64::from(per_second.max(1));
+        Self {
+            rate,
+            burst: rate,
+            tokens: rate,
+            refilled_at: None,
+        }
+    }
+
+    /// Allow bursts of up to `burst` transactions (at least one) before throttling
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = f64::from(burst.max(1));
+        self.tokens = self.burst;
+        self
+    }
+
+    /// Take a token if one is available at `now`
+    ///
+    /// # Errors
+    /// Returns how long to wait until a token becomes available.
+    pub fn try_acquire(&mut self, now: Instant) -> Result<(), Duration> {
+        if let Some(refilled_at) = self.refilled_at {
+            let elapsed = now.saturating_duration_since(refilled_at).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        }
+        self.refilled_at = Some(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        }
+    }
+
+    /// Take a token, sleeping until one is available
+    pub fn acquire(&mut self) {
+        while let Err(wait) = self.try_acquire(Instant::now()) {
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// A source that yields its transactions no faster than a [`TokenBucket`] allows
+///
+/// Every row counts against the bucket, including rows that turn out to be invalid.
+pub struct Throttled<'a, S> {
+    source: S,
+    bucket: &'a mut TokenBucket,
+}
+
+impl<'a, S: TransactionSource> Throttled<'a, S> {
+    /// Rate-limit `source` with `bucket`
+    pub fn new(source: S, bucket: &'a mut TokenBucket) -> Self {
+        Self { source, bucket }
+    }
+}
+
+impl<S: TransactionSource> TransactionSource for Throttled<'_, S> {
+    fn next_transaction(&mut self) -> Option<Result<SourcedTransaction, SourceError>> {
+        let next = self.source.next_transaction()?;
+        self.bucket.acquire();
+        Some(next)
+    }
+}
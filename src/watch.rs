@@ -0,0 +1,268 @@
+//! Drop-folder integration
+//!
+//! Upstream systems drop CSV files into an inbox directory; a [`DropFolder`] picks them
+//! up in name order, applies each to a long-lived [`Engine`], and moves it to a `done`
+//! or `failed` directory alongside a per-file report.
+//!
+//! The engine state is snapshotted before each file. A file containing rows that cannot
+//! be decoded at all is treated as corrupt: the snapshot is restored, so none of its
+//! rows take effect, and the file is moved to `failed` for inspection. Rows that decode
+//! but are refused (e.g. insufficient funds) are normal and don't fail the file.
+//!
+//! Writers should create files under another name (or outside the inbox) and rename
+//! them into place, so a half-written file is never picked up.
+//!
+//! When the engine state isn't persisted, moving files away would lose them on restart.
+//! [`DropFolder::with_retained_files`] leaves them in the inbox instead, so a restarted
+//! process rebuilds its state by applying them again.
+
+use crate::csv_processor::CsvSource;
+use crate::engine::{Engine, RunStats};
+use crate::export::{CsvSink, SummaryColumns, SummaryLayout};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// What happened to a dropped file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileOutcome {
+    /// Applied and moved to the done directory
+    Done,
+    /// Rolled back and moved to the failed directory
+    Failed,
+}
+
+/// A file picked up from the inbox
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessedFile {
+    /// Where the file is now: moved to the done or failed directory, or still in the
+    /// inbox if files are retained
+    pub path: PathBuf,
+    /// Whether it was applied or rolled back
+    pub outcome: FileOutcome,
+    /// What happened to its rows
+    pub stats: RunStats,
+}
+
+/// An inbox directory plus the directories processed files are moved to
+///
+/// # Examples
+/// ```
+/// # use transaction_processor::{DropFolder, Engine, FileOutcome};
+/// let inbox = tempfile::tempdir().unwrap();
+/// std::fs::write(
+///     inbox.path().join("batch1.csv"),
+///     "type,client,tx,amount\ndeposit,1,1,10.0\n",
+/// )
+/// .unwrap();
+///
+/// let mut folder = DropFolder::new(inbox.path());
+/// let mut engine = Engine::new();
+/// let processed = folder.process_pending(&mut engine).unwrap();
+///
+/// assert_eq!(processed[0].outcome, FileOutcome::Done);
+/// assert!(inbox.path().join("done/batch1.csv").exists());
+/// assert!(inbox.path().join("done/batch1.csv.summary.csv").exists());
+/// assert_eq!(engine.database().get_account(1).unwrap().available().to_string(), "10.0000");
+/// ```
+#[derive(Debug, Clone)]
+pub struct DropFolder {
+    inbox: PathBuf,
+    done: PathBuf,
+    failed: PathBuf,
+    columns: SummaryColumns,
+    layout: SummaryLayout,
+    /// Whether processed files stay in the inbox
+    retain: bool,
+    /// Retained files already processed, so they aren't applied twice
+    processed: HashSet<PathBuf>,
+}
+
+impl DropFolder {
+    /// Watch `inbox`, moving processed files to its `done` and `failed` subdirectories
+    pub fn new(inbox: impl Into<PathBuf>) -> Self {
+        let inbox = inbox.into();
+        Self {
+            done: inbox.join("done"),
+            failed: inbox.join("failed"),
+            inbox,
+            columns: SummaryColumns::default(),
+            layout: SummaryLayout::default(),
+            retain: false,
+            processed: HashSet::new(),
+        }
+    }
+
+    /// Move successfully processed files to `dir`
+    pub fn with_done_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.done = dir.into();
+        self
+    }
+
+    /// Move corrupt files to `dir`
+    pub fn with_failed_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.failed = dir.into();
+        self
+    }
+
+    /// Leave processed files in the inbox, writing only their reports to the done and
+    /// failed directories
+    ///
+    /// Each file is then processed once per `DropFolder`, and again by a fresh one after
+    /// a restart. Use this when the engine state isn't persisted, so that it can be
+    /// rebuilt from the files.
+    ///
+    /// # Examples
+    /// ```
+    /// # use transaction_processor::{DropFolder, Engine};
+    /// let inbox = tempfile::tempdir().unwrap();
+    /// std::fs::write(
+    ///     inbox.path().join("batch1.csv"),
+    ///     "type,client,tx,amount\ndeposit,1,1,10.0\n",
+    /// )
+    /// .unwrap();
+    ///
+    /// let mut folder = DropFolder::new(inbox.path()).with_retained_files();
+    /// let mut engine = Engine::new();
+    /// assert_eq!(folder.process_pending(&mut engine).unwrap().len(), 1);
+    /// assert!(folder.process_pending(&mut engine).unwrap().is_empty());
+    ///
+    /// assert!(inbox.path().join("batch1.csv").exists());
+    /// assert!(inbox.path().join("done/batch1.csv.summary.csv").exists());
+    /// ```
+    pub fn with_retained_files(mut self) -> Self {
+        self.retain = true;
+        self
+    }
+
+    /// Columns of the per-file summary reports
+    pub fn with_columns(mut self, columns: SummaryColumns) -> Self {
+        self.columns = columns;
+        self
+    }
+
+    /// Layout of the per-file summary reports
+    pub fn with_layout(mut self, layout: SummaryLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// CSV files waiting in the inbox, in name order
+    ///
+    /// Retained files that were already processed are left out.
+    ///
+    /// # Errors
+    /// Returns an error if the inbox cannot be read.
+    pub fn pending_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.inbox)? {
+            let path = entry?.path();
+            if path.is_file()
+                && path.extension().is_some_and(|ext| ext == "csv")
+                && !self.processed.contains(&path)
+            {
+                files.push(path);
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Process every file currently in the inbox, in name order
+    ///
+    /// Each file is moved to the done or failed directory, unless files are retained.
+    /// In that directory, a `<name>.summary.csv` report holds the account summary after the file was applied
+    /// (done files only), and `<name>.errors.txt` lists any errors and warnings.
+    ///
+    /// # Errors
+    /// Returns an error if a file cannot be read, moved, or reported on. Files processed
+    /// before the failure stay processed.
+    pub fn process_pending(
+        &mut self,
+        engine: &mut Engine,
+    ) -> Result<Vec<ProcessedFile>, Box<dyn Error>> {
+        fs::create_dir_all(&self.done)?;
+        fs::create_dir_all(&self.failed)?;
+
+        let mut processed = Vec::new();
+        for path in self.pending_files()? {
+            processed.push(self.process_file(engine, &path)?);
+        }
+        Ok(processed)
+    }
+
+    /// Apply one file, rolling it back if it is corrupt, then move and report on it
+    fn process_file(
+        &mut self,
+        engine: &mut Engine,
+        path: &Path,
+    ) -> Result<ProcessedFile, Box<dyn Error>> {
+        let snapshot = engine.clone();
+        let (errors_before, warnings_before) = (engine.errors().len(), engine.warnings().len());
+        let unmatched_before = engine.unmatched().len();
+
+        let name = path.display().to_string();
+        let stats = engine.process(CsvSource::new(name, File::open(path)?));
+        let messages: Vec<String> = engine.errors()[errors_before..]
+            .iter()
+            .cloned()
+            .chain(
+                engine.unmatched()[unmatched_before..]
+                    .iter()
+                    .map(ToString::to_string),
+            )
+            .chain(engine.warnings()[warnings_before..].iter().cloned())
+            .collect();
+
+        let outcome = if stats.malformed > 0 {
+            engine.roll_back(snapshot)?;
+            FileOutcome::Failed
+        } else {
+            FileOutcome::Done
+        };
+        let dir = match outcome {
+            FileOutcome::Done => &self.done,
+            FileOutcome::Failed => &self.failed,
+        };
+
+        // Checked by pending_files: every candidate is a file with a name
+        let file_name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let destination = if self.retain {
+            self.processed.insert(path.to_path_buf());
+            path.to_path_buf()
+        } else {
+            let destination = dir.join(&file_name);
+            fs::rename(path, &destination)?;
+            destination
+        };
+
+        if outcome == FileOutcome::Done {
+            let report = File::create(dir.join(format!("{}.summary.csv", file_name)))?;
+            engine.report(
+                CsvSink::new(BufWriter::new(report))
+                    .with_columns(self.columns.clone())
+                    .with_layout(self.layout),
+            )?;
+        }
+        if !messages.is_empty() {
+            let mut log =
+                BufWriter::new(File::create(dir.join(format!("{}.errors.txt", file_name)))?);
+            for message in &messages {
+                writeln!(log, "{}", message)?;
+            }
+            log.flush()?;
+        }
+
+        Ok(ProcessedFile {
+            path: destination,
+            outcome,
+            stats,
+        })
+    }
+}
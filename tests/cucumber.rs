@@ -1,10 +1,12 @@
 use cucumber::{World, given, then, when};
-use transaction_processor::{Database, MyError, Transaction};
+use transaction_processor::{
+    AccountState, Database, EngineError, Policy, Transaction, TransactionMeta,
+};
 
 #[derive(Debug, Default, World)]
 pub struct DatabaseWorld {
     database: Database,
-    last_error: Option<MyError>,
+    last_error: Option<EngineError>,
 }
 
 impl DatabaseWorld {
@@ -12,11 +14,11 @@ impl DatabaseWorld {
         let summary = self
             .database
             .get_account(client_id)
-            .expect(&format!("Client {} should have an account", client_id));
+            .unwrap_or_else(|| panic!("Client {} should have an account", client_id));
 
         let actual = match balance_type {
-            "available" => summary.available.to_f64(),
-            "held" => summary.held.to_f64(),
+            "available" => summary.available().to_f64(),
+            "held" => summary.held().to_f64(),
             "total" => summary.total().to_f64(),
             _ => panic!("Unknown balance type: {}", balance_type),
         };
@@ -38,6 +40,70 @@ fn given_new_database(world: &mut DatabaseWorld) {
     world.last_error = None;
 }
 
+#[given("a new database that unlocks accounts on chargeback reversal")]
+fn given_new_database_unlocking_on_reversal(world: &mut DatabaseWorld) {
+    world.database = Database::with_policy(Policy {
+        unlock_on_chargeback_reversal: true,
+        ..Policy::default()
+    });
+    world.last_error = None;
+}
+
+#[given("a new database that allows withdrawal disputes")]
+fn given_new_database_with_withdrawal_disputes(world: &mut DatabaseWorld) {
+    world.database = Database::with_policy(Policy {
+        withdrawal_disputes: true,
+        ..Policy::default()
+    });
+    world.last_error = None;
+}
+
+#[given(regex = r"^a new database that unlocks accounts ([0-9]+) seconds after a chargeback$")]
+fn given_new_database_with_cooling_off(world: &mut DatabaseWorld, seconds: u64) {
+    world.database = Database::with_policy(Policy {
+        auto_unlock_after: Some(seconds),
+        ..Policy::default()
+    });
+    world.last_error = None;
+}
+
+#[given("a new database that unlocks accounts after review")]
+fn given_new_database_with_review_unlock(world: &mut DatabaseWorld) {
+    world.database = Database::with_policy(Policy {
+        unlock_requires_review: true,
+        ..Policy::default()
+    });
+    world.last_error = None;
+}
+
+#[given(regex = r"^a new database that reserves ([0-9]+) percent of deposits for ([0-9]+) days$")]
+fn given_new_database_with_reserve(world: &mut DatabaseWorld, percent: u8, days: u64) {
+    world.database = Database::with_policy(Policy {
+        reserve_percent: percent,
+        reserve_days: days,
+        ..Policy::default()
+    });
+    world.last_error = None;
+}
+
+#[given(regex = r"^a new database that requires approval for withdrawals over ([0-9.]+)$")]
+fn given_new_database_with_approval_threshold(world: &mut DatabaseWorld, threshold: String) {
+    world.database = Database::with_policy(Policy {
+        approval_threshold: Some(threshold.parse().expect("Invalid threshold")),
+        ..Policy::default()
+    });
+    world.last_error = None;
+}
+
+#[given("a new database that requires approval for unlocks")]
+fn given_new_database_with_unlock_approval(world: &mut DatabaseWorld) {
+    world.database = Database::with_policy(Policy {
+        unlocks_require_approval: true,
+        ..Policy::default()
+    });
+    world.last_error = None;
+}
+
 #[when(
     regex = r"^I process a deposit of ([0-9.]+) for client ([0-9]+) with transaction id ([0-9]+)$"
 )]
@@ -58,7 +124,7 @@ fn when_process_deposit(world: &mut DatabaseWorld, amount: String, client_id: u1
         .process_transaction(client_id, txn_id, transaction);
 
     if let Err(err) = result {
-        world.last_error = Some(err);
+        world.last_error = Some(err.kind);
     } else {
         world.last_error = None;
     }
@@ -84,7 +150,7 @@ fn when_process_withdrawal(world: &mut DatabaseWorld, amount: String, client_id:
         .process_transaction(client_id, txn_id, transaction);
 
     if let Err(err) = result {
-        world.last_error = Some(err);
+        world.last_error = Some(err.kind);
     } else {
         world.last_error = None;
     }
@@ -106,7 +172,7 @@ fn when_attempt_deposit(world: &mut DatabaseWorld, amount: String, client_id: u1
         .database
         .process_transaction(client_id, txn_id, transaction);
 
-    world.last_error = result.err();
+    world.last_error = result.err().map(|err| err.kind);
 }
 
 #[when(
@@ -125,7 +191,7 @@ fn when_attempt_withdrawal(world: &mut DatabaseWorld, amount: String, client_id:
         .database
         .process_transaction(client_id, txn_id, transaction);
 
-    world.last_error = result.err();
+    world.last_error = result.err().map(|err| err.kind);
 }
 
 #[when(regex = r"^I dispute transaction ([0-9]+) for client ([0-9]+)$")]
@@ -138,7 +204,7 @@ fn when_dispute_transaction(world: &mut DatabaseWorld, txn_id: u32, client_id: u
     );
 
     if let Err(err) = result {
-        world.last_error = Some(err);
+        world.last_error = Some(err.kind);
     } else {
         world.last_error = None;
     }
@@ -150,7 +216,20 @@ fn when_attempt_dispute(world: &mut DatabaseWorld, txn_id: u32, client_id: u16)
         .database
         .process_transaction(client_id, txn_id, Transaction::dispute());
 
-    world.last_error = result.err();
+    world.last_error = result.err().map(|err| err.kind);
+}
+
+#[when(regex = r"^I dispute transactions ([0-9,]+) for client ([0-9]+) together$")]
+fn when_dispute_bundle(world: &mut DatabaseWorld, txn_ids: String, client_id: u16) {
+    let bundle: Vec<(u16, u32)> = txn_ids
+        .split(',')
+        .map(|txn_id| (client_id, txn_id.parse().expect("Invalid transaction id")))
+        .collect();
+    let result = world
+        .database
+        .dispute_bundle(&bundle, TransactionMeta::default());
+
+    world.last_error = result.err().map(|err| err.kind);
 }
 
 #[when(regex = r"^I resolve transaction ([0-9]+) for client ([0-9]+)$")]
@@ -161,7 +240,7 @@ fn when_resolve_transaction(world: &mut DatabaseWorld, txn_id: u32, client_id: u
         .process_transaction(client_id, txn_id, Transaction::resolve());
 
     if let Err(err) = result {
-        world.last_error = Some(err);
+        world.last_error = Some(err.kind);
     } else {
         world.last_error = None;
     }
@@ -173,7 +252,7 @@ fn when_attempt_resolve(world: &mut DatabaseWorld, txn_id: u32, client_id: u16)
         .database
         .process_transaction(client_id, txn_id, Transaction::resolve());
 
-    world.last_error = result.err();
+    world.last_error = result.err().map(|err| err.kind);
 }
 
 #[when(regex = r"^I chargeback transaction ([0-9]+) for client ([0-9]+)$")]
@@ -184,7 +263,7 @@ fn when_chargeback_transaction(world: &mut DatabaseWorld, txn_id: u32, client_id
         .process_transaction(client_id, txn_id, Transaction::chargeback());
 
     if let Err(err) = result {
-        world.last_error = Some(err);
+        world.last_error = Some(err.kind);
     } else {
         world.last_error = None;
     }
@@ -196,7 +275,77 @@ fn when_attempt_chargeback(world: &mut DatabaseWorld, txn_id: u32, client_id: u1
         .database
         .process_transaction(client_id, txn_id, Transaction::chargeback());
 
-    world.last_error = result.err();
+    world.last_error = result.err().map(|err| err.kind);
+}
+
+#[when(regex = r"^I reverse the chargeback of transaction ([0-9]+) for client ([0-9]+)$")]
+#[given(regex = r"^I reverse the chargeback of transaction ([0-9]+) for client ([0-9]+)$")]
+fn when_reverse_chargeback(world: &mut DatabaseWorld, txn_id: u32, client_id: u16) {
+    let result =
+        world
+            .database
+            .process_transaction(client_id, txn_id, Transaction::chargeback_reversal());
+
+    world.last_error = result.err().map(|err| err.kind);
+}
+
+#[when(
+    regex = r"^I process a deposit of ([0-9.]+) for client ([0-9]+) with transaction id ([0-9]+) on day ([0-9]+)$"
+)]
+#[given(
+    regex = r"^I process a deposit of ([0-9.]+) for client ([0-9]+) with transaction id ([0-9]+) on day ([0-9]+)$"
+)]
+fn when_process_deposit_on_day(
+    world: &mut DatabaseWorld,
+    amount: String,
+    client_id: u16,
+    txn_id: u32,
+    day: u64,
+) {
+    let transaction = Transaction::deposit(&amount).expect("Invalid deposit amount");
+    let result =
+        world
+            .database
+            .process_transaction_at(client_id, txn_id, transaction, Some(day * 86_400));
+
+    world.last_error = result.err().map(|err| err.kind);
+}
+
+#[when(regex = r"^I chargeback transaction ([0-9]+) for client ([0-9]+) at time ([0-9]+)$")]
+#[given(regex = r"^I chargeback transaction ([0-9]+) for client ([0-9]+) at time ([0-9]+)$")]
+fn when_chargeback_transaction_at(
+    world: &mut DatabaseWorld,
+    txn_id: u32,
+    client_id: u16,
+    timestamp: u64,
+) {
+    let result = world.database.process_transaction_at(
+        client_id,
+        txn_id,
+        Transaction::chargeback(),
+        Some(timestamp),
+    );
+
+    world.last_error = result.err().map(|err| err.kind);
+}
+
+#[when(regex = r#"^I mark the account for client ([0-9]+) as reviewed by "([^"]*)"$"#)]
+#[given(regex = r#"^I mark the account for client ([0-9]+) as reviewed by "([^"]*)"$"#)]
+fn when_mark_reviewed(world: &mut DatabaseWorld, client_id: u16, reviewer: String) {
+    world.last_error = world
+        .database
+        .mark_reviewed(client_id, &reviewer, None)
+        .err();
+}
+
+#[when(regex = r"^I apply automatic unlocks at time ([0-9]+)$")]
+fn when_apply_auto_unlocks(world: &mut DatabaseWorld, now: u64) {
+    world.database.apply_auto_unlocks(now);
+}
+
+#[when(regex = r"^I release matured reserves on day ([0-9]+)$")]
+fn when_release_matured_reserves(world: &mut DatabaseWorld, day: u64) {
+    world.database.release_matured_reserves(day * 86_400);
 }
 
 #[then(regex = r"^the available balance for client ([0-9]+) should be ([-]?[0-9.]+)$")]
@@ -222,9 +371,9 @@ fn then_account_not_locked(world: &mut DatabaseWorld, client_id: u16) {
     let account = world
         .database
         .get_account(client_id)
-        .expect(&format!("Client {} should have an account", client_id));
+        .unwrap_or_else(|| panic!("Client {} should have an account", client_id));
     assert!(
-        !account.locked,
+        !account.is_locked(),
         "Account for client {} should not be locked",
         client_id
     );
@@ -235,14 +384,68 @@ fn then_account_locked(world: &mut DatabaseWorld, client_id: u16) {
     let account = world
         .database
         .get_account(client_id)
-        .expect(&format!("Client {} should have an account", client_id));
+        .unwrap_or_else(|| panic!("Client {} should have an account", client_id));
     assert!(
-        account.locked,
+        account.is_locked(),
         "Account for client {} should be locked",
         client_id
     );
 }
 
+#[when(regex = r"^I set the state of client ([0-9]+) to ([a-z_]+)$")]
+#[given(regex = r"^I set the state of client ([0-9]+) to ([a-z_]+)$")]
+fn when_set_account_state(world: &mut DatabaseWorld, client_id: u16, state: AccountState) {
+    world.last_error = world.database.set_account_state(client_id, state).err();
+}
+
+#[when(regex = r"^I merge client ([0-9]+) into client ([0-9]+) from transaction id ([0-9]+)$")]
+#[given(regex = r"^I merge client ([0-9]+) into client ([0-9]+) from transaction id ([0-9]+)$")]
+fn when_merge_accounts(world: &mut DatabaseWorld, from: u16, into: u16, new_tx_base: u32) {
+    world.last_error = world.database.merge_accounts(from, into, new_tx_base).err();
+}
+
+#[when(regex = r"^I move transactions ([0-9,]+) from client ([0-9]+) to new client ([0-9]+)$")]
+fn when_split_account(world: &mut DatabaseWorld, txn_ids: String, from: u16, new_client: u16) {
+    let txs: Vec<u32> = txn_ids
+        .split(',')
+        .map(|txn_id| txn_id.parse().expect("Invalid transaction id"))
+        .collect();
+    world.last_error = world.database.split_account(from, new_client, &txs).err();
+}
+
+#[when(regex = r#"^I (approve|reject) the pending operation as "([^"]*)"$"#)]
+fn when_decide_pending(world: &mut DatabaseWorld, decision: String, approver: String) {
+    let id = world
+        .database
+        .pending_approvals()
+        .last()
+        .expect("Expected a pending operation")
+        .id;
+    let result = match decision.as_str() {
+        "approve" => world.database.approve(id, &approver),
+        _ => world.database.reject(id, &approver),
+    };
+    world.last_error = result.err();
+}
+
+#[then(regex = r"^there should be ([0-9]+) operations? pending approval$")]
+fn then_pending_count(world: &mut DatabaseWorld, expected: usize) {
+    assert_eq!(world.database.pending_approvals().len(), expected);
+}
+
+#[then(regex = r"^the state of client ([0-9]+) should be ([a-z_]+)$")]
+fn then_account_state(world: &mut DatabaseWorld, client_id: u16, expected: AccountState) {
+    let account = world
+        .database
+        .get_account(client_id)
+        .unwrap_or_else(|| panic!("Client {} should have an account", client_id));
+    assert_eq!(
+        account.state(), expected,
+        "Account for client {} should be {}",
+        client_id, expected
+    );
+}
+
 #[then(regex = r#"^the transaction should fail with "([^"]*)"$"#)]
 fn then_transaction_should_fail(world: &mut DatabaseWorld, expected_error: String) {
     let error = world
@@ -279,7 +482,7 @@ fn when_attempt_deposit_quoted(
     let result = world
         .database
         .process_transaction(client_id, txn_id, transaction);
-    world.last_error = result.err();
+    world.last_error = result.err().map(|err| err.kind);
 }
 
 #[when(
@@ -302,7 +505,7 @@ fn when_attempt_withdrawal_quoted(
     let result = world
         .database
         .process_transaction(client_id, txn_id, transaction);
-    world.last_error = result.err();
+    world.last_error = result.err().map(|err| err.kind);
 }
 
 #[tokio::test]
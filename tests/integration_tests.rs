@@ -2,11 +2,24 @@ use std::io::Write;
 use tempfile::NamedTempFile;
 
 // Import the CSV processing function from main.rs
-use transaction_processor::process_csv_file;
+use transaction_processor::{
+    AccountState, Action, AsOf, AuditEvent, ChargebackLimit, ConflictPolicy, CsvDialect,
+    CsvProcessor, CsvSink, CsvSource, Database, DisputeAction, DisputeState, DropFolder, Engine,
+    EngineError, EntryKind, ErrorClass, ErrorCode, EventLog, FileOutcome, Fixed4, Journal,
+    JsonFileStorage, JsonSink, LimitAction, LockReason, MergeError, Outbox, OutputFormat, Policy,
+    PolicyError, RejectedFields, Rejection, Reordered, ReplayPoint, Role, SCENARIOS, Sample,
+    Sampled, Scenario, Snapshot, SnapshotError, SnapshotStore, SummaryColumns, SummaryLayout,
+    SummaryQuery, TokenBucket, Transaction, TransactionMeta, process_csv_file,
+    process_csv_files_parallel, process_csv_outcome, process_csv_sample, process_csv_str, selftest,
+    write_account_summaries_json, write_dispute_graph, write_disputes_after_withdrawal,
+    write_rejections_json, write_running_balances, write_sql, write_unmatched_references,
+};
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "testing")]
+    use transaction_processor::{Interleavings, Row, interleave};
 
     fn create_temp_csv(content: &str) -> NamedTempFile {
         let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
@@ -26,7 +39,7 @@ withdrawal,1,4,1.5
 withdrawal,2,5,3.0"#;
 
         let temp_file = create_temp_csv(csv_content);
-        let (database, errors) = process_csv_file(temp_file.path().to_str().unwrap()).unwrap();
+        let (database, errors, _) = process_csv_file(temp_file.path().to_str().unwrap()).unwrap();
 
         // Should have one error (insufficient funds for client 2)
         assert_eq!(errors.len(), 1);
@@ -35,17 +48,17 @@ withdrawal,2,5,3.0"#;
 
         // Resolve should move funds back to available
         let account1 = database.get_account(1).unwrap();
-        assert_eq!(account1.available.to_f64(), 1.5);
-        assert_eq!(account1.held.to_f64(), 0.0);
+        assert_eq!(account1.available().to_f64(), 1.5);
+        assert_eq!(account1.held().to_f64(), 0.0);
         assert_eq!(account1.total().to_f64(), 1.5);
-        assert!(!account1.locked);
+        assert!(!account1.is_locked());
 
         // Check client 2: deposited 2.0, withdrawal failed, should still have 2.0
         let account2 = database.get_account(2).unwrap();
-        assert_eq!(account2.available.to_f64(), 2.0);
-        assert_eq!(account2.held.to_f64(), 0.0);
+        assert_eq!(account2.available().to_f64(), 2.0);
+        assert_eq!(account2.held().to_f64(), 0.0);
         assert_eq!(account2.total().to_f64(), 2.0);
-        assert!(!account2.locked);
+        assert!(!account2.is_locked());
     }
 
     #[test]
@@ -62,8 +75,7 @@ deposit,1,6,1.0
 dispute,1,3,
 chargeback,1,3,"#;
 
-        let temp_file = create_temp_csv(csv_content);
-        let (database, errors) = process_csv_file(temp_file.path().to_str().unwrap()).unwrap();
+        let (database, errors, _) = process_csv_str(csv_content);
 
         // Should have one error (insufficient funds for client 2)
         assert_eq!(errors.len(), 1);
@@ -71,15 +83,169 @@ chargeback,1,3,"#;
 
         // Verify final state after chargeback
         let account1 = database.get_account(1).unwrap();
-        assert_eq!(account1.available.to_f64(), 0.5);
-        assert_eq!(account1.held.to_f64(), 0.0);
+        assert_eq!(account1.available().to_f64(), 0.5);
+        assert_eq!(account1.held().to_f64(), 0.0);
         assert_eq!(account1.total().to_f64(), 0.5);
-        assert!(account1.locked);
+        assert!(account1.is_locked());
 
         // Check client 2: unchanged
         let account2 = database.get_account(2).unwrap();
-        assert_eq!(account2.available.to_f64(), 2.0);
-        assert!(!account2.locked);
+        assert_eq!(account2.available().to_f64(), 2.0);
+        assert!(!account2.is_locked());
+    }
+
+    #[test]
+    fn test_error_context_identifies_transaction() {
+        let csv_content = r#"type,client,tx,amount
+deposit,1,1,10.0
+withdrawal,1,2,25.0
+dispute,1,1,
+dispute,1,1,"#;
+
+        let (_, errors, _) = process_csv_str(csv_content);
+
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("Insufficient funds (client 1, tx 2, amount 25.0000)"));
+        // Disputes carry the amount of the deposit they reference
+        assert!(errors[1].contains("Transaction already disputed (client 1, tx 1, amount 10.0000)"));
+    }
+
+    #[test]
+    fn test_warnings_reported_separately() {
+        let csv_content = r#"type,client,tx,amount
+deposit,1,1,10.0
+deposit,1,1,10.0
+deposit,1,2,5.0
+dispute,1,2,5.0
+withdrawal,1,3,20.0"#;
+
+        let temp_file = create_temp_csv(csv_content);
+        let (database, errors, warnings) =
+            process_csv_file(temp_file.path().to_str().unwrap()).unwrap();
+
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("Insufficient funds"));
+
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains(":3") && warnings[0].contains("Duplicate row skipped"));
+        assert!(warnings[1].contains(":5") && warnings[1].contains("Amount ignored"));
+
+        // The duplicate deposit was not applied twice; the dispute still went through
+        let account1 = database.get_account(1).unwrap();
+        assert_eq!(account1.available().to_f64(), 10.0);
+        assert_eq!(account1.held().to_f64(), 5.0);
+    }
+
+    #[test]
+    fn test_timestamp_column_recorded_in_statement() {
+        let csv_content = r#"type,client,tx,amount,timestamp
+deposit,1,1,10.0,1700000000
+dispute,1,1,,1700000500
+chargeback,1,1,,1700000900"#;
+
+        let (database, errors, _) = process_csv_str(csv_content);
+        assert_eq!(errors.len(), 0);
+
+        let statement = database.statement(1).unwrap();
+        let deposit = &statement.lines[0];
+        assert_eq!(deposit.timestamp, Some(1700000000));
+        assert_eq!(deposit.dispute_state, Some(DisputeState::ChargedBack));
+
+        let timestamps: Vec<_> = deposit.transitions.iter().map(|t| t.timestamp).collect();
+        assert_eq!(timestamps, vec![Some(1700000500), Some(1700000900)]);
+    }
+
+    #[test]
+    fn test_running_balances_export() {
+        let csv_content = r#"type,client,tx,amount
+deposit,2,3,5.0
+deposit,1,1,10.0
+withdrawal,1,2,4.0
+withdrawal,1,4,100.0
+dispute,1,1,
+resolve,1,1,"#;
+
+        let (database, errors, _) = process_csv_str(csv_content);
+        assert_eq!(errors.len(), 1);
+
+        let mut output = Vec::new();
+        write_running_balances(&database, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,tx,type,timestamp,available,held
+1,1,deposit,,10.0000,0.0000
+1,2,withdrawal,,6.0000,0.0000
+1,1,dispute,,-4.0000,10.0000
+1,1,resolve,,6.0000,0.0000
+2,3,deposit,,5.0000,0.0000
+"
+        );
+    }
+
+    #[test]
+    fn test_drop_folder_rolls_back_corrupt_files() {
+        let inbox = tempfile::tempdir().unwrap();
+        std::fs::write(
+            inbox.path().join("1-good.csv"),
+            "type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,50.0\n",
+        )
+        .unwrap();
+        std::fs::write(
+            inbox.path().join("2-corrupt.csv"),
+            "type,client,tx,amount\ndeposit,1,3,5.0\ndeposit,not-a-client,4,5.0\n",
+        )
+        .unwrap();
+        std::fs::write(inbox.path().join("notes.txt"), "ignored").unwrap();
+
+        let mut engine = Engine::new();
+        let processed = DropFolder::new(inbox.path())
+            .process_pending(&mut engine)
+            .unwrap();
+
+        let outcomes: Vec<_> = processed.iter().map(|file| file.outcome).collect();
+        assert_eq!(outcomes, vec![FileOutcome::Done, FileOutcome::Failed]);
+
+        // The refused withdrawal is reported but doesn't fail the file
+        assert!(inbox.path().join("done/1-good.csv").exists());
+        assert!(inbox.path().join("done/1-good.csv.errors.txt").exists());
+        // The corrupt file's valid deposit was rolled back
+        assert!(inbox.path().join("failed/2-corrupt.csv").exists());
+        assert!(inbox.path().join("failed/2-corrupt.csv.errors.txt").exists());
+        assert!(inbox.path().join("notes.txt").exists());
+        let account = engine.database().get_account(1).unwrap();
+        assert_eq!(account.available().to_string(), "10.0000");
+    }
+
+    #[test]
+    fn test_case_id_column_links_dispute_events() {
+        let csv_content = r#"type,client,tx,amount,case_id
+deposit,1,1,10.0,
+deposit,2,2,20.0,
+dispute,1,1,,FRAUD-1
+dispute,2,2,,FRAUD-1
+resolve,1,1,,FRAUD-1
+chargeback,2,2,,FRAUD-1
+dispute,1,1,,OTHER"#;
+
+        let (database, errors, _) = process_csv_str(csv_content);
+        assert_eq!(errors.len(), 0);
+
+        let events: Vec<_> = database
+            .disputes_by_case("FRAUD-1")
+            .iter()
+            .map(|event| (event.client, event.tx, event.action))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (1, 1, DisputeAction::Dispute),
+                (2, 2, DisputeAction::Dispute),
+                (1, 1, DisputeAction::Resolve),
+                (2, 2, DisputeAction::Chargeback),
+            ]
+        );
+        assert_eq!(database.disputes_by_case("OTHER").len(), 1);
+        assert!(database.disputes_by_case("MISSING").is_empty());
     }
 
     #[test]
@@ -90,8 +256,7 @@ invalid_transaction,2,2,2.0
 deposit,1,3,abc
 deposit,3,4,5.0"#;
 
-        let temp_file = create_temp_csv(csv_content);
-        let (database, errors) = process_csv_file(temp_file.path().to_str().unwrap()).unwrap();
+        let (database, errors, _) = process_csv_str(csv_content);
 
         // Should have 2 errors
         assert_eq!(errors.len(), 2);
@@ -105,10 +270,10 @@ deposit,3,4,5.0"#;
 
         // Check that valid transactions still processed
         let account1 = database.get_account(1).unwrap();
-        assert_eq!(account1.available.to_f64(), 1.0);
+        assert_eq!(account1.available().to_f64(), 1.0);
 
         let account3 = database.get_account(3).unwrap();
-        assert_eq!(account3.available.to_f64(), 5.0);
+        assert_eq!(account3.available().to_f64(), 5.0);
     }
 
     #[test]
@@ -119,8 +284,7 @@ deposit,not_a_number,2,2.0
 deposit,3,not_a_number,1.5
 deposit,4,3,0.5"#;
 
-        let temp_file = create_temp_csv(csv_content);
-        let (database, errors) = process_csv_file(temp_file.path().to_str().unwrap()).unwrap();
+        let (database, errors, _) = process_csv_str(csv_content);
 
         // Should have 2 CSV parsing errors
         assert_eq!(errors.len(), 2);
@@ -131,10 +295,10 @@ deposit,4,3,0.5"#;
 
         // Check that valid transactions still processed
         let account1 = database.get_account(1).unwrap();
-        assert_eq!(account1.available.to_f64(), 1.0);
+        assert_eq!(account1.available().to_f64(), 1.0);
 
         let account4 = database.get_account(4).unwrap();
-        assert_eq!(account4.available.to_f64(), 0.5);
+        assert_eq!(account4.available().to_f64(), 0.5);
     }
 
     #[test]
@@ -145,8 +309,7 @@ deposit,1,2,0.9999
 deposit,2,3,123.45678
 withdrawal,1,4,1.0"#;
 
-        let temp_file = create_temp_csv(csv_content);
-        let (database, errors) = process_csv_file(temp_file.path().to_str().unwrap()).unwrap();
+        let (database, errors, _) = process_csv_str(csv_content);
 
         // Should have one error for too many decimal places
         assert_eq!(errors.len(), 1);
@@ -154,7 +317,7 @@ withdrawal,1,4,1.0"#;
 
         // Check precision handling
         let account1 = database.get_account(1).unwrap();
-        assert_eq!(account1.available.to_f64(), 0.0); // 0.0001 + 0.9999 - 1.0 = 0.0
+        assert_eq!(account1.available().to_f64(), 0.0); // 0.0001 + 0.9999 - 1.0 = 0.0
         assert_eq!(account1.total().to_f64(), 0.0);
 
         // Client 2 transaction should have failed due to precision error
@@ -165,8 +328,7 @@ withdrawal,1,4,1.0"#;
     fn test_empty_file() {
         let csv_content = "type,client,tx,amount\n";
 
-        let temp_file = create_temp_csv(csv_content);
-        let (database, errors) = process_csv_file(temp_file.path().to_str().unwrap()).unwrap();
+        let (database, errors, _) = process_csv_str(csv_content);
 
         // No errors, no accounts
         assert_eq!(errors.len(), 0);
@@ -179,8 +341,7 @@ withdrawal,1,4,1.0"#;
 deposit,1,1,100.0
 dispute,1,999,"#;
 
-        let temp_file = create_temp_csv(csv_content);
-        let (database, errors) = process_csv_file(temp_file.path().to_str().unwrap()).unwrap();
+        let (database, errors, _) = process_csv_str(csv_content);
 
         // Should have one error for transaction not found
         assert_eq!(errors.len(), 1);
@@ -189,8 +350,8 @@ dispute,1,999,"#;
 
         // Original deposit should still be there
         let account1 = database.get_account(1).unwrap();
-        assert_eq!(account1.available.to_f64(), 100.0);
-        assert!(!account1.locked);
+        assert_eq!(account1.available().to_f64(), 100.0);
+        assert!(!account1.is_locked());
     }
 
     #[test]
@@ -200,14 +361,13 @@ deposit,1,1,100.0
 withdrawal,1,2,25.5
 deposit,1,3,50.0"#;
 
-        let temp_file = create_temp_csv(csv_content);
-        let (database, errors) = process_csv_file(temp_file.path().to_str().unwrap()).unwrap();
+        let (database, errors, _) = process_csv_str(csv_content);
 
         assert_eq!(errors.len(), 0);
 
         let account1 = database.get_account(1).unwrap();
         // Verify final balance
-        assert_eq!(account1.available.to_f64(), 124.5); // 100 - 25.5 + 50
+        assert_eq!(account1.available().to_f64(), 124.5); // 100 - 25.5 + 50
 
         // Verify all transactions are stored
         assert_eq!(account1.transaction_count(), 3);
@@ -224,16 +384,15 @@ deposit, 1, 1, 1.0
 deposit, 2, 2, 2.0
 withdrawal, 1, 4, 0.5"#;
 
-        let temp_file = create_temp_csv(csv_content);
-        let (database, errors) = process_csv_file(temp_file.path().to_str().unwrap()).unwrap();
+        let (database, errors, _) = process_csv_str(csv_content);
 
         assert_eq!(errors.len(), 0);
 
         let account1 = database.get_account(1).unwrap();
-        assert_eq!(account1.available.to_f64(), 0.5); // 1.0 - 0.5
+        assert_eq!(account1.available().to_f64(), 0.5); // 1.0 - 0.5
 
         let account2 = database.get_account(2).unwrap();
-        assert_eq!(account2.available.to_f64(), 2.0);
+        assert_eq!(account2.available().to_f64(), 2.0);
     }
 
     #[test]
@@ -245,15 +404,14 @@ withdrawal, 1, 4, 0.5"#;
                           dispute,1, 1 ,\n\
                           resolve, 1,1,";
 
-        let temp_file = create_temp_csv(csv_content);
-        let (database, errors) = process_csv_file(temp_file.path().to_str().unwrap()).unwrap();
+        let (database, errors, _) = process_csv_str(csv_content);
 
         assert_eq!(errors.len(), 0);
 
         let account1 = database.get_account(1).unwrap();
         // After dispute and resolve, should have original balance
-        assert_eq!(account1.available.to_f64(), 75.25); // 100.50 - 25.25
-        assert_eq!(account1.held.to_f64(), 0.0);
+        assert_eq!(account1.available().to_f64(), 75.25); // 100.50 - 25.25
+        assert_eq!(account1.held().to_f64(), 0.0);
     }
 
     #[test]
@@ -264,16 +422,15 @@ deposit,1,1,  123.45
 deposit,2,2,	99.99	
 withdrawal,1,3,  50.00"#;
 
-        let temp_file = create_temp_csv(csv_content);
-        let (database, errors) = process_csv_file(temp_file.path().to_str().unwrap()).unwrap();
+        let (database, errors, _) = process_csv_str(csv_content);
 
         assert_eq!(errors.len(), 0);
 
         let account1 = database.get_account(1).unwrap();
-        assert_eq!(account1.available.to_f64(), 73.45); // 123.45 - 50.00
+        assert_eq!(account1.available().to_f64(), 73.45); // 123.45 - 50.00
 
         let account2 = database.get_account(2).unwrap();
-        assert_eq!(account2.available.to_f64(), 99.99);
+        assert_eq!(account2.available().to_f64(), 99.99);
     }
 
     #[test]
@@ -286,17 +443,16 @@ withdrawal,1,3,  50.00"#;
 	resolve	,1,1,
  chargeback ,1,1,"#;
 
-        let temp_file = create_temp_csv(csv_content);
-        let (database, errors) = process_csv_file(temp_file.path().to_str().unwrap()).unwrap();
+        let (database, errors, _) = process_csv_str(csv_content);
 
         // Should have one error - chargeback after resolve puts transaction in normal state
         assert_eq!(errors.len(), 1);
         assert!(errors[0].contains("Transaction is not disputed"));
 
         let account1 = database.get_account(1).unwrap();
-        assert_eq!(account1.available.to_f64(), 75.0); // 100.0 - 25.0 (after resolve)
-        assert_eq!(account1.held.to_f64(), 0.0);
-        assert!(!account1.locked); // Chargeback failed, so not locked
+        assert_eq!(account1.available().to_f64(), 75.0); // 100.0 - 25.0 (after resolve)
+        assert_eq!(account1.held().to_f64(), 0.0);
+        assert!(!account1.is_locked()); // Chargeback failed, so not locked
     }
 
     #[test]
@@ -309,8 +465,7 @@ deposit, 1, 3, 2.0
 withdrawal, 1, 4, 1.5
 withdrawal, 2, 5, 3.0"#;
 
-        let temp_file = create_temp_csv(csv_content);
-        let (database, errors) = process_csv_file(temp_file.path().to_str().unwrap()).unwrap();
+        let (database, errors, _) = process_csv_str(csv_content);
 
         // Should have one error (insufficient funds for client 2's withdrawal)
         assert_eq!(errors.len(), 1);
@@ -318,12 +473,2126 @@ withdrawal, 2, 5, 3.0"#;
 
         // Client 1: 1.0 + 2.0 - 1.5 = 1.5
         let account1 = database.get_account(1).unwrap();
-        assert_eq!(account1.available.to_f64(), 1.5);
+        assert_eq!(account1.available().to_f64(), 1.5);
         assert_eq!(account1.total().to_f64(), 1.5);
 
         // Client 2: 2.0 (withdrawal of 3.0 failed)
         let account2 = database.get_account(2).unwrap();
-        assert_eq!(account2.available.to_f64(), 2.0);
+        assert_eq!(account2.available().to_f64(), 2.0);
         assert_eq!(account2.total().to_f64(), 2.0);
     }
+
+    #[test]
+    fn test_rate_limited_engine() {
+        let mut csv_content = String::from("type,client,tx,amount\n");
+        for tx in 1..=20 {
+            csv_content.push_str(&format!("deposit,1,{},1.0\n", tx));
+        }
+
+        // 10 rows go through in the initial burst, the other 10 at 100 per second
+        let mut engine = Engine::new().with_rate_limit(TokenBucket::new(100).with_burst(10));
+        let start = std::time::Instant::now();
+        let stats = engine.process(CsvSource::new("input", csv_content.as_bytes()));
+
+        assert_eq!(stats.applied, 20);
+        assert!(start.elapsed() >= std::time::Duration::from_millis(90));
+        assert_eq!(engine.database().get_account(1).unwrap().available().to_f64(), 20.0);
+    }
+
+    #[test]
+    fn test_event_log_replay_until() {
+        let csv_content = r#"type,client,tx,amount,timestamp
+deposit,7,1,100.0,1000
+deposit,7,2,50.0,2000
+dispute,7,1,,3000
+withdrawal,7,3,500.0,4000
+withdrawal,7,4,20.0,5000"#;
+
+        let temp_file = create_temp_csv(csv_content);
+        let mut engine = Engine::new().with_event_log();
+        engine.process(CsvSource::from_path(temp_file.path().to_str().unwrap()).unwrap());
+
+        let log_file = NamedTempFile::new().unwrap();
+        engine
+            .event_log()
+            .unwrap()
+            .write_csv(log_file.reopen().unwrap())
+            .unwrap();
+        let log = EventLog::read_csv("log", log_file.reopen().unwrap()).unwrap();
+
+        // The refused withdrawal was never applied, so it isn't logged
+        assert_eq!(log.events().len(), 4);
+
+        let replay_until = |point| {
+            let mut replayed = Engine::new();
+            replayed.process(log.replay(Some(point)));
+            let account = replayed.database().get_account(7).unwrap().clone();
+            (account.available().to_f64(), account.held().to_f64())
+        };
+        assert_eq!(replay_until(ReplayPoint::Tx(2)), (150.0, 0.0));
+        assert_eq!(replay_until(ReplayPoint::Time(3500)), (50.0, 100.0));
+        assert_eq!(replay_until(ReplayPoint::Time(9999)), (30.0, 100.0));
+    }
+
+    #[test]
+    fn test_backdated_correction_as_of_both_times() {
+        // A fee owed since day 2 is only posted on day 5
+        let csv_content = r#"type,client,tx,amount,timestamp,effective_at
+deposit,1,1,100.0,86400,
+withdrawal,1,2,30.0,259200,
+withdrawal,1,3,10.0,432000,172800"#;
+
+        let temp_file = create_temp_csv(csv_content);
+        let mut engine = Engine::new().with_event_log();
+        engine.process(CsvSource::from_path(temp_file.path().to_str().unwrap()).unwrap());
+        assert_eq!(engine.database().get_account(1).unwrap().available().to_f64(), 60.0);
+
+        let log = engine.event_log().unwrap();
+        let available = |effective, recorded| {
+            let db = log.state_as_of(AsOf { effective, recorded });
+            db.get_account(1).unwrap().available().to_f64()
+        };
+        // Day 2 balance as reported on day 3, and as corrected
+        assert_eq!(available(Some(172800), Some(259200)), 100.0);
+        assert_eq!(available(Some(172800), None), 90.0);
+        // Day 3 balance as corrected includes both withdrawals
+        assert_eq!(available(Some(259200), None), 60.0);
+    }
+
+    #[test]
+    fn test_outbox_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = Engine::new().with_event_log();
+
+        // Two batches appended by one process, with an initiator recorded on the second
+        engine.process(CsvSource::new(
+            "batch1",
+            "type,client,tx,amount\ndeposit,1,1,10.0\n".as_bytes(),
+        ));
+        let mut outbox = Outbox::open(dir.path()).unwrap();
+        outbox.append(engine.event_log().unwrap().events()).unwrap();
+        engine.process(CsvSource::new(
+            "batch2",
+            "type,client,tx,amount,initiator\nwithdrawal,1,2,4.0,ops:alice\n".as_bytes(),
+        ));
+        outbox
+            .append(&engine.event_log().unwrap().events()[1..])
+            .unwrap();
+        outbox.deliver(|_| Ok(())).unwrap();
+
+        // A third batch lands, then the publisher restarts before delivering it
+        engine.process(CsvSource::new(
+            "batch3",
+            "type,client,tx,amount\ndeposit,2,3,1.0\n".as_bytes(),
+        ));
+        outbox
+            .append(&engine.event_log().unwrap().events()[2..])
+            .unwrap();
+
+        let outbox = Outbox::open(dir.path()).unwrap();
+        let pending: Vec<u32> = outbox.pending().iter().map(|event| event.tx).collect();
+        assert_eq!(pending, vec![3]);
+
+        let all = EventLog::read_csv(
+            "outbox",
+            std::fs::File::open(dir.path().join("events.csv")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(all.events().len(), 3);
+        assert_eq!(all.events()[1].meta.initiator.as_deref(), Some("ops:alice"));
+    }
+
+    #[test]
+    fn test_redelivered_offsets_skipped_after_restart() {
+        let batch = r#"type,client,tx,amount,offset
+deposit,1,1,10.0,payments/0@100
+deposit,1,2,5.0,payments/1@7
+withdrawal,1,3,3.0,payments/0@101"#;
+
+        let temp_file = create_temp_csv(batch);
+        let mut engine = Engine::new().with_event_log();
+        engine.process(CsvSource::from_path(temp_file.path().to_str().unwrap()).unwrap());
+
+        let log_file = NamedTempFile::new().unwrap();
+        engine
+            .event_log()
+            .unwrap()
+            .write_csv(log_file.reopen().unwrap())
+            .unwrap();
+
+        // Restart: replaying the log restores the balances and the committed offsets
+        let log = EventLog::read_csv("log", log_file.reopen().unwrap()).unwrap();
+        let mut restarted = Engine::new();
+        restarted.process(log.replay(None));
+        assert_eq!(restarted.committed_offset("payments", 0), Some(101));
+        assert_eq!(restarted.committed_offset("payments", 1), Some(7));
+
+        // The queue redelivers partition 0 from offset 101
+        let redelivery = r#"type,client,tx,amount,offset
+withdrawal,1,3,3.0,payments/0@101
+deposit,1,4,1.0,payments/0@102"#;
+        let temp_file = create_temp_csv(redelivery);
+        let stats =
+            restarted.process(CsvSource::from_path(temp_file.path().to_str().unwrap()).unwrap());
+
+        assert_eq!((stats.applied, stats.redelivered), (1, 1));
+        assert_eq!(restarted.database().get_account(1).unwrap().available().to_f64(), 13.0);
+        assert_eq!(restarted.committed_offset("payments", 0), Some(102));
+    }
+
+    #[test]
+    fn test_snapshot_store_restores_disputes_and_offsets() {
+        let dir = tempfile::tempdir().unwrap();
+        let batch = r#"type,client,tx,amount,offset
+deposit,1,1,100.0,payments/0@1
+deposit,1,2,50.0,payments/0@2
+dispute,1,1,,payments/0@3"#;
+
+        let temp_file = create_temp_csv(batch);
+        let mut engine = Engine::new().with_event_log();
+        let mut store = SnapshotStore::open(dir.path(), &mut engine).unwrap();
+        engine.process(CsvSource::from_path(temp_file.path().to_str().unwrap()).unwrap());
+        store.compact(&mut engine).unwrap();
+
+        let mut restarted = Engine::new().with_event_log();
+        SnapshotStore::open(dir.path(), &mut restarted).unwrap();
+        assert_eq!(restarted.committed_offset("payments", 0), Some(3));
+
+        // The dispute survived the snapshot, so it can still be resolved
+        let redelivery = r#"type,client,tx,amount,offset
+dispute,1,1,,payments/0@3
+resolve,1,1,,payments/0@4"#;
+        let temp_file = create_temp_csv(redelivery);
+        let stats =
+            restarted.process(CsvSource::from_path(temp_file.path().to_str().unwrap()).unwrap());
+        assert_eq!((stats.applied, stats.redelivered), (1, 1));
+
+        let account = restarted.database().get_account(1).unwrap();
+        assert_eq!((account.available().to_f64(), account.held().to_f64()), (150.0, 0.0));
+        assert_eq!(account.balance_history().len(), 4);
+    }
+
+    #[test]
+    fn test_background_snapshot_while_ingesting() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = Engine::new().with_event_log();
+        let mut store = SnapshotStore::open(dir.path(), &mut engine)
+            .unwrap()
+            .with_compact_every(10)
+            .with_background_writes();
+
+        for tx in 1..=25u32 {
+            let input = format!("type,client,tx,amount\ndeposit,{},{},1.0\n", tx % 3, tx);
+            engine.process(CsvSource::new("input", input.as_bytes()));
+            store.save(&mut engine).unwrap();
+        }
+        store.wait().unwrap();
+        assert!(store.generation() >= 1);
+        // The events covered by a snapshot were dropped from memory
+        assert!(engine.event_log().unwrap().events().len() <= 15);
+
+        let mut restarted = Engine::new().with_event_log();
+        SnapshotStore::open(dir.path(), &mut restarted).unwrap();
+        let total: f64 = (0..3)
+            .map(|client| restarted.database().get_account(client).unwrap().available().to_f64())
+            .sum();
+        assert_eq!(total, 25.0);
+    }
+
+    #[test]
+    fn test_snapshot_checksum_and_legacy_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = Engine::new().with_event_log();
+        let mut store = SnapshotStore::open(dir.path(), &mut engine).unwrap();
+        engine.process(CsvSource::new(
+            "input",
+            "type,client,tx,amount\ndeposit,1,1,10.0\n".as_bytes(),
+        ));
+        store.compact(&mut engine).unwrap();
+
+        let path = dir.path().join("snapshot.json");
+        let written = std::fs::read_to_string(&path).unwrap();
+        let (header, body) = written.split_once('\n').unwrap();
+        assert!(header.starts_with("TPSNAP 0001 "));
+
+        // A changed amount no longer matches the checksum
+        std::fs::write(&path, written.replace("10.0000", "99.0000")).unwrap();
+        let error = SnapshotStore::open(dir.path(), &mut Engine::new().with_event_log())
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<SnapshotError>(),
+            Some(SnapshotError::ChecksumMismatch)
+        ));
+
+        // Snapshots written before the header was introduced are migrated
+        std::fs::write(&path, body).unwrap();
+        let mut restored = Engine::new().with_event_log();
+        SnapshotStore::open(dir.path(), &mut restored).unwrap();
+        assert_eq!(restored.database().get_account(1).unwrap().available().to_f64(), 10.0);
+    }
+
+    #[test]
+    fn test_disputes_after_withdrawal_report() {
+        let csv_content = r#"type,client,tx,amount,timestamp
+deposit,1,1,100.0,100
+withdrawal,1,2,60.0,200
+withdrawal,1,3,30.0,300
+dispute,1,1,,400
+deposit,2,4,50.0,100
+deposit,2,5,500.0,150
+withdrawal,2,6,20.0,200
+dispute,2,4,,300
+deposit,3,7,10.0,100
+dispute,3,7,,200
+withdrawal,3,8,5.0,300"#;
+
+        let (database, errors, _) = process_csv_str(csv_content);
+        assert_eq!(errors.len(), 1);
+
+        // Client 2 still had funds after the dispute; client 3 withdrew after disputing
+        let mut output = Vec::new();
+        write_disputes_after_withdrawal(&database, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,tx,amount,withdrawn,disputed_at,available
+1,1,100.0000,90.0000,400,-90.0000
+"
+        );
+    }
+
+    #[test]
+    fn test_invalid_header_reported_once() {
+        let csv_content = r#"type,customer,tx,amount
+deposit,1,1,1.0
+deposit,2,2,2.0
+withdrawal,1,3,0.5"#;
+
+        let temp_file = create_temp_csv(csv_content);
+        let path = temp_file.path().to_str().unwrap();
+        let (database, errors, _) = process_csv_file(path).unwrap();
+        assert!(database.get_all_client_ids().is_empty());
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains(":1: Invalid header: missing column 'client'"));
+        assert!(errors[0].contains("--column-map customer=client"));
+
+        // Mapping the column lets every row through
+        let mut engine = Engine::new();
+        let source = CsvSource::from_path(path)
+            .unwrap()
+            .with_column_map("customer", "client");
+        let stats = engine.process(source);
+        assert_eq!((stats.rows, stats.applied), (3, 3));
+        assert_eq!(engine.database().get_account(1).unwrap().available().to_f64(), 0.5);
+    }
+
+    #[test]
+    fn test_sample_processes_only_the_first_rows() {
+        let csv_content = r#"type,client,tx,amount
+deposit,1,1,1.0
+withdrawal,2,2,1.0
+deposit,3,3,1.0
+deposit,4,4,1.0"#;
+
+        let temp_file = create_temp_csv(csv_content);
+        let path = temp_file.path().to_str().unwrap();
+        let (database, errors, _) = process_csv_sample(path, 2).unwrap();
+        let mut clients = database.get_all_client_ids();
+        clients.sort();
+        assert_eq!(clients, [1, 2]);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains(":3:"));
+
+        // The same seed draws the same rows
+        let draw = |seed| {
+            let mut engine = Engine::new();
+            let source = CsvSource::from_path(path).unwrap();
+            engine.process(Sampled::new(source, Sample::Random { rows: 2, seed }));
+            let mut clients = engine.database().get_all_client_ids();
+            clients.sort();
+            clients
+        };
+        assert_eq!(draw(5).len(), 2);
+        assert_eq!(draw(5), draw(5));
+    }
+
+    #[test]
+    fn test_balance_overflow_leaves_account_unchanged() {
+        let policy = Policy {
+            reserve_percent: 50,
+            reserve_days: 90,
+            ..Policy::default()
+        };
+        let mut database = Database::with_policy(policy);
+        let deposit = Transaction::deposit("900000000000000").unwrap();
+        database.process_transaction(1, 1, deposit).unwrap();
+        let withdrawal = Transaction::withdrawal("900000000000000").unwrap();
+        database.process_transaction(1, 2, withdrawal).unwrap();
+        database.process_transaction(1, 1, Transaction::dispute()).unwrap();
+
+        // Reserving half of this deposit would overflow the held balance, though not
+        // the available balance: rejected, in release builds too, rather than wrapping
+        let deposit = Transaction::deposit("100000000000000").unwrap();
+        let error = database
+            .process_transaction_at(1, 3, deposit, Some(0))
+            .unwrap_err();
+        assert!(matches!(error.kind, EngineError::BalanceOverflow));
+        assert_eq!(error.code(), ErrorCode::BALANCE_OVERFLOW);
+
+        let account = database.get_account(1).unwrap();
+        assert_eq!(account.available().to_string(), "-900000000000000.0000");
+        assert_eq!(account.held().to_string(), "900000000000000.0000");
+        assert!(!account.has_transaction(3));
+        assert!(database.audit_balances().is_empty());
+
+        // The database is still usable
+        database.process_transaction(1, 1, Transaction::resolve()).unwrap();
+        let account = database.get_account(1).unwrap();
+        assert_eq!(account.held().to_string(), "0.0000");
+    }
+
+    #[test]
+    fn test_parallel_files_reject_shared_clients() {
+        let east = create_temp_csv(
+            r#"type,client,tx,amount
+deposit,1,1,100.0
+deposit,2,2,50.0
+dispute,2,2,"#,
+        );
+        let west = create_temp_csv(
+            r#"type,client,tx,amount
+deposit,1,10,25.0
+dispute,1,1,
+deposit,3,12,7.0"#,
+        );
+        let paths = [east.path().to_str().unwrap(), west.path().to_str().unwrap()];
+        let err = process_csv_files_parallel(&paths).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("Client 1 is in both {} and {}", paths[0], paths[1])
+        );
+
+        // More files than threads are shared out among them
+        let files: Vec<NamedTempFile> = (1..=64u16)
+            .map(|client| {
+                create_temp_csv(&format!(
+                    "type,client,tx,amount\ndeposit,{},{},{}.0\nwithdrawal,{},{},1.0",
+                    client,
+                    u32::from(client) * 2,
+                    client,
+                    client,
+                    u32::from(client) * 2 + 1
+                ))
+            })
+            .collect();
+        let paths: Vec<&str> = files
+            .iter()
+            .map(|file| file.path().to_str().unwrap())
+            .collect();
+        let (database, errors, _) = process_csv_files_parallel(&paths).unwrap();
+        assert_eq!(database.get_all_client_ids().len(), 64);
+        assert!(errors.is_empty());
+        let account = database.get_account(64).unwrap();
+        assert_eq!(account.available().to_string(), "63.0000");
+    }
+
+    #[test]
+    fn test_negative_deposits_as_refunds() {
+        let csv_content = r#"type,client,tx,amount
+deposit,1,1,100.0
+deposit,1,2,-30.0
+deposit,1,3,-500.0
+dispute,1,2,"#;
+
+        // Rejected under the default policy
+        let (database, errors, _) = process_csv_str(csv_content);
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].contains("Amount must be positive"));
+        assert_eq!(database.get_account(1).unwrap().available().to_string(), "100.0000");
+
+        let policy = Policy {
+            negative_deposits_as_refunds: true,
+            ..Policy::default()
+        };
+        let mut engine = Engine::with_policy(policy);
+        let stats = engine.process(CsvSource::new("input", csv_content.as_bytes()));
+        assert_eq!(stats.applied, 2);
+        assert_eq!(stats.deposit_amounts.count(), 1);
+        assert_eq!(stats.refund_amounts.count(), 1);
+
+        // The refund larger than the balance and the dispute of a refund were rejected
+        let errors = engine.errors();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("Insufficient funds"));
+        assert!(errors[1].contains("Refund transaction cannot be disputed"));
+
+        let database = engine.database();
+        assert_eq!(database.get_account(1).unwrap().available().to_string(), "70.0000");
+        let statement = database.statement(1).unwrap();
+        assert_eq!(statement.lines[1].kind, EntryKind::Refund);
+        assert_eq!(statement.lines[1].amount.to_string(), "30.0000");
+        assert!(database.audit_balances().is_empty());
+    }
+
+    #[test]
+    fn test_refunds_linked_to_deposits() {
+        let csv_content = r#"type,client,tx,amount,original_tx
+deposit,1,1,100.0,
+deposit,1,2,50.0,
+refund,1,3,60.0,1
+refund,1,4,40.0,1
+refund,1,5,0.0001,1
+refund,1,6,10.0,
+refund,1,7,10.0,2
+refund,1,8,10.0,9
+dispute,1,3,,"#;
+        let (database, errors, _) = process_csv_str(csv_content);
+
+        assert_eq!(errors.len(), 4);
+        assert!(errors[0].contains("Refund exceeds the unrefunded amount of the deposit"));
+        assert!(errors[1].contains("Refund needs the original transaction ID"));
+        assert!(errors[2].contains("Transaction not found"));
+        assert!(errors[3].contains("Refund transaction cannot be disputed"));
+
+        let account = database.get_account(1).unwrap();
+        assert_eq!(account.available().to_string(), "40.0000");
+        assert_eq!(account.refunded(1).to_string(), "100.0000");
+        assert_eq!(account.refunded(2).to_string(), "10.0000");
+
+        let statement = database.statement(1).unwrap();
+        let refunds: Vec<_> = statement
+            .lines
+            .iter()
+            .filter(|line| line.kind == EntryKind::Refund)
+            .map(|line| (line.tx, line.refund_of))
+            .collect();
+        assert_eq!(refunds, [(3, Some(1)), (4, Some(1)), (7, Some(2))]);
+        assert!(database.audit_balances().is_empty());
+    }
+
+    #[test]
+    fn test_refunds_and_chargebacks_never_exceed_deposit() {
+        let csv_content = r#"type,client,tx,amount,original_tx
+deposit,1,1,100.0,
+deposit,1,2,50.0,
+refund,1,3,60.0,1
+dispute,1,1,,
+refund,1,4,10.0,1
+chargeback,1,1,,
+resolve,1,1,,"#;
+        let (mut database, errors, _) = process_csv_str(csv_content);
+
+        // The dispute holds only the 40 left unrefunded, which the chargeback takes back
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains(":6:"));
+        assert!(errors[0].contains("already disputed"));
+        assert!(errors[1].contains(":8:"));
+        let account = database.get_account(1).unwrap();
+        assert_eq!(account.available().to_string(), "50.0000");
+        assert_eq!(account.held().to_string(), "0.0000");
+        assert!(account.is_locked());
+        assert!(database.audit_balances().is_empty());
+
+        // Even once the account is unlocked, the charged back deposit can't be refunded
+        database.set_account_state(1, AccountState::Active).unwrap();
+        let refund = Transaction::refund(1, "10").unwrap();
+        let error = database.process_transaction(1, 5, refund).unwrap_err();
+        assert!(matches!(error.kind, EngineError::ReversalExceedsDeposit));
+        assert_eq!(database.get_account(1).unwrap().refunded(1).to_string(), "60.0000");
+    }
+
+    #[test]
+    fn test_chargeback_volume_limit_locks_until_unlocked_by_hand() {
+        const HOUR: u64 = 60 * 60;
+        let policy = Policy {
+            auto_unlock_after: Some(HOUR),
+            chargeback_limits: vec![ChargebackLimit {
+                max_chargebacks: None,
+                max_volume: Some("100".parse().unwrap()),
+                period: Some(HOUR),
+                action: LimitAction::Lock,
+            }],
+            ..Policy::default()
+        };
+        let mut database = Database::with_policy(policy);
+        for (tx, amount, at) in [(1, "80", 0), (2, "30", 2 * HOUR), (3, "90", 2 * HOUR + 60)] {
+            let deposit = Transaction::deposit(amount).unwrap();
+            database.process_transaction(1, tx, deposit).unwrap();
+            database
+                .process_transaction(1, tx, Transaction::dispute())
+                .unwrap();
+            database
+                .process_transaction_at(1, tx, Transaction::chargeback(), Some(at))
+                .unwrap();
+            // Until the third, no hour has more than 100 charged back, so chargebacks only
+            // lock the account as usual
+            if tx < 3 {
+                let account = database.get_account(1).unwrap();
+                assert_eq!(
+                    account.lock_details().unwrap().reason,
+                    LockReason::Chargeback { tx }
+                );
+                assert_eq!(database.apply_auto_unlocks(at + HOUR), [1]);
+            }
+        }
+
+        let account = database.get_account(1).unwrap();
+        let lock = account.lock_details().unwrap();
+        assert_eq!(
+            lock.reason,
+            LockReason::ChargebackLimit {
+                chargebacks: 2,
+                volume: "120".parse().unwrap(),
+            }
+        );
+        assert_eq!(
+            account.audit_log().last().unwrap().event.to_string(),
+            "locked after reaching the chargeback limit (2 chargebacks totalling 120.0000)"
+        );
+        assert!(database.apply_auto_unlocks(10 * HOUR).is_empty());
+
+        database.set_account_state(1, AccountState::Active).unwrap();
+        assert!(!database.get_account(1).unwrap().is_locked());
+    }
+
+    #[test]
+    fn test_json_file_storage_carries_accounts_between_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("accounts.json");
+        let run = |input: &str| {
+            let mut engine = Engine::new().with_storage(JsonFileStorage::open(&path).unwrap());
+            let stats = engine.process(CsvSource::new("input", input.as_bytes()));
+            engine.flush().unwrap();
+            (engine, stats)
+        };
+
+        let (_, stats) = run("type,client,tx,amount\ndeposit,1,1,100\ndeposit,2,2,50\n");
+        assert_eq!(stats.applied, 2);
+        assert!(path.exists());
+
+        // The second run disputes a deposit from the first and skips one sent again
+        let (engine, stats) = run("type,client,tx,amount\ndispute,1,1,\ndeposit,2,2,50\n");
+        assert_eq!((stats.applied, stats.warnings), (1, 1));
+        let database = engine.database();
+        assert_eq!(
+            database.get_account(1).unwrap().held().to_string(),
+            "100.0000"
+        );
+        assert_eq!(
+            database.get_account(2).unwrap().available().to_string(),
+            "50.0000"
+        );
+
+        // A copy is in memory, so it reads the same but doesn't touch the file
+        let copy = database.clone();
+        assert_eq!(copy.digest(), database.digest());
+        let reopened =
+            Database::with_storage(JsonFileStorage::open(&path).unwrap(), Policy::default());
+        assert_eq!(reopened.digest(), database.digest());
+    }
+
+    #[test]
+    fn test_reordering_by_tx_within_window() {
+        // Client 1's deposit arrives after its dispute and chargeback, with two rows of
+        // client 2 in between
+        let input = "type,client,tx,amount\n\
+                     dispute,1,1,\n\
+                     chargeback,1,1,\n\
+                     deposit,2,3,10\n\
+                     withdrawal,2,4,5\n\
+                     deposit,1,1,100\n\
+                     deposit,2,5,20\n";
+        let run = |window| {
+            let mut engine = Engine::new();
+            let stats = engine.process(Reordered::new(
+                CsvSource::new("input", input.as_bytes()),
+                window,
+            ));
+            (engine, stats)
+        };
+
+        let (engine, stats) = run(0);
+        assert_eq!((stats.applied, stats.rejected), (4, 2));
+        assert!(engine.unmatched()[0].location.contains("input:2"));
+
+        // Too small a window to bring the deposit forward far enough
+        let (_, stats) = run(2);
+        assert_eq!((stats.applied, stats.rejected), (4, 2));
+
+        let (engine, stats) = run(4);
+        assert_eq!((stats.applied, stats.rejected), (6, 0));
+        let account = engine.database().get_account(1).unwrap();
+        assert!(account.is_locked());
+        assert_eq!(account.total().to_string(), "0.0000");
+        let account = engine.database().get_account(2).unwrap();
+        assert_eq!(account.available().to_string(), "25.0000");
+    }
+
+    #[test]
+    fn test_deferred_disputes_retried_when_deposit_arrives() {
+        let input = "type,client,tx,amount\n\
+                     dispute,1,1,\n\
+                     resolve,1,1,\n\
+                     dispute,1,1,\n\
+                     deposit,2,2,10\n\
+                     chargeback,2,9,\n\
+                     deposit,1,1,100\n\
+                     dispute,2,8,\n";
+        let policy = Policy {
+            defer_unmatched_disputes: true,
+            ..Policy::default()
+        };
+        let mut engine = Engine::with_policy(policy);
+        let stats = engine.process(CsvSource::new("input", input.as_bytes()));
+
+        // The three rows of tx 1 apply in order once the deposit arrives; the two rows
+        // for transactions that never arrive are rejected at the end, in input order
+        assert_eq!(stats.rows, 7);
+        assert_eq!(stats.deferred, 5);
+        assert_eq!((stats.applied, stats.rejected), (5, 2));
+        assert_eq!(engine.unmatched()[0].location, "input:6");
+        assert_eq!(engine.unmatched()[1].location, "input:8");
+        let account = engine.database().get_account(1).unwrap();
+        assert_eq!(account.held().to_string(), "100.0000");
+        assert_eq!(account.available().to_string(), "0.0000");
+
+        // Without the policy, the rows before the deposit are rejected
+        let mut engine = Engine::new();
+        let stats = engine.process(CsvSource::new("input", input.as_bytes()));
+        assert_eq!((stats.applied, stats.rejected, stats.deferred), (2, 5, 0));
+    }
+
+    #[test]
+    fn test_unmatched_references_reported_apart_from_errors() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,100\n\
+                     dispute,1,5,\n\
+                     withdrawal,1,2,500\n\
+                     resolve,2,6,\n\
+                     dispute,1,1,\n\
+                     chargeback,1,7,\n";
+        let mut engine = Engine::new().with_error_summary(1);
+        let stats = engine.process(CsvSource::new("input", input.as_bytes()));
+
+        assert_eq!((stats.applied, stats.rejected, stats.unmatched), (2, 4, 3));
+        let unmatched: Vec<_> = engine
+            .unmatched()
+            .iter()
+            .map(|reference| (reference.client, reference.tx, reference.action))
+            .collect();
+        assert_eq!(
+            unmatched,
+            [
+                (1, 5, DisputeAction::Dispute),
+                (2, 6, DisputeAction::Resolve),
+                (1, 7, DisputeAction::Chargeback),
+            ]
+        );
+        // Only the failed withdrawal is counted as an error
+        assert_eq!(engine.error_summary().unwrap().total(), 1);
+
+        let mut output = Vec::new();
+        write_unmatched_references(engine.unmatched(), &mut output).unwrap();
+        let report = String::from_utf8(output).unwrap();
+        assert_eq!(report.lines().count(), 4);
+        assert!(report.contains("input:5,2,6,resolve\n"));
+
+        // The line-by-line processing functions still report them as errors
+        let (_, errors, _) = process_csv_str(input);
+        assert_eq!(errors.len(), 4);
+    }
+
+    #[test]
+    fn test_processing_outcome_matches_tuple_result() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "type,client,tx,amount").unwrap();
+        writeln!(file, "deposit,1,1,10").unwrap();
+        writeln!(file, "dispute,1,1,").unwrap();
+        writeln!(file, "resolve,1,3,").unwrap();
+        let path = file.path().to_str().unwrap();
+
+        let outcome = process_csv_outcome(path).unwrap();
+        assert_eq!(
+            (
+                outcome.stats.rows,
+                outcome.stats.applied,
+                outcome.stats.rejected
+            ),
+            (3, 2, 1)
+        );
+        assert_ne!(outcome.run_id, process_csv_outcome(path).unwrap().run_id);
+
+        let (database, errors, warnings) = process_csv_file(path).unwrap();
+        assert_eq!(database.digest(), outcome.database.digest());
+        assert_eq!((errors, warnings), (outcome.errors, outcome.warnings));
+    }
+
+    #[test]
+    fn test_csv_processor_reused_across_files() {
+        let processor = CsvProcessor::builder()
+            .dialect(CsvDialect {
+                delimiter: b'|',
+                ..CsvDialect::default()
+            })
+            .trim(false)
+            .policy(Policy {
+                withdrawal_disputes: true,
+                ..Policy::default()
+            })
+            .build();
+
+        let mut first = NamedTempFile::new().unwrap();
+        write!(
+            first,
+            "type|client|tx|amount\ndeposit|1|1|10\nwithdrawal|1|2|4\ndispute|1|2|\n"
+        )
+        .unwrap();
+        let mut second = NamedTempFile::new().unwrap();
+        write!(second, "type|client|tx|amount\ndeposit |2|1|5\n").unwrap();
+
+        let outcome = processor
+            .process_file(first.path().to_str().unwrap())
+            .unwrap();
+        assert!(outcome.errors.is_empty());
+        let account = outcome.database.get_account(1).unwrap();
+        assert_eq!(account.held().to_string(), "4.0000");
+
+        // Each file gets a database of its own; untrimmed, the padded type is unknown
+        let outcome = processor
+            .process_file(second.path().to_str().unwrap())
+            .unwrap();
+        assert!(outcome.database.get_account(1).is_none());
+        assert_eq!(outcome.stats.rejected, 1);
+    }
+
+    #[test]
+    fn test_parallel_file_processing_matches_sequential() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "type,client,tx,amount").unwrap();
+        for tx in 1..=500u32 {
+            let client = tx % 13;
+            writeln!(file, "deposit,{},{},{}", client, tx, tx % 50).unwrap();
+            if tx % 3 == 0 {
+                writeln!(file, "dispute,{},{},", client, tx).unwrap();
+            }
+            if tx % 5 == 0 {
+                writeln!(file, "withdrawal,{},{},40", client, tx + 10_000).unwrap();
+            }
+            if tx % 9 == 0 {
+                writeln!(file, "chargeback,{},{},", client, tx).unwrap();
+            }
+            if tx % 11 == 0 {
+                writeln!(file, "deposit,{},{},x", client, tx + 20_000).unwrap();
+            }
+        }
+        let path = file.path().to_str().unwrap();
+
+        let (expected, expected_errors, expected_warnings) = process_csv_file(path).unwrap();
+        assert!(!expected_errors.is_empty());
+        for threads in [1, 2, 8] {
+            let outcome = CsvProcessor::default()
+                .process_file_parallel(path, threads)
+                .unwrap();
+            assert_eq!(outcome.database.digest(), expected.digest());
+            assert_eq!(outcome.errors, expected_errors);
+            assert_eq!(outcome.warnings, expected_warnings);
+            assert_eq!(outcome.stats.rows, 500 + 166 + 100 + 55 + 45);
+        }
+    }
+
+    #[test]
+    fn test_fixed4_serde_round_trip() {
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Refund {
+            client: u16,
+            amount: Fixed4,
+            fee: Option<Fixed4>,
+        }
+
+        let refund = Refund {
+            client: 7,
+            amount: "1.5".parse().unwrap(),
+            fee: None,
+        };
+        let json = serde_json::to_string(&refund).unwrap();
+        assert_eq!(json, r#"{"client":7,"amount":"1.5000","fee":null}"#);
+        assert_eq!(serde_json::from_str::<Refund>(&json).unwrap(), refund);
+
+        // Numbers are accepted too, and read exactly
+        let refund: Refund = serde_json::from_str(r#"{"client":7,"amount":1.5,"fee":2}"#).unwrap();
+        assert_eq!(refund.amount.to_string(), "1.5000");
+        assert_eq!(refund.fee.unwrap().to_string(), "2.0000");
+        let small: Fixed4 = serde_json::from_str("0.0001").unwrap();
+        assert_eq!(small.to_string(), "0.0001");
+
+        // Out of range or too precise amounts are errors, not rounded
+        assert!(serde_json::from_str::<Fixed4>("1e300").is_err());
+        assert!(serde_json::from_str::<Fixed4>("18446744073709551615").is_err());
+        assert!(serde_json::from_str::<Fixed4>("0.12345").is_err());
+        assert!(serde_json::from_str::<Fixed4>("true").is_err());
+    }
+
+    #[test]
+    fn test_engine_error_classes_and_deprecated_alias() {
+        let mut db = Database::new();
+        db.process_transaction(1, 1, Transaction::deposit("10").unwrap())
+            .unwrap();
+        let class = |db: &mut Database, tx, transaction| {
+            db.process_transaction(1, tx, transaction)
+                .unwrap_err()
+                .class()
+        };
+        let overdraw = class(&mut db, 2, Transaction::withdrawal("50").unwrap());
+        assert_eq!(overdraw, ErrorClass::BusinessRule);
+        assert_eq!(class(&mut db, 1, Transaction::resolve()), ErrorClass::State);
+        assert_eq!(
+            Transaction::deposit("ten").unwrap_err().class(),
+            ErrorClass::InputError
+        );
+
+        let io = EngineError::from(std::io::Error::other("disk full"));
+        assert_eq!(io.class(), ErrorClass::SystemError);
+        assert_eq!(io.code(), ErrorCode::IO);
+        assert!(io.is_retryable());
+
+        // Match arms written against the old name still compile
+        #[allow(deprecated)]
+        fn describe(error: &transaction_processor::MyError) -> &'static str {
+            match error {
+                transaction_processor::MyError::InsufficientFunds => "insufficient",
+                _ => "other",
+            }
+        }
+        let err = db
+            .process_transaction(1, 3, Transaction::withdrawal("50").unwrap())
+            .unwrap_err();
+        assert_eq!(describe(&err.kind), "insufficient");
+    }
+
+    #[test]
+    fn test_database_snapshot_round_trip() {
+        let mut db = Database::new();
+        db.process_transaction(1, 1, Transaction::deposit("100").unwrap())
+            .unwrap();
+        db.process_transaction(1, 1, Transaction::dispute())
+            .unwrap();
+        db.process_transaction(2, 2, Transaction::deposit("40").unwrap())
+            .unwrap();
+        db.process_transaction(2, 3, Transaction::withdrawal("15").unwrap())
+            .unwrap();
+        db.process_transaction(2, 2, Transaction::dispute())
+            .unwrap();
+        db.process_transaction(2, 2, Transaction::chargeback())
+            .unwrap();
+
+        let saved = serde_json::to_string(&db.to_snapshot()).unwrap();
+        let snapshot: Snapshot = serde_json::from_str(&saved).unwrap();
+        let mut restored = Database::from_snapshot(snapshot, Policy::default());
+        assert_eq!(restored.digest(), db.digest());
+
+        // Balances, lock state and ledger all carry over
+        let locked = restored.get_account(2).unwrap();
+        assert!(locked.is_locked());
+        assert_eq!(locked.total().to_string(), "-15.0000");
+        assert_eq!(restored.statement(2), db.statement(2));
+        let err = restored
+            .process_transaction(2, 4, Transaction::deposit("1").unwrap())
+            .unwrap_err();
+        assert!(matches!(err.kind, EngineError::AccountLocked));
+
+        // The open dispute can still be settled
+        restored
+            .process_transaction(1, 1, Transaction::resolve())
+            .unwrap();
+        assert_eq!(
+            restored.get_account(1).unwrap().available().to_string(),
+            "100.0000"
+        );
+    }
+
+    #[test]
+    fn test_long_format_summary() {
+        let mut engine = Engine::new();
+        let input = "type,client,tx,amount\n\
+                     deposit,2,1,5\n\
+                     deposit,1,2,10\n\
+                     dispute,1,2,\n\
+                     chargeback,1,2,\n";
+        engine.process(CsvSource::new("input", input.as_bytes()));
+
+        let mut output = Vec::new();
+        engine
+            .report(
+                CsvSink::new(&mut output)
+                    .with_columns(SummaryColumns::balances())
+                    .with_layout(SummaryLayout::Long),
+            )
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let rows: Vec<&str> = output.lines().collect();
+        assert_eq!(rows.len(), 1 + 2 * 5);
+        assert_eq!(rows[0], "client,metric,value");
+        assert_eq!(
+            &rows[1..6],
+            [
+                "1,available,0.0000",
+                "1,held,0.0000",
+                "1,total,0.0000",
+                "1,locked,true",
+                "1,reserved,0.0000"
+            ]
+        );
+        assert_eq!(rows[8], "2,total,5.0000");
+
+        // Any columns can be written long; the client is never repeated as a metric
+        let mut output = Vec::new();
+        engine
+            .report(
+                CsvSink::new(&mut output)
+                    .with_columns("client,state".parse().unwrap())
+                    .with_layout(SummaryLayout::Long),
+            )
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,metric,value\n1,state,locked\n2,state,active\n"
+        );
+    }
+
+    #[test]
+    fn test_journal_rebuilds_state_after_crash() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.jsonl");
+
+        let mut db = Database::new();
+        db.process_transaction(1, 1, Transaction::deposit("100").unwrap())
+            .unwrap();
+        // Start journaling after a snapshot, as a long-running service would
+        let snapshot = db.to_snapshot();
+        db.set_journal(Journal::open(&path).unwrap().with_sync(true));
+        let case = TransactionMeta {
+            case_id: Some("CASE-1".to_string()),
+            timestamp: Some(50),
+            ..TransactionMeta::default()
+        };
+        db.process_transaction_with(1, 1, Transaction::dispute(), case)
+            .unwrap();
+        db.process_transaction(2, 2, Transaction::deposit("7.5").unwrap())
+            .unwrap();
+        assert!(
+            db.process_transaction(2, 3, Transaction::withdrawal("10").unwrap())
+                .is_err()
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        // The crash cut the next entry short
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        std::io::Write::write_all(&mut file, b"{\"client\":2,\"tx\":4,").unwrap();
+
+        let mut recovered = Database::from_snapshot(snapshot, Policy::default());
+        assert_eq!(recovered.replay_journal(&path).unwrap(), 2);
+        assert_eq!(recovered.digest(), db.digest());
+        assert_eq!(recovered.disputes_by_case("CASE-1").len(), 1);
+
+        // Replaying onto the wrong state fails rather than diverging silently
+        let mut empty = Database::new();
+        assert!(empty.replay_journal(&path).is_err());
+
+        // A damaged entry before the end is an error, naming its line
+        std::fs::write(&path, "not json\n").unwrap();
+        let err = Database::new().replay_journal(&path).unwrap_err();
+        assert!(err.to_string().contains("journal.jsonl:1"), "{}", err);
+    }
+
+    #[test]
+    fn test_rejections_json() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10\n\
+                     withdrawal,1,2,50\n\
+                     deposit,1,3,abc\n\
+                     dispute,1,9,\n\
+                     deposit,x,4,1\n";
+        let mut engine = Engine::new().with_error_summary(1).with_rejections();
+        let stats = engine.process(CsvSource::new("input.csv", input.as_bytes()));
+        assert_eq!(stats.rejected, 4);
+
+        let rejections = engine.rejections();
+        assert_eq!(rejections.len(), 4);
+        let codes: Vec<&str> = rejections.iter().map(|r| r.code.as_str()).collect();
+        assert_eq!(
+            codes,
+            [
+                "INSUFFICIENT_FUNDS",
+                "INVALID_AMOUNT_FORMAT",
+                "TRANSACTION_NOT_FOUND",
+                "MALFORMED"
+            ]
+        );
+        let lines: Vec<Option<u64>> = rejections.iter().map(|r| r.line).collect();
+        assert_eq!(lines, [Some(3), Some(4), Some(5), Some(6)]);
+
+        // A decoded row keeps all its fields, an invalid one its client and tx
+        let withdrawal = &rejections[0].fields;
+        assert_eq!(withdrawal.transaction_type.as_deref(), Some("withdrawal"));
+        assert_eq!((withdrawal.client, withdrawal.tx), (Some(1), Some(2)));
+        assert_eq!(withdrawal.amount, Some("50".parse().unwrap()));
+        assert_eq!(rejections[1].fields.tx, Some(3));
+        assert_eq!(rejections[1].fields.transaction_type, None);
+        assert_eq!(
+            rejections[2].fields.transaction_type.as_deref(),
+            Some("dispute")
+        );
+        assert_eq!(rejections[3].fields, RejectedFields::default());
+
+        // Every line reads back as the rejection written
+        let mut output = Vec::new();
+        write_rejections_json(rejections, &mut output).unwrap();
+        let read: Vec<Rejection> = std::str::from_utf8(&output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(read, rejections);
+
+        // Not kept unless asked for
+        let mut engine = Engine::new();
+        engine.process(CsvSource::new("input.csv", input.as_bytes()));
+        assert!(engine.rejections().is_empty());
+    }
+
+    #[test]
+    fn test_sql_export() {
+        let mut db = Database::new();
+        for tx in 1..=600 {
+            db.process_transaction((tx % 3) as u16, tx, Transaction::deposit("1.5").unwrap())
+                .unwrap();
+        }
+        db.process_transaction(1, 601, Transaction::withdrawal("0.25").unwrap())
+            .unwrap();
+        db.process_transaction(2, 2, Transaction::dispute())
+            .unwrap();
+        db.process_transaction(2, 2, Transaction::chargeback())
+            .unwrap();
+
+        let mut output = Vec::new();
+        write_sql(&db, &mut output).unwrap();
+        let script = String::from_utf8(output).unwrap();
+
+        assert!(script.starts_with("CREATE TABLE IF NOT EXISTS accounts ("));
+        assert_eq!(script.matches("INSERT INTO accounts ").count(), 1);
+        // 601 ledger entries, 500 to a statement
+        assert_eq!(script.matches("INSERT INTO ledger ").count(), 2);
+        assert_eq!(script.matches("BEGIN;\n").count(), 1);
+        assert!(script.contains("(0, 300.0000, 0.0000, 300.0000, FALSE, 'active'),\n"));
+        assert!(script.contains("(1, 299.7500, 0.0000, 299.7500, FALSE, 'active'),\n"));
+        assert!(script.contains("(2, 298.5000, 0.0000, 298.5000, TRUE, 'locked');\n"));
+        assert!(script.contains("(1, 601, 'withdrawal', 0.2500, NULL, NULL, NULL)"));
+        assert!(script.contains("(2, 2, 'deposit', 1.5000, NULL, 'charged_back', NULL)"));
+        assert!(script.ends_with(";\nCOMMIT;\n"));
+    }
+
+    #[test]
+    fn test_json_summary() {
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,2,1,10,1700000000\n\
+                     deposit,1,2,5,\n\
+                     dispute,1,2,,\n\
+                     chargeback,1,2,,\n";
+        let mut engine = Engine::new();
+        engine.process(CsvSource::new("input", input.as_bytes()));
+
+        let columns: SummaryColumns = "total,client,locked,state,tx_count,last_activity"
+            .parse()
+            .unwrap();
+        let mut output = Vec::new();
+        engine
+            .report(JsonSink::new(&mut output).with_columns(columns.clone()))
+            .unwrap();
+
+        let text = String::from_utf8(output).unwrap();
+        // Members keep the column order
+        assert!(text.contains(r#"{"total":"0.0000","client":1,"locked":true,"#));
+        let rows: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(
+            rows,
+            serde_json::json!([
+                {"total": "0.0000", "client": 1, "locked": true, "state": "locked",
+                 "tx_count": 1, "last_activity": null},
+                {"total": "10.0000", "client": 2, "locked": false, "state": "active",
+                 "tx_count": 1, "last_activity": 1700000000u64},
+            ])
+        );
+
+        // The format parses as given on the command line
+        assert_eq!("json".parse(), Ok(OutputFormat::Json));
+        assert!("xml".parse::<OutputFormat>().is_err());
+
+        // No accounts is an empty array
+        let mut output = Vec::new();
+        write_account_summaries_json(&[], &columns, &mut output).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&output).unwrap(),
+            serde_json::json!([])
+        );
+    }
+
+    #[test]
+    fn test_dispute_graph() {
+        let mut db = Database::new();
+        db.process_transaction(1, 1, Transaction::deposit("100").unwrap())
+            .unwrap();
+        db.process_transaction(1, 2, Transaction::deposit("50").unwrap())
+            .unwrap();
+        db.process_transaction(1, 3, Transaction::refund(1, "20").unwrap())
+            .unwrap();
+        db.process_transaction_at(1, 2, Transaction::dispute(), Some(1_700_000_000))
+            .unwrap();
+        db.process_transaction(1, 2, Transaction::resolve())
+            .unwrap();
+        db.process_transaction(2, 4, Transaction::deposit("10").unwrap())
+            .unwrap();
+        db.process_transaction(3, 5, Transaction::deposit("7").unwrap())
+            .unwrap();
+        db.process_transaction(3, 5, Transaction::dispute())
+            .unwrap();
+        db.process_transaction(3, 5, Transaction::chargeback())
+            .unwrap();
+
+        let mut output = Vec::new();
+        write_dispute_graph(&db, &mut output).unwrap();
+        let dot = String::from_utf8(output).unwrap();
+
+        // The refund links back to the deposit it refunds, both drawn
+        assert!(dot.contains("\"c1 tx3\" -> \"c1 tx1\" [style=dashed, label=\"refunds\"];"));
+        assert!(dot.contains(
+            "\"c1 tx1\" [style=filled, fillcolor=white, label=\"deposit 1\\n100.0000\"];"
+        ));
+        // The resolved dispute is chained in order, with its timestamp
+        assert!(dot.contains("\"c1 tx2 #1\" [shape=diamond, label=\"dispute\\n@1700000000\"];"));
+        assert!(dot.contains("\"c1 tx2 #1\" -> \"c1 tx2 #2\";"));
+        assert!(dot.contains("\"c1 tx2 #2\" [shape=diamond, label=\"resolve\"];"));
+        // Client 2 had nothing to draw; client 3 was charged back and locked
+        assert!(!dot.contains("\"c2\""));
+        assert!(dot.contains("\"c3\" [shape=box, color=red, label=\"client 3\\nlocked\"];"));
+        assert!(dot.contains("fillcolor=tomato, label=\"deposit 5\\n7.0000\\ncharged_back\""));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn test_fixed4_operators() {
+        let amount = |s: &str| s.parse::<Fixed4>().unwrap();
+
+        // A monthly fee of 1/12 of 2.5% a year on 1000, without going through f64
+        let balance = amount("1000");
+        let annual_fee = balance.percentage(2) + balance.percentage(1) / 2;
+        let monthly_fee = annual_fee / 12;
+        assert_eq!(monthly_fee, amount("2.0833"));
+        assert_eq!(balance - monthly_fee * 12, amount("975.0004"));
+
+        assert_eq!(-amount("1.5"), amount("-1.5"));
+        assert_eq!(-(-amount("1.5")), amount("1.5"));
+        assert_eq!(amount("1") - amount("2.5"), amount("-1.5"));
+        assert_eq!(amount("-2.5") * -2, amount("5"));
+
+        // Division rounds to nearest, halves away from zero, whatever the signs
+        assert_eq!(amount("0.0005") / 10, amount("0.0001"));
+        assert_eq!(amount("0.0004") / 10, amount("0"));
+        assert_eq!(amount("-0.0005") / 10, amount("-0.0001"));
+        assert_eq!(amount("0.0005") / -10, amount("-0.0001"));
+        assert_eq!(amount("-0.0015") / -10, amount("0.0002"));
+        assert_eq!(amount("10") / 4, amount("2.5"));
+    }
+
+    #[test]
+    fn test_process_with_progress() {
+        let input = "type,client,tx,amount\n\
+                     dispute,1,1,\n\
+                     deposit,1,1,10\n\
+                     withdrawal,1,2,50\n\
+                     deposit,1,x,5\n\
+                     chargeback,1,1,\n";
+        let policy = Policy {
+            defer_unmatched_disputes: true,
+            ..Policy::default()
+        };
+        let mut engine = Engine::with_policy(policy);
+        let mut applied = Vec::new();
+        let mut rows = Vec::new();
+        let mut last = None;
+        let stats =
+            engine.process_with_progress(CsvSource::new("input", input.as_bytes()), |progress| {
+                applied.extend(progress.applied.map(|row| (row.client, row.tx, row.action)));
+                rows.push(progress.stats.rows);
+                last = Some((progress.stats.clone(), progress.database.get_account(1).cloned()));
+            });
+
+        // The held-back dispute is reported when it is applied, after its deposit
+        assert_eq!(
+            applied,
+            [
+                (1, 1, None),
+                (1, 1, Some(DisputeAction::Dispute)),
+                (1, 1, Some(DisputeAction::Chargeback)),
+            ]
+        );
+        // Rows only ever go up, and the last call sees the whole input
+        assert!(rows.windows(2).all(|pair| pair[0] <= pair[1]));
+        let (final_stats, account) = last.unwrap();
+        assert_eq!(final_stats, stats);
+        assert_eq!((stats.rows, stats.applied, stats.rejected), (5, 3, 2));
+        assert!(account.unwrap().is_locked());
+        // The same statistics as processing without a callback
+        assert_eq!(engine.stats(), stats);
+    }
+
+    #[test]
+    fn test_unlock_account_with_approval() {
+        let policy = Policy {
+            unlocks_require_approval: true,
+            ..Policy::default()
+        };
+        let mut database = Database::with_policy(policy);
+        database
+            .process_transaction(1, 1, Transaction::deposit("25").unwrap())
+            .unwrap();
+        database
+            .process_transaction(1, 1, Transaction::dispute())
+            .unwrap();
+        database
+            .process_transaction(1, 1, Transaction::chargeback())
+            .unwrap();
+
+        // Staged rather than applied: the account stays locked until approved
+        let error = database
+            .unlock_account(1, "dispute resolved with the merchant")
+            .unwrap_err();
+        let EngineError::PendingApproval { id } = error else {
+            panic!("expected the unlock to be staged, got {:?}", error);
+        };
+        assert!(database.get_account(1).unwrap().is_locked());
+        assert_eq!(
+            database.get_account(1).unwrap().audit_log()[0]
+                .event
+                .to_string(),
+            format!(
+                "operation {} (unlock (dispute resolved with the merchant)) staged for approval",
+                id
+            )
+        );
+
+        database.approve(id, "supervisor").unwrap();
+        let account = database.get_account(1).unwrap();
+        assert!(!account.is_locked());
+        assert_eq!(account.state(), AccountState::Active);
+        assert!(account.lock_details().is_none());
+        let events: Vec<String> = account
+            .audit_log()
+            .iter()
+            .skip(1)
+            .map(|entry| entry.event.to_string())
+            .collect();
+        assert_eq!(
+            events,
+            [
+                "unlocked by an operator: dispute resolved with the merchant".to_string(),
+                format!("operation {} approved by supervisor", id),
+            ]
+        );
+        // The account takes transactions again
+        database
+            .process_transaction(1, 2, Transaction::deposit("5").unwrap())
+            .unwrap();
+
+        assert!(matches!(
+            database.unlock_account(1, "not locked"),
+            Err(EngineError::AccountNotLocked)
+        ));
+        assert!(matches!(
+            database.unlock_account(2, "no such account"),
+            Err(EngineError::AccountNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_selftest_scenarios() {
+        let results = selftest();
+        assert_eq!(results.len(), SCENARIOS.len());
+        for (scenario, result) in &results {
+            assert_eq!(*result, Ok(()), "scenario '{}'", scenario.name);
+        }
+
+        // A wrong expectation is reported with what was produced instead
+        let scenario = Scenario {
+            expected: "client,available,held,total,locked,state\n\
+                       1,5.0000,0.0000,5.0000,false,active\n",
+            ..SCENARIOS[3]
+        };
+        let failure = scenario.run().unwrap_err();
+        assert!(failure.starts_with("expected summary\n"));
+        assert!(failure.ends_with(
+            "got\nclient,available,held,total,locked,state\n1,5.0000,0.0000,5.0000,true,locked\n"
+        ));
+
+        let scenario = Scenario {
+            warnings: 0,
+            ..SCENARIOS[6]
+        };
+        assert_eq!(
+            scenario.run(),
+            Err("expected 0 warnings, got 1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_deterministic_processing_is_reproducible() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "type,client,tx,amount").unwrap();
+        for tx in 1..=300u32 {
+            let client = tx % 11;
+            writeln!(file, "deposit,{},{},{}", client, tx, tx % 40).unwrap();
+            if tx % 4 == 0 {
+                writeln!(file, "dispute,{},{},", client, tx).unwrap();
+            }
+            if tx % 6 == 0 {
+                writeln!(file, "withdrawal,{},{},30", client, tx + 10_000).unwrap();
+            }
+            if tx % 10 == 0 {
+                writeln!(file, "deposit,{},{},{}", client, tx, tx % 40).unwrap();
+            }
+            if tx % 13 == 0 {
+                writeln!(file, "resolve,{},{},", client, tx + 1).unwrap();
+            }
+        }
+        let path = file.path().to_str().unwrap();
+
+        let processor = CsvProcessor::builder().deterministic(true).build();
+        let expected = processor.process_file(path).unwrap();
+        assert!(!expected.errors.is_empty());
+        assert!(!expected.warnings.is_empty());
+
+        // Errors come in the order of the lines they relate to
+        let lines: Vec<usize> = expected
+            .errors
+            .iter()
+            .map(|error| {
+                let rest = &error[error.find(path).unwrap() + path.len() + 1..];
+                rest[..rest.find(':').unwrap()].parse().unwrap()
+            })
+            .collect();
+        assert!(lines.windows(2).all(|pair| pair[0] < pair[1]));
+
+        for threads in [1, 2, 8] {
+            let outcome = processor.process_file_parallel(path, threads).unwrap();
+            assert_eq!(outcome.database.to_json(), expected.database.to_json());
+            assert_eq!(outcome.errors, expected.errors);
+            assert_eq!(outcome.warnings, expected.warnings);
+            assert_eq!(outcome.stats, expected.stats);
+            assert_eq!(outcome.run_id, expected.run_id);
+        }
+
+        // The ID doesn't depend on where the input was read from
+        let moved = processor.process_reader("moved.csv", std::fs::File::open(path).unwrap());
+        assert_ne!(moved.errors, expected.errors);
+        assert_eq!(moved.run_id, expected.run_id);
+        // Only the location is left out, not the name elsewhere in a message
+        let input = |kind: &str| format!("type,client,tx,amount\n{},1,1,1.0\n", kind);
+        let named = processor.process_reader("zz", input("zz:x").as_bytes());
+        let unnamed = processor.process_reader("zz", input("x").as_bytes());
+        assert!(named.errors[0].contains("zz:x"));
+        assert_ne!(named.run_id, unnamed.run_id);
+
+        // Otherwise every run gets an ID of its own
+        let first = CsvProcessor::default().process_file(path).unwrap();
+        let second = CsvProcessor::default().process_file(path).unwrap();
+        assert_ne!(first.run_id, second.run_id);
+        assert_eq!(first.database.to_json(), expected.database.to_json());
+
+        // The ID depends on the input
+        let other = processor.process_reader("input", "type,client,tx,amount\n".as_bytes());
+        assert_ne!(other.run_id, expected.run_id);
+    }
+
+    #[test]
+    fn test_journal_records_bundles_whole_and_undoes_failed_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.jsonl");
+
+        let mut db = Database::new();
+        db.set_journal(Journal::open(&path).unwrap());
+        db.process_transaction(1, 1, Transaction::deposit("10").unwrap())
+            .unwrap();
+        db.process_transaction(2, 2, Transaction::deposit("20").unwrap())
+            .unwrap();
+        db.dispute_bundle(&[(1, 1), (2, 2)], TransactionMeta::default())
+            .unwrap();
+        // One line for the bundle, so a crash can't cut it in half
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 3);
+
+        let mut recovered = Database::new();
+        assert_eq!(recovered.replay_journal(&path).unwrap(), 4);
+        assert_eq!(recovered.digest(), db.digest());
+
+        // A journal that can't be written rejects the transaction and the bundle whole
+        #[cfg(target_os = "linux")]
+        {
+            let mut db = Database::new();
+            db.process_transaction(1, 1, Transaction::deposit("10").unwrap())
+                .unwrap();
+            db.process_transaction(2, 2, Transaction::deposit("20").unwrap())
+                .unwrap();
+            db.set_journal(Journal::open("/dev/full").unwrap());
+            let err = db
+                .process_transaction(1, 3, Transaction::deposit("5").unwrap())
+                .unwrap_err();
+            assert!(matches!(err.kind, EngineError::Io(_)));
+            let err = db
+                .dispute_bundle(&[(1, 1), (2, 2)], TransactionMeta::default())
+                .unwrap_err();
+            assert!(matches!(err.kind, EngineError::Io(_)));
+            assert_eq!(db.get_account(1).unwrap().total().to_string(), "10.0000");
+            assert_eq!(db.get_account(1).unwrap().held().to_string(), "0.0000");
+            assert_eq!(db.get_account(2).unwrap().held().to_string(), "0.0000");
+        }
+    }
+
+    #[test]
+    fn test_roll_back_keeps_storage_and_journal() {
+        let inbox = tempfile::tempdir().unwrap();
+        std::fs::write(
+            inbox.path().join("1-good.csv"),
+            "type,client,tx,amount\ndeposit,1,1,10.0\n",
+        )
+        .unwrap();
+        std::fs::write(
+            inbox.path().join("2-corrupt.csv"),
+            "type,client,tx,amount\ndeposit,1,3,5.0\ndeposit,not-a-client,4,5.0\n",
+        )
+        .unwrap();
+        std::fs::write(
+            inbox.path().join("3-good.csv"),
+            "type,client,tx,amount\ndeposit,2,5,7.0\n",
+        )
+        .unwrap();
+
+        let state = tempfile::tempdir().unwrap();
+        let accounts = state.path().join("accounts.json");
+        let mut engine = Engine::new().with_storage(JsonFileStorage::open(&accounts).unwrap());
+        DropFolder::new(inbox.path())
+            .process_pending(&mut engine)
+            .unwrap();
+        engine.flush().unwrap();
+
+        // Rolling back the corrupt file kept the engine writing to its storage
+        let reopened = Engine::new().with_storage(JsonFileStorage::open(&accounts).unwrap());
+        let totals: Vec<String> = [1, 2]
+            .iter()
+            .map(|&client| {
+                let account = reopened.database().get_account(client).unwrap();
+                account.total().to_string()
+            })
+            .collect();
+        assert_eq!(totals, ["10.0000", "7.0000"]);
+
+        // Transactions rolled back are cut off the journal too
+        let path = state.path().join("journal.jsonl");
+        let mut db = Database::new();
+        db.set_journal(Journal::open(&path).unwrap());
+        db.process_transaction(1, 1, Transaction::deposit("10").unwrap())
+            .unwrap();
+        let checkpoint = db.clone();
+        db.process_transaction(1, 2, Transaction::deposit("99").unwrap())
+            .unwrap();
+        db.roll_back(checkpoint).unwrap();
+        db.process_transaction(1, 3, Transaction::deposit("1").unwrap())
+            .unwrap();
+
+        let mut recovered = Database::new();
+        assert_eq!(recovered.replay_journal(&path).unwrap(), 2);
+        assert_eq!(recovered.digest(), db.digest());
+    }
+
+    #[test]
+    fn test_reserve_percent_over_100_is_rejected() {
+        let policy = Policy {
+            reserve_percent: 150,
+            reserve_days: 90,
+            ..Policy::default()
+        };
+        assert_eq!(
+            policy.validate(),
+            Err(PolicyError::ReservePercentOver100(150))
+        );
+        assert_eq!(
+            policy.validate().unwrap_err().to_string(),
+            "Reserve percentage 150 is over 100"
+        );
+
+        // Never applied, so a deposit can't leave available negative
+        let panic = std::panic::catch_unwind(|| Database::with_policy(policy.clone())).unwrap_err();
+        let message = panic.downcast_ref::<String>().unwrap();
+        assert_eq!(
+            message,
+            "invalid policy: Reserve percentage 150 is over 100"
+        );
+        assert!(std::panic::catch_unwind(|| Engine::with_policy(policy)).is_err());
+
+        // Reserving the whole deposit is allowed
+        let mut database = Database::with_policy(Policy {
+            reserve_percent: 100,
+            reserve_days: 90,
+            ..Policy::default()
+        });
+        let deposit = Transaction::deposit("100").unwrap();
+        database
+            .process_transaction_at(1, 1, deposit, Some(0))
+            .unwrap();
+        let account = database.get_account(1).unwrap();
+        assert_eq!(account.available().to_string(), "0.0000");
+        assert_eq!(account.held().to_string(), "100.0000");
+    }
+
+    #[test]
+    fn test_split_account_never_overdraws_either_account() {
+        let mut db = Database::new();
+        db.process_transaction(1, 1, Transaction::deposit("100").unwrap())
+            .unwrap();
+        db.process_transaction(1, 2, Transaction::withdrawal("90").unwrap())
+            .unwrap();
+        db.process_transaction(1, 3, Transaction::deposit("5").unwrap())
+            .unwrap();
+
+        // Moving the deposit away would leave client 1 at -85
+        let err = db.split_account(1, 2, &[1]).unwrap_err();
+        assert!(matches!(err, EngineError::InsufficientFunds));
+        // Moving the withdrawal alone would leave client 2 at -90
+        let err = db.split_account(1, 2, &[2]).unwrap_err();
+        assert!(matches!(err, EngineError::InsufficientFunds));
+        assert!(db.get_account(2).is_none());
+        assert_eq!(db.get_account(1).unwrap().total().to_string(), "15.0000");
+
+        // Together they can move
+        db.split_account(1, 2, &[1, 2]).unwrap();
+        assert_eq!(db.get_account(1).unwrap().available().to_string(), "5.0000");
+        assert_eq!(
+            db.get_account(2).unwrap().available().to_string(),
+            "10.0000"
+        );
+        assert!(db.audit_balances().is_empty());
+    }
+
+    #[test]
+    fn test_only_valid_operations_are_staged_for_approval() {
+        let mut db = Database::with_policy(Policy {
+            approval_threshold: Some("1000".parse().unwrap()),
+            ..Policy::default()
+        });
+        db.process_transaction(1, 1, Transaction::deposit("5000").unwrap())
+            .unwrap();
+        db.process_transaction(2, 2, Transaction::deposit("500").unwrap())
+            .unwrap();
+        db.set_account_state(2, AccountState::Locked).unwrap();
+        let withdrawal = || Transaction::withdrawal("2500").unwrap();
+
+        // Unknown client, insufficient funds, locked account and a reused ID are all
+        // rejected straight away, and no account is created
+        let err = db.process_transaction(3, 3, withdrawal()).unwrap_err();
+        assert!(matches!(err.kind, EngineError::InsufficientFunds));
+        assert!(db.get_account(3).is_none());
+        let err = db.process_transaction(2, 4, withdrawal()).unwrap_err();
+        assert!(matches!(err.kind, EngineError::AccountLocked));
+        let err = db.process_transaction(1, 1, withdrawal()).unwrap_err();
+        assert!(matches!(err.kind, EngineError::TransactionIdInUse));
+        let err = db
+            .process_transaction(1, 5, Transaction::withdrawal("6000").unwrap())
+            .unwrap_err();
+        assert!(matches!(err.kind, EngineError::InsufficientFunds));
+        assert!(db.pending_approvals().is_empty());
+
+        // A valid withdrawal is staged once; its ID can't be staged again
+        let err = db.process_transaction(1, 6, withdrawal()).unwrap_err();
+        assert!(matches!(err.kind, EngineError::PendingApproval { .. }));
+        let err = db.process_transaction(1, 6, withdrawal()).unwrap_err();
+        assert!(matches!(err.kind, EngineError::TransactionIdInUse));
+        assert_eq!(db.pending_approvals().len(), 1);
+
+        // Manual adjustments over the threshold need approval too
+        let err = db
+            .with_account_mut(1, |account| {
+                account.post_adjustment(7, Transaction::deposit("1500").unwrap(), "operator")
+            })
+            .unwrap()
+            .unwrap_err();
+        let EngineError::PendingApproval { id } = err.kind else {
+            panic!("expected the adjustment to be staged, got {:?}", err.kind)
+        };
+        assert_eq!(
+            db.get_account(1).unwrap().available().to_string(),
+            "5000.0000"
+        );
+        db.approve(id, "supervisor").unwrap();
+        assert_eq!(
+            db.get_account(1).unwrap().available().to_string(),
+            "6500.0000"
+        );
+
+        // Small adjustments still apply straight away
+        db.with_account_mut(1, |account| {
+            account.post_adjustment(8, Transaction::withdrawal("10").unwrap(), "operator")
+        })
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            db.get_account(1).unwrap().available().to_string(),
+            "6490.0000"
+        );
+    }
+
+    #[test]
+    fn test_descending_sort_breaks_ties_by_ascending_client() {
+        let mut db = Database::new();
+        for (client, amount) in [(1, "500"), (2, "2000"), (3, "500"), (4, "2000")] {
+            db.process_transaction(client, client.into(), Transaction::deposit(amount).unwrap())
+                .unwrap();
+        }
+        let clients = |sort: &str| -> Vec<u16> {
+            let query = SummaryQuery {
+                sort: sort.parse().unwrap(),
+                ..SummaryQuery::default()
+            };
+            db.summaries(&query).iter().map(|(id, _)| *id).collect()
+        };
+        assert_eq!(clients("total:desc"), vec![2, 4, 1, 3]);
+        assert_eq!(clients("total:asc"), vec![1, 3, 2, 4]);
+        assert_eq!(clients("client:desc"), vec![4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn test_snapshot_is_unaffected_by_later_changes() {
+        let mut engine = Engine::new();
+        engine.process(CsvSource::new(
+            "input",
+            "type,client,tx,amount\ndeposit,1,1,100\ndeposit,2,2,50\n".as_bytes(),
+        ));
+        let snapshot = engine.snapshot();
+
+        engine.process(CsvSource::new(
+            "input",
+            "type,client,tx,amount\nwithdrawal,1,3,40\ndispute,2,2,\n".as_bytes(),
+        ));
+        let account = snapshot.database().get_account(1).unwrap();
+        assert_eq!(account.available().to_string(), "100.0000");
+        assert_eq!(account.balance_history().len(), 1);
+        let account = snapshot.database().get_account(2).unwrap();
+        assert_eq!(account.held().to_string(), "0.0000");
+        assert_eq!(
+            engine
+                .database()
+                .get_account(1)
+                .unwrap()
+                .available()
+                .to_string(),
+            "60.0000"
+        );
+
+        engine.restore(snapshot);
+        assert_eq!(
+            engine
+                .database()
+                .get_account(2)
+                .unwrap()
+                .available()
+                .to_string(),
+            "50.0000"
+        );
+    }
+
+    #[test]
+    fn test_rejected_rows_are_committed_and_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let batch = "type,client,tx,amount,offset
+deposit,1,1,10.0,payments/0@1
+withdrawal,1,2,50.0,payments/0@2
+deposit,1,1,10.0,payments/0@3";
+
+        let mut engine = Engine::new().with_event_log();
+        let mut store = SnapshotStore::open(dir.path(), &mut engine).unwrap();
+        let stats = engine.process(CsvSource::new("input", batch.as_bytes()));
+        assert_eq!((stats.applied, stats.rejected), (1, 1));
+        // The rejected withdrawal and the duplicate deposit were consumed too
+        assert_eq!(engine.committed_offset("payments", 0), Some(3));
+        store.save(&mut engine).unwrap();
+
+        // The event log only holds the deposit, but the offsets survive a restart
+        let mut restarted = Engine::new().with_event_log();
+        SnapshotStore::open(dir.path(), &mut restarted).unwrap();
+        assert_eq!(restarted.committed_offset("payments", 0), Some(3));
+        let stats = restarted.process(CsvSource::new("input", batch.as_bytes()));
+        assert_eq!((stats.redelivered, stats.rejected), (3, 0));
+        assert!(restarted.errors().is_empty());
+    }
+
+    #[test]
+    fn test_merge_checks_balances_and_keeps_approval_ids_unique() {
+        let big = || Transaction::deposit("900000000000000").unwrap();
+        let mut east = Database::new();
+        east.process_transaction(1, 1, big()).unwrap();
+        let mut west = Database::new();
+        west.process_transaction(1, 2, big()).unwrap();
+        assert_eq!(
+            east.merge(west, ConflictPolicy::Error),
+            Err(MergeError::BalanceOverflow { client: 1 })
+        );
+        let account = east.get_account(1).unwrap();
+        assert_eq!(account.available().to_string(), "900000000000000.0000");
+        assert!(!account.has_transaction(2));
+
+        // Both sides staged an operation under the same ID
+        let policy = Policy {
+            approval_threshold: Some("100".parse().unwrap()),
+            ..Policy::default()
+        };
+        let mut east = Database::with_policy(policy.clone());
+        let mut west = Database::with_policy(policy);
+        for (db, client) in [(&mut east, 1), (&mut west, 2)] {
+            let tx = u32::from(client) * 10;
+            db.process_transaction(client, tx, Transaction::deposit("500").unwrap())
+                .unwrap();
+            db.process_transaction(client, tx + 1, Transaction::withdrawal("200").unwrap())
+                .unwrap_err();
+        }
+        east.merge(west, ConflictPolicy::Error).unwrap();
+
+        let ids: Vec<u64> = east.pending_approvals().iter().map(|p| p.id).collect();
+        assert_eq!(ids.len(), 2);
+        assert_ne!(ids[0], ids[1]);
+        let west_id = east
+            .pending_approvals()
+            .iter()
+            .find(|pending| pending.client == 2)
+            .unwrap()
+            .id;
+        let requested = east.get_account(2).unwrap().audit_log()[0].event.clone();
+        assert!(matches!(requested, AuditEvent::ApprovalRequested { id, .. } if id == west_id));
+
+        east.approve(west_id, "supervisor").unwrap();
+        assert_eq!(
+            east.get_account(2).unwrap().available().to_string(),
+            "300.0000"
+        );
+        assert_eq!(
+            east.get_account(1).unwrap().available().to_string(),
+            "500.0000"
+        );
+    }
+
+    #[test]
+    fn test_roles_allow_transactions_by_type() {
+        let feed_types = [
+            Transaction::deposit("1").unwrap(),
+            Transaction::withdrawal("1").unwrap(),
+            Transaction::refund(1, "1").unwrap(),
+            Transaction::dispute(),
+            Transaction::resolve(),
+            Transaction::chargeback(),
+        ];
+        for transaction in feed_types {
+            let action = Action::Transaction(transaction);
+            assert!(
+                Role::Feed.allows(&action) && Role::Ops.allows(&action),
+                "{action}"
+            );
+            assert!(!Role::Auditor.allows(&action), "{action}");
+        }
+
+        let ops_only = [
+            Action::Transaction(Transaction::chargeback_reversal()),
+            Action::Adjustment,
+            Action::Unlock,
+            Action::AuditAndRepair,
+        ];
+        for action in ops_only {
+            assert!(Role::Ops.allows(&action), "{action}");
+            assert!(
+                !Role::Feed.allows(&action) && !Role::Auditor.allows(&action),
+                "{action}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_fixed4_arithmetic_never_wraps() {
+        let max: Fixed4 = "922337203685477.5807".parse().unwrap();
+        let min = -max - "0.0001".parse().unwrap();
+        assert_eq!(max.checked_mul(2), None);
+        assert_eq!(min.checked_div(-1), None);
+        assert_eq!(max.checked_div(0), None);
+        assert_eq!(max.checked_div(1), Some(max));
+
+        assert!(std::panic::catch_unwind(|| max * 2).is_err());
+        assert!(std::panic::catch_unwind(|| min / -1).is_err());
+        assert!(std::panic::catch_unwind(|| max / 0).is_err());
+        let tiny: Fixed4 = "0.0001".parse().unwrap();
+        assert!(std::panic::catch_unwind(|| max + tiny).is_err());
+        assert!(std::panic::catch_unwind(|| min - tiny).is_err());
+        assert!(std::panic::catch_unwind(|| -min).is_err());
+        assert_eq!(-max - tiny, min);
+    }
+
+    #[test]
+    fn test_balance_history_keeps_the_latest_rows() {
+        let mut db = Database::with_policy(Policy {
+            balance_history_limit: Some(2),
+            ..Policy::default()
+        });
+        for tx in 1..=4 {
+            db.process_transaction(1, tx, Transaction::deposit("10").unwrap())
+                .unwrap();
+        }
+        db.process_transaction(1, 1, Transaction::dispute())
+            .unwrap();
+
+        let account = db.get_account(1).unwrap();
+        let txs: Vec<u32> = account.balance_history().iter().map(|row| row.tx).collect();
+        assert_eq!(txs, [4, 1]);
+        assert_eq!(account.total().to_string(), "40.0000");
+        assert_eq!(account.held().to_string(), "10.0000");
+
+        let statement = db.statement(1).unwrap();
+        assert_eq!(statement.history.len(), 2);
+        assert_eq!(statement.lines.len(), 4);
+    }
+
+    #[test]
+    fn test_chargeback_on_locked_account_renews_the_lock() {
+        const HOUR: u64 = 60 * 60;
+        let mut database = Database::with_policy(Policy {
+            auto_unlock_after: Some(HOUR),
+            unlock_requires_review: true,
+            ..Policy::default()
+        });
+        for tx in 1..=2 {
+            let deposit = Transaction::deposit("10").unwrap();
+            database.process_transaction(1, tx, deposit).unwrap();
+            database
+                .process_transaction(1, tx, Transaction::dispute())
+                .unwrap();
+        }
+        database
+            .process_transaction_at(1, 1, Transaction::chargeback(), Some(0))
+            .unwrap();
+        database.mark_reviewed(1, "ops", Some(60)).unwrap();
+        database
+            .process_transaction_at(1, 2, Transaction::chargeback(), Some(HOUR / 2))
+            .unwrap();
+
+        let lock = database.get_account(1).unwrap().lock_details().unwrap();
+        assert_eq!(lock.reason, LockReason::Chargeback { tx: 2 });
+        assert_eq!(lock.locked_at, Some(HOUR / 2));
+        // The first lock's cooling-off and review no longer count
+        assert!(database.apply_auto_unlocks(HOUR).is_empty());
+        assert!(database.apply_auto_unlocks(2 * HOUR).is_empty());
+        database.mark_reviewed(1, "ops", Some(2 * HOUR)).unwrap();
+        assert_eq!(database.apply_auto_unlocks(2 * HOUR), [1]);
+    }
+
+    /// Rows for six clients covering disputes, chargebacks and rejected transactions
+    #[cfg(feature = "testing")]
+    fn interleaving_rows() -> Vec<Row> {
+        let mut rows = Vec::new();
+        for client in 1..=6u16 {
+            let tx = |n: u32| u32::from(client) * 100 + n;
+            rows.push((client, tx(1), Transaction::deposit("100").unwrap()));
+            rows.push((client, tx(2), Transaction::deposit("50").unwrap()));
+            rows.push((client, tx(3), Transaction::withdrawal("30").unwrap()));
+            rows.push((client, tx(1), Transaction::dispute()));
+            let settle = if client % 2 == 0 {
+                Transaction::chargeback()
+            } else {
+                Transaction::resolve()
+            };
+            rows.push((client, tx(1), settle));
+            rows.push((client, tx(4), Transaction::withdrawal("500").unwrap()));
+            rows.push((client, tx(9), Transaction::dispute()));
+        }
+        rows
+    }
+
+    /// `rows` as CSV input
+    #[cfg(feature = "testing")]
+    fn rows_csv<'a>(rows: impl IntoIterator<Item = &'a Row>) -> String {
+        let mut csv = "type,client,tx,amount\n".to_string();
+        for (client, tx, transaction) in rows {
+            let amount = transaction
+                .amount()
+                .map_or(String::new(), |amount| amount.to_string());
+            csv.push_str(&format!(
+                "{},{},{},{}\n",
+                transaction.name(),
+                client,
+                tx,
+                amount
+            ));
+        }
+        csv
+    }
+
+    /// The accounts and number of rejections of a sequential run over `rows`
+    #[cfg(feature = "testing")]
+    fn sequential_run(rows: &[Row]) -> (Database, usize) {
+        let mut database = Database::new();
+        let rejected = rows
+            .iter()
+            .filter(|(client, tx, transaction)| {
+                database
+                    .process_transaction(*client, *tx, transaction.clone())
+                    .is_err()
+            })
+            .count();
+        (database, rejected)
+    }
+
+    /// Compare every account of `actual` with the sequential run in `expected`
+    #[cfg(feature = "testing")]
+    fn assert_same_accounts(expected: &Database, actual: &Database, seed: u64) {
+        assert_eq!(
+            actual.get_all_client_ids().len(),
+            expected.get_all_client_ids().len()
+        );
+        for client in expected.get_all_client_ids() {
+            let want = expected.get_account(client).unwrap();
+            let got = actual.get_account(client).unwrap();
+            assert_eq!(got.summary(), want.summary(), "seed {}", seed);
+            assert_eq!(
+                actual.statement(client),
+                expected.statement(client),
+                "seed {}",
+                seed
+            );
+        }
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn test_parallel_file_matches_sequential_run_under_interleavings() {
+        let rows = interleaving_rows();
+        let checked = Interleavings::new(rows.clone()).with_shards(4).check();
+        assert_eq!(checked, Ok(101));
+
+        let (expected, rejected) = sequential_run(&rows);
+        let processor = CsvProcessor::builder().build();
+        for seed in 0..20 {
+            let file = create_temp_csv(&rows_csv(&interleave(&rows, seed)));
+            let outcome = processor
+                .process_file_parallel(file.path().to_str().unwrap(), 4)
+                .unwrap();
+            assert_same_accounts(&expected, &outcome.database, seed);
+            assert_eq!(outcome.errors.len(), rejected, "seed {}", seed);
+        }
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn test_parallel_files_match_sequential_run_under_interleavings() {
+        let rows = interleaving_rows();
+        let (expected, rejected) = sequential_run(&rows);
+        for seed in 0..20 {
+            // Each client's rows stay in one file, interleaved with the other clients'
+            let shuffled = interleave(&rows, seed);
+            let files: Vec<NamedTempFile> = (0..3)
+                .map(|file| {
+                    let rows = shuffled.iter().filter(|row| row.0 % 3 == file);
+                    create_temp_csv(&rows_csv(rows))
+                })
+                .collect();
+            let paths: Vec<&str> = files
+                .iter()
+                .map(|file| file.path().to_str().unwrap())
+                .collect();
+            let (database, errors, _) = process_csv_files_parallel(&paths).unwrap();
+            assert_same_accounts(&expected, &database, seed);
+            assert_eq!(errors.len(), rejected, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn test_reopened_journal_drops_an_entry_cut_short() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.jsonl");
+
+        let mut db = Database::new();
+        db.set_journal(Journal::open(&path).unwrap());
+        db.process_transaction(1, 1, Transaction::deposit("10").unwrap())
+            .unwrap();
+        drop(db);
+        // The process crashed part way through writing an entry
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"{\"type\":\"dep").unwrap();
+        drop(file);
+
+        let mut db = Database::new();
+        assert_eq!(db.replay_journal(&path).unwrap(), 1);
+        db.set_journal(Journal::open(&path).unwrap());
+        db.process_transaction(1, 2, Transaction::deposit("5").unwrap())
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        let mut recovered = Database::new();
+        assert_eq!(recovered.replay_journal(&path).unwrap(), 2);
+        assert_eq!(recovered.digest(), db.digest());
+    }
+
+    #[test]
+    fn test_each_run_reports_its_own_error_summary() {
+        let mut engine = Engine::new().with_error_summary(1);
+        let first = "type,client,tx,amount\n\
+                     withdrawal,1,1,5\n\
+                     withdrawal,1,2,5\n";
+        let second = "type,client,tx,amount\n\
+                      withdrawal,1,3,5\n\
+                      deposit,1,x,5\n";
+        let first = engine.process(CsvSource::new("first", first.as_bytes()));
+        let second = engine.process(CsvSource::new("second", second.as_bytes()));
+
+        let first = first.error_summary.unwrap();
+        assert_eq!(first.total(), 2);
+        assert!(first.top(1)[0].1.examples[0].contains("first:2"));
+        let second = second.error_summary.unwrap();
+        assert_eq!(second.total(), 2);
+        let top = second.top(2);
+        assert_eq!((top[0].0, top[0].1.count), ("INSUFFICIENT_FUNDS", 1));
+        assert!(top[0].1.examples[0].contains("second:2"));
+
+        // The engine and its accumulated statistics count every run
+        let total = engine.error_summary().unwrap();
+        assert_eq!(total.total(), 4);
+        assert_eq!(engine.stats().error_summary.as_ref(), Some(total));
+        let top = total.top(1);
+        assert_eq!((top[0].0, top[0].1.count), ("INSUFFICIENT_FUNDS", 3));
+        assert_eq!(top[0].1.examples.len(), 1);
+    }
+
+    #[test]
+    fn test_amounts_parsed_exactly_when_read() {
+        // 16 significant digits, more than an f64 holds exactly
+        let csv_content = "type,client,tx,amount\n\
+                           deposit,1,1,922337203685.4775\n\
+                           deposit,1,2,abc\n\
+                           dispute,1,1,abc\n";
+
+        let (database, errors, warnings) = process_csv_str(csv_content);
+
+        let account = database.get_account(1).unwrap();
+        assert_eq!(account.held().to_string(), "922337203685.4775");
+        assert_eq!(account.available().to_string(), "0.0000");
+        // An invalid amount rejects only a row that needs it
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("input:3") && errors[0].contains("Invalid amount format"));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("Amount ignored"));
+    }
 }